@@ -0,0 +1,100 @@
+// Purpose: compare full decoding with peeking at the header through `Envelope` on broadcast
+// traffic.
+//
+// usage: cargo bench --bench parsing
+//
+// The lines mimic `efficient-broadcast`: client broadcasts and reads plus node to node shares
+// whose size grows with the number of values in flight. `peek` is what a line costs if only its
+// header is needed, `peek+decode` what it costs if the payload is decoded after all, which is
+// the like for like comparison with `full`.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rust_distributed_sys_challenge::envelope::{Decode, Envelope};
+use rust_distributed_sys_challenge::workloads::broadcast::Payload;
+
+const LINES: usize = 20_000;
+
+fn traffic(values_per_share: usize) -> Vec<String> {
+    let values: Vec<String> = (0..values_per_share)
+        .map(|value| value.to_string())
+        .collect();
+    let values = values.join(",");
+    return (0..LINES)
+        .map(|i| match i % 4 {
+            | 0 => format!(
+                r#"{{"src":"c{}","dest":"n{}","body":{{"type":"broadcast","msg_id":{},"message":{}}}}}"#,
+                i % 50,
+                i % 25,
+                i,
+                i
+            ),
+            | 1 => format!(
+                r#"{{"src":"c{}","dest":"n{}","body":{{"type":"read","msg_id":{}}}}}"#,
+                i % 50,
+                i % 25,
+                i
+            ),
+            | 2 => format!(
+                r#"{{"src":"n{}","dest":"n{}","body":{{"type":"share","messages":[{}]}}}}"#,
+                i % 25,
+                (i + 1) % 25,
+                values
+            ),
+            | _ => format!(
+                r#"{{"src":"n{}","dest":"n{}","body":{{"type":"share_ok","msg_id":{},"in_reply_to":{},"messages":[{}]}}}}"#,
+                i % 25,
+                (i + 1) % 25,
+                i,
+                i - 1,
+                values
+            ),
+        })
+        .collect();
+}
+
+/// Messages per second of `parse` over `lines`, best of a few runs
+fn throughput(lines: &[String], parse: impl Fn(&str)) -> f64 {
+    let mut best = Duration::MAX;
+    for _ in 0..5 {
+        let started = Instant::now();
+        for line in lines {
+            parse(line);
+        }
+        best = best.min(started.elapsed());
+    }
+    return lines.len() as f64 / best.as_secs_f64();
+}
+
+fn main() {
+    println!(
+        "{:>13} {:>12} {:>12} {:>14} {:>20}",
+        "values/share", "full msg/s", "peek msg/s", "peek+decode/s", "peek+decode vs full"
+    );
+    for values_per_share in [1, 10, 100, 1000] {
+        let lines = traffic(values_per_share);
+        let full = throughput(&lines, |line| {
+            black_box(Payload::decode_line(line).unwrap());
+        });
+        // NOTE: what `async_loop` does to every line before it knows whether it is a reply
+        let peek = throughput(&lines, |line| {
+            let envelope = Envelope::parse(line).unwrap();
+            black_box(envelope.header().unwrap().in_reply_to);
+        });
+        let peek_decoded = throughput(&lines, |line| {
+            let envelope = Envelope::parse(line).unwrap();
+            black_box(envelope.header().unwrap().in_reply_to);
+            black_box(envelope.payload::<Payload>().unwrap());
+        });
+        println!(
+            "{:>13} {:>12.0} {:>12.0} {:>14.0} {:>19.2}x",
+            values_per_share,
+            full,
+            peek,
+            peek_decoded,
+            peek_decoded / full
+        );
+    }
+    // NOTE: efficient-broadcast sends 100 client msg/s, plus the node to node shares
+    println!("efficient-broadcast needs: 100 msg/s plus shares");
+}
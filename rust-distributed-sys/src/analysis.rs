@@ -0,0 +1,314 @@
+// Purpose: measure a broadcast run the way Maelstrom's report does, from recorded traffic.
+//
+// Works on `sim::TraceEntry`s, straight from `Simulation::trace()` or parsed from JSON lines, so the
+// 3d/3e goals (messages per operation, median and maximum latency) can be checked without
+// Maelstrom, e.g. in CI.
+//
+// NOTE: like Maelstrom, clients are `c1`, `c2`, ... and every other id is a server
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::is_client;
+use crate::sim::TraceEntry;
+
+/// Parse a trace from JSON lines, one `TraceEntry` per line
+///
+/// `dropped` is optional, so a plain message log with a `time` (in seconds) on every line works too.
+pub fn read_trace(text: &str) -> anyhow::Result<Vec<TraceEntry>> {
+    return text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            return serde_json::from_str(line)
+                .context(format!("line {} is not a trace entry", number + 1));
+        })
+        .collect();
+}
+
+/// Distribution summary, the same points Maelstrom reports
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Percentiles {
+    pub min: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    /// Nearest-rank percentiles, `None` if there are no samples
+    pub fn from_samples(samples: &[f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = |quantile: f64| {
+            let index = (quantile * sorted.len() as f64).ceil() as usize;
+            return sorted[index.saturating_sub(1).min(sorted.len() - 1)];
+        };
+        return Some(Percentiles {
+            min: sorted[0],
+            p50: rank(0.5),
+            p95: rank(0.95),
+            p99: rank(0.99),
+            max: sorted[sorted.len() - 1],
+        });
+    }
+}
+
+struct Read {
+    node: String,
+    time: f64,
+    values: BTreeSet<String>,
+}
+
+struct Broadcast {
+    value: Value,
+    invoked: f64,
+    acknowledged: bool,
+}
+
+/// Server load, stable latencies and missing values of a broadcast run
+#[derive(Debug, Clone, Serialize)]
+pub struct BroadcastReport {
+    /// client requests of any kind
+    pub operations: usize,
+    /// messages between servers, dropped ones included
+    pub server_messages: usize,
+    pub dropped_messages: usize,
+    pub messages_per_op: f64,
+    /// seconds from a broadcast being sent until every read from then on contains the value
+    pub stable_latencies: Option<Percentiles>,
+    /// values whose broadcast was acknowledged but that are missing from a node's final read
+    pub lost: Vec<Value>,
+    /// values no read ever returned
+    pub never_read: Vec<Value>,
+}
+
+impl BroadcastReport {
+    pub fn from_trace(trace: &[TraceEntry]) -> Self {
+        let mut entries: Vec<&TraceEntry> = trace.iter().collect();
+        entries.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let mut operations = 0;
+        let mut server_messages = 0;
+        let mut dropped_messages = 0;
+        // NOTE: (client, msg_id) -> index into `broadcasts`, `None` for reads
+        let mut requests: HashMap<(String, u64), Option<usize>> = HashMap::new();
+        let mut broadcasts: Vec<Broadcast> = Vec::new();
+        let mut reads: Vec<Read> = Vec::new();
+
+        for entry in entries {
+            let kind = entry.body["type"].as_str().unwrap_or_default();
+            if !is_client(&entry.src) && !is_client(&entry.dest) {
+                server_messages += 1;
+                if entry.dropped {
+                    dropped_messages += 1;
+                }
+            } else if is_client(&entry.src) {
+                operations += 1;
+                let Some(msg_id) = entry.body["msg_id"].as_u64() else {
+                    continue;
+                };
+                let broadcast = match kind {
+                    | "broadcast" => {
+                        broadcasts.push(Broadcast {
+                            value: entry.body["message"].clone(),
+                            invoked: entry.time,
+                            acknowledged: false,
+                        });
+                        Some(broadcasts.len() - 1)
+                    },
+                    | "read" => None,
+                    | _ => continue,
+                };
+                requests.insert((entry.src.clone(), msg_id), broadcast);
+            } else {
+                let Some(in_reply_to) = entry.body["in_reply_to"].as_u64() else {
+                    continue;
+                };
+                let Some(broadcast) = requests.remove(&(entry.dest.clone(), in_reply_to)) else {
+                    continue;
+                };
+                match (kind, broadcast) {
+                    | ("broadcast_ok", Some(index)) => broadcasts[index].acknowledged = true,
+                    | ("read_ok", None) => {
+                        let values = entry.body["messages"]
+                            .as_array()
+                            .map(|messages| messages.iter().map(|m| m.to_string()).collect())
+                            .unwrap_or_default();
+                        reads.push(Read {
+                            node: entry.src.clone(),
+                            time: entry.time,
+                            values,
+                        });
+                    },
+                    | _ => {},
+                }
+            }
+        }
+
+        let mut final_reads: BTreeMap<&str, &Read> = BTreeMap::new();
+        for read in reads.iter() {
+            final_reads.insert(&read.node, read);
+        }
+
+        let mut latencies = Vec::new();
+        let mut lost = Vec::new();
+        let mut never_read = Vec::new();
+        for broadcast in broadcasts.iter() {
+            let value = broadcast.value.to_string();
+            if !reads.iter().any(|read| read.values.contains(&value)) {
+                never_read.push(broadcast.value.clone());
+            }
+            let missing_at_end = final_reads.values().any(|read| {
+                return read.time >= broadcast.invoked && !read.values.contains(&value);
+            });
+            if missing_at_end {
+                if broadcast.acknowledged {
+                    lost.push(broadcast.value.clone());
+                }
+                continue;
+            }
+
+            // NOTE: the value is stable from the first read after the last one that missed it
+            let later: Vec<&Read> = reads
+                .iter()
+                .filter(|read| read.time >= broadcast.invoked)
+                .collect();
+            let stable_from = match later.iter().rposition(|read| !read.values.contains(&value)) {
+                | Some(last_miss) => later.get(last_miss + 1),
+                | None => later.first(),
+            };
+            if let Some(read) = stable_from {
+                latencies.push(read.time - broadcast.invoked);
+            }
+        }
+
+        let messages_per_op = if operations == 0 {
+            0.0
+        } else {
+            server_messages as f64 / operations as f64
+        };
+        return BroadcastReport {
+            operations,
+            server_messages,
+            dropped_messages,
+            messages_per_op,
+            stable_latencies: Percentiles::from_samples(&latencies),
+            lost,
+            never_read,
+        };
+    }
+}
+
+impl fmt::Display for BroadcastReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "operations:       {}", self.operations)?;
+        writeln!(
+            f,
+            "server messages:  {} ({} dropped)",
+            self.server_messages, self.dropped_messages
+        )?;
+        writeln!(f, "msgs-per-op:      {:.2}", self.messages_per_op)?;
+        match &self.stable_latencies {
+            | Some(latencies) => writeln!(
+                f,
+                "stable latency:   min {:.0}ms, p50 {:.0}ms, p95 {:.0}ms, p99 {:.0}ms, max {:.0}ms",
+                latencies.min * 1000.0,
+                latencies.p50 * 1000.0,
+                latencies.p95 * 1000.0,
+                latencies.p99 * 1000.0,
+                latencies.max * 1000.0
+            )?,
+            | None => writeln!(f, "stable latency:   no value became stable")?,
+        }
+        writeln!(f, "lost:             {}", Value::Array(self.lost.clone()))?;
+        return writeln!(
+            f,
+            "never read:       {}",
+            Value::Array(self.never_read.clone())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        let expected = Percentiles {
+            min: 1.0,
+            p50: 50.0,
+            p95: 95.0,
+            p99: 99.0,
+            max: 100.0,
+        };
+        assert_eq!(Percentiles::from_samples(&samples), Some(expected));
+
+        let small = Percentiles::from_samples(&[3.0, 1.0, 2.0]).unwrap();
+        assert_eq!(
+            (small.min, small.p50, small.p95, small.max),
+            (1.0, 2.0, 3.0, 3.0)
+        );
+        assert_eq!(Percentiles::from_samples(&[]), None);
+    }
+
+    #[test]
+    fn malformed_line_is_reported() {
+        let error = read_trace("\n{\"time\": 0.0}\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"), "{}", error);
+    }
+
+    /// c1 broadcasts 1 and 2, both acknowledged, c2 broadcasts 3 and never hears back. n1 is read
+    /// once, before 2 reaches it, n0 last, with 1 and 2.
+    const TRACE: &str = r#"
+{"time":0.00,"src":"c1","dest":"n0","body":{"type":"broadcast","msg_id":1,"message":1}}
+{"time":0.01,"src":"n0","dest":"n1","body":{"type":"broadcast","message":1}}
+{"time":0.02,"src":"n0","dest":"n1","body":{"type":"broadcast","message":1},"dropped":true}
+{"time":0.03,"src":"n0","dest":"c1","body":{"type":"broadcast_ok","in_reply_to":1}}
+{"time":0.10,"src":"c1","dest":"n0","body":{"type":"broadcast","msg_id":2,"message":2}}
+{"time":0.11,"src":"n0","dest":"c1","body":{"type":"broadcast_ok","in_reply_to":2}}
+{"time":0.20,"src":"c1","dest":"n1","body":{"type":"read","msg_id":3}}
+{"time":0.25,"src":"n1","dest":"c1","body":{"type":"read_ok","in_reply_to":3,"messages":[1]}}
+{"time":0.30,"src":"c1","dest":"n0","body":{"type":"read","msg_id":4}}
+{"time":0.40,"src":"c2","dest":"n0","body":{"type":"broadcast","msg_id":1,"message":3}}
+{"time":0.50,"src":"n0","dest":"c1","body":{"type":"read_ok","in_reply_to":4,"messages":[1, 2]}}
+"#;
+
+    #[test]
+    fn report_of_a_small_trace() {
+        let report = BroadcastReport::from_trace(&read_trace(TRACE).unwrap());
+        assert_eq!(report.operations, 5);
+        assert_eq!(report.server_messages, 2);
+        assert_eq!(report.dropped_messages, 1);
+        assert!((report.messages_per_op - 0.4).abs() < 1e-9);
+
+        // NOTE: only 1 is in every final read, stable from n1's read 0.25s after it was sent
+        let latencies = report.stable_latencies.unwrap();
+        assert!((latencies.min - 0.25).abs() < 1e-9);
+        assert_eq!(latencies.min, latencies.max);
+
+        // NOTE: n1's last read came after 2 was acknowledged and misses it, 3 was never acked
+        assert_eq!(report.lost, vec![json!(2)]);
+        assert_eq!(report.never_read, vec![json!(3)]);
+    }
+
+    #[test]
+    fn empty_trace() {
+        let report = BroadcastReport::from_trace(&[]);
+        assert_eq!(report.operations, 0);
+        assert_eq!(report.messages_per_op, 0.0);
+        assert_eq!(report.stable_latencies, None);
+        assert!(report.lost.is_empty() && report.never_read.is_empty());
+    }
+}
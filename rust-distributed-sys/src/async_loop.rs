@@ -0,0 +1,612 @@
+// Purpose: async flavour of `event_loop`, behind the `async` cargo feature.
+//
+// With threads and events (see the README) a request that needs a peer's answer is split across
+// several `step` calls. Here every incoming request is handled by its own task on a single
+// threaded tokio runtime, so a handler can `ctx.rpc(..).await` a peer or one of Maelstrom's KV
+// services and carry on with the reply. Timers are tasks that `sleep` in a loop.
+//
+// Input goes through the same bounded `queue` as in `event_loop`, with the lanes, overflow and
+// metrics of `EventLoopConfig::queue`, and output is flushed by `EventLoopConfig::flush`.
+//
+// NOTE: all tasks run on one thread, node state only needs `Cell`/`RefCell`, never a `Mutex`
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context as _};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::LocalSet;
+
+use crate::envelope::{Decode, Envelope};
+use crate::output::Output;
+use crate::queue::event_queue;
+use crate::status::{self, StatusPayload};
+use crate::{clock, Body, Event, EventLoopConfig, InitNodes, InitPayload, Message};
+
+/// Async counterpart of `Node`
+///
+/// Handlers take `Rc<Self>` because many of them can be suspended at the same time.
+pub trait AsyncNode<State, Payload>: Sized + 'static {
+    fn from_init(state: State, init: InitNodes, ctx: &Context) -> anyhow::Result<Self>;
+
+    /// Runs once after init, e.g. to start timers with `ctx.spawn`
+    fn started(self: Rc<Self>, ctx: Context) -> impl Future<Output = anyhow::Result<()>> + 'static {
+        let _ = (self, ctx);
+        return async { Ok(()) };
+    }
+
+    /// Handle a message that isn't a reply to one of our own `rpc`s
+    fn handle(
+        self: Rc<Self>,
+        ctx: Context,
+        message: Message<Payload>,
+    ) -> impl Future<Output = anyhow::Result<()>> + 'static;
+}
+
+struct Inner {
+    node_id: String,
+    node_ids: HashSet<String>,
+    next_id: Cell<usize>,
+    output: RefCell<Output<Box<dyn Write>>>,
+    /// the loop waits for input, nothing else will flush what a task writes
+    idle: Cell<bool>,
+    /// `msg_id` of an outstanding rpc -> where its reply goes
+    pending: RefCell<HashMap<usize, oneshot::Sender<Box<RawValue>>>>,
+}
+
+/// Handle to send messages, await replies and spawn tasks, cheap to clone
+#[derive(Clone)]
+pub struct Context {
+    inner: Rc<Inner>,
+}
+
+impl Context {
+    pub fn node_id(&self) -> &str {
+        return &self.inner.node_id;
+    }
+
+    pub fn node_ids(&self) -> &HashSet<String> {
+        return &self.inner.node_ids;
+    }
+
+    fn next_id(&self) -> usize {
+        let id = self.inner.next_id.get();
+        self.inner.next_id.set(id + 1);
+        return id;
+    }
+
+    fn write<Payload: Serialize>(&self, message: Message<Payload>) -> anyhow::Result<()> {
+        let mut output = self.inner.output.borrow_mut();
+        let dest = message.dest.clone();
+        message.send(&mut *output, &dest)?;
+        // NOTE: e.g. a task woken by a timer or an rpc timeout
+        if self.inner.idle.get() {
+            output.flush().context("Flushing output.")?;
+        }
+        return Ok(());
+    }
+
+    /// Fire and forget, nobody waits for a reply
+    pub fn send<Payload: Serialize>(&self, dest: &str, payload: Payload) -> anyhow::Result<()> {
+        return self.write(Message {
+            src: self.inner.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(self.next_id()),
+                in_reply_to: None,
+                clock: None,
+                payload,
+            },
+        });
+    }
+
+    pub fn reply<Request, Payload: Serialize>(
+        &self,
+        request: &Message<Request>,
+        payload: Payload,
+    ) -> anyhow::Result<()> {
+        return self.write(Message {
+            src: self.inner.node_id.clone(),
+            dest: request.src.clone(),
+            body: Body {
+                id: Some(self.next_id()),
+                in_reply_to: request.body.id,
+                clock: None,
+                payload,
+            },
+        });
+    }
+
+    /// Send `request` to `dest` and wait for the reply
+    ///
+    /// returns:
+    ///   - the reply decoded as `Response`, an error if it doesn't arrive within `timeout`
+    pub async fn rpc<Request: Serialize, Response: DeserializeOwned>(
+        &self,
+        dest: &str,
+        request: Request,
+        timeout: Duration,
+    ) -> anyhow::Result<Response> {
+        let id = self.next_id();
+        let (sender, reply) = oneshot::channel();
+        self.inner.pending.borrow_mut().insert(id, sender);
+        self.write(Message {
+            src: self.inner.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                clock: None,
+                payload: request,
+            },
+        })?;
+
+        let Ok(Ok(body)) = tokio::time::timeout(timeout, reply).await else {
+            // NOTE: a late reply finds no entry and is dropped
+            self.inner.pending.borrow_mut().remove(&id);
+            bail!("no reply from {} to msg {} within {:?}", dest, id, timeout);
+        };
+        return serde_json::from_str(body.get())
+            .with_context(|| format!("unexpected reply {} from {}", body.get(), dest));
+    }
+
+    /// Run `task` concurrently, an error ends up on stderr (the Maelstrom node log)
+    pub fn spawn(&self, task: impl Future<Output = anyhow::Result<()>> + 'static) {
+        tokio::task::spawn_local(async move {
+            if let Err(error) = task.await {
+                eprintln!("task failed: {:?}", error);
+            }
+        });
+    }
+
+    pub async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Run an `AsyncNode` as a Maelstrom binary, reading stdin and writing stdout
+pub fn async_event_loop<N, State, Payload>(inital_state: State) -> anyhow::Result<()>
+where
+    N: AsyncNode<State, Payload>,
+    Payload: Decode + 'static,
+{
+    return async_event_loop_with::<N, State, Payload>(
+        inital_state,
+        std::io::stdin(),
+        std::io::stdout(),
+    );
+}
+
+/// Run an `AsyncNode` over any line based input and output, e.g. the network of `sim`
+pub fn async_event_loop_with<N, State, Payload>(
+    inital_state: State,
+    input: impl Read + Send + 'static,
+    output: impl Write + 'static,
+) -> anyhow::Result<()>
+where
+    N: AsyncNode<State, Payload>,
+    Payload: Decode + 'static,
+{
+    return async_event_loop_with_config::<N, State, Payload>(
+        inital_state,
+        input,
+        output,
+        EventLoopConfig::default(),
+    );
+}
+
+/// What the runtime thread gets from the event queue
+enum Incoming {
+    Line(String),
+    Status(Message<StatusPayload>),
+}
+
+/// Run an `AsyncNode` with custom output and event queue settings
+pub fn async_event_loop_with_config<N, State, Payload>(
+    inital_state: State,
+    input: impl Read + Send + 'static,
+    output: impl Write + 'static,
+    config: EventLoopConfig,
+) -> anyhow::Result<()>
+where
+    N: AsyncNode<State, Payload>,
+    Payload: Decode + 'static,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .context("Failed to start the async runtime.")?;
+
+    // NOTE: blocking reads stay on a plain thread, lines are queued in the lane of their sender.
+    // The queue only needs `src` for that, the line itself is decoded on the runtime thread.
+    let (sender, receiver) = event_queue::<String, ()>(config.queue);
+    let metrics = sender.clone();
+    let reader = thread::spawn(move || -> anyhow::Result<()> {
+        for line in BufReader::new(input).lines() {
+            let line = line.context("Maelstrom input could not be read.")?;
+            if let Some(request) = status::parse_request(&line) {
+                sender.send_status(request);
+                continue;
+            }
+            let Envelope { src, dest, .. } = Envelope::parse(&line)?;
+            let message = Message {
+                src: src.into_owned(),
+                dest: dest.into_owned(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: line,
+                },
+            };
+            if sender.send(Event::Message(message)).is_err() {
+                return Ok(());
+            }
+        }
+        let _ = sender.send(Event::EndOfMessages);
+        return Ok(());
+    });
+    // NOTE: the runtime can't wait on the queue's condvar, a thread hands events over one by one
+    let (forward, mut incoming) = mpsc::channel::<Incoming>(1);
+    let forwarder = thread::spawn(move || loop {
+        receiver.wait();
+        while let Some(request) = receiver.try_recv_status() {
+            if forward.blocking_send(Incoming::Status(request)).is_err() {
+                return;
+            }
+        }
+        let line = match receiver.try_recv() {
+            | Ok(Event::Message(message)) => message.body.payload,
+            | Ok(Event::GeneratedEvent(_)) | Err(std::sync::mpsc::TryRecvError::Empty) => continue,
+            | Ok(Event::EndOfMessages) | Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                return;
+            },
+        };
+        if forward.blocking_send(Incoming::Line(line)).is_err() {
+            return;
+        }
+    });
+
+    let output: Box<dyn Write> = Box::new(output);
+    let local = LocalSet::new();
+    let result = local.block_on(&runtime, async move {
+        let Some(Incoming::Line(init_line)) = incoming.recv().await else {
+            bail!("No init message received.");
+        };
+        let init_message: Message<InitPayload> =
+            serde_json::from_str(&init_line).context("Init message could not be deserialized!")?;
+        let InitPayload::Init(init) = init_message.body.payload else {
+            bail!("First message should be an init!");
+        };
+
+        let ctx = Context {
+            inner: Rc::new(Inner {
+                node_id: init.node_id.clone(),
+                node_ids: init.node_ids.clone(),
+                next_id: Cell::new(1),
+                output: RefCell::new(Output::new(output, config.flush)),
+                idle: Cell::new(false),
+                pending: RefCell::new(HashMap::new()),
+            }),
+        };
+        let node =
+            Rc::new(N::from_init(inital_state, init, &ctx).context("Node initilization failed")?);
+        ctx.write(Message {
+            src: init_message.dest,
+            dest: init_message.src,
+            body: Body {
+                id: Some(0),
+                in_reply_to: init_message.body.id,
+                clock: None,
+                payload: InitPayload::InitOk,
+            },
+        })?;
+        ctx.spawn(node.clone().started(ctx.clone()));
+        let mut runtime = status::Runtime {
+            node_id: ctx.inner.node_id.clone(),
+            started: Instant::now(),
+            steps: 0,
+        };
+        let mut last_status = Instant::now();
+        let node_status = |runtime: &status::Runtime| {
+            // NOTE: the interesting part of an async node is who it is waiting for
+            let pending = ctx.inner.pending.borrow().len();
+            return runtime.status(
+                metrics.metrics(),
+                serde_json::json!({ "pending_rpcs": pending }),
+            );
+        };
+
+        loop {
+            let incoming = match incoming.try_recv() {
+                | Ok(incoming) => incoming,
+                | Err(TryRecvError::Empty) => {
+                    // IMPORTANT: never go idle with output still buffered
+                    ctx.inner
+                        .output
+                        .borrow_mut()
+                        .flush()
+                        .context("Flushing output.")?;
+                    ctx.inner.idle.set(true);
+                    let incoming = incoming.recv().await;
+                    ctx.inner.idle.set(false);
+                    let Some(incoming) = incoming else {
+                        break;
+                    };
+                    incoming
+                },
+                | Err(TryRecvError::Disconnected) => break,
+            };
+            let line = match incoming {
+                | Incoming::Line(line) => line,
+                | Incoming::Status(request) => {
+                    ctx.reply(
+                        &request,
+                        StatusPayload::StatusOk(Box::new(node_status(&runtime))),
+                    )?;
+                    continue;
+                },
+            };
+
+            let envelope = Envelope::parse(&line)?;
+            let header = envelope.header()?;
+            if let Some(remote) = &header.clock {
+                clock::on_receive(remote);
+            }
+            let waiting = header
+                .in_reply_to
+                .and_then(|id| ctx.inner.pending.borrow_mut().remove(&id));
+            if let Some(waiting) = waiting {
+                let _ = waiting.send(envelope.body.to_owned());
+            } else {
+                let message = Payload::decode_line(&line)?;
+                ctx.spawn(node.clone().handle(ctx.clone(), message));
+            }
+            // NOTE: let the woken or spawned tasks run up to their next await, that is the step
+            tokio::task::yield_now().await;
+            ctx.inner
+                .output
+                .borrow_mut()
+                .end_step()
+                .context("Flushing output.")?;
+            runtime.steps += 1;
+            if let Some(interval) = config.status_interval {
+                if last_status.elapsed() >= interval {
+                    last_status = Instant::now();
+                    eprintln!(
+                        "status: {}",
+                        serde_json::to_string(&node_status(&runtime)).unwrap_or_default()
+                    );
+                }
+            }
+        }
+        return Ok(());
+    });
+
+    // NOTE: dropping the runtime cancels timers and handlers still waiting on replies
+    drop(local);
+    drop(runtime);
+    reader.join().expect("thread paniced")?;
+    forwarder.join().expect("thread paniced");
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::PipeWriter;
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::output::FlushPolicy;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    #[serde(rename_all = "snake_case")]
+    enum Payload {
+        /// answered with what n1 answers to a `ping`
+        Ask,
+        AskOk {
+            value: u64,
+        },
+        Ping,
+        Pong {
+            value: u64,
+        },
+        /// answered after `millis`
+        Later {
+            millis: u64,
+        },
+        LaterOk,
+    }
+
+    struct Asker;
+
+    impl AsyncNode<(), Payload> for Asker {
+        fn from_init(_state: (), _init: InitNodes, _ctx: &Context) -> anyhow::Result<Self> {
+            return Ok(Asker);
+        }
+
+        fn handle(
+            self: Rc<Self>,
+            ctx: Context,
+            message: Message<Payload>,
+        ) -> impl Future<Output = anyhow::Result<()>> + 'static {
+            return async move {
+                match message.body.payload {
+                    | Payload::Ask => {
+                        let Payload::Pong { value } =
+                            ctx.rpc("n1", Payload::Ping, Duration::from_secs(5)).await?
+                        else {
+                            bail!("n1 didn't pong");
+                        };
+                        ctx.reply(&message, Payload::AskOk { value })?;
+                    },
+                    | Payload::Later { millis } => {
+                        ctx.sleep(Duration::from_millis(millis)).await;
+                        ctx.reply(&message, Payload::LaterOk)?;
+                    },
+                    | _ => {},
+                }
+                return Ok(());
+            };
+        }
+    }
+
+    /// Output shared with the test
+    #[derive(Clone, Default)]
+    struct Written(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Written {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    impl Written {
+        /// First message written so far that `matches`, waits up to a second for it
+        fn wait_for(&self, matches: impl Fn(&Value) -> bool) -> Value {
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while Instant::now() < deadline {
+                let written = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+                let found = written
+                    .lines()
+                    .map(|line| serde_json::from_str::<Value>(line).unwrap())
+                    .find(|message| matches(message));
+                if let Some(message) = found {
+                    return message;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            panic!(
+                "nothing matched in {:?}",
+                String::from_utf8_lossy(&self.0.lock().unwrap())
+            );
+        }
+    }
+
+    struct Running {
+        input: PipeWriter,
+        written: Written,
+        handle: JoinHandle<anyhow::Result<()>>,
+    }
+
+    impl Running {
+        fn start(config: EventLoopConfig) -> Self {
+            let (reader, input) = std::io::pipe().unwrap();
+            let written = Written::default();
+            let output = written.clone();
+            let handle = thread::spawn(move || {
+                return async_event_loop_with_config::<Asker, _, _>((), reader, output, config);
+            });
+            let mut running = Running {
+                input,
+                written,
+                handle,
+            };
+            running.send(json!({
+                "src": "c0",
+                "dest": "n0",
+                "body": {"type": "init", "msg_id": 1, "node_id": "n0", "node_ids": ["n0", "n1"]},
+            }));
+            running
+                .written
+                .wait_for(|message| message["body"]["type"] == "init_ok");
+            return running;
+        }
+
+        fn send(&mut self, message: Value) {
+            writeln!(self.input, "{}", message).unwrap();
+        }
+
+        /// Close the input and wait for the loop to end
+        fn stop(self) {
+            drop(self.input);
+            self.handle.join().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn rpc_reply_resumes_the_handler() {
+        let mut running = Running::start(EventLoopConfig::default());
+        running.send(json!({"src": "c1", "dest": "n0", "body": {"type": "ask", "msg_id": 1}}));
+        let ping = running
+            .written
+            .wait_for(|message| message["body"]["type"] == "ping");
+        assert_eq!(ping["dest"], "n1");
+
+        running.send(json!({
+            "src": "n1",
+            "dest": "n0",
+            "body": {"type": "pong", "in_reply_to": ping["body"]["msg_id"], "value": 7},
+        }));
+        let answer = running
+            .written
+            .wait_for(|message| message["body"]["type"] == "ask_ok");
+        assert_eq!(answer["dest"], "c1");
+        assert_eq!(answer["body"]["in_reply_to"], 1);
+        assert_eq!(answer["body"]["value"], 7);
+        running.stop();
+    }
+
+    #[test]
+    fn idle_loop_flushes_what_a_timer_wrote() {
+        // NOTE: a batch this big is never full, only going idle writes it out
+        let config = EventLoopConfig {
+            flush: FlushPolicy::Batch {
+                max_messages: 64,
+                max_bytes: 64 * 1024,
+            },
+            ..EventLoopConfig::default()
+        };
+        let mut running = Running::start(config);
+        running.send(json!({
+            "src": "c1",
+            "dest": "n0",
+            "body": {"type": "later", "msg_id": 1, "millis": 50},
+        }));
+        let answer = running
+            .written
+            .wait_for(|message| message["body"]["type"] == "later_ok");
+        assert_eq!(answer["body"]["in_reply_to"], 1);
+        running.stop();
+    }
+
+    #[test]
+    fn status_reports_the_queue() {
+        let mut running = Running::start(EventLoopConfig::default());
+        for msg_id in 1..=3 {
+            running.send(json!({
+                "src": "c1",
+                "dest": "n0",
+                "body": {"type": "later", "msg_id": msg_id, "millis": 0},
+            }));
+        }
+        running.send(json!({"src": "n1", "dest": "n0", "body": {"type": "pong", "value": 1}}));
+        running.send(json!({"src": "c2", "dest": "n0", "body": {"type": "status", "msg_id": 9}}));
+        let status = running
+            .written
+            .wait_for(|message| message["body"]["type"] == "status_ok");
+        assert_eq!(status["body"]["in_reply_to"], 9);
+        // NOTE: `init` comes from a client too
+        assert_eq!(status["body"]["queue"]["client"]["enqueued"], 4);
+        assert_eq!(status["body"]["queue"]["peer"]["enqueued"], 1);
+        assert_eq!(status["body"]["node"]["pending_rpcs"], 0);
+        running.stop();
+    }
+}
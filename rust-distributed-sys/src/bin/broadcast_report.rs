@@ -0,0 +1,101 @@
+// Purpose: report msgs-per-op and stable latencies of a recorded broadcast run, failing on regressions.
+//
+// usage: broadcast_report [TRACE] [--max-msgs-per-op N] [--max-median-latency MS] [--max-latency MS]
+//
+// The trace is JSON lines of `sim::TraceEntry` (see `Simulation::write_trace`), read from stdin if
+// no file is given. The exit code is non-zero if a value was lost or a limit is exceeded.
+use std::io::Read;
+
+use anyhow::{bail, Context};
+use rust_distributed_sys_challenge::analysis::{read_trace, BroadcastReport};
+
+#[derive(Debug, Default)]
+struct Limits {
+    max_msgs_per_op: Option<f64>,
+    /// milliseconds
+    max_median_latency: Option<f64>,
+    /// milliseconds
+    max_latency: Option<f64>,
+}
+
+fn parse_args() -> anyhow::Result<(Option<String>, Limits)> {
+    let mut path = None;
+    let mut limits = Limits::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let limit = match arg.as_str() {
+            | "--max-msgs-per-op" => &mut limits.max_msgs_per_op,
+            | "--max-median-latency" => &mut limits.max_median_latency,
+            | "--max-latency" => &mut limits.max_latency,
+            | _ if arg.starts_with("--") => bail!("unknown option {}", arg),
+            | _ => {
+                path = Some(arg);
+                continue;
+            },
+        };
+        let value = args.next().context(format!("{} needs a value", arg))?;
+        *limit = Some(
+            value
+                .parse()
+                .context(format!("{} is not a number", value))?,
+        );
+    }
+    return Ok((path, limits));
+}
+
+fn main() -> anyhow::Result<()> {
+    let (path, limits) = parse_args()?;
+    let text = match path {
+        | Some(path) => std::fs::read_to_string(&path).context(format!("reading {}", path))?,
+        | None => {
+            let mut text = String::new();
+            std::io::stdin()
+                .read_to_string(&mut text)
+                .context("reading stdin")?;
+            text
+        },
+    };
+    let report = BroadcastReport::from_trace(&read_trace(&text)?);
+    print!("{}", report);
+
+    let mut failures = Vec::new();
+    if !report.lost.is_empty() {
+        failures.push(format!("{} values lost", report.lost.len()));
+    }
+    if let Some(max) = limits.max_msgs_per_op {
+        if report.messages_per_op > max {
+            failures.push(format!(
+                "msgs-per-op {:.2} above {}",
+                report.messages_per_op, max
+            ));
+        }
+    }
+    let latencies = report.stable_latencies;
+    let checks = [
+        (
+            limits.max_median_latency,
+            latencies.map(|l| l.p50),
+            "median latency",
+        ),
+        (limits.max_latency, latencies.map(|l| l.max), "max latency"),
+    ];
+    for (limit, latency, name) in checks {
+        let Some(max) = limit else {
+            continue;
+        };
+        match latency {
+            | Some(latency) if latency * 1000.0 > max => failures.push(format!(
+                "{} {:.0}ms above {}ms",
+                name,
+                latency * 1000.0,
+                max
+            )),
+            | Some(_) => {},
+            | None => failures.push(format!("{} unknown, no value became stable", name)),
+        }
+    }
+    if !failures.is_empty() {
+        bail!("{}", failures.join(", "));
+    }
+    return Ok(());
+}
@@ -0,0 +1,54 @@
+// Purpose: One node binary for every workload.
+//
+// usage: node <WORKLOAD> | node --workload <WORKLOAD>
+//
+// Maelstrom can't pass arguments to `--bin`, so the workload can also come from the `WORKLOAD`
+// environment variable, which Maelstrom hands down to every node it spawns. `FLUSH_POLICY`
+// (`message`, `step` or `batch`) picks how output is flushed, `step` if unset. With `DATA_DIR`
+// set, broadcast, the counters, the txn-2pc coordinator and Raft's term, vote and log are kept
+// in `DATA_DIR/<node_id>` and recovered after a restart. `STATUS_INTERVAL` (milliseconds) logs a
+// `status` report to stderr that often. `BROADCAST_ORDER` (`unordered`, `causal`, `sequencer`
+// or `consensus`) picks how broadcast delivers values, `unordered` if unset. `FAILURE_DETECTOR`
+// (`timeout` or `phi-accrual`) picks how broadcast and chain-kv suspect peers, `timeout` if unset.
+use std::time::Duration;
+
+use anyhow::Context;
+use rust_distributed_sys_challenge::failure_detector::DetectorKind;
+use rust_distributed_sys_challenge::persist::PersistConfig;
+use rust_distributed_sys_challenge::workloads::broadcast::DeliveryOrder;
+use rust_distributed_sys_challenge::workloads::Workload;
+use rust_distributed_sys_challenge::EventLoopConfig;
+
+fn workload_name() -> anyhow::Result<String> {
+    let mut args = std::env::args().skip(1);
+    return match args.next() {
+        | Some(flag) if flag == "--workload" => args.next().context("--workload needs a value"),
+        | Some(name) => Ok(name),
+        | None => std::env::var("WORKLOAD")
+            .context("pass the workload as an argument, --workload or in WORKLOAD"),
+    };
+}
+
+fn main() -> anyhow::Result<()> {
+    let workload: Workload = workload_name()?.parse()?;
+    let mut config = EventLoopConfig::default();
+    if let Ok(policy) = std::env::var("FLUSH_POLICY") {
+        config.flush = policy.parse()?;
+    }
+    if let Ok(millis) = std::env::var("STATUS_INTERVAL") {
+        let millis = millis
+            .parse()
+            .context("STATUS_INTERVAL is in milliseconds")?;
+        config.status_interval = Some(Duration::from_millis(millis));
+    }
+    let persist = std::env::var_os("DATA_DIR").map(PersistConfig::new);
+    let order = match std::env::var("BROADCAST_ORDER") {
+        | Ok(order) => order.parse()?,
+        | Err(_) => DeliveryOrder::default(),
+    };
+    let detector = match std::env::var("FAILURE_DETECTOR") {
+        | Ok(detector) => detector.parse()?,
+        | Err(_) => DetectorKind::default(),
+    };
+    return workload.run(config, persist, order, detector);
+}
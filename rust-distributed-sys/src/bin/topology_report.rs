@@ -0,0 +1,83 @@
+// Purpose: report connectivity, diameter and clustering of a topology and export it as DOT.
+//
+// usage: topology_report [TOPOLOGY] [--nodes N] [--neighbors K] [--rewire P] [--seed S] [--dot FILE] [--max-diameter D]
+//
+// TOPOLOGY is a JSON object of node -> neighbors, like the body of Maelstrom's `topology` message.
+// Without it the small world topology of the broadcast node is generated for `--nodes` (25) nodes
+// `n0`, `n1`, ... with the defaults of `SmallWorld` unless `--neighbors`, `--rewire` or `--seed`
+// are given. The exit code is non-zero if the graph isn't connected or its diameter is above
+// `--max-diameter`.
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Context};
+use rust_distributed_sys_challenge::topology::{to_dot, SmallWorld, TopologyReport};
+
+struct Args {
+    path: Option<String>,
+    nodes: usize,
+    small_world: SmallWorld,
+    dot: Option<String>,
+    max_diameter: Option<usize>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut parsed = Args {
+        path: None,
+        nodes: 25,
+        small_world: SmallWorld::default(),
+        dot: None,
+        max_diameter: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            parsed.path = Some(arg);
+            continue;
+        }
+        let value = args.next().context(format!("{} needs a value", arg))?;
+        let invalid = || format!("{} is not a valid {}", value, arg);
+        match arg.as_str() {
+            | "--nodes" => parsed.nodes = value.parse().with_context(invalid)?,
+            | "--neighbors" => {
+                parsed.small_world.neighbors = value.parse().with_context(invalid)?
+            },
+            | "--rewire" => {
+                parsed.small_world.rewire_probability = value.parse().with_context(invalid)?
+            },
+            | "--seed" => parsed.small_world.seed = value.parse().with_context(invalid)?,
+            | "--max-diameter" => parsed.max_diameter = Some(value.parse().with_context(invalid)?),
+            | "--dot" => parsed.dot = Some(value),
+            | _ => bail!("unknown option {}", arg),
+        }
+    }
+    return Ok(parsed);
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let topology: HashMap<String, HashSet<String>> = match &args.path {
+        | Some(path) => {
+            let text = std::fs::read_to_string(path).context(format!("reading {}", path))?;
+            serde_json::from_str(&text).context(format!("{} is not a topology", path))?
+        },
+        | None => {
+            let node_ids: Vec<String> = (0..args.nodes).map(|i| format!("n{}", i)).collect();
+            args.small_world.generate(&node_ids)
+        },
+    };
+    let report = TopologyReport::from_topology(&topology);
+    print!("{}", report);
+    if let Some(path) = &args.dot {
+        std::fs::write(path, to_dot(&topology)).context(format!("writing {}", path))?;
+    }
+
+    if !report.is_connected() {
+        bail!("not connected, {} components", report.components);
+    }
+    if let (Some(max), Some(diameter)) = (args.max_diameter, report.diameter) {
+        if diameter > max {
+            bail!("diameter {} above {}", diameter, max);
+        }
+    }
+    return Ok(());
+}
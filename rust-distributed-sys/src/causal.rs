@@ -0,0 +1,125 @@
+// Purpose: causal delivery of broadcasts, a value is never seen before the values it depends on.
+//
+// Every broadcast is stamped with a vector clock that counts, per origin node, the broadcasts the
+// sender had delivered when it sent it (Birman, Schiper and Stephenson). A receiver delivers a
+// broadcast of `origin` once it delivered the previous one of `origin` and everything the sender
+// had delivered, until then it waits in a buffer. Delivery order respects happened-before,
+// concurrent broadcasts may be delivered in a different order on different nodes.
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::VectorClock;
+
+/// Where and after what a value was broadcast
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalStamp {
+    pub origin: String,
+    pub clock: VectorClock,
+}
+
+impl CausalStamp {
+    /// Number of the broadcast among those of `origin`, starting at 1
+    pub fn sequence(&self) -> u64 {
+        return self.clock.get(&self.origin);
+    }
+}
+
+pub struct CausalOrder<T> {
+    node_id: String,
+    /// broadcasts delivered per origin
+    delivered: VectorClock,
+    /// received, waiting for a causal predecessor
+    pending: Vec<(T, CausalStamp)>,
+    /// delivered values, in delivery order
+    log: Vec<T>,
+    stamps: HashMap<T, CausalStamp>,
+}
+
+impl<T: Clone + Eq + Hash> CausalOrder<T> {
+    pub fn new(node_id: &str) -> Self {
+        return CausalOrder {
+            node_id: node_id.to_string(),
+            delivered: VectorClock::new(),
+            pending: Vec::new(),
+            log: Vec::new(),
+            stamps: HashMap::new(),
+        };
+    }
+
+    /// Broadcast `value` from this node, it is delivered here right away
+    ///
+    /// returns:
+    ///   - the stamp to send along with `value`
+    pub fn broadcast(&mut self, value: T) -> CausalStamp {
+        self.delivered.increment(&self.node_id);
+        let stamp = CausalStamp {
+            origin: self.node_id.clone(),
+            clock: self.delivered.clone(),
+        };
+        self.deliver(value, stamp.clone());
+        return stamp;
+    }
+
+    /// Take in a broadcast from another node
+    ///
+    /// returns:
+    ///   - the values delivered because of it, in delivery order; empty for duplicates and for
+    ///     broadcasts that still wait for a predecessor
+    pub fn receive(&mut self, value: T, stamp: CausalStamp) -> Vec<T> {
+        let duplicate = stamp.sequence() <= self.delivered.get(&stamp.origin)
+            || self.pending.iter().any(|(_, waiting)| {
+                return waiting.origin == stamp.origin && waiting.sequence() == stamp.sequence();
+            });
+        if duplicate {
+            return Vec::new();
+        }
+        self.pending.push((value, stamp));
+
+        // NOTE: each delivery can unblock others, repeat until nothing moves
+        let mut delivered = Vec::new();
+        while let Some(ready) = self
+            .pending
+            .iter()
+            .position(|(_, stamp)| self.is_deliverable(stamp))
+        {
+            let (value, stamp) = self.pending.swap_remove(ready);
+            self.delivered.merge(&stamp.clock);
+            self.deliver(value.clone(), stamp);
+            delivered.push(value);
+        }
+        return delivered;
+    }
+
+    /// Next broadcast of its origin, and everything its sender had delivered is delivered here
+    fn is_deliverable(&self, stamp: &CausalStamp) -> bool {
+        return stamp.clock.iter().all(|(node_id, count)| {
+            let delivered = self.delivered.get(node_id);
+            return if *node_id == stamp.origin {
+                *count == delivered + 1
+            } else {
+                *count <= delivered
+            };
+        });
+    }
+
+    fn deliver(&mut self, value: T, stamp: CausalStamp) {
+        self.stamps.insert(value.clone(), stamp);
+        self.log.push(value);
+    }
+
+    /// Stamp of a delivered value
+    pub fn stamp(&self, value: &T) -> Option<&CausalStamp> {
+        return self.stamps.get(value);
+    }
+
+    pub fn delivered(&self) -> &[T] {
+        return &self.log;
+    }
+
+    /// Broadcasts waiting for a predecessor
+    pub fn pending(&self) -> usize {
+        return self.pending.len();
+    }
+}
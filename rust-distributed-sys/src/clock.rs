@@ -0,0 +1,446 @@
+// Purpose: logical clocks to order events without trusting wall clocks.
+//
+// A node opts in by calling `clock::install` in `from_init`. From then on every message sent to
+// another cluster node carries the current timestamp in `body.clock`, and `event_loop` merges the
+// timestamp of every received message before the node's `step` sees it.
+//
+// dynamo-kv installs a hybrid clock to stamp its writes. Installing a clock doesn't make
+// broadcast causal: one share relays values of many origins, each must carry its origin's clock
+// and not the relaying node's, and delivery counts broadcasts, not every send and receive. So
+// `causal::CausalStamp` keeps a `VectorClock` per value and `Body.clock` plays no part in it.
+//
+// NOTE: the installed clock is thread local - `event_loop` runs `from_init` and `step` on the
+// same thread, so a node never has to pass its clock around.
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::InitNodes;
+
+/// Classic Lamport clock, a single counter that is bumped on every event
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LamportClock {
+    time: u64,
+}
+
+impl LamportClock {
+    pub fn new() -> Self {
+        return LamportClock::default();
+    }
+
+    pub fn time(&self) -> u64 {
+        return self.time;
+    }
+
+    /// Local event or send
+    pub fn tick(&mut self) -> u64 {
+        self.time += 1;
+        return self.time;
+    }
+
+    /// Receive of a message stamped with `remote`
+    pub fn observe(&mut self, remote: u64) -> u64 {
+        self.time = self.time.max(remote) + 1;
+        return self.time;
+    }
+}
+
+/// Vector clock, one counter per node
+///
+/// Vector clocks are only partially ordered: `partial_cmp` returns `None` for concurrent clocks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct VectorClock {
+    entries: BTreeMap<String, u64>,
+}
+
+impl VectorClock {
+    pub fn new() -> Self {
+        return VectorClock::default();
+    }
+
+    pub fn get(&self, node_id: &str) -> u64 {
+        return self.entries.get(node_id).copied().unwrap_or(0);
+    }
+
+    /// Count one more event of `node_id`
+    pub fn increment(&mut self, node_id: &str) -> u64 {
+        let entry = self.entries.entry(node_id.to_string()).or_insert(0);
+        *entry += 1;
+        return *entry;
+    }
+
+    /// Raise the count of `node_id` to at least `count`
+    pub fn raise(&mut self, node_id: &str, count: u64) {
+        let entry = self.entries.entry(node_id.to_string()).or_insert(0);
+        *entry = (*entry).max(count);
+    }
+
+    /// Pointwise maximum of both clocks
+    pub fn merge(&mut self, other: &VectorClock) {
+        for (node_id, count) in &other.entries {
+            let entry = self.entries.entry(node_id.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+    }
+
+    /// `self` causally precedes `other`
+    pub fn happened_before(&self, other: &VectorClock) -> bool {
+        return self.partial_cmp(other) == Some(Ordering::Less);
+    }
+
+    /// Neither clock precedes the other
+    pub fn concurrent_with(&self, other: &VectorClock) -> bool {
+        return self.partial_cmp(other).is_none();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &u64)> {
+        return self.entries.iter();
+    }
+}
+
+impl PartialOrd for VectorClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let node_ids: HashSet<&String> = self.entries.keys().chain(other.entries.keys()).collect();
+        let mut less = false;
+        let mut greater = false;
+        for node_id in node_ids {
+            match self.get(node_id).cmp(&other.get(node_id)) {
+                | Ordering::Less => less = true,
+                | Ordering::Greater => greater = true,
+                | Ordering::Equal => {},
+            }
+        }
+        return match (less, greater) {
+            | (false, false) => Some(Ordering::Equal),
+            | (true, false) => Some(Ordering::Less),
+            | (false, true) => Some(Ordering::Greater),
+            | (true, true) => None,
+        };
+    }
+}
+
+/// Timestamp of a hybrid logical clock, ordered by `wall` then `logical`
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HybridTimestamp {
+    /// milliseconds since the unix epoch
+    pub wall: u64,
+    pub logical: u32,
+}
+
+/// Hybrid logical clock (Kulkarni et al.)
+///
+/// Stays close to physical time, which makes it usable for last-writer-wins, while still
+/// respecting causality when wall clocks drift between nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HybridClock {
+    last: HybridTimestamp,
+}
+
+fn physical_now() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0);
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        return HybridClock::default();
+    }
+
+    pub fn last(&self) -> HybridTimestamp {
+        return self.last;
+    }
+
+    /// Local event or send
+    pub fn tick(&mut self) -> HybridTimestamp {
+        return self.tick_at(physical_now());
+    }
+
+    /// Receive of a message stamped with `remote`
+    pub fn observe(&mut self, remote: HybridTimestamp) -> HybridTimestamp {
+        return self.observe_at(remote, physical_now());
+    }
+
+    /// [`HybridClock::tick`] with an explicit physical time
+    pub fn tick_at(&mut self, physical: u64) -> HybridTimestamp {
+        if physical > self.last.wall {
+            self.last = HybridTimestamp {
+                wall: physical,
+                logical: 0,
+            };
+        } else {
+            self.last.logical += 1;
+        }
+        return self.last;
+    }
+
+    /// [`HybridClock::observe`] with an explicit physical time
+    pub fn observe_at(&mut self, remote: HybridTimestamp, physical: u64) -> HybridTimestamp {
+        let wall = physical.max(self.last.wall).max(remote.wall);
+        let logical = if wall == self.last.wall && wall == remote.wall {
+            self.last.logical.max(remote.logical) + 1
+        } else if wall == self.last.wall {
+            self.last.logical + 1
+        } else if wall == remote.wall {
+            remote.logical + 1
+        } else {
+            0
+        };
+        self.last = HybridTimestamp { wall, logical };
+        return self.last;
+    }
+}
+
+/// What travels in `Body::clock`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timestamp {
+    Lamport(u64),
+    Vector(VectorClock),
+    Hybrid(HybridTimestamp),
+}
+
+impl Timestamp {
+    /// The timestamp of a hybrid logical clock, `None` for other kinds of clocks
+    pub fn hybrid(&self) -> Option<HybridTimestamp> {
+        return match self {
+            | Timestamp::Hybrid(timestamp) => Some(*timestamp),
+            | _ => None,
+        };
+    }
+}
+
+/// The clock a node runs
+#[derive(Debug, Clone)]
+pub enum Clock {
+    Lamport(LamportClock),
+    Vector { node_id: String, clock: VectorClock },
+    Hybrid(HybridClock),
+}
+
+impl Clock {
+    pub fn lamport() -> Self {
+        return Clock::Lamport(LamportClock::new());
+    }
+
+    pub fn vector(node_id: &str) -> Self {
+        return Clock::Vector {
+            node_id: node_id.to_string(),
+            clock: VectorClock::new(),
+        };
+    }
+
+    pub fn hybrid() -> Self {
+        return Clock::Hybrid(HybridClock::new());
+    }
+
+    /// Current time without advancing the clock
+    pub fn now(&self) -> Timestamp {
+        return match self {
+            | Clock::Lamport(clock) => Timestamp::Lamport(clock.time()),
+            | Clock::Vector { clock, .. } => Timestamp::Vector(clock.clone()),
+            | Clock::Hybrid(clock) => Timestamp::Hybrid(clock.last()),
+        };
+    }
+
+    /// Advance the clock for a local event or a send
+    pub fn tick(&mut self) -> Timestamp {
+        return match self {
+            | Clock::Lamport(clock) => Timestamp::Lamport(clock.tick()),
+            | Clock::Vector { node_id, clock } => {
+                clock.increment(node_id);
+                Timestamp::Vector(clock.clone())
+            },
+            | Clock::Hybrid(clock) => Timestamp::Hybrid(clock.tick()),
+        };
+    }
+
+    /// Merge a received timestamp, timestamps of another kind of clock are ignored
+    pub fn observe(&mut self, remote: &Timestamp) {
+        match (self, remote) {
+            | (Clock::Lamport(clock), Timestamp::Lamport(remote)) => {
+                clock.observe(*remote);
+            },
+            | (Clock::Vector { node_id, clock }, Timestamp::Vector(remote)) => {
+                clock.merge(remote);
+                clock.increment(node_id);
+            },
+            | (Clock::Hybrid(clock), Timestamp::Hybrid(remote)) => {
+                clock.observe(*remote);
+            },
+            | _ => {},
+        }
+    }
+}
+
+struct Installed {
+    clock: Clock,
+    /// only messages to cluster nodes are stamped, clients and services get unmodified bodies
+    node_ids: HashSet<String>,
+}
+
+thread_local! {
+    static INSTALLED: RefCell<Option<Installed>> = const { RefCell::new(None) };
+}
+
+/// Run `clock` for the node described by `init` on this thread
+pub fn install(clock: Clock, init: &InitNodes) {
+    INSTALLED.with(|installed| {
+        *installed.borrow_mut() = Some(Installed {
+            clock,
+            node_ids: init.node_ids.clone(),
+        });
+    });
+}
+
+/// Stop stamping messages sent from this thread
+pub fn uninstall() {
+    INSTALLED.with(|installed| *installed.borrow_mut() = None);
+}
+
+/// Current time of the installed clock
+pub fn now() -> Option<Timestamp> {
+    return INSTALLED.with(|installed| installed.borrow().as_ref().map(|i| i.clock.now()));
+}
+
+/// Advance the installed clock for a local event, e.g. a client write that needs a timestamp
+pub fn tick() -> Option<Timestamp> {
+    return INSTALLED.with(|installed| installed.borrow_mut().as_mut().map(|i| i.clock.tick()));
+}
+
+/// Timestamp for a message to `dest`, `None` if no clock is installed or `dest` isn't a node
+pub(crate) fn on_send(dest: &str) -> Option<Timestamp> {
+    return INSTALLED.with(|installed| {
+        let mut installed = installed.borrow_mut();
+        let installed = installed.as_mut()?;
+        if !installed.node_ids.contains(dest) {
+            return None;
+        }
+        return Some(installed.clock.tick());
+    });
+}
+
+/// Merge the timestamp of a received message into the installed clock
+pub(crate) fn on_receive(remote: &Timestamp) {
+    INSTALLED.with(|installed| {
+        if let Some(installed) = installed.borrow_mut().as_mut() {
+            installed.clock.observe(remote);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hybrid(wall: u64, logical: u32) -> HybridTimestamp {
+        return HybridTimestamp { wall, logical };
+    }
+
+    fn vector(counts: &[(&str, u64)]) -> VectorClock {
+        let mut clock = VectorClock::new();
+        for (node_id, count) in counts {
+            clock.raise(node_id, *count);
+        }
+        return clock;
+    }
+
+    #[test]
+    fn lamport_observe_jumps_past_the_remote_time() {
+        let mut clock = LamportClock::new();
+        assert_eq!(clock.tick(), 1);
+        assert_eq!(clock.observe(5), 6);
+        assert_eq!(clock.observe(2), 7);
+    }
+
+    #[test]
+    fn hybrid_tick_follows_physical_time_or_counts() {
+        let mut clock = HybridClock::new();
+        assert_eq!(clock.tick_at(100), hybrid(100, 0));
+        // NOTE: the physical clock stood still or went back, only the logical part moves
+        assert_eq!(clock.tick_at(100), hybrid(100, 1));
+        assert_eq!(clock.tick_at(90), hybrid(100, 2));
+        assert_eq!(clock.tick_at(101), hybrid(101, 0));
+    }
+
+    #[test]
+    fn hybrid_observe_covers_every_branch() {
+        // NOTE: local and remote wall are equal and ahead of physical time
+        let mut clock = HybridClock::new();
+        clock.tick_at(100);
+        clock.tick_at(100);
+        assert_eq!(clock.observe_at(hybrid(100, 5), 50), hybrid(100, 6));
+        assert_eq!(clock.observe_at(hybrid(100, 2), 50), hybrid(100, 7));
+
+        // NOTE: the local wall is the highest
+        let mut clock = HybridClock::new();
+        clock.tick_at(200);
+        assert_eq!(clock.observe_at(hybrid(150, 9), 100), hybrid(200, 1));
+
+        // NOTE: the remote wall is the highest
+        let mut clock = HybridClock::new();
+        clock.tick_at(100);
+        assert_eq!(clock.observe_at(hybrid(300, 4), 200), hybrid(300, 5));
+
+        // NOTE: physical time is ahead of both
+        let mut clock = HybridClock::new();
+        clock.tick_at(100);
+        assert_eq!(clock.observe_at(hybrid(150, 4), 400), hybrid(400, 0));
+    }
+
+    #[test]
+    fn vector_clocks_are_partially_ordered() {
+        let a = vector(&[("n0", 1), ("n1", 2)]);
+        let b = vector(&[("n0", 1), ("n1", 3)]);
+        let c = vector(&[("n0", 2), ("n1", 1)]);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+        assert_eq!(b.partial_cmp(&a), Some(Ordering::Greater));
+        assert!(a.happened_before(&b));
+        assert_eq!(a.partial_cmp(&c), None);
+        assert_eq!(c.partial_cmp(&a), None);
+        assert!(b.concurrent_with(&c));
+        // NOTE: a missing entry counts as 0
+        assert_eq!(
+            vector(&[("n0", 1)]).partial_cmp(&vector(&[("n0", 1), ("n1", 0)])),
+            Some(Ordering::Equal)
+        );
+        assert!(vector(&[("n0", 1)]).happened_before(&vector(&[("n0", 1), ("n2", 1)])));
+
+        let mut merged = a.clone();
+        merged.merge(&c);
+        assert_eq!(merged, vector(&[("n0", 2), ("n1", 2)]));
+        assert!(a.happened_before(&merged) && c.happened_before(&merged));
+    }
+
+    #[test]
+    fn only_messages_to_nodes_are_stamped() {
+        let init = InitNodes {
+            node_id: "n0".to_string(),
+            node_ids: ["n0", "n1"].iter().map(|id| id.to_string()).collect(),
+        };
+        assert_eq!(on_send("n1"), None);
+
+        install(Clock::lamport(), &init);
+        assert_eq!(on_send("n1"), Some(Timestamp::Lamport(1)));
+        // NOTE: clients and Maelstrom's services get unmodified bodies and don't move the clock
+        assert_eq!(on_send("c1"), None);
+        assert_eq!(on_send("lin-kv"), None);
+        assert_eq!(now(), Some(Timestamp::Lamport(1)));
+
+        on_receive(&Timestamp::Lamport(10));
+        assert_eq!(now(), Some(Timestamp::Lamport(11)));
+        // NOTE: a timestamp of another kind of clock is ignored
+        on_receive(&Timestamp::Hybrid(hybrid(500, 0)));
+        assert_eq!(on_send("n1"), Some(Timestamp::Lamport(12)));
+
+        uninstall();
+        assert_eq!(on_send("n1"), None);
+    }
+}
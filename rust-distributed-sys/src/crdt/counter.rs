@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Crdt;
+
+/// Grow-only counter, every node only ever increments its own entry
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> Self {
+        return GCounter::default();
+    }
+
+    pub fn increment(&mut self, node_id: &str, amount: u64) {
+        *self.counts.entry(node_id.to_string()).or_insert(0) += amount;
+    }
+
+    pub fn value(&self) -> u64 {
+        return self.counts.values().sum();
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node_id, count) in &other.counts {
+            let entry = self.counts.entry(node_id.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+    }
+
+    fn delta_since(&self, known: &Self) -> Self {
+        return GCounter {
+            counts: self
+                .counts
+                .iter()
+                .filter(|(node_id, count)| known.counts.get(*node_id) < Some(*count))
+                .map(|(node_id, count)| (node_id.clone(), *count))
+                .collect(),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.counts.is_empty();
+    }
+}
+
+/// Counter that supports decrements, a pair of grow-only counters
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> Self {
+        return PNCounter::default();
+    }
+
+    pub fn add(&mut self, node_id: &str, delta: i64) {
+        if delta >= 0 {
+            self.increments.increment(node_id, delta as u64);
+        } else {
+            self.decrements.increment(node_id, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        return self.increments.value() as i64 - self.decrements.value() as i64;
+    }
+}
+
+impl Crdt for PNCounter {
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+
+    fn delta_since(&self, known: &Self) -> Self {
+        return PNCounter {
+            increments: self.increments.delta_since(&known.increments),
+            decrements: self.decrements.delta_since(&known.decrements),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.increments.is_empty() && self.decrements.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    #[test]
+    fn g_counter_laws() {
+        let mut a = GCounter::new();
+        a.increment("n0", 3);
+        let mut b = a.clone();
+        b.increment("n0", 2);
+        b.increment("n1", 1);
+        let mut c = GCounter::new();
+        c.increment("n2", 4);
+        laws::check(&a, &b, &c);
+    }
+
+    #[test]
+    fn g_counter_merge_keeps_the_highest_count_per_node() {
+        let mut a = GCounter::new();
+        a.increment("n0", 5);
+        let mut b = GCounter::new();
+        b.increment("n0", 2);
+        b.increment("n1", 1);
+        a.merge(&b);
+        assert_eq!(a.value(), 6);
+    }
+
+    #[test]
+    fn g_counter_delta_holds_only_newer_counts() {
+        let mut known = GCounter::new();
+        known.increment("n0", 1);
+        known.increment("n1", 1);
+        let mut state = known.clone();
+        state.increment("n1", 1);
+        let mut expected = GCounter::new();
+        expected.increment("n1", 2);
+        assert_eq!(state.delta_since(&known), expected);
+    }
+
+    #[test]
+    fn pn_counter_laws() {
+        let mut a = PNCounter::new();
+        a.add("n0", 5);
+        let mut b = a.clone();
+        b.add("n1", -3);
+        let mut c = PNCounter::new();
+        c.add("n0", -1);
+        c.add("n2", 2);
+        laws::check(&a, &b, &c);
+    }
+
+    #[test]
+    fn pn_counter_adds_up_increments_and_decrements() {
+        let mut a = PNCounter::new();
+        a.add("n0", 5);
+        a.add("n0", -7);
+        let mut b = PNCounter::new();
+        b.add("n1", -1);
+        a.merge(&b);
+        assert_eq!(a.value(), -3);
+        assert!(b.delta_since(&a).is_empty());
+    }
+}
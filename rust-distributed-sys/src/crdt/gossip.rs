@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Crdt;
+use crate::persist::{Durable, PersistConfig, Store};
+use crate::queue::EventSender;
+use crate::{Body, Event, InitNodes, Message, Node};
+
+/// Client facing half of a CRDT backed node
+pub trait CrdtWorkload {
+    type State: Crdt + Send + 'static;
+    /// client requests and their replies, e.g. `add`/`add_ok`/`read`/`read_ok`
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+
+    /// Answer a client request, updates are applied to `state` directly
+    ///
+    /// returns:
+    ///   - the reply payload, `None` for messages that need no reply
+    fn handle(
+        node_id: &str,
+        state: &mut Self::State,
+        request: Self::Payload,
+    ) -> Option<Self::Payload>;
+}
+
+/// Node to node messages of the gossip driver
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Gossip<C> {
+    Gossip { delta: C },
+    GossipOk,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)] // NOTE: `type` decides, anything that isn't gossip belongs to the workload
+pub enum CrdtPayload<C, Request> {
+    Gossip(Gossip<C>),
+    Client(Request),
+}
+
+/// Timer event that triggers a round of gossip
+#[derive(Debug)]
+pub struct GossipTick;
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// delay between two rounds of gossip
+    pub interval: Duration,
+    /// keep the state on disk, see `persist`
+    pub persist: Option<PersistConfig>,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        return GossipConfig {
+            interval: Duration::from_millis(200),
+            persist: None,
+        };
+    }
+}
+
+/// The log of a CRDT is the deltas merged into it
+impl<C: Crdt> Durable for C {
+    type Entry = C;
+
+    fn apply(&mut self, delta: &C) {
+        self.merge(delta);
+    }
+}
+
+/// Node that serves a `CrdtWorkload` and replicates its state to every other node
+///
+/// Like `known_by_node` in the broadcast node, it remembers what each peer has acknowledged and
+/// only gossips the delta, so an idle cluster sends nothing.
+pub struct GossipNode<W: CrdtWorkload> {
+    node_id: String,
+    local_id: usize,
+    peers: Vec<String>,
+    state: W::State,
+    store: Option<Store<W::State>>,
+    known_by_node: HashMap<String, W::State>,
+    /// last unacknowledged gossip per peer, `(msg_id, delta)`
+    in_flight: HashMap<String, (usize, W::State)>,
+    workload: PhantomData<W>,
+}
+
+impl<W: CrdtWorkload> GossipNode<W> {
+    pub fn state(&self) -> &W::State {
+        return &self.state;
+    }
+
+    /// Log `delta`, which is already merged into the state
+    fn record(&mut self, delta: &W::State) -> anyhow::Result<()> {
+        if let Some(store) = &mut self.store {
+            store
+                .record(&self.state, delta)
+                .context("persisting crdt state")?;
+        }
+        return Ok(());
+    }
+
+    fn gossip(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        for peer in &self.peers {
+            let delta = self.state.delta_since(&self.known_by_node[peer]);
+            if delta.is_empty() {
+                continue;
+            }
+            let id = self.local_id;
+            self.local_id += 1;
+            Message {
+                src: self.node_id.clone(),
+                dest: peer.clone(),
+                body: Body {
+                    id: Some(id),
+                    in_reply_to: None,
+                    clock: None,
+                    payload: CrdtPayload::<W::State, W::Payload>::Gossip(Gossip::Gossip {
+                        delta: delta.clone(),
+                    }),
+                },
+            }
+            .send(&mut *output, "gossip")?;
+            // NOTE: an older gossip that is still in flight is simply sent again next round
+            self.in_flight.insert(peer.clone(), (id, delta));
+        }
+        return Ok(());
+    }
+}
+
+impl<W: CrdtWorkload> Node<GossipConfig, CrdtPayload<W::State, W::Payload>, GossipTick>
+    for GossipNode<W>
+{
+    fn from_init(
+        config: GossipConfig,
+        init: InitNodes,
+        sender: EventSender<CrdtPayload<W::State, W::Payload>, GossipTick>,
+    ) -> anyhow::Result<Self> {
+        let (store, state) = match &config.persist {
+            | Some(persist) => {
+                let (store, state) = Store::open(persist, &init.node_id)?;
+                (Some(store), state)
+            },
+            | None => (None, W::State::default()),
+        };
+
+        let node_id = init.node_id.clone();
+        let interval = config.interval;
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: GossipTick,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        let peers: Vec<String> = init
+            .node_ids
+            .into_iter()
+            .filter(|node_id| *node_id != init.node_id)
+            .collect();
+        return Ok(GossipNode {
+            node_id: init.node_id,
+            local_id: 1,
+            known_by_node: peers
+                .iter()
+                .map(|peer| (peer.clone(), W::State::default()))
+                .collect(),
+            peers,
+            state,
+            store,
+            in_flight: HashMap::new(),
+            workload: PhantomData,
+        });
+    }
+
+    fn step(
+        &mut self,
+        event: Event<CrdtPayload<W::State, W::Payload>, GossipTick>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        match event {
+            | Event::EndOfMessages => {},
+            | Event::GeneratedEvent(_) => self.gossip(output)?,
+            | Event::Message(message) => {
+                let in_reply_to = message.body.in_reply_to;
+                let mut reply = message.into_reply(Some(&mut self.local_id));
+                match reply.body.payload {
+                    | CrdtPayload::Gossip(Gossip::Gossip { delta }) => {
+                        self.state.merge(&delta);
+                        // IMPORTANT: after the ack the sender never gossips this delta again
+                        self.record(&delta)?;
+                        // NOTE: the sender obviously knows what it sent us
+                        self.known_by_node
+                            .entry(reply.dest.clone())
+                            .or_default()
+                            .merge(&delta);
+                        reply.body.payload = CrdtPayload::Gossip(Gossip::GossipOk);
+                        reply.send(output, "gossip")?;
+                    },
+                    | CrdtPayload::Gossip(Gossip::GossipOk) => {
+                        let acked = matches!(
+                            self.in_flight.get(&reply.dest),
+                            Some((id, _)) if Some(*id) == in_reply_to
+                        );
+                        if acked {
+                            let (_, delta) = self.in_flight.remove(&reply.dest).unwrap();
+                            self.known_by_node
+                                .entry(reply.dest)
+                                .or_default()
+                                .merge(&delta);
+                        }
+                    },
+                    | CrdtPayload::Client(request) => {
+                        let before = self.store.is_some().then(|| self.state.clone());
+                        let response = W::handle(&self.node_id, &mut self.state, request);
+                        if let Some(before) = before {
+                            let delta = self.state.delta_since(&before);
+                            if !delta.is_empty() {
+                                self.record(&delta)?;
+                            }
+                        }
+                        if let Some(response) = response {
+                            reply.body.payload = CrdtPayload::Client(response);
+                            reply.send(output, "client request")?;
+                        }
+                    },
+                }
+                self.local_id += 1;
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> serde_json::Value {
+        // NOTE: peers that haven't acknowledged everything we know
+        let mut behind: Vec<&String> = self
+            .peers
+            .iter()
+            .filter(|peer| {
+                !self
+                    .state
+                    .delta_since(&self.known_by_node[*peer])
+                    .is_empty()
+            })
+            .collect();
+        behind.sort();
+        let mut in_flight: Vec<&String> = self.in_flight.keys().collect();
+        in_flight.sort();
+        return serde_json::json!({
+            "peers": self.peers,
+            "state": self.state,
+            "behind": behind,
+            "in_flight": in_flight,
+        });
+    }
+}
@@ -0,0 +1,74 @@
+// Purpose: conflict-free replicated data types that converge by gossiping deltas.
+//
+// Every replica mutates its own copy and periodically sends peers the part of its state they
+// haven't acknowledged yet (`Crdt::delta_since`). Because `merge` is commutative, associative and
+// idempotent, replicas converge no matter how often deltas are lost, duplicated or reordered.
+use serde::{de::DeserializeOwned, Serialize};
+
+mod counter;
+mod gossip;
+mod register;
+mod set;
+
+pub use counter::{GCounter, PNCounter};
+pub use gossip::{CrdtPayload, CrdtWorkload, Gossip, GossipConfig, GossipNode, GossipTick};
+pub use register::{LwwMap, LwwRegister, LwwStamp};
+pub use set::{GSet, ORSet};
+
+pub trait Crdt: Clone + Default + Serialize + DeserializeOwned {
+    /// Join `other` into `self`
+    fn merge(&mut self, other: &Self);
+
+    /// The part of `self` that `known` is missing
+    ///
+    /// Merging the result into `known` must yield the same state as merging all of `self`.
+    fn delta_since(&self, known: &Self) -> Self;
+
+    /// Whether this is the initial state, i.e. merging it changes nothing
+    fn is_empty(&self) -> bool;
+}
+
+/// Properties every `Crdt` must have, checked by the tests of each type
+#[cfg(test)]
+mod laws {
+    use std::fmt::Debug;
+
+    use super::Crdt;
+
+    fn merged<C: Crdt>(state: &C, other: &C) -> C {
+        let mut merged = state.clone();
+        merged.merge(other);
+        return merged;
+    }
+
+    /// Check `merge` and `delta_since` on the states of three replicas
+    pub(crate) fn check<C: Crdt + PartialEq + Debug>(a: &C, b: &C, c: &C) {
+        for (x, y) in [(a, b), (b, c), (a, c)] {
+            assert_eq!(merged(x, y), merged(y, x), "merge is not commutative");
+        }
+        assert_eq!(
+            merged(&merged(a, b), c),
+            merged(a, &merged(b, c)),
+            "merge is not associative"
+        );
+        for x in [a, b, c] {
+            assert_eq!(merged(x, x), *x, "merge is not idempotent");
+            assert_eq!(
+                merged(&C::default(), x),
+                *x,
+                "the initial state is not neutral"
+            );
+        }
+        for (x, y) in [(a, b), (b, a), (a, c), (c, a), (b, c), (c, b)] {
+            assert_eq!(
+                merged(y, &x.delta_since(y)),
+                merged(y, x),
+                "delta_since misses part of the state"
+            );
+            assert!(
+                x.delta_since(&merged(y, x)).is_empty(),
+                "delta_since sends what is already known"
+            );
+        }
+    }
+}
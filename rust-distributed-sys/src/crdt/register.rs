@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Crdt;
+use crate::clock::HybridTimestamp;
+
+/// When and where a value was written, ties on the timestamp are broken by node id
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct LwwStamp {
+    pub time: HybridTimestamp,
+    pub node_id: String,
+}
+
+/// Last-writer-wins register, `None` until the first write
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: DeserializeOwned"))]
+pub struct LwwRegister<T> {
+    value: Option<T>,
+    stamp: LwwStamp,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        return LwwRegister {
+            value: None,
+            stamp: LwwStamp::default(),
+        };
+    }
+}
+
+impl<T: Clone> LwwRegister<T> {
+    pub fn new() -> Self {
+        return LwwRegister::default();
+    }
+
+    /// Write `value`, ignored if a later write is already known
+    ///
+    /// returns:
+    ///   - whether the write took effect
+    pub fn set(&mut self, value: Option<T>, stamp: LwwStamp) -> bool {
+        if stamp <= self.stamp {
+            return false;
+        }
+        self.value = value;
+        self.stamp = stamp;
+        return true;
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        return self.value.as_ref();
+    }
+
+    pub fn stamp(&self) -> &LwwStamp {
+        return &self.stamp;
+    }
+}
+
+impl<T> Crdt for LwwRegister<T>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    fn merge(&mut self, other: &Self) {
+        self.set(other.value.clone(), other.stamp.clone());
+    }
+
+    fn delta_since(&self, known: &Self) -> Self {
+        if self.stamp > known.stamp {
+            return self.clone();
+        }
+        return LwwRegister::default();
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.stamp == LwwStamp::default();
+    }
+}
+
+/// Map of last-writer-wins registers, removes are writes of `None`
+///
+/// NOTE: keys become JSON object keys, so they have to serialize as strings or integers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(deserialize = "K: Ord + DeserializeOwned, V: DeserializeOwned"))]
+pub struct LwwMap<K: Ord, V> {
+    entries: BTreeMap<K, LwwRegister<V>>,
+}
+
+impl<K: Ord, V> Default for LwwMap<K, V> {
+    fn default() -> Self {
+        return LwwMap {
+            entries: BTreeMap::new(),
+        };
+    }
+}
+
+impl<K: Ord + Clone, V: Clone> LwwMap<K, V> {
+    pub fn new() -> Self {
+        return LwwMap::default();
+    }
+
+    pub fn insert(&mut self, key: K, value: V, stamp: LwwStamp) -> bool {
+        return self.entries.entry(key).or_default().set(Some(value), stamp);
+    }
+
+    pub fn remove(&mut self, key: K, stamp: LwwStamp) -> bool {
+        return self.entries.entry(key).or_default().set(None, stamp);
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        return self.entries.get(key).and_then(|register| register.get());
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        return self
+            .entries
+            .iter()
+            .filter_map(|(key, register)| register.get().map(|value| (key, value)));
+    }
+}
+
+impl<K, V> Crdt for LwwMap<K, V>
+where
+    K: Ord + Clone + Serialize + DeserializeOwned,
+    V: Clone + Serialize + DeserializeOwned,
+{
+    fn merge(&mut self, other: &Self) {
+        for (key, register) in &other.entries {
+            self.entries.entry(key.clone()).or_default().merge(register);
+        }
+    }
+
+    fn delta_since(&self, known: &Self) -> Self {
+        return LwwMap {
+            entries: self
+                .entries
+                .iter()
+                .filter(|(key, register)| {
+                    known
+                        .entries
+                        .get(*key)
+                        .is_none_or(|known| register.stamp > known.stamp)
+                })
+                .map(|(key, register)| (key.clone(), register.clone()))
+                .collect(),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.entries.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    fn stamp(wall: u64, node_id: &str) -> LwwStamp {
+        return LwwStamp {
+            time: HybridTimestamp { wall, logical: 0 },
+            node_id: node_id.to_string(),
+        };
+    }
+
+    fn register(value: u64, stamp: LwwStamp) -> LwwRegister<u64> {
+        let mut register = LwwRegister::new();
+        register.set(Some(value), stamp);
+        return register;
+    }
+
+    #[test]
+    fn register_laws() {
+        let a = register(1, stamp(1, "n0"));
+        let b = register(2, stamp(2, "n0"));
+        let c = register(3, stamp(2, "n1"));
+        laws::check(&a, &b, &c);
+    }
+
+    #[test]
+    fn register_keeps_the_latest_write() {
+        let mut a = register(2, stamp(2, "n0"));
+        assert!(!a.set(Some(1), stamp(1, "n1")));
+        a.merge(&register(1, stamp(1, "n1")));
+        assert_eq!(a.get(), Some(&2));
+        a.merge(&register(3, stamp(3, "n1")));
+        assert_eq!(a.get(), Some(&3));
+    }
+
+    #[test]
+    fn register_tie_goes_to_the_higher_node_id() {
+        let n0 = register(1, stamp(5, "n0"));
+        let n1 = register(2, stamp(5, "n1"));
+        let mut a = n0.clone();
+        a.merge(&n1);
+        let mut b = n1.clone();
+        b.merge(&n0);
+        assert_eq!(a.get(), Some(&2));
+        assert_eq!(a, b);
+
+        // NOTE: the logical part of the timestamp decides before the node id
+        let later = LwwStamp {
+            time: HybridTimestamp {
+                wall: 5,
+                logical: 1,
+            },
+            node_id: "n0".to_string(),
+        };
+        b.merge(&register(3, later));
+        assert_eq!(b.get(), Some(&3));
+    }
+
+    #[test]
+    fn register_delta_is_empty_unless_newer() {
+        let a = register(1, stamp(1, "n0"));
+        let b = register(2, stamp(2, "n0"));
+        assert!(a.delta_since(&b).is_empty());
+        assert_eq!(b.delta_since(&a), b);
+    }
+
+    #[test]
+    fn map_laws() {
+        let mut a = LwwMap::new();
+        a.insert("x".to_string(), 1, stamp(1, "n0"));
+        a.insert("y".to_string(), 1, stamp(1, "n0"));
+        let mut b = a.clone();
+        b.remove("x".to_string(), stamp(2, "n1"));
+        let mut c = LwwMap::new();
+        c.insert("x".to_string(), 3, stamp(2, "n2"));
+        c.insert("z".to_string(), 3, stamp(1, "n2"));
+        laws::check(&a, &b, &c);
+    }
+
+    #[test]
+    fn map_remove_and_write_race_by_stamp() {
+        let mut a = LwwMap::new();
+        a.insert("x".to_string(), 1, stamp(1, "n0"));
+        let mut removed = a.clone();
+        removed.remove("x".to_string(), stamp(2, "n0"));
+        let mut written = a.clone();
+        written.insert("x".to_string(), 2, stamp(2, "n1"));
+
+        removed.merge(&written);
+        written.merge(&removed);
+        assert_eq!(removed.get(&"x".to_string()), Some(&2));
+        assert_eq!(removed, written);
+    }
+
+    #[test]
+    fn map_delta_holds_only_newer_keys() {
+        let mut known = LwwMap::new();
+        known.insert("x".to_string(), 1, stamp(1, "n0"));
+        known.insert("y".to_string(), 1, stamp(1, "n0"));
+        let mut state = known.clone();
+        state.insert("y".to_string(), 2, stamp(2, "n0"));
+        let delta = state.delta_since(&known);
+        assert_eq!(delta.iter().collect::<Vec<_>>(), [(&"y".to_string(), &2)]);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Crdt;
+
+/// Grow-only set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(deserialize = "T: Ord + DeserializeOwned"))]
+pub struct GSet<T: Ord> {
+    elements: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        return GSet {
+            elements: BTreeSet::new(),
+        };
+    }
+}
+
+impl<T: Ord + Clone> GSet<T> {
+    pub fn new() -> Self {
+        return GSet::default();
+    }
+
+    pub fn insert(&mut self, element: T) -> bool {
+        return self.elements.insert(element);
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        return self.elements.contains(element);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        return self.elements.iter();
+    }
+}
+
+impl<T> Crdt for GSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned,
+{
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+
+    fn delta_since(&self, known: &Self) -> Self {
+        return GSet {
+            elements: self.elements.difference(&known.elements).cloned().collect(),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.elements.is_empty();
+    }
+}
+
+/// Unique id of one `insert`, the inserting node and its insert count
+type Tag = (String, u64);
+
+/// Observed-remove set with add-wins semantics
+///
+/// Every insert is tagged uniquely, a remove only tombstones the tags it has seen. An insert that
+/// is concurrent with a remove therefore survives it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Ord + DeserializeOwned"))]
+pub struct ORSet<T: Ord> {
+    inserts: BTreeSet<(T, Tag)>,
+    removes: BTreeSet<Tag>,
+    /// highest tag handed out per node
+    counters: BTreeMap<String, u64>,
+}
+
+impl<T: Ord> Default for ORSet<T> {
+    fn default() -> Self {
+        return ORSet {
+            inserts: BTreeSet::new(),
+            removes: BTreeSet::new(),
+            counters: BTreeMap::new(),
+        };
+    }
+}
+
+impl<T: Ord + Clone> ORSet<T> {
+    pub fn new() -> Self {
+        return ORSet::default();
+    }
+
+    pub fn insert(&mut self, node_id: &str, element: T) {
+        let counter = self.counters.entry(node_id.to_string()).or_insert(0);
+        *counter += 1;
+        self.inserts
+            .insert((element, (node_id.to_string(), *counter)));
+    }
+
+    /// Remove every insert of `element` observed so far
+    pub fn remove(&mut self, element: &T) {
+        let observed: Vec<Tag> = self
+            .inserts
+            .iter()
+            .filter(|(inserted, tag)| inserted == element && !self.removes.contains(tag))
+            .map(|(_, tag)| tag.clone())
+            .collect();
+        self.removes.extend(observed);
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        return self
+            .inserts
+            .iter()
+            .any(|(inserted, tag)| inserted == element && !self.removes.contains(tag));
+    }
+
+    pub fn elements(&self) -> BTreeSet<T> {
+        return self
+            .inserts
+            .iter()
+            .filter(|(_, tag)| !self.removes.contains(tag))
+            .map(|(element, _)| element.clone())
+            .collect();
+    }
+}
+
+impl<T> Crdt for ORSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned,
+{
+    fn merge(&mut self, other: &Self) {
+        self.inserts.extend(other.inserts.iter().cloned());
+        self.removes.extend(other.removes.iter().cloned());
+        for (node_id, counter) in &other.counters {
+            let entry = self.counters.entry(node_id.clone()).or_insert(0);
+            *entry = (*entry).max(*counter);
+        }
+    }
+
+    fn delta_since(&self, known: &Self) -> Self {
+        return ORSet {
+            inserts: self.inserts.difference(&known.inserts).cloned().collect(),
+            removes: self.removes.difference(&known.removes).cloned().collect(),
+            counters: self
+                .counters
+                .iter()
+                .filter(|(node_id, counter)| known.counters.get(*node_id) < Some(*counter))
+                .map(|(node_id, counter)| (node_id.clone(), *counter))
+                .collect(),
+        };
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.inserts.is_empty() && self.removes.is_empty() && self.counters.is_empty();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    fn g_set(elements: &[u64]) -> GSet<u64> {
+        let mut set = GSet::new();
+        for element in elements {
+            set.insert(*element);
+        }
+        return set;
+    }
+
+    #[test]
+    fn g_set_laws() {
+        laws::check(&g_set(&[1, 2]), &g_set(&[2, 3]), &g_set(&[4]));
+    }
+
+    #[test]
+    fn g_set_delta_holds_only_unknown_elements() {
+        assert_eq!(g_set(&[1, 2, 3]).delta_since(&g_set(&[2])), g_set(&[1, 3]));
+    }
+
+    #[test]
+    fn or_set_laws() {
+        let mut a = ORSet::new();
+        a.insert("n0", 1);
+        a.insert("n0", 2);
+        let mut b = a.clone();
+        b.remove(&1);
+        b.insert("n1", 3);
+        let mut c = a.clone();
+        c.insert("n2", 1);
+        c.remove(&2);
+        laws::check(&a, &b, &c);
+    }
+
+    #[test]
+    fn or_set_concurrent_insert_wins_over_remove() {
+        let mut a = ORSet::new();
+        a.insert("n0", 1);
+        let mut b = a.clone();
+        b.remove(&1);
+        a.insert("n0", 1);
+
+        let mut merged = a.clone();
+        merged.merge(&b);
+        assert!(merged.contains(&1));
+        b.merge(&a);
+        assert_eq!(b, merged);
+
+        // NOTE: a remove that saw both inserts takes the element out for good
+        merged.remove(&1);
+        b.merge(&merged);
+        assert!(!b.contains(&1));
+    }
+
+    #[test]
+    fn or_set_remove_only_tombstones_observed_inserts() {
+        let mut a = ORSet::new();
+        a.insert("n0", 1);
+        let mut b = ORSet::new();
+        b.insert("n1", 1);
+        a.remove(&1);
+        a.merge(&b);
+        assert_eq!(a.elements(), BTreeSet::from([1]));
+    }
+
+    #[test]
+    fn or_set_delta_carries_removes() {
+        let mut known = ORSet::new();
+        known.insert("n0", 1);
+        known.insert("n0", 2);
+        let mut state = known.clone();
+        state.remove(&1);
+        let delta = state.delta_since(&known);
+        assert!(delta.elements().is_empty());
+        known.merge(&delta);
+        assert_eq!(known.elements(), BTreeSet::from([2]));
+    }
+}
@@ -0,0 +1,903 @@
+// Purpose: pick a single coordinator among `InitNodes::node_ids`, for nodes that need one.
+//
+// Two variants behind the `LeaderElection` trait, both numbering leaderships with terms:
+//
+// - `Bully` (Garcia-Molina): the highest ranked node that is reachable wins, ranks follow
+//   Maelstrom's numbering (`n4` outranks `n0`). It is fast and needs no majority, so every side
+//   of a partition elects its own leader; after the partition heals the highest ranked one takes
+//   over with a new term.
+// - `Lease`: a leader needs grants from a majority, a grant is a promise not to grant anyone else
+//   for `lease_duration`, and the leader counts its lease from before it asked. At most one node
+//   holds a lease at any time (given bounded clock drift), a minority side has no leader.
+//
+// The node owns the election, feeds it `ElectionMessage`s and calls `tick` regularly. Both calls
+// return a `LeaderChange` whenever the leader or the term changed since the last one. A node
+// that only needs to hear of those changes runs inside `Elected`, which does all of that for it
+// and calls `Follower::leader_changed`.
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::queue::{event_queue, EventSender, QueueConfig};
+use crate::topology::natural_order;
+use crate::{Body, Event, InitNodes, Message, Node};
+
+/// Node to node messages of both variants, each ignores the messages of the other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ElectionMessage {
+    /// bully: a candidate asks the nodes that outrank it whether they are alive
+    Election { term: u64 },
+    /// bully: the answer, the candidate waits for a `Coordinator` instead
+    Alive { term: u64 },
+    /// bully: the leader announces itself, repeated as heartbeat
+    Coordinator { term: u64 },
+    /// lease: ask for a grant, `holder` if the sender's lease is valid right now
+    LeaseRequest { term: u64, round: u64, holder: bool },
+    LeaseGrant {
+        term: u64,
+        round: u64,
+        granted: bool,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)] // NOTE: `type` decides, anything that isn't an election belongs to the node
+pub enum ElectionPayload<Payload> {
+    Election(ElectionMessage),
+    Node(Payload),
+}
+
+/// Notification that the node's view of the leadership changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderChange {
+    pub term: u64,
+    /// `None` while there is no leader
+    pub leader: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ElectionConfig {
+    /// how often the leader announces itself (bully) or renews its lease
+    pub heartbeat_interval: Duration,
+    /// a bully leader not heard from this long is presumed dead, also the lease duration
+    pub leader_timeout: Duration,
+    /// how long a bully candidate waits for nodes that outrank it to answer
+    pub answer_timeout: Duration,
+}
+
+impl Default for ElectionConfig {
+    fn default() -> Self {
+        return ElectionConfig {
+            heartbeat_interval: Duration::from_millis(100),
+            leader_timeout: Duration::from_millis(1000),
+            answer_timeout: Duration::from_millis(300),
+        };
+    }
+}
+
+pub trait LeaderElection {
+    fn node_id(&self) -> &str;
+
+    /// Highest term this node knows of
+    fn term(&self) -> u64;
+
+    /// Current leader as far as this node knows, `None` while there is none
+    fn leader(&self, now: Instant) -> Option<&str>;
+
+    /// Drive timeouts, heartbeats and renewals, call it every few milliseconds
+    fn tick(
+        &mut self,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<LeaderChange>>;
+
+    /// Handle an election message from `src`
+    fn receive(
+        &mut self,
+        src: &str,
+        message: ElectionMessage,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<LeaderChange>>;
+
+    fn is_leader(&self, now: Instant) -> bool {
+        return self.leader(now) == Some(self.node_id());
+    }
+}
+
+/// Peers from `InitNodes::node_ids` without the node itself, in rank order
+fn peers_of(init: &InitNodes) -> Vec<String> {
+    let mut peers: Vec<String> = init
+        .node_ids
+        .iter()
+        .filter(|node_id| **node_id != init.node_id)
+        .cloned()
+        .collect();
+    peers.sort_by(|a, b| natural_order(a, b));
+    return peers;
+}
+
+fn send(
+    src: &str,
+    dest: &str,
+    message: ElectionMessage,
+    output: &mut dyn Write,
+) -> anyhow::Result<()> {
+    return Message {
+        src: src.to_string(),
+        dest: dest.to_string(),
+        body: Body {
+            id: None,
+            in_reply_to: None,
+            clock: None,
+            payload: message,
+        },
+    }
+    .send(&mut *output, "election");
+}
+
+/// The leadership seen last, to tell callers only about changes
+#[derive(Debug, Default)]
+struct Reported {
+    term: u64,
+    leader: Option<String>,
+}
+
+impl Reported {
+    fn update(&mut self, term: u64, leader: Option<&str>) -> Option<LeaderChange> {
+        if self.term == term && self.leader.as_deref() == leader {
+            return None;
+        }
+        self.term = term;
+        self.leader = leader.map(str::to_string);
+        return Some(LeaderChange {
+            term,
+            leader: self.leader.clone(),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BullyPhase {
+    Following,
+    /// waiting for nodes that outrank us to answer
+    Electing {
+        deadline: Instant,
+    },
+    /// a node that outranks us answered, it should announce itself soon
+    AwaitingCoordinator {
+        deadline: Instant,
+    },
+    Leading {
+        next_heartbeat: Instant,
+    },
+}
+
+pub struct Bully {
+    node_id: String,
+    peers: Vec<String>,
+    config: ElectionConfig,
+    term: u64,
+    leader: Option<String>,
+    phase: BullyPhase,
+    /// last announcement of the leader
+    last_heard: Instant,
+    reported: Reported,
+}
+
+impl Bully {
+    /// Join the election, the first `tick` starts one
+    pub fn new(init: &InitNodes, config: ElectionConfig, now: Instant) -> Self {
+        return Bully {
+            node_id: init.node_id.clone(),
+            peers: peers_of(init),
+            config,
+            term: 0,
+            leader: None,
+            phase: BullyPhase::Following,
+            last_heard: now,
+            reported: Reported::default(),
+        };
+    }
+
+    fn outranks(&self, node_id: &str) -> bool {
+        return natural_order(&self.node_id, node_id) == Ordering::Greater;
+    }
+
+    fn start_election(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.term += 1;
+        self.leader = None;
+        let higher: Vec<String> = self
+            .peers
+            .iter()
+            .filter(|peer| !self.outranks(peer))
+            .cloned()
+            .collect();
+        if higher.is_empty() {
+            return self.become_leader(now, output);
+        }
+        for peer in higher {
+            send(
+                &self.node_id,
+                &peer,
+                ElectionMessage::Election { term: self.term },
+                output,
+            )?;
+        }
+        self.phase = BullyPhase::Electing {
+            deadline: now + self.config.answer_timeout,
+        };
+        return Ok(());
+    }
+
+    fn become_leader(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.leader = Some(self.node_id.clone());
+        self.phase = BullyPhase::Leading {
+            next_heartbeat: now + self.config.heartbeat_interval,
+        };
+        return self.announce(output);
+    }
+
+    fn announce(&self, output: &mut dyn Write) -> anyhow::Result<()> {
+        for peer in &self.peers {
+            send(
+                &self.node_id,
+                peer,
+                ElectionMessage::Coordinator { term: self.term },
+                output,
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+impl LeaderElection for Bully {
+    fn node_id(&self) -> &str {
+        return &self.node_id;
+    }
+
+    fn term(&self) -> u64 {
+        return self.term;
+    }
+
+    fn leader(&self, _now: Instant) -> Option<&str> {
+        return self.leader.as_deref();
+    }
+
+    fn tick(
+        &mut self,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<LeaderChange>> {
+        match self.phase {
+            | BullyPhase::Following => {
+                let silent = now.saturating_duration_since(self.last_heard);
+                if self.leader.is_none() || silent > self.config.leader_timeout {
+                    self.start_election(now, output)?;
+                }
+            },
+            | BullyPhase::Electing { deadline } if now >= deadline => {
+                // NOTE: nobody that outranks us answered, we are the highest one alive
+                self.become_leader(now, output)?;
+            },
+            | BullyPhase::AwaitingCoordinator { deadline } if now >= deadline => {
+                self.start_election(now, output)?;
+            },
+            | BullyPhase::Leading { next_heartbeat } if now >= next_heartbeat => {
+                self.phase = BullyPhase::Leading {
+                    next_heartbeat: now + self.config.heartbeat_interval,
+                };
+                self.announce(output)?;
+            },
+            | BullyPhase::Electing { .. }
+            | BullyPhase::AwaitingCoordinator { .. }
+            | BullyPhase::Leading { .. } => {},
+        }
+        return Ok(self.reported.update(self.term, self.leader.as_deref()));
+    }
+
+    fn receive(
+        &mut self,
+        src: &str,
+        message: ElectionMessage,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<LeaderChange>> {
+        match message {
+            | ElectionMessage::Election { term } if self.outranks(src) => {
+                self.term = self.term.max(term);
+                send(
+                    &self.node_id,
+                    src,
+                    ElectionMessage::Alive { term: self.term },
+                    output,
+                )?;
+                match self.phase {
+                    | BullyPhase::Leading { .. } => {
+                        send(
+                            &self.node_id,
+                            src,
+                            ElectionMessage::Coordinator { term: self.term },
+                            output,
+                        )?;
+                    },
+                    // NOTE: we outrank the candidate, so we take over its election
+                    | BullyPhase::Following | BullyPhase::AwaitingCoordinator { .. } => {
+                        self.start_election(now, output)?;
+                    },
+                    | BullyPhase::Electing { .. } => {},
+                }
+            },
+            | ElectionMessage::Alive { term } => {
+                self.term = self.term.max(term);
+                if let BullyPhase::Electing { .. } = self.phase {
+                    self.phase = BullyPhase::AwaitingCoordinator {
+                        deadline: now + self.config.leader_timeout,
+                    };
+                }
+            },
+            | ElectionMessage::Coordinator { term } if term < self.term => {
+                // NOTE: a stale leader, e.g. from the other side of a healed partition
+                if let BullyPhase::Leading { .. } = self.phase {
+                    send(
+                        &self.node_id,
+                        src,
+                        ElectionMessage::Coordinator { term: self.term },
+                        output,
+                    )?;
+                }
+            },
+            | ElectionMessage::Coordinator { term } => {
+                self.term = term;
+                if self.outranks(src) {
+                    self.start_election(now, output)?;
+                } else {
+                    self.leader = Some(src.to_string());
+                    self.phase = BullyPhase::Following;
+                    self.last_heard = now;
+                }
+            },
+            | ElectionMessage::Election { .. }
+            | ElectionMessage::LeaseRequest { .. }
+            | ElectionMessage::LeaseGrant { .. } => {},
+        }
+        return Ok(self.reported.update(self.term, self.leader.as_deref()));
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LeaseRole {
+    Follower {
+        election_deadline: Instant,
+    },
+    /// asking for `term`, which only becomes ours with a majority of grants
+    Candidate {
+        term: u64,
+        deadline: Instant,
+    },
+    /// holds `term`, the lease is valid until `expiry`
+    Leader {
+        next_renewal: Instant,
+        expiry: Instant,
+    },
+}
+
+/// One round of lease requests, `(sent, granted_by)`
+type Round = (Instant, HashSet<String>);
+
+pub struct Lease {
+    node_id: String,
+    peers: Vec<String>,
+    config: ElectionConfig,
+    rng: StdRng,
+    /// highest term granted, won or refused with
+    term: u64,
+    /// who `term` was granted to, `None` if we only heard of it
+    granted_to: Option<String>,
+    role: LeaseRole,
+    /// node this node promised not to compete with and until when, itself while running
+    promise: Option<(String, Instant)>,
+    /// the leader and until when we believe in it, from its renewals
+    leader: Option<(String, Instant)>,
+    rounds: HashMap<u64, Round>,
+    next_round: u64,
+    reported: Reported,
+}
+
+impl Lease {
+    /// Join the election, a node stands for election after a random timeout without a leader
+    pub fn new(init: &InitNodes, config: ElectionConfig, now: Instant) -> Self {
+        let mut rng = StdRng::from_entropy();
+        let election_deadline = now + rng.gen_range(Duration::ZERO..config.leader_timeout / 2);
+        return Lease {
+            node_id: init.node_id.clone(),
+            peers: peers_of(init),
+            config,
+            rng,
+            term: 0,
+            role: LeaseRole::Follower { election_deadline },
+            granted_to: None,
+            promise: None,
+            leader: None,
+            rounds: HashMap::new(),
+            next_round: 1,
+            reported: Reported::default(),
+        };
+    }
+
+    fn majority(&self) -> usize {
+        let cluster_size = self.peers.len() + 1;
+        return cluster_size / 2 + 1;
+    }
+
+    fn lease_duration(&self) -> Duration {
+        return self.config.leader_timeout;
+    }
+
+    /// A randomised election timeout, so that candidates rarely collide
+    fn election_deadline(&mut self, now: Instant) -> Instant {
+        let timeout = self.config.leader_timeout;
+        return now + timeout + self.rng.gen_range(Duration::ZERO..timeout);
+    }
+
+    fn promised_to_other(&self, node_id: &str, now: Instant) -> bool {
+        return matches!(
+            &self.promise,
+            Some((promised, until)) if promised != node_id && now < *until
+        );
+    }
+
+    /// Ask every peer for a grant of `term`, promising ourselves not to grant anyone else
+    fn request(&mut self, term: u64, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        let round = self.next_round;
+        self.next_round += 1;
+        self.promise = Some((self.node_id.clone(), now + self.lease_duration()));
+        self.rounds
+            .insert(round, (now, HashSet::from([self.node_id.clone()])));
+        // NOTE: grants older than a lease can't extend anything anymore
+        let lease = self.lease_duration();
+        self.rounds
+            .retain(|_, (sent, _)| now.saturating_duration_since(*sent) < lease);
+        let holder = self.is_leader(now);
+        for peer in &self.peers {
+            send(
+                &self.node_id,
+                peer,
+                ElectionMessage::LeaseRequest {
+                    term,
+                    round,
+                    holder,
+                },
+                output,
+            )?;
+        }
+        self.granted(round, term, now);
+        return Ok(());
+    }
+
+    /// Count the grants of `round`, the lease starts when the round was sent
+    fn granted(&mut self, round: u64, term: u64, now: Instant) {
+        let Some((sent, grants)) = self.rounds.get(&round) else {
+            return;
+        };
+        if grants.len() < self.majority() {
+            return;
+        }
+        let expiry = *sent + self.lease_duration();
+        match self.role {
+            | LeaseRole::Candidate {
+                term: requested, ..
+            } if requested == term => {
+                self.term = term;
+                self.granted_to = Some(self.node_id.clone());
+                self.role = LeaseRole::Leader {
+                    next_renewal: now + self.config.heartbeat_interval,
+                    expiry,
+                };
+            },
+            | LeaseRole::Leader {
+                next_renewal,
+                expiry: current,
+            } if term == self.term => {
+                self.role = LeaseRole::Leader {
+                    next_renewal,
+                    expiry: current.max(expiry),
+                };
+            },
+            | LeaseRole::Follower { .. }
+            | LeaseRole::Candidate { .. }
+            | LeaseRole::Leader { .. } => {},
+        }
+    }
+
+    fn step_down(&mut self, now: Instant) {
+        let election_deadline = self.election_deadline(now);
+        self.role = LeaseRole::Follower { election_deadline };
+    }
+}
+
+impl LeaderElection for Lease {
+    fn node_id(&self) -> &str {
+        return &self.node_id;
+    }
+
+    fn term(&self) -> u64 {
+        return self.term;
+    }
+
+    fn leader(&self, now: Instant) -> Option<&str> {
+        if let LeaseRole::Leader { expiry, .. } = self.role {
+            return (now < expiry).then_some(self.node_id.as_str());
+        }
+        return match &self.leader {
+            | Some((leader, until)) if now < *until => Some(leader),
+            | _ => None,
+        };
+    }
+
+    fn tick(
+        &mut self,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<LeaderChange>> {
+        match self.role {
+            | LeaseRole::Follower { election_deadline } => {
+                // NOTE: while our promise holds, nobody else can win anyway
+                let promised = self.promise.as_ref().is_some_and(|(_, until)| now < *until);
+                if now >= election_deadline && !promised {
+                    let term = self.term + 1;
+                    self.role = LeaseRole::Candidate {
+                        term,
+                        deadline: now + self.config.leader_timeout,
+                    };
+                    self.request(term, now, output)?;
+                }
+            },
+            | LeaseRole::Candidate { deadline, .. } if now >= deadline => {
+                self.step_down(now);
+            },
+            | LeaseRole::Leader {
+                next_renewal,
+                expiry,
+            } if now >= next_renewal => {
+                // NOTE: an expired leader keeps renewing, a majority can still give the lease back
+                self.role = LeaseRole::Leader {
+                    next_renewal: now + self.config.heartbeat_interval,
+                    expiry,
+                };
+                self.request(self.term, now, output)?;
+            },
+            | LeaseRole::Candidate { .. } | LeaseRole::Leader { .. } => {},
+        }
+        let leader = self.leader(now).map(str::to_string);
+        return Ok(self.reported.update(self.term, leader.as_deref()));
+    }
+
+    fn receive(
+        &mut self,
+        src: &str,
+        message: ElectionMessage,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<LeaderChange>> {
+        match message {
+            | ElectionMessage::LeaseRequest {
+                term,
+                round,
+                holder,
+            } => {
+                // IMPORTANT: a term is granted to one node only, so two nodes never win the same
+                // NOTE: only the winner of a term holds a lease, so a holder is granted even by
+                // the nodes that granted its term to a losing candidate
+                let renewal = term == self.term
+                    && (holder
+                        || self
+                            .granted_to
+                            .as_ref()
+                            .is_none_or(|granted_to| granted_to == src));
+                let granted = (term > self.term || renewal) && !self.promised_to_other(src, now);
+                if granted {
+                    self.term = term;
+                    self.granted_to = Some(src.to_string());
+                    self.promise = Some((src.to_string(), now + self.lease_duration()));
+                    if holder {
+                        self.leader = Some((src.to_string(), now + self.lease_duration()));
+                    }
+                    // NOTE: also pushes our own candidacy back while the grantee is renewing
+                    self.step_down(now);
+                }
+                send(
+                    &self.node_id,
+                    src,
+                    ElectionMessage::LeaseGrant {
+                        term: self.term,
+                        round,
+                        granted,
+                    },
+                    output,
+                )?;
+            },
+            | ElectionMessage::LeaseGrant {
+                term,
+                round,
+                granted: true,
+            } => {
+                if let Some((_, grants)) = self.rounds.get_mut(&round) {
+                    grants.insert(src.to_string());
+                }
+                self.granted(round, term, now);
+            },
+            | ElectionMessage::LeaseGrant {
+                term,
+                granted: false,
+                ..
+            } => {
+                // NOTE: somebody holds a newer term, our lease can't be renewed anymore
+                if term > self.term {
+                    self.term = term;
+                    self.granted_to = None;
+                    if let LeaseRole::Leader { .. } = self.role {
+                        self.step_down(now);
+                    }
+                }
+            },
+            | ElectionMessage::Election { .. }
+            | ElectionMessage::Alive { .. }
+            | ElectionMessage::Coordinator { .. } => {},
+        }
+        let leader = self.leader(now).map(str::to_string);
+        return Ok(self.reported.update(self.term, leader.as_deref()));
+    }
+}
+
+/// Which `LeaderElection` an `Elected` node runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ElectionKind {
+    Bully,
+    #[default]
+    Lease,
+}
+
+impl ElectionKind {
+    pub const ALL: [ElectionKind; 2] = [ElectionKind::Bully, ElectionKind::Lease];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            | ElectionKind::Bully => "bully",
+            | ElectionKind::Lease => "lease",
+        };
+    }
+
+    pub fn build(
+        &self,
+        init: &InitNodes,
+        config: ElectionConfig,
+        now: Instant,
+    ) -> Box<dyn LeaderElection> {
+        return match self {
+            | ElectionKind::Bully => Box::new(Bully::new(init, config, now)),
+            | ElectionKind::Lease => Box::new(Lease::new(init, config, now)),
+        };
+    }
+}
+
+impl fmt::Display for ElectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+impl FromStr for ElectionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let Some(kind) = ElectionKind::ALL.iter().find(|kind| kind.name() == name) else {
+            let names: Vec<&str> = ElectionKind::ALL.iter().map(|kind| kind.name()).collect();
+            bail!(
+                "unknown election {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+        };
+        return Ok(*kind);
+    }
+}
+
+/// A node that is told whenever the leadership changes, run it as `Elected<Self>`
+pub trait Follower<State, Payload, GeneratedPayload>:
+    Node<State, Payload, GeneratedPayload>
+{
+    fn leader_changed(
+        &mut self,
+        change: LeaderChange,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ElectedConfig<State> {
+    pub kind: ElectionKind,
+    pub election: ElectionConfig,
+    /// how often the election is ticked
+    pub tick_interval: Duration,
+    /// handed to the inner node's `from_init`
+    pub node: State,
+}
+
+impl<State: Default> Default for ElectedConfig<State> {
+    fn default() -> Self {
+        return ElectedConfig {
+            kind: ElectionKind::default(),
+            election: ElectionConfig::default(),
+            tick_interval: Duration::from_millis(10),
+            node: State::default(),
+        };
+    }
+}
+
+/// Timer events of an `Elected` node
+#[derive(Debug)]
+pub enum ElectedTick<GeneratedPayload> {
+    Election,
+    Node(GeneratedPayload),
+}
+
+/// Runs a `LeaderElection` next to the node `N` and tells it of every `LeaderChange`
+///
+/// Election messages never reach `N`, everything else is passed through unchanged.
+pub struct Elected<N> {
+    election: Box<dyn LeaderElection>,
+    node: N,
+}
+
+impl<N> Elected<N> {
+    pub fn election(&self) -> &dyn LeaderElection {
+        return self.election.as_ref();
+    }
+
+    pub fn node(&self) -> &N {
+        return &self.node;
+    }
+}
+
+/// `message` with its payload replaced by `wrap(payload)`
+fn rewrap<Payload, Wrapped>(
+    message: Message<Payload>,
+    wrap: impl FnOnce(Payload) -> Wrapped,
+) -> Message<Wrapped> {
+    return Message {
+        src: message.src,
+        dest: message.dest,
+        body: Body {
+            id: message.body.id,
+            in_reply_to: message.body.in_reply_to,
+            clock: message.body.clock,
+            payload: wrap(message.body.payload),
+        },
+    };
+}
+
+impl<N, State, Payload, GeneratedPayload>
+    Node<ElectedConfig<State>, ElectionPayload<Payload>, ElectedTick<GeneratedPayload>>
+    for Elected<N>
+where
+    N: Follower<State, Payload, GeneratedPayload>,
+    Payload: Send + 'static,
+    GeneratedPayload: Send + 'static,
+{
+    fn from_init(
+        config: ElectedConfig<State>,
+        init: InitNodes,
+        sender: EventSender<ElectionPayload<Payload>, ElectedTick<GeneratedPayload>>,
+    ) -> anyhow::Result<Self> {
+        let node_id = init.node_id.clone();
+        let ticks = sender.clone();
+        let tick_interval = config.tick_interval;
+        std::thread::spawn(move || loop {
+            std::thread::sleep(tick_interval);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: ElectedTick::Election,
+                },
+            };
+            if ticks.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        // NOTE: the inner node queues its own timer events, they are moved over to ours
+        let (node_sender, node_events) = event_queue(QueueConfig::default());
+        std::thread::spawn(move || {
+            while let Some(event) = node_events.recv() {
+                let event = match event {
+                    | Event::Message(message) => {
+                        Event::Message(rewrap(message, ElectionPayload::Node))
+                    },
+                    | Event::GeneratedEvent(message) => {
+                        Event::GeneratedEvent(rewrap(message, ElectedTick::Node))
+                    },
+                    | Event::EndOfMessages => continue,
+                };
+                if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let election = config.kind.build(&init, config.election, Instant::now());
+        let node = N::from_init(config.node, init, node_sender)?;
+        return Ok(Elected { election, node });
+    }
+
+    fn step(
+        &mut self,
+        event: Event<ElectionPayload<Payload>, ElectedTick<GeneratedPayload>>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let change = match event {
+            | Event::Message(Message { src, dest, body }) => match body.payload {
+                | ElectionPayload::Election(election) => {
+                    self.election.receive(&src, election, now, output)?
+                },
+                | ElectionPayload::Node(payload) => {
+                    let body = Body {
+                        id: body.id,
+                        in_reply_to: body.in_reply_to,
+                        clock: body.clock,
+                        payload,
+                    };
+                    self.node
+                        .step(Event::Message(Message { src, dest, body }), output)?;
+                    None
+                },
+            },
+            | Event::GeneratedEvent(Message { src, dest, body }) => match body.payload {
+                | ElectedTick::Election => self.election.tick(now, output)?,
+                | ElectedTick::Node(payload) => {
+                    let body = Body {
+                        id: body.id,
+                        in_reply_to: body.in_reply_to,
+                        clock: body.clock,
+                        payload,
+                    };
+                    self.node
+                        .step(Event::GeneratedEvent(Message { src, dest, body }), output)?;
+                    None
+                },
+            },
+            | Event::EndOfMessages => {
+                self.node.step(Event::EndOfMessages, output)?;
+                None
+            },
+        };
+        if let Some(change) = change {
+            self.node.leader_changed(change, output)?;
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> Value {
+        let now = Instant::now();
+        return serde_json::json!({
+            "term": self.election.term(),
+            "leader": self.election.leader(now),
+            "node": self.node.status(),
+        });
+    }
+}
@@ -0,0 +1,383 @@
+// Purpose: decide which peers are reachable from the traffic we receive from them.
+//
+// Every message from a peer counts as a heartbeat, so nodes that already talk a lot get failure
+// detection for free and only idle links need explicit heartbeats.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::InitNodes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerStatus {
+    Alive,
+    Suspected,
+}
+
+pub trait FailureDetector {
+    /// Record that something was received from `peer` at `now`
+    fn heartbeat(&mut self, peer: &str, now: Instant);
+
+    /// Current opinion about `peer`, unknown peers are always suspected
+    fn status(&self, peer: &str, now: Instant) -> PeerStatus;
+
+    /// All monitored peers
+    fn peers(&self) -> Vec<String>;
+
+    fn is_alive(&self, peer: &str, now: Instant) -> bool {
+        return self.status(peer, now) == PeerStatus::Alive;
+    }
+
+    /// Status of every monitored peer
+    fn statuses(&self, now: Instant) -> HashMap<String, PeerStatus> {
+        return self
+            .peers()
+            .into_iter()
+            .map(|peer| {
+                let status = self.status(&peer, now);
+                (peer, status)
+            })
+            .collect();
+    }
+
+    fn suspected(&self, now: Instant) -> HashSet<String> {
+        return self
+            .peers()
+            .into_iter()
+            .filter(|peer| !self.is_alive(peer, now))
+            .collect();
+    }
+}
+
+/// Peers from `InitNodes::node_ids` without the node itself
+fn peers_of(init: &InitNodes) -> impl Iterator<Item = String> + '_ {
+    return init
+        .node_ids
+        .iter()
+        .filter(move |node_id| **node_id != init.node_id)
+        .cloned();
+}
+
+/// Suspect a peer once nothing was heard from it for `timeout`
+#[derive(Debug, Clone)]
+pub struct TimeoutDetector {
+    timeout: Duration,
+    last_heard: HashMap<String, Instant>,
+}
+
+impl TimeoutDetector {
+    /// Monitor all other nodes, every peer starts alive with a full `timeout` of grace
+    pub fn new(init: &InitNodes, timeout: Duration, now: Instant) -> Self {
+        return TimeoutDetector {
+            timeout,
+            last_heard: peers_of(init).map(|peer| (peer, now)).collect(),
+        };
+    }
+}
+
+impl FailureDetector for TimeoutDetector {
+    fn heartbeat(&mut self, peer: &str, now: Instant) {
+        if let Some(last_heard) = self.last_heard.get_mut(peer) {
+            *last_heard = (*last_heard).max(now);
+        }
+    }
+
+    fn status(&self, peer: &str, now: Instant) -> PeerStatus {
+        return match self.last_heard.get(peer) {
+            | Some(last_heard) if now.saturating_duration_since(*last_heard) <= self.timeout => {
+                PeerStatus::Alive
+            },
+            | _ => PeerStatus::Suspected,
+        };
+    }
+
+    fn peers(&self) -> Vec<String> {
+        return self.last_heard.keys().cloned().collect();
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ArrivalWindow {
+    last_heard: Instant,
+    intervals: VecDeque<f64>,
+}
+
+/// φ accrual failure detector (Hayashibara et al.)
+///
+/// Instead of a fixed timeout, the inter-arrival times of heartbeats are modelled as a normal
+/// distribution and `φ = -log10(P(next heartbeat arrives later than now))` is compared against
+/// `threshold`. A `threshold` of 8 means a ~1e-8 chance that a suspected peer is in fact alive.
+#[derive(Debug, Clone)]
+pub struct PhiAccrualDetector {
+    threshold: f64,
+    window_size: usize,
+    min_std_deviation: Duration,
+    windows: HashMap<String, ArrivalWindow>,
+}
+
+impl PhiAccrualDetector {
+    /// Monitor all other nodes
+    ///
+    /// args:
+    ///    - `threshold`: φ above which a peer is suspected
+    ///    - `expected_interval`: heartbeat interval assumed until real intervals are observed
+    pub fn new(
+        init: &InitNodes,
+        threshold: f64,
+        expected_interval: Duration,
+        now: Instant,
+    ) -> Self {
+        let bootstrap = expected_interval.as_secs_f64();
+        return PhiAccrualDetector {
+            threshold,
+            window_size: 100,
+            min_std_deviation: Duration::from_millis(100),
+            windows: peers_of(init)
+                .map(|peer| {
+                    // NOTE: seed with a wide interval so the first real heartbeat isn't an outlier
+                    let intervals =
+                        VecDeque::from([bootstrap - bootstrap / 4.0, bootstrap + bootstrap / 4.0]);
+                    (
+                        peer,
+                        ArrivalWindow {
+                            last_heard: now,
+                            intervals,
+                        },
+                    )
+                })
+                .collect(),
+        };
+    }
+
+    /// Current suspicion level of `peer`, `f64::INFINITY` for unknown peers
+    pub fn phi(&self, peer: &str, now: Instant) -> f64 {
+        let Some(window) = self.windows.get(peer) else {
+            return f64::INFINITY;
+        };
+        let elapsed = now
+            .saturating_duration_since(window.last_heard)
+            .as_secs_f64();
+        let count = window.intervals.len() as f64;
+        let mean = window.intervals.iter().sum::<f64>() / count;
+        let variance = window
+            .intervals
+            .iter()
+            .map(|interval| (interval - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        let std_deviation = variance.sqrt().max(self.min_std_deviation.as_secs_f64());
+
+        // NOTE: logistic approximation of the normal CDF, as used by Akka and Cassandra
+        let y = (elapsed - mean) / std_deviation;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        let p_later = if elapsed > mean {
+            e / (1.0 + e)
+        } else {
+            1.0 - 1.0 / (1.0 + e)
+        };
+        return -p_later.max(f64::MIN_POSITIVE).log10();
+    }
+}
+
+impl FailureDetector for PhiAccrualDetector {
+    fn heartbeat(&mut self, peer: &str, now: Instant) {
+        let Some(window) = self.windows.get_mut(peer) else {
+            return;
+        };
+        if now <= window.last_heard {
+            return;
+        }
+        let interval = now.duration_since(window.last_heard).as_secs_f64();
+        window.last_heard = now;
+        if window.intervals.len() >= self.window_size {
+            window.intervals.pop_front();
+        }
+        window.intervals.push_back(interval);
+    }
+
+    fn status(&self, peer: &str, now: Instant) -> PeerStatus {
+        if self.phi(peer, now) < self.threshold {
+            return PeerStatus::Alive;
+        }
+        return PeerStatus::Suspected;
+    }
+
+    fn peers(&self) -> Vec<String> {
+        return self.windows.keys().cloned().collect();
+    }
+}
+
+/// φ above which `DetectorKind::PhiAccrual` suspects a peer
+const PHI_THRESHOLD: f64 = 8.0;
+
+/// Which failure detector a node runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DetectorKind {
+    /// `TimeoutDetector`
+    #[default]
+    Timeout,
+    /// `PhiAccrualDetector`, adapts to how regularly each peer is heard from
+    PhiAccrual,
+}
+
+impl DetectorKind {
+    pub const ALL: [DetectorKind; 2] = [DetectorKind::Timeout, DetectorKind::PhiAccrual];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            | DetectorKind::Timeout => "timeout",
+            | DetectorKind::PhiAccrual => "phi-accrual",
+        };
+    }
+
+    /// A detector of this kind monitoring every other node of `init`
+    ///
+    /// args:
+    ///   - `heartbeat_interval`: how often an idle peer sends a heartbeat, φ accrual's first guess
+    ///     of the interval between two messages
+    ///   - `timeout`: silence after which the timeout detector suspects a peer
+    pub fn build(
+        &self,
+        init: &InitNodes,
+        heartbeat_interval: Duration,
+        timeout: Duration,
+        now: Instant,
+    ) -> Box<dyn FailureDetector> {
+        return match self {
+            | DetectorKind::Timeout => Box::new(TimeoutDetector::new(init, timeout, now)),
+            | DetectorKind::PhiAccrual => Box::new(PhiAccrualDetector::new(
+                init,
+                PHI_THRESHOLD,
+                heartbeat_interval,
+                now,
+            )),
+        };
+    }
+}
+
+impl fmt::Display for DetectorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+impl FromStr for DetectorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let Some(kind) = DetectorKind::ALL.iter().find(|kind| kind.name() == name) else {
+            let names: Vec<&str> = DetectorKind::ALL.iter().map(|kind| kind.name()).collect();
+            bail!(
+                "unknown failure detector {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+        };
+        return Ok(*kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn init() -> InitNodes {
+        return InitNodes {
+            node_id: "n0".to_string(),
+            node_ids: ["n0", "n1", "n2"].iter().map(|id| id.to_string()).collect(),
+        };
+    }
+
+    fn ms(millis: u64) -> Duration {
+        return Duration::from_millis(millis);
+    }
+
+    #[test]
+    fn timeout_suspects_after_the_timeout() {
+        let start = Instant::now();
+        let mut detector = TimeoutDetector::new(&init(), ms(500), start);
+        let mut peers = detector.peers();
+        peers.sort();
+        assert_eq!(peers, vec!["n1", "n2"]);
+
+        // NOTE: the grace period counts as having heard from everyone at `start`
+        assert!(detector.is_alive("n1", start + ms(500)));
+        assert!(!detector.is_alive("n1", start + ms(501)));
+
+        detector.heartbeat("n1", start + ms(400));
+        // NOTE: a heartbeat delivered out of order doesn't move the last time back
+        detector.heartbeat("n1", start + ms(100));
+        assert!(detector.is_alive("n1", start + ms(900)));
+        assert!(!detector.is_alive("n1", start + ms(901)));
+        assert_eq!(
+            detector.suspected(start + ms(600)),
+            ["n2".to_string()].into_iter().collect()
+        );
+
+        detector.heartbeat("n9", start);
+        assert_eq!(detector.status("n9", start), PeerStatus::Suspected);
+        assert_eq!(detector.status("n0", start), PeerStatus::Suspected);
+    }
+
+    #[test]
+    fn phi_follows_the_logistic_approximation() {
+        let start = Instant::now();
+        let detector = PhiAccrualDetector::new(&init(), PHI_THRESHOLD, ms(1000), start);
+        // NOTE: seeded with 750ms and 1250ms, mean 1s and standard deviation 250ms
+        let half = detector.phi("n1", start + ms(1000));
+        assert!(
+            (half - 2f64.log10()).abs() < 1e-9,
+            "φ at the mean was {}",
+            half
+        );
+
+        let y: f64 = 1.0;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        let expected = -(e / (1.0 + e)).log10();
+        let phi = detector.phi("n1", start + ms(1250));
+        assert!(
+            (phi - expected).abs() < 1e-9,
+            "φ one deviation late was {}",
+            phi
+        );
+
+        let phis: Vec<f64> = (0..=20)
+            .map(|step| detector.phi("n1", start + ms(step * 250)))
+            .collect();
+        assert!(phis.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(detector.is_alive("n1", start + ms(1500)));
+        assert!(!detector.is_alive("n1", start + ms(5000)));
+        assert_eq!(detector.phi("n9", start), f64::INFINITY);
+    }
+
+    #[test]
+    fn phi_adapts_to_how_often_a_peer_is_heard_from() {
+        let start = Instant::now();
+        let mut detector = PhiAccrualDetector::new(&init(), PHI_THRESHOLD, ms(1000), start);
+        let mut now = start;
+        for _ in 0..150 {
+            now += ms(100);
+            detector.heartbeat("n1", now);
+        }
+        detector.heartbeat("n1", now - ms(50));
+        assert_eq!(detector.windows["n1"].intervals.len(), 100);
+        assert!(detector.is_alive("n1", now + ms(200)));
+        // NOTE: far less than the 1s first guess, but 14 deviations late for 100ms heartbeats
+        assert!(!detector.is_alive("n1", now + ms(1500)));
+        // NOTE: the idle peer keeps the wide first guess
+        assert!(detector.is_alive("n2", start + ms(1500)));
+    }
+
+    #[test]
+    fn detector_kind_names_round_trip() {
+        for kind in DetectorKind::ALL {
+            assert_eq!(kind.name().parse::<DetectorKind>().unwrap(), kind);
+        }
+        assert!("gossip".parse::<DetectorKind>().is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// Purpose: Maelstrom's key/value schema, shared by every KV flavoured node and service.
+//
+// Keys and values are arbitrary JSON, just like in Maelstrom's `lin-kv`/`seq-kv`/`lww-kv`.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::ErrorCode;
+
+fn is_false(value: &bool) -> bool {
+    return !value;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum KvPayload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default, skip_serializing_if = "is_false")]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+    Error {
+        code: ErrorCode,
+        text: String,
+    },
+}
+
+impl KvPayload {
+    pub fn error(code: ErrorCode, text: impl Into<String>) -> Self {
+        return KvPayload::Error {
+            code,
+            text: text.into(),
+        };
+    }
+
+    /// Key a request operates on, `None` for replies
+    pub fn key(&self) -> Option<&Value> {
+        return match self {
+            | KvPayload::Read { key }
+            | KvPayload::Write { key, .. }
+            | KvPayload::Cas { key, .. } => Some(key),
+            | KvPayload::ReadOk { .. }
+            | KvPayload::WriteOk
+            | KvPayload::CasOk
+            | KvPayload::Error { .. } => None,
+        };
+    }
+
+    pub fn is_request(&self) -> bool {
+        return self.key().is_some();
+    }
+
+    /// Whether the request can change the store
+    pub fn is_update(&self) -> bool {
+        return matches!(self, KvPayload::Write { .. } | KvPayload::Cas { .. });
+    }
+}
+
+/// Single copy key/value map with Maelstrom's read/write/cas semantics
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KvStore {
+    /// NOTE: keys are stored as their JSON text so that any JSON value can be a key
+    data: BTreeMap<String, Value>,
+}
+
+impl KvStore {
+    pub fn new() -> Self {
+        return KvStore::default();
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        return self.data.get(&key.to_string());
+    }
+
+    pub fn insert(&mut self, key: &Value, value: Value) {
+        self.data.insert(key.to_string(), value);
+    }
+
+    pub fn len(&self) -> usize {
+        return self.data.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.data.is_empty();
+    }
+
+    /// Execute `request` and build its reply
+    pub fn apply(&mut self, request: &KvPayload) -> KvPayload {
+        return match request {
+            | KvPayload::Read { key } => match self.get(key) {
+                | Some(value) => KvPayload::ReadOk {
+                    value: value.clone(),
+                },
+                | None => KvPayload::error(ErrorCode::KeyDoesNotExist, "key does not exist"),
+            },
+            | KvPayload::Write { key, value } => {
+                self.insert(key, value.clone());
+                KvPayload::WriteOk
+            },
+            | KvPayload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.get(key) {
+                | None if *create_if_not_exists => {
+                    self.insert(key, to.clone());
+                    KvPayload::CasOk
+                },
+                | None => KvPayload::error(ErrorCode::KeyDoesNotExist, "key does not exist"),
+                | Some(current) if current != from => KvPayload::error(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {}, but had {}", from, current),
+                ),
+                | Some(_) => {
+                    self.insert(key, to.clone());
+                    KvPayload::CasOk
+                },
+            },
+            | KvPayload::ReadOk { .. }
+            | KvPayload::WriteOk
+            | KvPayload::CasOk
+            | KvPayload::Error { .. } => {
+                KvPayload::error(ErrorCode::NotSupported, "not a key/value request")
+            },
+        };
+    }
+}
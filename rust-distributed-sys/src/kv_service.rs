@@ -0,0 +1,179 @@
+// Purpose: in-process stand-ins for Maelstrom's `lin-kv`, `seq-kv` and `lww-kv` services.
+//
+// `sim::Simulation` answers messages to these names itself, so nodes that keep their state in one
+// of Maelstrom's services can run offline. Each one keeps the promise Maelstrom makes for it:
+// - `lin-kv`: a single copy, every request sees everything answered before it (linearizable)
+// - `seq-kv`: writes and cas go to the end of one history, a read is served from any point of it
+//   since the last one its node saw: reads can be stale, but a node never goes back in time and
+//   always sees its own writes (sequentially consistent)
+// - `lww-kv`: a few replicas that take requests on their own and learn of each other's writes
+//   after a random delay, the newest write wins: reads can be stale and go back in time, and two
+//   cas at different replicas can both succeed (eventually consistent)
+//
+// NOTE: seq-kv keeps every version it ever had, fine for a simulation that runs for seconds
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng};
+use serde_json::Value;
+
+use crate::kv::{KvPayload, KvStore};
+
+/// Names the services answer to, a node must not use them as its id
+pub const NAMES: [&str; 3] = ["lin-kv", "seq-kv", "lww-kv"];
+
+/// replicas of `lww-kv`
+const LWW_REPLICAS: usize = 3;
+/// longest time before a write to one `lww-kv` replica reaches the others
+const LWW_PROPAGATION: Duration = Duration::from_millis(100);
+
+/// Run `request` against `current`, the value of its key as far as the service knows
+///
+/// returns:
+///   - the reply, and the value to store if the request changed the key
+fn run(key: &Value, current: Option<&Value>, request: &KvPayload) -> (KvPayload, Option<Value>) {
+    let mut store = KvStore::new();
+    if let Some(value) = current {
+        store.insert(key, value.clone());
+    }
+    let reply = store.apply(request);
+    let written = match reply {
+        | KvPayload::WriteOk | KvPayload::CasOk => store.get(key).cloned(),
+        | _ => None,
+    };
+    return (reply, written);
+}
+
+#[derive(Debug, Default)]
+pub struct SequentialKv {
+    /// every value a key had, `(version, value)` in version order
+    history: HashMap<String, Vec<(u64, Value)>>,
+    /// version of the latest write
+    version: u64,
+    /// latest version each node has seen
+    seen: HashMap<String, u64>,
+}
+
+impl SequentialKv {
+    fn value_at(&self, key: &Value, version: u64) -> Option<&Value> {
+        let versions = self.history.get(&key.to_string())?;
+        let newer = versions.partition_point(|(written, _)| *written <= version);
+        return newer.checked_sub(1).map(|index| &versions[index].1);
+    }
+
+    fn handle(
+        &mut self,
+        src: &str,
+        key: &Value,
+        request: &KvPayload,
+        rng: &mut StdRng,
+    ) -> KvPayload {
+        let seen = self.seen.get(src).copied().unwrap_or_default();
+        if let KvPayload::Read { .. } = request {
+            let version = rng.gen_range(seen..=self.version);
+            self.seen.insert(src.to_string(), version);
+            return run(key, self.value_at(key, version), request).0;
+        }
+
+        let (reply, written) = run(key, self.value_at(key, self.version), request);
+        if let Some(value) = written {
+            self.version += 1;
+            self.history
+                .entry(key.to_string())
+                .or_default()
+                .push((self.version, value));
+        }
+        self.seen.insert(src.to_string(), self.version);
+        return reply;
+    }
+}
+
+/// A write to `lww-kv` and when every replica learns of it
+#[derive(Debug)]
+struct LwwWrite {
+    value: Value,
+    visible: [Instant; LWW_REPLICAS],
+}
+
+#[derive(Debug, Default)]
+pub struct LastWriteWinsKv {
+    /// writes of every key, oldest first
+    writes: HashMap<String, Vec<LwwWrite>>,
+}
+
+impl LastWriteWinsKv {
+    fn handle(
+        &mut self,
+        key: &Value,
+        request: &KvPayload,
+        now: Instant,
+        rng: &mut StdRng,
+    ) -> KvPayload {
+        let replica = rng.gen_range(0..LWW_REPLICAS);
+        let writes = self.writes.entry(key.to_string()).or_default();
+        let current = writes
+            .iter()
+            .rev()
+            .find(|write| write.visible[replica] <= now)
+            .map(|write| &write.value);
+        let (reply, written) = run(key, current, request);
+        let Some(value) = written else {
+            return reply;
+        };
+
+        let mut visible = [now; LWW_REPLICAS];
+        for (other, visible) in visible.iter_mut().enumerate() {
+            if other != replica {
+                *visible += rng.gen_range(Duration::ZERO..=LWW_PROPAGATION);
+            }
+        }
+        // NOTE: all replicas share one clock here, so the latest write is also the newest
+        writes.push(LwwWrite { value, visible });
+        // NOTE: a write every replica knows of hides everything before it
+        if let Some(settled) = writes
+            .iter()
+            .rposition(|write| write.visible.iter().all(|visible| *visible <= now))
+        {
+            writes.drain(..settled);
+        }
+        return reply;
+    }
+}
+
+#[derive(Debug)]
+pub enum KvService {
+    Linearizable(KvStore),
+    Sequential(SequentialKv),
+    LastWriteWins(LastWriteWinsKv),
+}
+
+impl KvService {
+    /// The service Maelstrom runs as `name`, `None` if it runs none by that name
+    pub fn named(name: &str) -> Option<Self> {
+        return match name {
+            | "lin-kv" => Some(KvService::Linearizable(KvStore::new())),
+            | "seq-kv" => Some(KvService::Sequential(SequentialKv::default())),
+            | "lww-kv" => Some(KvService::LastWriteWins(LastWriteWinsKv::default())),
+            | _ => None,
+        };
+    }
+
+    /// Serve `request` from node or client `src`
+    ///
+    /// returns:
+    ///   - the reply, `None` if `request` is a reply itself
+    pub fn handle(
+        &mut self,
+        src: &str,
+        request: &KvPayload,
+        now: Instant,
+        rng: &mut StdRng,
+    ) -> Option<KvPayload> {
+        let key = request.key()?;
+        return Some(match self {
+            | KvService::Linearizable(store) => store.apply(request),
+            | KvService::Sequential(service) => service.handle(src, key, request, rng),
+            | KvService::LastWriteWins(service) => service.handle(key, request, now, rng),
+        });
+    }
+}
@@ -1,10 +1,46 @@
 use std::collections::HashSet;
-use std::io::{BufRead, StdoutLock, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Ok};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
+
+use crate::envelope::Decode;
+use crate::output::{FlushPolicy, Output};
+use crate::queue::{event_queue, EventSender, QueueConfig};
+use crate::status::StatusPayload;
+
+pub mod analysis;
+#[cfg(feature = "async")]
+pub mod async_loop;
+pub mod causal;
+pub mod clock;
+pub mod crdt;
+pub mod election;
+pub mod envelope;
+pub mod failure_detector;
+pub mod kv;
+pub mod kv_service;
+pub mod linearizability;
+pub mod output;
+pub mod partition;
+pub mod persist;
+pub mod queue;
+pub mod raft;
+pub mod reliable;
+pub mod sim;
+pub mod status;
+pub mod topology;
+pub mod total_order;
+pub mod two_phase;
+pub mod workloads;
+
+/// Maelstrom convention: clients are `c1`, `c2`, ..., every other id is a node or a service
+pub(crate) fn is_client(id: &str) -> bool {
+    return id.starts_with('c');
+}
 
 #[derive(Debug)]
 pub enum Event<Payload, GeneratedPayload> {
@@ -13,7 +49,7 @@ pub enum Event<Payload, GeneratedPayload> {
     EndOfMessages,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message<Payload> {
     pub src: String,
     pub dest: String,
@@ -29,31 +65,105 @@ impl<Payload> Message<Payload> {
             body: Body {
                 id: Some(*id.unwrap()),
                 in_reply_to: self.body.id,
+                clock: None,
                 payload: self.body.payload,
             },
         };
     }
     /// Send message to stdin
-    pub fn send(self, output: &mut StdoutLock, reply_to: &str) -> anyhow::Result<()>
+    pub fn send(mut self, output: &mut (impl Write + ?Sized), reply_to: &str) -> anyhow::Result<()>
     where
         Payload: Serialize,
     {
+        if self.body.clock.is_none() {
+            self.body.clock = clock::on_send(&self.dest);
+        }
         serde_json::to_writer(&mut *output, &self)
-            .context(format!("serialize response to {}", reply_to))?;
+            .with_context(|| format!("serialize response to {}", reply_to))?;
         output.write_all(b"\n").context("write trailing newline")?;
         return Ok(());
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Body<Payload> {
     #[serde(rename = "msg_id")]
     pub id: Option<usize>,
     pub in_reply_to: Option<usize>,
+    /// logical timestamp, only present if the sender installed a `clock::Clock`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<clock::Timestamp>,
     #[serde(flatten)] // IMPORTANT: removes "payload" from json serialization
     pub payload: Payload,
 }
 
+/// Maelstrom error codes, sent as `{"type": "error", "code": <code>, "text": "..."}`
+///
+/// NOTE: see https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "u32", try_from = "u32")]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+}
+
+impl ErrorCode {
+    /// Whether the request definitely did not take effect
+    ///
+    /// Clients can only safely retry definite errors, the others leave the outcome unknown.
+    pub fn is_definite(&self) -> bool {
+        return !matches!(self, ErrorCode::Timeout | ErrorCode::Crash);
+    }
+}
+
+impl From<ErrorCode> for u32 {
+    fn from(code: ErrorCode) -> Self {
+        return match code {
+            | ErrorCode::Timeout => 0,
+            | ErrorCode::NodeNotFound => 1,
+            | ErrorCode::NotSupported => 10,
+            | ErrorCode::TemporarilyUnavailable => 11,
+            | ErrorCode::MalformedRequest => 12,
+            | ErrorCode::Crash => 13,
+            | ErrorCode::Abort => 14,
+            | ErrorCode::KeyDoesNotExist => 20,
+            | ErrorCode::KeyAlreadyExists => 21,
+            | ErrorCode::PreconditionFailed => 22,
+            | ErrorCode::TxnConflict => 30,
+        };
+    }
+}
+
+impl TryFrom<u32> for ErrorCode {
+    type Error = String;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        return match code {
+            | 0 => Result::Ok(ErrorCode::Timeout),
+            | 1 => Result::Ok(ErrorCode::NodeNotFound),
+            | 10 => Result::Ok(ErrorCode::NotSupported),
+            | 11 => Result::Ok(ErrorCode::TemporarilyUnavailable),
+            | 12 => Result::Ok(ErrorCode::MalformedRequest),
+            | 13 => Result::Ok(ErrorCode::Crash),
+            | 14 => Result::Ok(ErrorCode::Abort),
+            | 20 => Result::Ok(ErrorCode::KeyDoesNotExist),
+            | 21 => Result::Ok(ErrorCode::KeyAlreadyExists),
+            | 22 => Result::Ok(ErrorCode::PreconditionFailed),
+            | 30 => Result::Ok(ErrorCode::TxnConflict),
+            | code => Err(format!("unknown maelstrom error code {}", code)),
+        };
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -73,7 +183,7 @@ pub trait Node<State, Payload, GeneratedPayload> {
     fn from_init(
         state: State,
         init: InitNodes,
-        sender: mpsc::Sender<Event<Payload, GeneratedPayload>>,
+        sender: EventSender<Payload, GeneratedPayload>,
     ) -> anyhow::Result<Self>
     //IMPORTANT: need to tell compiler `Node` is of fixed size
     where
@@ -81,35 +191,94 @@ pub trait Node<State, Payload, GeneratedPayload> {
     fn step(
         &mut self,
         event: Event<Payload, GeneratedPayload>,
-        output: &mut StdoutLock,
+        output: &mut dyn Write,
     ) -> anyhow::Result<()>;
+
+    /// Node specific part of the reply to a `status` request, see `status`
+    fn status(&self) -> serde_json::Value {
+        return serde_json::Value::Null;
+    }
 }
 
-// TODO: move initialization to private function
+/// Run a node as a Maelstrom binary, reading stdin and writing stdout
 pub fn event_loop<N, State, Payload, GeneratedPayload>(inital_state: State) -> anyhow::Result<()>
 where
-    Payload: DeserializeOwned + Send + 'static,
+    Payload: Decode + Send + 'static,
+    GeneratedPayload: Send + 'static,
+    N: Node<State, Payload, GeneratedPayload>,
+{
+    return event_loop_with::<N, State, Payload, GeneratedPayload>(
+        inital_state,
+        std::io::stdin(),
+        std::io::stdout().lock(),
+    );
+}
+
+/// Run a node over any line based input and output, e.g. the in-process network of `sim`
+pub fn event_loop_with<N, State, Payload, GeneratedPayload>(
+    inital_state: State,
+    input: impl Read + Send + 'static,
+    stdout: impl Write,
+) -> anyhow::Result<()>
+where
+    Payload: Decode + Send + 'static,
     GeneratedPayload: Send + 'static,
     N: Node<State, Payload, GeneratedPayload>,
 {
-    let stdin = std::io::stdin().lock();
-    let mut lines = stdin.lines();
+    return event_loop_with_config::<N, State, Payload, GeneratedPayload>(
+        inital_state,
+        input,
+        stdout,
+        EventLoopConfig::default(),
+    );
+}
+
+/// Tuning of `event_loop_with_config`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EventLoopConfig {
+    /// when buffered output is written, see `output::Output`
+    pub flush: FlushPolicy,
+    /// capacities and overflow behaviour of the event lanes, see `queue`
+    pub queue: QueueConfig,
+    /// log a `status` report to stderr this often, checked whenever an event is handled
+    pub status_interval: Option<Duration>,
+}
 
-    let mut stdout = std::io::stdout().lock();
+/// Run a node with custom output and event queue settings
+// TODO: move initialization to private function
+pub fn event_loop_with_config<N, State, Payload, GeneratedPayload>(
+    inital_state: State,
+    input: impl Read + Send + 'static,
+    stdout: impl Write,
+    config: EventLoopConfig,
+) -> anyhow::Result<()>
+where
+    Payload: Decode + Send + 'static,
+    GeneratedPayload: Send + 'static,
+    N: Node<State, Payload, GeneratedPayload>,
+{
+    let mut stdout = Output::new(stdout, config.flush);
+    let mut input = BufReader::new(input);
 
-    let init_message: Message<InitPayload> = serde_json::from_str(
-        &lines
-            .next()
-            .expect("No init message received.")
-            .context("Failed to read init message.")?,
-    )
-    .context("Init message could not be deserialized!")?;
+    let mut init_line = String::new();
+    let read = input
+        .read_line(&mut init_line)
+        .context("Failed to read init message.")?;
+    anyhow::ensure!(read > 0, "No init message received.");
+    let init_message: Message<InitPayload> =
+        serde_json::from_str(&init_line).context("Init message could not be deserialized!")?;
 
     let InitPayload::Init(init) = init_message.body.payload else {
         panic!("First message should be an init!");
     };
 
-    let (sender, reciever) = mpsc::channel();
+    let mut runtime = status::Runtime {
+        node_id: init.node_id.clone(),
+        started: Instant::now(),
+        steps: 0,
+    };
+    let mut last_status = Instant::now();
+    let (sender, reciever) = event_queue(config.queue);
     let mut node: N =
         Node::from_init(inital_state, init, sender.clone()).context("Node initilization failed")?;
 
@@ -120,6 +289,7 @@ where
         body: Body {
             id: Some(0),
             in_reply_to: init_message.body.id,
+            clock: None,
             payload: InitPayload::InitOk,
         },
     };
@@ -128,30 +298,103 @@ where
     stdout
         .write_all(b"\n")
         .context("Writing trailing newline.")?;
+    stdout.flush().context("Flushing init reply.")?;
 
-    drop(lines);
     // NOTE: Handle message parsing in other thread - spawned in node::init
     let handler = thread::spawn(move || {
-        let stdin = std::io::stdin().lock();
-        let lines = stdin.lines();
-
-        for input in lines {
-            let input = input.context("Maelstrom input could not be read.")?;
-            let message: Message<Payload> = serde_json::from_str(&input)
-                .context("Maelstrom input could not be deserialized.")?;
-            if let Err(_) = sender.send(Event::Message(message)) {
+        // NOTE: one buffer for the whole run, every line is decoded before the next is read
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = input
+                .read_line(&mut line)
+                .context("Maelstrom input could not be read.")?;
+            if read == 0 {
+                break;
+            }
+            if let Some(request) = status::parse_request(&line) {
+                sender.send_status(request);
+                continue;
+            }
+            let message = Payload::decode_line(&line)?;
+            if sender.send(Event::Message(message)).is_err() {
                 return Ok(());
             }
         }
-        if let Err(_) = sender.send(Event::EndOfMessages) {
+        if sender.send(Event::EndOfMessages).is_err() {
             return Ok(());
         }
         return Ok(());
     });
 
-    for message in reciever {
+    loop {
+        while let Some(request) = reciever.try_recv_status() {
+            let status = runtime.status(reciever.metrics(), node.status());
+            // NOTE: no msg_id, the node owns the id sequence and nothing replies to this
+            let reply = Message {
+                src: request.dest,
+                dest: request.src,
+                body: Body {
+                    id: None,
+                    in_reply_to: request.body.id,
+                    clock: None,
+                    payload: StatusPayload::StatusOk(Box::new(status)),
+                },
+            };
+            reply.send(&mut stdout, "status")?;
+        }
+        let message = match reciever.try_recv() {
+            | Result::Ok(message) => message,
+            | Err(mpsc::TryRecvError::Empty) => {
+                // IMPORTANT: never go idle with replies still buffered
+                stdout.flush().context("Flushing output.")?;
+                reciever.wait();
+                continue;
+            },
+            | Err(mpsc::TryRecvError::Disconnected) => break,
+        };
+        // NOTE: timer threads hold senders forever, so the channel never closes on its own
+        let is_last = matches!(message, Event::EndOfMessages);
+        if let Event::Message(Message {
+            body: Body {
+                clock: Some(remote),
+                ..
+            },
+            ..
+        }) = &message
+        {
+            clock::on_receive(remote);
+        }
         node.step(message, &mut stdout)
             .context("Node step function failed.")?;
+        stdout.end_step().context("Flushing output.")?;
+        runtime.steps += 1;
+        if let Some(interval) = config.status_interval {
+            if last_status.elapsed() >= interval {
+                last_status = Instant::now();
+                let status = runtime.status(reciever.metrics(), node.status());
+                eprintln!(
+                    "status: {}",
+                    serde_json::to_string(&status).unwrap_or_default()
+                );
+            }
+        }
+        if is_last {
+            break;
+        }
+    }
+    stdout.flush().context("Flushing output.")?;
+    // NOTE: stderr ends up in Maelstrom's node logs
+    let metrics = reciever.metrics();
+    let lanes = [metrics.timer, metrics.peer, metrics.client];
+    if lanes
+        .iter()
+        .any(|lane| lane.dropped > 0 || lane.blocked > 0)
+    {
+        eprintln!(
+            "event queue overflowed: {}",
+            serde_json::to_string(&metrics).unwrap_or_default()
+        );
     }
     handler.join().expect("thread paniced")?;
     return Ok(());
@@ -0,0 +1,754 @@
+// Purpose: check recorded histories for linearizability without Maelstrom's Knossos.
+//
+// A history is a list of Jepsen style events: a client invokes an operation and later sees it
+// complete with `ok`, `fail` (definitely didn't happen) or `info` (outcome unknown). The history
+// is linearizable if every operation can be placed at a single point between its invocation and
+// completion such that the resulting sequence is legal for the `Model`.
+//
+// The search is the Wing & Gong algorithm with Lowe's memoisation of `(linearized ops, state)`.
+// Operations on different keys are independent, so every key is checked on its own.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::sim::TraceEntry;
+use crate::{is_client, ErrorCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEvent {
+    #[serde(rename = "type")]
+    pub kind: EventType,
+    pub process: String,
+    pub f: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<Value>,
+    /// argument on invoke, result on `ok`
+    #[serde(default)]
+    pub value: Value,
+    /// pairs a completion with its invocation, without it the oldest open invocation of the
+    /// process is completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+}
+
+/// One operation of a history, `fail`ed operations are never part of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub process: String,
+    pub f: String,
+    pub key: Option<Value>,
+    pub input: Value,
+    /// `None` if the outcome is unknown
+    pub output: Option<Value>,
+    /// index of the invoke event
+    pub invoked: usize,
+    /// index of the completion, `None` if the operation may take effect at any later time
+    pub completed: Option<usize>,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.process, self.f)?;
+        if let Some(key) = &self.key {
+            write!(f, " key={}", key)?;
+        }
+        if !self.input.is_null() {
+            write!(f, " {}", self.input)?;
+        }
+        match &self.output {
+            | Some(output) if !output.is_null() => write!(f, " -> {}", output)?,
+            | Some(_) => {},
+            | None => write!(f, " -> ?")?,
+        }
+        let completed = self
+            .completed
+            .map(|completed| completed.to_string())
+            .unwrap_or("∞".to_string());
+        return write!(f, " [{}..{}]", self.invoked, completed);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct History {
+    pub events: Vec<HistoryEvent>,
+}
+
+impl History {
+    /// Build a history from client traffic, `(src, dest, body)` in the order it was sent
+    ///
+    /// Understands Maelstrom's `read`/`write`/`cas` (KV), `add` (counters and sets) and
+    /// `broadcast` (recorded as a set `add`) requests and their replies. Error replies are `fail`
+    /// if the code is definite and `info` otherwise.
+    pub fn from_messages(messages: impl IntoIterator<Item = (String, String, Value)>) -> Self {
+        let mut events = Vec::new();
+        // NOTE: (client, msg_id) -> (f, key) of every open request
+        let mut open: HashMap<(String, u64), (String, Option<Value>)> = HashMap::new();
+
+        for (src, dest, body) in messages {
+            let kind = body["type"].as_str().unwrap_or_default();
+            if is_client(&src) && !is_client(&dest) {
+                let Some(msg_id) = body["msg_id"].as_u64() else {
+                    continue;
+                };
+                let (f, value) = match kind {
+                    | "read" => ("read", Value::Null),
+                    | "write" => ("write", body["value"].clone()),
+                    | "cas" => (
+                        "cas",
+                        Value::Array(vec![body["from"].clone(), body["to"].clone()]),
+                    ),
+                    | "add" if !body["delta"].is_null() => ("add", body["delta"].clone()),
+                    | "add" => ("add", body["element"].clone()),
+                    | "broadcast" => ("add", body["message"].clone()),
+                    | _ => continue,
+                };
+                let key = body.get("key").cloned();
+                open.insert((src.clone(), msg_id), (f.to_string(), key.clone()));
+                events.push(HistoryEvent {
+                    kind: EventType::Invoke,
+                    process: src,
+                    f: f.to_string(),
+                    key,
+                    value,
+                    id: Some(msg_id as usize),
+                });
+            } else if is_client(&dest) {
+                let Some(in_reply_to) = body["in_reply_to"].as_u64() else {
+                    continue;
+                };
+                let Some((f, key)) = open.remove(&(dest.clone(), in_reply_to)) else {
+                    continue;
+                };
+                let (kind, value) = if kind == "error" {
+                    let definite = serde_json::from_value::<ErrorCode>(body["code"].clone())
+                        .is_ok_and(|code| code.is_definite());
+                    let kind = if definite {
+                        EventType::Fail
+                    } else {
+                        EventType::Info
+                    };
+                    (kind, Value::Null)
+                } else if f == "read" {
+                    let value = match body.get("messages") {
+                        | Some(messages) => messages.clone(),
+                        | None => body["value"].clone(),
+                    };
+                    (EventType::Ok, value)
+                } else {
+                    (EventType::Ok, Value::Null)
+                };
+                events.push(HistoryEvent {
+                    kind,
+                    process: dest,
+                    f,
+                    key,
+                    value,
+                    id: Some(in_reply_to as usize),
+                });
+            }
+        }
+        return History { events };
+    }
+
+    /// Build a history from a transcript of JSON messages, one `{src, dest, body}` per line
+    pub fn from_transcript(transcript: &str) -> Self {
+        let messages = transcript.lines().filter_map(|line| {
+            let message: Value = serde_json::from_str(line).ok()?;
+            let src = message["src"].as_str()?.to_string();
+            let dest = message["dest"].as_str()?.to_string();
+            return Some((src, dest, message["body"].clone()));
+        });
+        return History::from_messages(messages);
+    }
+
+    /// Build a history from the trace of a `sim::Simulation`
+    pub fn from_trace(trace: &[TraceEntry]) -> Self {
+        return History::from_messages(
+            trace
+                .iter()
+                .map(|entry| (entry.src.clone(), entry.dest.clone(), entry.body.clone())),
+        );
+    }
+
+    /// Pair invocations with completions
+    ///
+    /// `fail`ed operations are dropped, reads with an unknown outcome can't constrain anything
+    /// and are dropped too.
+    pub fn operations(&self) -> Vec<Operation> {
+        let mut operations: Vec<Option<Operation>> = Vec::new();
+        let mut open: HashMap<(String, Option<usize>), Vec<usize>> = HashMap::new();
+
+        for (index, event) in self.events.iter().enumerate() {
+            if event.kind == EventType::Invoke {
+                open.entry((event.process.clone(), event.id))
+                    .or_default()
+                    .push(operations.len());
+                operations.push(Some(Operation {
+                    process: event.process.clone(),
+                    f: event.f.clone(),
+                    key: event.key.clone(),
+                    input: event.value.clone(),
+                    output: None,
+                    invoked: index,
+                    completed: None,
+                }));
+                continue;
+            }
+            let Some(invocations) = open.get_mut(&(event.process.clone(), event.id)) else {
+                continue;
+            };
+            if invocations.is_empty() {
+                continue;
+            }
+            let position = invocations.remove(0);
+            match event.kind {
+                | EventType::Ok => {
+                    let operation = operations[position].as_mut().unwrap();
+                    operation.output = Some(event.value.clone());
+                    operation.completed = Some(index);
+                },
+                | EventType::Fail => operations[position] = None,
+                | EventType::Info | EventType::Invoke => {},
+            }
+        }
+
+        return operations
+            .into_iter()
+            .flatten()
+            .filter(|operation| !(operation.f == "read" && operation.output.is_none()))
+            .collect();
+    }
+}
+
+/// Sequential specification the history is checked against
+pub trait Model {
+    type State: Clone + Eq + Hash + fmt::Debug;
+
+    fn init(&self) -> Self::State;
+
+    /// State after `operation`, `None` if it can't return `operation.output` in `state`
+    ///
+    /// `operation.output` is `None` for operations with an unknown outcome.
+    fn step(&self, state: &Self::State, operation: &Operation) -> Option<Self::State>;
+}
+
+/// Whether a read observed `state`
+fn read_matches(state: &Option<String>, output: &Option<Value>) -> bool {
+    return match (state, output) {
+        | (_, None) => true,
+        | (None, Some(Value::Null)) => true,
+        | (Some(state), Some(output)) => {
+            serde_json::from_str::<Value>(state).is_ok_and(|state| state == *output)
+        },
+        | (None, Some(_)) => false,
+    };
+}
+
+/// Read/write register
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Register;
+
+impl Model for Register {
+    type State = Option<String>;
+
+    fn init(&self) -> Self::State {
+        return None;
+    }
+
+    fn step(&self, state: &Self::State, operation: &Operation) -> Option<Self::State> {
+        return match operation.f.as_str() {
+            | "write" => Some(Some(operation.input.to_string())),
+            | "read" if read_matches(state, &operation.output) => Some(state.clone()),
+            | _ => None,
+        };
+    }
+}
+
+/// Read/write/compare-and-set register, `cas` takes `[from, to]`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CasRegister;
+
+impl Model for CasRegister {
+    type State = Option<String>;
+
+    fn init(&self) -> Self::State {
+        return None;
+    }
+
+    fn step(&self, state: &Self::State, operation: &Operation) -> Option<Self::State> {
+        if operation.f != "cas" {
+            return Register.step(state, operation);
+        }
+        let from = operation.input.get(0)?.to_string();
+        let to = operation.input.get(1)?.to_string();
+        if state.as_ref() != Some(&from) {
+            return None;
+        }
+        return Some(Some(to));
+    }
+}
+
+/// Counter with `add` of a delta and `read` of the total
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counter;
+
+impl Model for Counter {
+    type State = i64;
+
+    fn init(&self) -> Self::State {
+        return 0;
+    }
+
+    fn step(&self, state: &Self::State, operation: &Operation) -> Option<Self::State> {
+        return match operation.f.as_str() {
+            | "add" => Some(state + operation.input.as_i64()?),
+            | "read" => match &operation.output {
+                | None => Some(*state),
+                | Some(output) if output.as_i64() == Some(*state) => Some(*state),
+                | Some(_) => None,
+            },
+            | _ => None,
+        };
+    }
+}
+
+/// Set with `add` of an element and `read` of all elements
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Set;
+
+impl Model for Set {
+    type State = BTreeSet<String>;
+
+    fn init(&self) -> Self::State {
+        return BTreeSet::new();
+    }
+
+    fn step(&self, state: &Self::State, operation: &Operation) -> Option<Self::State> {
+        return match operation.f.as_str() {
+            | "add" => {
+                let mut next = state.clone();
+                next.insert(operation.input.to_string());
+                Some(next)
+            },
+            | "read" => {
+                let Some(output) = &operation.output else {
+                    return Some(state.clone());
+                };
+                let observed: BTreeSet<String> = output
+                    .as_array()?
+                    .iter()
+                    .map(|element| element.to_string())
+                    .collect();
+                (observed == *state).then(|| state.clone())
+            },
+            | _ => None,
+        };
+    }
+}
+
+/// Why a history isn't linearizable
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    pub key: Option<Value>,
+    /// the first operation (by completion) that can't be linearized
+    pub operation: Operation,
+    /// operations overlapping with `operation`, one of them has to explain it
+    pub concurrent: Vec<Operation>,
+    /// a valid order of everything that completed before `operation`
+    pub linearized: Vec<Operation>,
+    /// model state after `linearized`
+    pub state: String,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            | Some(key) => writeln!(f, "history of key {} is not linearizable", key)?,
+            | None => writeln!(f, "history is not linearizable")?,
+        }
+        writeln!(f, "cannot linearize: {}", self.operation)?;
+        writeln!(f, "concurrent with:")?;
+        for operation in &self.concurrent {
+            writeln!(f, "  {}", operation)?;
+        }
+        writeln!(
+            f,
+            "valid order up to there, ending in state {}:",
+            self.state
+        )?;
+        for operation in &self.linearized {
+            writeln!(f, "  {}", operation)?;
+        }
+        return Ok(());
+    }
+}
+
+/// Check `history` against `model`, every key on its own
+pub fn check<M: Model>(model: &M, history: &History) -> Result<(), Box<Counterexample>> {
+    let mut by_key: BTreeMap<Option<String>, Vec<Operation>> = BTreeMap::new();
+    for operation in history.operations() {
+        let key = operation.key.as_ref().map(|key| key.to_string());
+        by_key.entry(key).or_default().push(operation);
+    }
+    for operations in by_key.values() {
+        check_operations(model, operations)?;
+    }
+    return Ok(());
+}
+
+/// Check operations of a single key
+pub fn check_operations<M: Model>(
+    model: &M,
+    operations: &[Operation],
+) -> Result<(), Box<Counterexample>> {
+    if linearize(model, operations).is_some() {
+        return Ok(());
+    }
+
+    // NOTE: a prefix of a linearizable history is linearizable, so the shortest failing
+    // prefix can be found with a binary search over the event index where it is cut
+    let end = operations
+        .iter()
+        .map(|operation| operation.completed.unwrap_or(operation.invoked) + 1)
+        .max()
+        .unwrap_or(0);
+    let (mut good, mut bad) = (0, end);
+    while bad - good > 1 {
+        let cut = (good + bad) / 2;
+        if linearize(model, &prefix(operations, cut)).is_some() {
+            good = cut;
+        } else {
+            bad = cut;
+        }
+    }
+
+    let culprit = operations
+        .iter()
+        .find(|operation| operation.completed == Some(bad - 1))
+        .cloned()
+        .expect("the shortest failing prefix ends with a completion");
+    let before = prefix(operations, good);
+    let (order, state) = linearize(model, &before).expect("prefix is linearizable");
+    let concurrent = prefix(operations, bad)
+        .into_iter()
+        .filter(|operation| {
+            *operation != culprit
+                && operation.invoked < bad - 1
+                && operation
+                    .completed
+                    .is_none_or(|completed| completed > culprit.invoked)
+        })
+        .collect();
+    return Err(Box::new(Counterexample {
+        key: culprit.key.clone(),
+        operation: culprit,
+        concurrent,
+        linearized: order.into_iter().map(|i| before[i].clone()).collect(),
+        state: format!("{:?}", state),
+    }));
+}
+
+/// The history as it looked right before event `cut`, operations still running become unknown
+fn prefix(operations: &[Operation], cut: usize) -> Vec<Operation> {
+    return operations
+        .iter()
+        .filter(|operation| operation.invoked < cut)
+        .map(|operation| {
+            let mut operation = operation.clone();
+            if operation
+                .completed
+                .is_some_and(|completed| completed >= cut)
+            {
+                operation.completed = None;
+                operation.output = None;
+            }
+            operation
+        })
+        .filter(|operation| !(operation.f == "read" && operation.output.is_none()))
+        .collect();
+}
+
+/// Find a valid order of `operations`
+///
+/// returns:
+///   - the order as indexes into `operations` and the final state, `None` if there is none
+fn linearize<M: Model>(model: &M, operations: &[Operation]) -> Option<(Vec<usize>, M::State)> {
+    let count = operations.len();
+    let required = operations
+        .iter()
+        .filter(|operation| operation.completed.is_some())
+        .count();
+
+    let mut linearized = vec![false; count];
+    let mut bits = vec![0u64; count.div_ceil(64)];
+    let mut required_done = 0;
+    let mut order: Vec<usize> = Vec::new();
+    let mut states = vec![model.init()];
+    // NOTE: next candidate to try at every depth of the search
+    let mut next: Vec<usize> = vec![0];
+    let mut visited: HashSet<(Vec<u64>, M::State)> = HashSet::new();
+
+    loop {
+        if required_done == required {
+            return Some((order, states.pop().unwrap()));
+        }
+
+        // NOTE: an operation can only go next if it was invoked before every pending
+        // operation completed
+        let deadline = (0..count)
+            .filter(|i| !linearized[*i])
+            .filter_map(|i| operations[i].completed)
+            .min()
+            .unwrap_or(usize::MAX);
+        let state = states.last().unwrap();
+        let depth = order.len();
+
+        let mut found = None;
+        for i in next[depth]..count {
+            if linearized[i] || operations[i].invoked >= deadline {
+                continue;
+            }
+            let Some(after) = model.step(state, &operations[i]) else {
+                continue;
+            };
+            bits[i / 64] |= 1 << (i % 64);
+            let unseen = visited.insert((bits.clone(), after.clone()));
+            bits[i / 64] &= !(1 << (i % 64));
+            if unseen {
+                found = Some((i, after));
+                break;
+            }
+        }
+
+        match found {
+            | Some((i, after)) => {
+                next[depth] = i + 1;
+                linearized[i] = true;
+                bits[i / 64] |= 1 << (i % 64);
+                if operations[i].completed.is_some() {
+                    required_done += 1;
+                }
+                order.push(i);
+                states.push(after);
+                next.push(0);
+            },
+            | None => {
+                let i = order.pop()?;
+                next.pop();
+                states.pop();
+                linearized[i] = false;
+                bits[i / 64] &= !(1 << (i % 64));
+                if operations[i].completed.is_some() {
+                    required_done -= 1;
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn event(kind: EventType, process: &str, f: &str, value: Value) -> HistoryEvent {
+        return HistoryEvent {
+            kind,
+            process: process.to_string(),
+            f: f.to_string(),
+            key: None,
+            value,
+            id: None,
+        };
+    }
+
+    fn invoke(process: &str, f: &str, value: Value) -> HistoryEvent {
+        return event(EventType::Invoke, process, f, value);
+    }
+
+    fn ok(process: &str, f: &str, value: Value) -> HistoryEvent {
+        return event(EventType::Ok, process, f, value);
+    }
+
+    /// `f` by `process` that completes before anything else is invoked
+    fn sequential(process: &str, f: &str, input: Value, output: Value) -> [HistoryEvent; 2] {
+        return [invoke(process, f, input), ok(process, f, output)];
+    }
+
+    fn history(events: impl IntoIterator<Item = HistoryEvent>) -> History {
+        return History {
+            events: events.into_iter().collect(),
+        };
+    }
+
+    #[test]
+    fn register() {
+        // NOTE: the read overlaps the write, so it may see either value
+        let concurrent = history([
+            invoke("c1", "write", json!(1)),
+            invoke("c2", "read", Value::Null),
+            invoke("c3", "read", Value::Null),
+            ok("c2", "read", Value::Null),
+            ok("c1", "write", Value::Null),
+            ok("c3", "read", json!(1)),
+        ]);
+        assert!(check(&Register, &concurrent).is_ok());
+
+        let stale = history(
+            [
+                sequential("c1", "write", json!(1), Value::Null),
+                sequential("c2", "read", Value::Null, Value::Null),
+            ]
+            .concat(),
+        );
+        assert!(check(&Register, &stale).is_err());
+    }
+
+    #[test]
+    fn register_with_unknown_outcome() {
+        let mut events = vec![invoke("c1", "write", json!(1))];
+        events.extend(sequential("c2", "read", Value::Null, Value::Null));
+        events.push(event(EventType::Info, "c1", "write", Value::Null));
+        events.extend(sequential("c2", "read", Value::Null, json!(1)));
+        assert!(check(&Register, &history(events.clone())).is_ok());
+
+        // NOTE: once seen, the write can't be undone
+        events.extend(sequential("c2", "read", Value::Null, Value::Null));
+        assert!(check(&Register, &history(events)).is_err());
+    }
+
+    #[test]
+    fn cas_register() {
+        let mut events = sequential("c1", "write", json!(0), Value::Null).to_vec();
+        events.extend([
+            invoke("c1", "cas", json!([0, 1])),
+            invoke("c2", "cas", json!([0, 2])),
+            ok("c1", "cas", Value::Null),
+            event(EventType::Fail, "c2", "cas", Value::Null),
+        ]);
+        events.extend(sequential("c3", "read", Value::Null, json!(1)));
+        assert!(check(&CasRegister, &history(events)).is_ok());
+
+        let both_succeed = history(
+            [
+                sequential("c1", "write", json!(0), Value::Null),
+                sequential("c1", "cas", json!([0, 1]), Value::Null),
+                sequential("c2", "cas", json!([0, 2]), Value::Null),
+            ]
+            .concat(),
+        );
+        assert!(check(&CasRegister, &both_succeed).is_err());
+    }
+
+    #[test]
+    fn counter() {
+        let mut events = sequential("c1", "add", json!(1), Value::Null).to_vec();
+        events.extend([
+            invoke("c2", "read", Value::Null),
+            invoke("c1", "add", json!(2)),
+            ok("c2", "read", json!(3)),
+            ok("c1", "add", Value::Null),
+        ]);
+        assert!(check(&Counter, &history(events)).is_ok());
+
+        let lost_add = history(
+            [
+                sequential("c1", "add", json!(1), Value::Null),
+                sequential("c2", "add", json!(2), Value::Null),
+                sequential("c1", "read", Value::Null, json!(1)),
+            ]
+            .concat(),
+        );
+        assert!(check(&Counter, &lost_add).is_err());
+    }
+
+    #[test]
+    fn set() {
+        let mut events = sequential("c1", "add", json!(1), Value::Null).to_vec();
+        events.extend([
+            invoke("c1", "add", json!(2)),
+            invoke("c2", "read", Value::Null),
+            ok("c2", "read", json!([1])),
+            ok("c1", "add", Value::Null),
+        ]);
+        events.extend(sequential("c2", "read", Value::Null, json!([1, 2])));
+        assert!(check(&Set, &history(events)).is_ok());
+
+        let lost_add = history(
+            [
+                sequential("c1", "add", json!(1), Value::Null),
+                sequential("c2", "add", json!(2), Value::Null),
+                sequential("c1", "read", Value::Null, json!([2])),
+            ]
+            .concat(),
+        );
+        assert!(check(&Set, &lost_add).is_err());
+    }
+
+    #[test]
+    fn keys_are_checked_on_their_own() {
+        let mut events = [
+            sequential("c1", "write", json!(1), Value::Null),
+            sequential("c2", "read", Value::Null, Value::Null),
+        ]
+        .concat();
+        events[0].key = Some(json!("x"));
+        events[1].key = Some(json!("x"));
+        events[2].key = Some(json!("y"));
+        events[3].key = Some(json!("y"));
+        assert!(check(&Register, &history(events.clone())).is_ok());
+
+        events[2].key = Some(json!("x"));
+        events[3].key = Some(json!("x"));
+        let counterexample = check(&Register, &history(events)).unwrap_err();
+        assert_eq!(counterexample.key, Some(json!("x")));
+    }
+
+    #[test]
+    fn counterexample_is_minimal() {
+        let events = [
+            sequential("c1", "write", json!(1), Value::Null).to_vec(),
+            vec![
+                invoke("c2", "write", json!(2)),
+                invoke("c3", "read", Value::Null),
+                ok("c2", "write", Value::Null),
+                ok("c3", "read", json!(1)),
+            ],
+            // NOTE: stale, the write of 2 completed before this read was invoked
+            sequential("c1", "read", Value::Null, json!(1)).to_vec(),
+            sequential("c2", "write", json!(3), Value::Null).to_vec(),
+            sequential("c3", "read", Value::Null, json!(2)).to_vec(),
+        ]
+        .concat();
+        let counterexample = check(&Register, &history(events.clone())).unwrap_err();
+
+        let completed = counterexample.operation.completed.unwrap();
+        assert_eq!(completed, 7);
+        assert_eq!(counterexample.operation.process, "c1");
+        assert!(check(&Register, &history(events[..completed].to_vec())).is_ok());
+        assert!(check(&Register, &history(events[..=completed].to_vec())).is_err());
+
+        assert!(counterexample.concurrent.is_empty());
+        let mut state = Register.init();
+        for operation in &counterexample.linearized {
+            state = Register.step(&state, operation).unwrap();
+        }
+        assert_eq!(format!("{:?}", state), counterexample.state);
+        assert_eq!(state, Some("2".to_string()));
+        assert_eq!(counterexample.linearized.len(), 3);
+    }
+}
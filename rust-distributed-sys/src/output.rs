@@ -0,0 +1,127 @@
+// Purpose: batch the lines a node writes into few large writes.
+//
+// `Output` is what `event_loop` hands to `Node::step` as its `dyn Write`. Messages are serialized
+// into one reusable buffer and reach the real output according to a `FlushPolicy`. Whatever the
+// policy, the event loop flushes before it waits for the next event, so a reply is never left
+// sitting in the buffer while the node is idle.
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::bail;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// write every message as soon as its line is complete
+    EveryMessage,
+    /// write everything a step produced at the end of the step
+    #[default]
+    EveryStep,
+    /// keep collecting across steps until a limit is reached or the node goes idle
+    Batch {
+        max_messages: usize,
+        max_bytes: usize,
+    },
+}
+
+impl FromStr for FlushPolicy {
+    type Err = anyhow::Error;
+
+    /// `message`, `step` or `batch`, optionally `batch:<max messages>:<max bytes>`
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let mut parts = policy.split(':');
+        return match (parts.next(), parts.next(), parts.next()) {
+            | (Some("message"), None, None) => Ok(FlushPolicy::EveryMessage),
+            | (Some("step"), None, None) => Ok(FlushPolicy::EveryStep),
+            | (Some("batch"), None, None) => Ok(FlushPolicy::Batch {
+                max_messages: 64,
+                max_bytes: 64 * 1024,
+            }),
+            | (Some("batch"), Some(max_messages), Some(max_bytes)) => Ok(FlushPolicy::Batch {
+                max_messages: max_messages.parse()?,
+                max_bytes: max_bytes.parse()?,
+            }),
+            | _ => bail!(
+                "unknown flush policy {}, expected message, step or batch",
+                policy
+            ),
+        };
+    }
+}
+
+/// Buffered line writer with an explicit flush policy
+#[derive(Debug)]
+pub struct Output<W: Write> {
+    inner: W,
+    policy: FlushPolicy,
+    buffer: Vec<u8>,
+    /// complete lines in `buffer`
+    pending: usize,
+}
+
+impl<W: Write> Output<W> {
+    pub fn new(inner: W, policy: FlushPolicy) -> Self {
+        return Output {
+            inner,
+            policy,
+            buffer: Vec::with_capacity(8 * 1024),
+            pending: 0,
+        };
+    }
+
+    pub fn policy(&self) -> FlushPolicy {
+        return self.policy;
+    }
+
+    /// Complete lines that haven't been written yet
+    pub fn pending(&self) -> usize {
+        return self.pending;
+    }
+
+    /// Called by the event loop after every step
+    pub fn end_step(&mut self) -> io::Result<()> {
+        return match self.policy {
+            | FlushPolicy::EveryMessage | FlushPolicy::EveryStep => self.flush(),
+            | FlushPolicy::Batch { .. } => Ok(()),
+        };
+    }
+
+    fn batch_full(&self) -> bool {
+        return match self.policy {
+            | FlushPolicy::EveryMessage => self.pending > 0,
+            | FlushPolicy::EveryStep => false,
+            | FlushPolicy::Batch {
+                max_messages,
+                max_bytes,
+            } => self.pending >= max_messages || self.buffer.len() >= max_bytes,
+        };
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        // NOTE: serialized JSON escapes newlines, every newline ends a message
+        let lines = buf.iter().filter(|byte| **byte == b'\n').count();
+        self.pending += lines;
+        if lines > 0 && self.batch_full() {
+            self.flush()?;
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.inner.write_all(&self.buffer)?;
+            // NOTE: `clear` keeps the allocation for the next batch
+            self.buffer.clear();
+            self.pending = 0;
+        }
+        return self.inner.flush();
+    }
+}
+
+impl<W: Write> Drop for Output<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
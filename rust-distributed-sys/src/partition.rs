@@ -0,0 +1,447 @@
+// Purpose: give every key an owner by consistent hashing and proxy requests to it.
+//
+// `HashRing` places `virtual_nodes` tokens per node on a 64 bit ring; a key belongs to the node
+// of the first token at or after its hash, its replicas are the next distinct nodes clockwise.
+// Every node builds the same ring from `InitNodes::node_ids`, so they agree on owners without
+// talking, and a node joining or leaving only moves the keys next to its own tokens.
+//
+// `Forwarder` proxies a request to another node under a `msg_id` of our own and hands the reply
+// back to the client that asked, `PartitionedNode` puts both together: a node that serves the keys
+// it owns and forwards everything else, so clients can talk to any node.
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::kv::{KvPayload, KvStore};
+use crate::queue::EventSender;
+use crate::topology::natural_order;
+use crate::{Body, ErrorCode, Event, InitNodes, Message, Node};
+
+#[derive(Debug, Clone)]
+pub struct RingConfig {
+    /// tokens per node, more tokens spread the keys more evenly
+    pub virtual_nodes: usize,
+    /// nodes that hold a copy of every key, the owner included
+    pub replication: usize,
+}
+
+impl Default for RingConfig {
+    fn default() -> Self {
+        return RingConfig {
+            virtual_nodes: 64,
+            replication: 3,
+        };
+    }
+}
+
+/// FNV-1a with a splitmix64 finalizer: stable across processes and builds, unlike `DefaultHasher`
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    // NOTE: FNV alone clusters similar keys like `n1#1`, `n1#2` on the ring
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return hash ^ (hash >> 31);
+}
+
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    nodes: Vec<String>,
+    tokens: BTreeMap<u64, String>,
+    replication: usize,
+}
+
+impl HashRing {
+    pub fn new(
+        node_ids: impl IntoIterator<Item = impl AsRef<str>>,
+        config: &RingConfig,
+    ) -> anyhow::Result<Self> {
+        let mut nodes: Vec<String> = node_ids
+            .into_iter()
+            .map(|node_id| node_id.as_ref().to_string())
+            .collect();
+        // NOTE: a fixed order, so that every node resolves token collisions the same way
+        nodes.sort_by(|a, b| natural_order(a, b));
+        nodes.dedup();
+        anyhow::ensure!(!nodes.is_empty(), "a hash ring needs at least one node");
+        anyhow::ensure!(config.virtual_nodes > 0, "a hash ring needs virtual nodes");
+        anyhow::ensure!(
+            config.replication > 0,
+            "the replication factor must be at least 1"
+        );
+
+        let mut tokens = BTreeMap::new();
+        for node in &nodes {
+            for index in 0..config.virtual_nodes {
+                let token = hash(format!("{}#{}", node, index).as_bytes());
+                tokens.entry(token).or_insert_with(|| node.clone());
+            }
+        }
+        return Ok(HashRing {
+            nodes,
+            tokens,
+            replication: config.replication,
+        });
+    }
+
+    pub fn from_init(init: &InitNodes, config: &RingConfig) -> anyhow::Result<Self> {
+        return HashRing::new(&init.node_ids, config);
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        return &self.nodes;
+    }
+
+    /// Every node in the order they are met walking the ring clockwise from `key`
+    ///
+    /// NOTE: KV keys are arbitrary JSON, pass their JSON text like `kv::KvStore` does
+    pub fn successors(&self, key: impl AsRef<[u8]>) -> Vec<&str> {
+        let position = hash(key.as_ref());
+        let mut successors: Vec<&str> = Vec::with_capacity(self.nodes.len());
+        for node in self
+            .tokens
+            .range(position..)
+            .chain(self.tokens.range(..position))
+            .map(|(_, node)| node.as_str())
+        {
+            if successors.len() == self.nodes.len() {
+                break;
+            }
+            if !successors.contains(&node) {
+                successors.push(node);
+            }
+        }
+        return successors;
+    }
+
+    /// Nodes that hold `key`, the owner first, at most `replication` of them
+    pub fn replicas(&self, key: impl AsRef<[u8]>) -> Vec<&str> {
+        let mut replicas = self.successors(key);
+        replicas.truncate(self.replication);
+        return replicas;
+    }
+
+    /// Nodes that hold a copy of every key, the owner included
+    pub fn replication(&self) -> usize {
+        return self.replication.min(self.nodes.len());
+    }
+
+    /// The node responsible for `key`
+    pub fn owner(&self, key: impl AsRef<[u8]>) -> &str {
+        // IMPORTANT: `new` ensures the ring isn't empty, so there always is a first replica
+        return self.replicas(key)[0];
+    }
+
+    pub fn is_replica(&self, node_id: &str, key: impl AsRef<[u8]>) -> bool {
+        return self.replicas(key).contains(&node_id);
+    }
+
+    /// Fraction of the hash space each node owns
+    pub fn shares(&self) -> BTreeMap<&str, f64> {
+        let mut shares: BTreeMap<&str, f64> =
+            self.nodes.iter().map(|node| (node.as_str(), 0.0)).collect();
+        let mut previous = self.tokens.keys().next_back().copied().unwrap_or(0);
+        for (token, node) in &self.tokens {
+            // NOTE: a token owns the arc from the previous token, the first one wraps around
+            let arc = token.wrapping_sub(previous);
+            *shares.entry(node.as_str()).or_default() += arc as f64 / u64::MAX as f64;
+            previous = *token;
+        }
+        return shares;
+    }
+}
+
+/// A request proxied to its owner, `(client, in_reply_to, sent)`
+type Forwarded = (String, Option<usize>, Instant);
+
+/// Requests proxied to other nodes, keyed by the `msg_id` we sent them with
+#[derive(Debug)]
+pub struct Forwarder {
+    timeout: Duration,
+    forwarded: HashMap<usize, Forwarded>,
+}
+
+impl Forwarder {
+    /// args:
+    ///   - `timeout`: how long to wait for the owner before giving up, see `expire`
+    pub fn new(timeout: Duration) -> Self {
+        return Forwarder {
+            timeout,
+            forwarded: HashMap::new(),
+        };
+    }
+
+    /// Send `request` on to `owner`, its reply goes back to the sender of `request`
+    pub fn forward<Payload: Serialize>(
+        &mut self,
+        request: Message<Payload>,
+        owner: &str,
+        local_id: &mut usize,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let id = *local_id;
+        *local_id += 1;
+        self.forwarded
+            .insert(id, (request.src, request.body.id, now));
+        return Message {
+            src: request.dest,
+            dest: owner.to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                clock: None,
+                payload: request.body.payload,
+            },
+        }
+        .send(&mut *output, "forward to owner")
+        .with_context(|| format!("forwarding msg {} to {}", id, owner));
+    }
+
+    /// Hand `reply` to the client whose request it answers
+    ///
+    /// returns:
+    ///   - `reply` untouched if it doesn't answer a forwarded request
+    pub fn relay<Payload: Serialize>(
+        &mut self,
+        reply: Message<Payload>,
+        local_id: &mut usize,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<Message<Payload>>> {
+        let Some((client, in_reply_to, _)) = reply
+            .body
+            .in_reply_to
+            .and_then(|id| self.forwarded.remove(&id))
+        else {
+            return Ok(Some(reply));
+        };
+        let id = *local_id;
+        *local_id += 1;
+        Message {
+            src: reply.dest,
+            dest: client,
+            body: Body {
+                id: Some(id),
+                in_reply_to,
+                clock: None,
+                payload: reply.body.payload,
+            },
+        }
+        .send(&mut *output, "forwarded reply")?;
+        return Ok(None);
+    }
+
+    /// Forget requests the owner didn't answer within the timeout
+    ///
+    /// returns:
+    ///   - `(client, in_reply_to)` of each, to answer them with an error
+    pub fn expire(&mut self, now: Instant) -> Vec<(String, Option<usize>)> {
+        let timeout = self.timeout;
+        let mut expired = Vec::new();
+        self.forwarded.retain(|_, (client, in_reply_to, sent)| {
+            if now.saturating_duration_since(*sent) < timeout {
+                return true;
+            }
+            expired.push((client.clone(), *in_reply_to));
+            return false;
+        });
+        return expired;
+    }
+
+    /// Requests still waiting for their owner
+    pub fn len(&self) -> usize {
+        return self.forwarded.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.forwarded.is_empty();
+    }
+}
+
+/// Single key state machine that `PartitionedNode` splits over the ring
+pub trait PartitionedService: Default {
+    /// client requests and their replies
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+
+    /// Key of a request as it is hashed, `None` for replies
+    fn key(payload: &Self::Payload) -> Option<String>;
+
+    /// Execute a request for a key this node owns
+    fn apply(&mut self, request: &Self::Payload) -> Self::Payload;
+
+    fn error(code: ErrorCode, text: String) -> Self::Payload;
+}
+
+impl PartitionedService for KvStore {
+    type Payload = KvPayload;
+
+    fn key(payload: &KvPayload) -> Option<String> {
+        return payload.key().map(|key| key.to_string());
+    }
+
+    fn apply(&mut self, request: &KvPayload) -> KvPayload {
+        return KvStore::apply(self, request);
+    }
+
+    fn error(code: ErrorCode, text: String) -> KvPayload {
+        return KvPayload::error(code, text);
+    }
+}
+
+/// Timer event that gives up on forwarded requests
+#[derive(Debug)]
+pub struct ExpireTick;
+
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    pub ring: RingConfig,
+    /// how long a forwarded request may take before the client gets a timeout error
+    pub forward_timeout: Duration,
+}
+
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        return PartitionConfig {
+            ring: RingConfig::default(),
+            forward_timeout: Duration::from_secs(1),
+        };
+    }
+}
+
+/// Node that serves the keys it owns from its own `S` and forwards the rest to their owners
+///
+/// NOTE: only the owner keeps a key, `RingConfig::replication` is for services that replicate
+pub struct PartitionedNode<S: PartitionedService> {
+    node_id: String,
+    local_id: usize,
+    ring: HashRing,
+    forwarder: Forwarder,
+    service: S,
+    /// requests applied here and requests forwarded, for `status`
+    served: u64,
+    forwarded: u64,
+}
+
+impl<S: PartitionedService> PartitionedNode<S> {
+    pub fn ring(&self) -> &HashRing {
+        return &self.ring;
+    }
+
+    pub fn service(&self) -> &S {
+        return &self.service;
+    }
+
+    fn answer(
+        &mut self,
+        dest: String,
+        in_reply_to: Option<usize>,
+        payload: S::Payload,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let id = self.local_id;
+        self.local_id += 1;
+        return Message {
+            src: self.node_id.clone(),
+            dest,
+            body: Body {
+                id: Some(id),
+                in_reply_to,
+                clock: None,
+                payload,
+            },
+        }
+        .send(&mut *output, "partitioned request");
+    }
+}
+
+impl<S: PartitionedService> Node<PartitionConfig, S::Payload, ExpireTick> for PartitionedNode<S> {
+    fn from_init(
+        config: PartitionConfig,
+        init: InitNodes,
+        sender: EventSender<S::Payload, ExpireTick>,
+    ) -> anyhow::Result<Self> {
+        let ring = HashRing::from_init(&init, &config.ring)?;
+
+        let node_id = init.node_id.clone();
+        let interval = config.forward_timeout / 4;
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: ExpireTick,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        return Ok(PartitionedNode {
+            node_id: init.node_id,
+            local_id: 1,
+            ring,
+            forwarder: Forwarder::new(config.forward_timeout),
+            service: S::default(),
+            served: 0,
+            forwarded: 0,
+        });
+    }
+
+    fn step(
+        &mut self,
+        event: Event<S::Payload, ExpireTick>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        match event {
+            | Event::EndOfMessages => {},
+            | Event::GeneratedEvent(_) => {
+                for (client, in_reply_to) in self.forwarder.expire(now) {
+                    let error = S::error(
+                        ErrorCode::Timeout,
+                        "the owner of the key didn't answer".to_string(),
+                    );
+                    self.answer(client, in_reply_to, error, output)?;
+                }
+            },
+            | Event::Message(message) => {
+                let Some(key) = S::key(&message.body.payload) else {
+                    // NOTE: replies only ever come back for requests we forwarded
+                    self.forwarder.relay(message, &mut self.local_id, output)?;
+                    return Ok(());
+                };
+                let owner = self.ring.owner(&key).to_string();
+                if owner == self.node_id {
+                    self.served += 1;
+                    let response = self.service.apply(&message.body.payload);
+                    self.answer(message.src, message.body.id, response, output)?;
+                } else {
+                    self.forwarded += 1;
+                    self.forwarder
+                        .forward(message, &owner, &mut self.local_id, now, output)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> serde_json::Value {
+        return serde_json::json!({
+            "share": self.ring.shares().get(self.node_id.as_str()),
+            "served": self.served,
+            "forwarded": self.forwarded,
+            "waiting_for_owner": self.forwarder.len(),
+        });
+    }
+}
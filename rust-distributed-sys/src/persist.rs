@@ -0,0 +1,292 @@
+// Purpose: keep node state on disk so a node that crashes comes back with what it acknowledged.
+//
+// A `Store` lives in its own directory per node and holds two files: `snapshot.json`, the whole
+// state up to some sequence number, and `wal.jsonl`, one line per change after it. Recovery
+// loads the snapshot and replays the log on top. Every `snapshot_every` entries the state is
+// snapshotted again and the log starts over.
+//
+// IMPORTANT: append the change *before* acknowledging it, an ack for state that only lived in
+// memory is a promise the restarted node can't keep
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+const SNAPSHOT: &str = "snapshot.json";
+const WAL: &str = "wal.jsonl";
+
+/// State that can be rebuilt from a snapshot and a log of changes
+pub trait Durable: Default + Serialize + DeserializeOwned {
+    type Entry: Serialize + DeserializeOwned;
+
+    /// Replay one logged change
+    fn apply(&mut self, entry: &Self::Entry);
+}
+
+#[derive(Debug, Clone)]
+pub struct PersistConfig {
+    /// every node keeps its files in `dir/<node_id>`
+    pub dir: PathBuf,
+    /// log entries between two snapshots
+    pub snapshot_every: usize,
+    /// fsync every append, survives losing the machine and not only the process
+    ///
+    /// NOTE: Maelstrom and `sim` only kill the process, the OS still writes its buffers then
+    pub sync: bool,
+}
+
+impl PersistConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        return PersistConfig {
+            dir: dir.into(),
+            snapshot_every: 1000,
+            sync: false,
+        };
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Snapshot<S> {
+    /// sequence number of the last entry included in `state`
+    seq: u64,
+    state: S,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WalEntry<E> {
+    seq: u64,
+    entry: E,
+}
+
+pub struct Store<D: Durable> {
+    dir: PathBuf,
+    wal: File,
+    /// sequence number of the last entry written
+    seq: u64,
+    since_snapshot: usize,
+    snapshot_every: usize,
+    sync: bool,
+    state: PhantomData<D>,
+}
+
+impl<D: Durable> Store<D> {
+    /// Open the store of `node_id`, recovering whatever an earlier run left behind
+    ///
+    /// returns:
+    ///   - the store and the recovered state, `D::default()` on the first start
+    pub fn open(config: &PersistConfig, node_id: &str) -> anyhow::Result<(Self, D)> {
+        let dir = config.dir.join(node_id);
+        fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        let (state, seq) = recover::<D>(&dir)?;
+
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(WAL))
+            .with_context(|| format!("opening log in {}", dir.display()))?;
+        let mut store = Store {
+            dir,
+            wal,
+            seq,
+            since_snapshot: 0,
+            snapshot_every: config.snapshot_every,
+            sync: config.sync,
+            state: PhantomData,
+        };
+        // NOTE: also compacts the log and cuts off a line torn by the crash
+        store.snapshot(&state)?;
+        return Ok((store, state));
+    }
+
+    /// Log `entry`, it is durable once this returns
+    pub fn append(&mut self, entry: &D::Entry) -> anyhow::Result<()> {
+        self.seq += 1;
+        let mut line = serde_json::to_vec(&WalEntry {
+            seq: self.seq,
+            entry,
+        })
+        .context("serializing log entry")?;
+        line.push(b'\n');
+        // IMPORTANT: one write per entry, a crash can only tear the last line
+        self.wal.write_all(&line).context("appending to log")?;
+        if self.sync {
+            self.wal.sync_data().context("syncing log")?;
+        }
+        self.since_snapshot += 1;
+        return Ok(());
+    }
+
+    /// `append` and snapshot `state` (which already includes `entry`) when the log is long enough
+    pub fn record(&mut self, state: &D, entry: &D::Entry) -> anyhow::Result<()> {
+        self.append(entry)?;
+        if self.since_snapshot >= self.snapshot_every {
+            self.snapshot(state)?;
+        }
+        return Ok(());
+    }
+
+    /// Write `state` as the new snapshot and empty the log
+    pub fn snapshot(&mut self, state: &D) -> anyhow::Result<()> {
+        let snapshot = Snapshot {
+            seq: self.seq,
+            state,
+        };
+        let temp = self.dir.join(format!("{}.tmp", SNAPSHOT));
+        let mut file = File::create(&temp).context("creating snapshot")?;
+        serde_json::to_writer(&mut file, &snapshot).context("serializing snapshot")?;
+        file.sync_all().context("syncing snapshot")?;
+        // NOTE: rename is atomic, a crash leaves either the old or the new snapshot
+        fs::rename(&temp, self.dir.join(SNAPSHOT)).context("replacing snapshot")?;
+        // NOTE: a crash before this point only leaves entries the snapshot already covers
+        self.wal.set_len(0).context("truncating log")?;
+        self.since_snapshot = 0;
+        return Ok(());
+    }
+}
+
+/// Load the snapshot in `dir` and replay the log entries that came after it
+fn recover<D: Durable>(dir: &Path) -> anyhow::Result<(D, u64)> {
+    let (mut state, mut seq) = match fs::read_to_string(dir.join(SNAPSHOT)) {
+        | Ok(text) => {
+            let snapshot: Snapshot<D> = serde_json::from_str(&text)
+                .with_context(|| format!("corrupt snapshot in {}", dir.display()))?;
+            (snapshot.state, snapshot.seq)
+        },
+        | Err(error) if error.kind() == std::io::ErrorKind::NotFound => (D::default(), 0),
+        | Err(error) => return Err(error).context("reading snapshot"),
+    };
+
+    let Ok(wal) = File::open(dir.join(WAL)) else {
+        return Ok((state, seq));
+    };
+    let mut lines = BufReader::new(wal).lines().peekable();
+    while let Some(line) = lines.next() {
+        let line = line.context("reading log")?;
+        let entry: WalEntry<D::Entry> = match serde_json::from_str(&line) {
+            | Ok(entry) => entry,
+            // NOTE: the last append was cut short, it was never acknowledged
+            | Err(_) if lines.peek().is_none() => break,
+            | Err(error) => bail!("corrupt log in {}: {}", dir.display(), error),
+        };
+        if entry.seq <= seq {
+            continue;
+        }
+        state.apply(&entry.entry);
+        seq = entry.seq;
+    }
+    return Ok((state, seq));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every entry ever applied, in order
+    #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Applied {
+        entries: Vec<u64>,
+    }
+
+    impl Durable for Applied {
+        type Entry = u64;
+
+        fn apply(&mut self, entry: &u64) {
+            self.entries.push(*entry);
+        }
+    }
+
+    /// Config for a fresh directory, the store of node `n0` lives in `dir/n0`
+    fn config(name: &str, snapshot_every: usize) -> PersistConfig {
+        let dir = std::env::temp_dir().join(format!("persist-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        return PersistConfig {
+            snapshot_every,
+            ..PersistConfig::new(dir)
+        };
+    }
+
+    fn write_files(config: &PersistConfig, snapshot: Option<&str>, wal: &str) {
+        let dir = config.dir.join("n0");
+        fs::create_dir_all(&dir).unwrap();
+        if let Some(snapshot) = snapshot {
+            fs::write(dir.join(SNAPSHOT), snapshot).unwrap();
+        }
+        fs::write(dir.join(WAL), wal).unwrap();
+    }
+
+    fn open(config: &PersistConfig) -> anyhow::Result<(Store<Applied>, Applied)> {
+        return Store::open(config, "n0");
+    }
+
+    #[test]
+    fn recovers_snapshot_and_log() {
+        let config = config("replay", 2);
+        let (mut store, mut state) = open(&config).unwrap();
+        assert_eq!(state, Applied::default());
+        for entry in 1..=5 {
+            state.apply(&entry);
+            store.record(&state, &entry).unwrap();
+        }
+        drop(store);
+        // NOTE: snapshots after 2 and 4 entries, the fifth only in the log
+        let wal = fs::read_to_string(config.dir.join("n0").join(WAL)).unwrap();
+        assert_eq!(wal.lines().count(), 1);
+
+        let (mut store, state) = open(&config).unwrap();
+        assert_eq!(state.entries, vec![1, 2, 3, 4, 5]);
+        // NOTE: sequence numbers go on where the last run stopped
+        store.append(&6).unwrap();
+        drop(store);
+        let (_, state) = open(&config).unwrap();
+        assert_eq!(state.entries, vec![1, 2, 3, 4, 5, 6]);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn skips_entries_the_snapshot_has() {
+        let config = config("skip", 1000);
+        // NOTE: a crash after the snapshot was replaced but before the log was truncated
+        let snapshot = r#"{"seq": 2, "state": {"entries": [1, 2]}}"#;
+        let wal = "{\"seq\":1,\"entry\":1}\n{\"seq\":2,\"entry\":2}\n{\"seq\":3,\"entry\":3}\n";
+        write_files(&config, Some(snapshot), wal);
+        let (_, state) = open(&config).unwrap();
+        assert_eq!(state.entries, vec![1, 2, 3]);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn drops_a_torn_last_line() {
+        let config = config("torn", 1000);
+        write_files(&config, None, "{\"seq\":1,\"entry\":1}\n{\"seq\":2,\"en");
+        let (mut store, state) = open(&config).unwrap();
+        assert_eq!(state.entries, vec![1]);
+
+        // NOTE: opening compacted the torn line away, the next entry isn't glued to it
+        store.append(&2).unwrap();
+        drop(store);
+        let (_, state) = open(&config).unwrap();
+        assert_eq!(state.entries, vec![1, 2]);
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_corrupt_line_in_the_middle() {
+        let config = config("corrupt", 1000);
+        let wal = "{\"seq\":1,\"entry\":1}\nnot json\n{\"seq\":3,\"entry\":3}\n";
+        write_files(&config, None, wal);
+        let error = open(&config).err().unwrap();
+        assert!(error.to_string().contains("corrupt log"), "{:#}", error);
+
+        write_files(&config, Some("{\"seq\": 1"), "");
+        let error = open(&config).err().unwrap();
+        assert!(
+            error.to_string().contains("corrupt snapshot"),
+            "{:#}",
+            error
+        );
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
@@ -0,0 +1,550 @@
+// Purpose: bounded event queue between the input thread, timer threads and the node.
+//
+// Events are split into lanes: timer events, messages from peers and messages from clients
+// (Maelstrom's `c1`, `c2`, ...). Every lane has its own capacity and overflow behaviour, so a
+// flood of client requests can neither grow memory without limit nor delay peer traffic. The
+// node takes events by priority: timers, then peers, then clients, but a lane that was passed
+// over `starvation_limit` times in a row goes first, so clients are served even under a steady
+// stream of peer messages.
+//
+// IMPORTANT: all lanes are fed by the same stdin thread, a lane that blocks holds back every
+// message behind it in stdin, those of the other lanes too. That's why only the peer lane blocks
+// by default: a full client lane drops the request, the client times out and may retry, while
+// the peers' messages keep flowing.
+use std::collections::VecDeque;
+use std::sync::mpsc::{SendError, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::status::StatusPayload;
+use crate::{is_client, Event, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Timer,
+    Peer,
+    Client,
+}
+
+impl Lane {
+    /// Lanes in the order they are served
+    const PRIORITY: [Lane; 3] = [Lane::Timer, Lane::Peer, Lane::Client];
+
+    fn of<Payload, GeneratedPayload>(event: &Event<Payload, GeneratedPayload>) -> Self {
+        return match event {
+            | Event::Message(message) if is_client(&message.src) => Lane::Client,
+            | Event::Message(_) => Lane::Peer,
+            | Event::GeneratedEvent(_) | Event::EndOfMessages => Lane::Timer,
+        };
+    }
+
+    fn index(&self) -> usize {
+        return match self {
+            | Lane::Timer => 0,
+            | Lane::Peer => 1,
+            | Lane::Client => 2,
+        };
+    }
+}
+
+/// What a sender does when its lane is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// wait until the node made room
+    Block,
+    /// drop the event being sent
+    DropNewest,
+    /// drop the oldest queued event of the lane to make room
+    DropOldest,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LaneConfig {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QueueConfig {
+    pub timer: LaneConfig,
+    pub peer: LaneConfig,
+    pub client: LaneConfig,
+    /// a waiting lane is served ahead of the priority order once this many events of other lanes
+    /// went first, `usize::MAX` for strict priority
+    pub starvation_limit: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        return QueueConfig {
+            // NOTE: ticks are interchangeable, a node that is behind only needs one of them
+            timer: LaneConfig {
+                capacity: 16,
+                overflow: Overflow::DropNewest,
+            },
+            peer: LaneConfig {
+                capacity: 4096,
+                overflow: Overflow::Block,
+            },
+            client: LaneConfig {
+                capacity: 1024,
+                overflow: Overflow::DropNewest,
+            },
+            starvation_limit: 16,
+        };
+    }
+}
+
+impl QueueConfig {
+    fn lane(&self, lane: Lane) -> LaneConfig {
+        return match lane {
+            | Lane::Timer => self.timer,
+            | Lane::Peer => self.peer,
+            | Lane::Client => self.client,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LaneMetrics {
+    pub enqueued: u64,
+    pub dropped: u64,
+    /// sends that had to wait for room
+    pub blocked: u64,
+    /// events waiting right now
+    pub depth: usize,
+    pub max_depth: usize,
+    /// events served ahead of their priority because the lane had waited `starvation_limit` times
+    pub promoted: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueMetrics {
+    pub timer: LaneMetrics,
+    pub peer: LaneMetrics,
+    pub client: LaneMetrics,
+}
+
+impl QueueMetrics {
+    fn lane_mut(&mut self, lane: Lane) -> &mut LaneMetrics {
+        return match lane {
+            | Lane::Timer => &mut self.timer,
+            | Lane::Peer => &mut self.peer,
+            | Lane::Client => &mut self.client,
+        };
+    }
+}
+
+struct Lanes<Payload, GeneratedPayload> {
+    queues: [VecDeque<Event<Payload, GeneratedPayload>>; 3],
+    /// events of other lanes served since each lane's head event was waiting
+    passed_over: [usize; 3],
+    metrics: QueueMetrics,
+    /// `EndOfMessages` was sent, it is handed out once every lane is empty
+    end_of_messages: bool,
+    /// answered by the event loop itself, ahead of every lane, see `status`
+    status_requests: VecDeque<Message<StatusPayload>>,
+    senders: usize,
+    receiver_alive: bool,
+}
+
+struct Shared<Payload, GeneratedPayload> {
+    config: QueueConfig,
+    lanes: Mutex<Lanes<Payload, GeneratedPayload>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+}
+
+/// Sending half, cloned into the input thread and every timer thread
+pub struct EventSender<Payload, GeneratedPayload> {
+    shared: Arc<Shared<Payload, GeneratedPayload>>,
+}
+
+/// Receiving half, owned by the event loop
+pub struct EventReceiver<Payload, GeneratedPayload> {
+    shared: Arc<Shared<Payload, GeneratedPayload>>,
+}
+
+pub fn event_queue<Payload, GeneratedPayload>(
+    config: QueueConfig,
+) -> (
+    EventSender<Payload, GeneratedPayload>,
+    EventReceiver<Payload, GeneratedPayload>,
+) {
+    let shared = Arc::new(Shared {
+        config,
+        lanes: Mutex::new(Lanes {
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            passed_over: [0; 3],
+            metrics: QueueMetrics::default(),
+            end_of_messages: false,
+            status_requests: VecDeque::new(),
+            senders: 1,
+            receiver_alive: true,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+    return (
+        EventSender {
+            shared: shared.clone(),
+        },
+        EventReceiver { shared },
+    );
+}
+
+impl<Payload, GeneratedPayload> EventSender<Payload, GeneratedPayload> {
+    /// Queue `event` in its lane, see `Overflow` for what happens if the lane is full
+    ///
+    /// returns:
+    ///   - `Err` with the event once the receiver is gone, like `mpsc::Sender::send`
+    pub fn send(
+        &self,
+        event: Event<Payload, GeneratedPayload>,
+    ) -> Result<(), SendError<Event<Payload, GeneratedPayload>>> {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        if !lanes.receiver_alive {
+            return Err(SendError(event));
+        }
+        if matches!(event, Event::EndOfMessages) {
+            lanes.end_of_messages = true;
+            self.shared.not_empty.notify_one();
+            return Ok(());
+        }
+
+        let lane = Lane::of(&event);
+        let config = self.shared.config.lane(lane);
+        let mut waited = false;
+        while lanes.queues[lane.index()].len() >= config.capacity {
+            match config.overflow {
+                | Overflow::Block => {
+                    if !waited {
+                        lanes.metrics.lane_mut(lane).blocked += 1;
+                        waited = true;
+                    }
+                    lanes = self.shared.not_full.wait(lanes).unwrap();
+                    if !lanes.receiver_alive {
+                        return Err(SendError(event));
+                    }
+                },
+                | Overflow::DropNewest => {
+                    lanes.metrics.lane_mut(lane).dropped += 1;
+                    return Ok(());
+                },
+                | Overflow::DropOldest => {
+                    lanes.queues[lane.index()].pop_front();
+                    lanes.metrics.lane_mut(lane).dropped += 1;
+                },
+            }
+        }
+
+        lanes.queues[lane.index()].push_back(event);
+        let depth = lanes.queues[lane.index()].len();
+        let metrics = lanes.metrics.lane_mut(lane);
+        metrics.enqueued += 1;
+        metrics.depth = depth;
+        metrics.max_depth = metrics.max_depth.max(depth);
+        self.shared.not_empty.notify_one();
+        return Ok(());
+    }
+
+    /// Queue a status request for the event loop, it bypasses the lanes and is never dropped
+    pub(crate) fn send_status(&self, request: Message<StatusPayload>) {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        lanes.status_requests.push_back(request);
+        self.shared.not_empty.notify_one();
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        return self.shared.lanes.lock().unwrap().metrics;
+    }
+}
+
+impl<Payload, GeneratedPayload> Clone for EventSender<Payload, GeneratedPayload> {
+    fn clone(&self) -> Self {
+        self.shared.lanes.lock().unwrap().senders += 1;
+        return EventSender {
+            shared: self.shared.clone(),
+        };
+    }
+}
+
+impl<Payload, GeneratedPayload> Drop for EventSender<Payload, GeneratedPayload> {
+    fn drop(&mut self) {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        lanes.senders -= 1;
+        if lanes.senders == 0 {
+            self.shared.not_empty.notify_all();
+        }
+    }
+}
+
+impl<Payload, GeneratedPayload> EventReceiver<Payload, GeneratedPayload> {
+    /// Next event by priority (see `QueueConfig::starvation_limit`) without waiting
+    pub fn try_recv(&self) -> Result<Event<Payload, GeneratedPayload>, TryRecvError> {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        return self.pop(&mut lanes);
+    }
+
+    /// Next event by priority, waits while all lanes are empty
+    ///
+    /// returns:
+    ///   - `None` once every sender is gone and nothing is left
+    pub fn recv(&self) -> Option<Event<Payload, GeneratedPayload>> {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        loop {
+            match self.pop(&mut lanes) {
+                | Ok(event) => return Some(event),
+                | Err(TryRecvError::Disconnected) => return None,
+                | Err(TryRecvError::Empty) => {
+                    lanes = self.shared.not_empty.wait(lanes).unwrap();
+                },
+            }
+        }
+    }
+
+    pub(crate) fn try_recv_status(&self) -> Option<Message<StatusPayload>> {
+        return self
+            .shared
+            .lanes
+            .lock()
+            .unwrap()
+            .status_requests
+            .pop_front();
+    }
+
+    /// Wait until there is an event or a status request, or every sender is gone
+    pub(crate) fn wait(&self) {
+        let mut lanes = self.shared.lanes.lock().unwrap();
+        while lanes.queues.iter().all(|queue| queue.is_empty())
+            && lanes.status_requests.is_empty()
+            && !lanes.end_of_messages
+            && lanes.senders > 0
+        {
+            lanes = self.shared.not_empty.wait(lanes).unwrap();
+        }
+    }
+
+    fn pop(
+        &self,
+        lanes: &mut Lanes<Payload, GeneratedPayload>,
+    ) -> Result<Event<Payload, GeneratedPayload>, TryRecvError> {
+        let waiting: Vec<Lane> = Lane::PRIORITY
+            .into_iter()
+            .filter(|lane| !lanes.queues[lane.index()].is_empty())
+            .collect();
+        let starved = waiting.iter().copied().find(|lane| {
+            return lanes.passed_over[lane.index()] >= self.shared.config.starvation_limit;
+        });
+        if let Some(lane) = starved.or(waiting.first().copied()) {
+            for other in &waiting {
+                lanes.passed_over[other.index()] += 1;
+            }
+            lanes.passed_over[lane.index()] = 0;
+            // NOTE: only count it if the lane really jumped the queue
+            if starved.is_some() && waiting.first() != Some(&lane) {
+                lanes.metrics.lane_mut(lane).promoted += 1;
+            }
+            let event = lanes.queues[lane.index()].pop_front().unwrap();
+            lanes.metrics.lane_mut(lane).depth = lanes.queues[lane.index()].len();
+            self.shared.not_full.notify_all();
+            return Ok(event);
+        }
+        if lanes.end_of_messages {
+            lanes.end_of_messages = false;
+            return Ok(Event::EndOfMessages);
+        }
+        if lanes.senders == 0 {
+            return Err(TryRecvError::Disconnected);
+        }
+        return Err(TryRecvError::Empty);
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        return self.shared.lanes.lock().unwrap().metrics;
+    }
+}
+
+impl<Payload, GeneratedPayload> Drop for EventReceiver<Payload, GeneratedPayload> {
+    fn drop(&mut self) {
+        self.shared.lanes.lock().unwrap().receiver_alive = false;
+        self.shared.not_full.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::Body;
+
+    type Sender = EventSender<u64, u64>;
+    type Receiver = EventReceiver<u64, u64>;
+
+    fn queue(client: LaneConfig, starvation_limit: usize) -> (Sender, Receiver) {
+        return event_queue(QueueConfig {
+            client,
+            starvation_limit,
+            ..QueueConfig::default()
+        });
+    }
+
+    fn message(src: &str, payload: u64) -> Event<u64, u64> {
+        return Event::Message(Message {
+            src: src.to_string(),
+            dest: "n0".to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                clock: None,
+                payload,
+            },
+        });
+    }
+
+    fn tick(payload: u64) -> Event<u64, u64> {
+        return Event::GeneratedEvent(Message {
+            src: "n0".to_string(),
+            dest: "n0".to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                clock: None,
+                payload,
+            },
+        });
+    }
+
+    /// Payloads of everything queued right now, in the order the node gets them
+    fn drain(receiver: &Receiver) -> Vec<u64> {
+        let mut payloads = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            match event {
+                | Event::Message(message) | Event::GeneratedEvent(message) => {
+                    payloads.push(message.body.payload)
+                },
+                | Event::EndOfMessages => break,
+            }
+        }
+        return payloads;
+    }
+
+    fn lane(capacity: usize, overflow: Overflow) -> LaneConfig {
+        return LaneConfig { capacity, overflow };
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_queued_events() {
+        let (sender, receiver) = queue(lane(2, Overflow::DropNewest), usize::MAX);
+        for payload in 1..=3 {
+            sender.send(message("c1", payload)).unwrap();
+        }
+        assert_eq!(drain(&receiver), vec![1, 2]);
+        let metrics = receiver.metrics().client;
+        assert_eq!(
+            (metrics.enqueued, metrics.dropped, metrics.blocked),
+            (2, 1, 0)
+        );
+        assert_eq!((metrics.depth, metrics.max_depth), (0, 2));
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_events() {
+        let (sender, receiver) = queue(lane(2, Overflow::DropOldest), usize::MAX);
+        for payload in 1..=3 {
+            sender.send(message("c1", payload)).unwrap();
+        }
+        assert_eq!(drain(&receiver), vec![2, 3]);
+        let metrics = receiver.metrics().client;
+        assert_eq!((metrics.enqueued, metrics.dropped), (3, 1));
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let (sender, receiver) = queue(lane(1, Overflow::Block), usize::MAX);
+        sender.send(message("c1", 1)).unwrap();
+        let blocked = std::thread::spawn(move || sender.send(message("c1", 2)).is_ok());
+        while receiver.metrics().client.blocked == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(receiver.metrics().client.enqueued, 1);
+
+        assert!(matches!(receiver.recv(), Some(Event::Message(_))));
+        assert!(blocked.join().unwrap());
+        assert_eq!(drain(&receiver), vec![2]);
+        let metrics = receiver.metrics().client;
+        assert_eq!(
+            (metrics.enqueued, metrics.blocked, metrics.dropped),
+            (2, 1, 0)
+        );
+    }
+
+    #[test]
+    fn blocked_sender_fails_once_the_receiver_is_gone() {
+        let (sender, receiver) = queue(lane(1, Overflow::Block), usize::MAX);
+        sender.send(message("c1", 1)).unwrap();
+        let blocked = std::thread::spawn(move || sender.send(message("c1", 2)).is_err());
+        while receiver.metrics().client.blocked == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(receiver);
+        assert!(blocked.join().unwrap());
+    }
+
+    #[test]
+    fn default_client_lane_never_blocks() {
+        let (sender, receiver) = event_queue::<u64, u64>(QueueConfig::default());
+        let capacity = QueueConfig::default().client.capacity as u64;
+        for payload in 0..capacity + 10 {
+            sender.send(message("c1", payload)).unwrap();
+        }
+        // NOTE: a peer message behind the flood still gets in, and out first
+        sender.send(message("n1", 1000)).unwrap();
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.client.dropped, metrics.client.blocked), (10, 0));
+        assert_eq!(drain(&receiver)[0], 1000);
+    }
+
+    #[test]
+    fn lanes_are_served_by_priority() {
+        let (sender, receiver) = queue(lane(16, Overflow::Block), usize::MAX);
+        sender.send(message("c1", 1)).unwrap();
+        sender.send(message("n1", 2)).unwrap();
+        sender.send(tick(3)).unwrap();
+        sender.send(message("n2", 4)).unwrap();
+        assert_eq!(drain(&receiver), vec![3, 2, 4, 1]);
+    }
+
+    #[test]
+    fn starved_lane_goes_first() {
+        let (sender, receiver) = queue(lane(16, Overflow::Block), 2);
+        sender.send(message("c1", 100)).unwrap();
+        sender.send(message("c1", 101)).unwrap();
+        for payload in 0..6 {
+            sender.send(message("n1", payload)).unwrap();
+        }
+        // NOTE: a client every third event while peers keep coming
+        assert_eq!(drain(&receiver), vec![0, 1, 100, 2, 3, 101, 4, 5]);
+        let metrics = receiver.metrics();
+        assert_eq!((metrics.client.promoted, metrics.peer.promoted), (2, 0));
+    }
+
+    #[test]
+    fn end_of_messages_comes_after_every_lane() {
+        let (sender, receiver) = queue(lane(16, Overflow::Block), usize::MAX);
+        sender.send(message("c1", 1)).unwrap();
+        sender.send(Event::EndOfMessages).unwrap();
+        sender.send(message("n1", 2)).unwrap();
+        assert_eq!(drain(&receiver), vec![2, 1]);
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+
+        drop(sender);
+        assert!(matches!(
+            receiver.try_recv(),
+            Err(TryRecvError::Disconnected)
+        ));
+        assert!(receiver.recv().is_none());
+    }
+}
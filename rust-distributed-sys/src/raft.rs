@@ -0,0 +1,1234 @@
+// Purpose: Raft consensus (Ongaro & Ousterhout) for replicated state machines.
+//
+// `RaftNode` is a complete `Node`: it elects a leader, replicates client requests through the log
+// and applies them to a `RaftService` once they are committed. Followers proxy client requests to
+// the leader, so clients can talk to any node. The log is compacted into a snapshot of the service
+// every `snapshot_threshold` entries, lagging followers are caught up with that snapshot.
+//
+// With a `PersistConfig` the term, the vote, the log and the latest snapshot survive a crash: a
+// restarted node can't vote twice in one term or forget entries it acknowledged.
+//
+// IMPORTANT: every change is written before any peer hears of it, the vote before `RequestVoteOk`
+// and appended entries before `AppendEntriesOk`
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::persist::{Durable, PersistConfig, Store};
+use crate::queue::EventSender;
+use crate::{Body, ErrorCode, Event, InitNodes, Message, Node};
+
+/// State machine replicated by Raft
+pub trait RaftService: Default + Serialize + DeserializeOwned {
+    /// client requests and their replies, requests are replicated as they are
+    type Payload: Serialize + DeserializeOwned + Clone + Send + 'static;
+
+    /// Whether `payload` is a request that has to go through the log
+    fn is_request(payload: &Self::Payload) -> bool;
+
+    /// Apply a committed request
+    ///
+    /// returns:
+    ///   - the reply to the client
+    fn apply(&mut self, request: &Self::Payload) -> Self::Payload;
+
+    /// Reply for requests that can't be served right now
+    fn error(code: ErrorCode, text: String) -> Self::Payload;
+
+    /// Answer a request on the spot, without the log and even without a leader
+    ///
+    /// returns:
+    ///   - the reply, `None` for requests that go through the log
+    fn answer_locally(&self, _request: &Self::Payload) -> Option<Self::Payload> {
+        return None;
+    }
+
+    /// Fill in what only the leader knows, e.g. the time, before `request` goes into the log
+    ///
+    /// NOTE: `apply` runs on every node at its own pace, so it must only depend on the entry
+    fn stamp(_request: &mut Self::Payload) {}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry<Command> {
+    pub term: u64,
+    pub command: Command,
+}
+
+/// Node to node messages of Raft
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum RaftMessage<Command> {
+    RequestVote {
+        term: u64,
+        candidate_id: String,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    RequestVoteOk {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        leader_id: String,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry<Command>>,
+        leader_commit: u64,
+    },
+    AppendEntriesOk {
+        term: u64,
+        success: bool,
+        /// on success the last index the follower has in common with the leader,
+        /// otherwise a hint where the leader should retry
+        match_index: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        leader_id: String,
+        last_included_index: u64,
+        last_included_term: u64,
+        data: Value,
+    },
+    InstallSnapshotOk {
+        term: u64,
+        last_included_index: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)] // NOTE: `type` decides, anything that isn't raft belongs to the service
+pub enum RaftPayload<Command> {
+    Raft(RaftMessage<Command>),
+    Client(Command),
+}
+
+/// Timer event that drives elections and heartbeats
+#[derive(Debug)]
+pub struct RaftTick;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    /// a follower that hears nothing from a leader within a random timeout in this range
+    /// starts an election
+    pub election_timeout_min: Duration,
+    pub election_timeout_max: Duration,
+    pub heartbeat_interval: Duration,
+    /// resolution of the timer thread
+    pub tick_interval: Duration,
+    /// compact the log once this many applied entries are not yet in the snapshot
+    pub snapshot_threshold: u64,
+    /// most entries sent in a single `append_entries`
+    pub max_batch: usize,
+    /// where the term, vote, log and snapshot are kept, in memory if `None`
+    pub persist: Option<PersistConfig>,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        return RaftConfig {
+            election_timeout_min: Duration::from_millis(300),
+            election_timeout_max: Duration::from_millis(600),
+            heartbeat_interval: Duration::from_millis(50),
+            tick_interval: Duration::from_millis(10),
+            snapshot_threshold: 1000,
+            max_batch: 100,
+            persist: None,
+        };
+    }
+}
+
+/// What a node must remember across crashes to never vote twice in a term or lose an entry
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "Command: Serialize + DeserializeOwned")]
+struct HardState<Command> {
+    term: u64,
+    voted_for: Option<String>,
+    /// `log[0]` has index `snapshot_index + 1`
+    log: Vec<LogEntry<Command>>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    /// the service as of `snapshot_index`, `Null` before the first snapshot
+    snapshot: Value,
+}
+
+impl<Command> Default for HardState<Command> {
+    fn default() -> Self {
+        return HardState {
+            term: 0,
+            voted_for: None,
+            log: Vec::new(),
+            snapshot_index: 0,
+            snapshot_term: 0,
+            snapshot: Value::Null,
+        };
+    }
+}
+
+/// One logged change of `HardState`, snapshots replace the whole state instead
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound = "Command: Serialize + DeserializeOwned")]
+enum HardChange<Command> {
+    Vote {
+        term: u64,
+        voted_for: Option<String>,
+    },
+    /// the log from `index` on is now `entries`, whatever was there before is dropped
+    Append {
+        index: u64,
+        entries: Vec<LogEntry<Command>>,
+    },
+}
+
+impl<Command: Serialize + DeserializeOwned + Clone> Durable for HardState<Command> {
+    type Entry = HardChange<Command>;
+
+    fn apply(&mut self, entry: &HardChange<Command>) {
+        match entry {
+            | HardChange::Vote { term, voted_for } => {
+                self.term = *term;
+                self.voted_for = voted_for.clone();
+            },
+            | HardChange::Append { index, entries } => {
+                let kept = index.saturating_sub(self.snapshot_index + 1) as usize;
+                self.log.truncate(kept);
+                self.log.extend(entries.iter().cloned());
+            },
+        }
+    }
+}
+
+/// Client waiting for the entry at some log index, `(term, client, in_reply_to)`
+type PendingReply = (u64, String, Option<usize>);
+
+pub struct RaftNode<S: RaftService> {
+    node_id: String,
+    peers: Vec<String>,
+    local_id: usize,
+    config: RaftConfig,
+    rng: StdRng,
+
+    role: Role,
+    term: u64,
+    voted_for: Option<String>,
+    leader_id: Option<String>,
+    votes: HashSet<String>,
+    election_deadline: Instant,
+    /// `None` unless the node runs with a `PersistConfig`
+    store: Option<Store<HardState<S::Payload>>>,
+
+    /// `log[0]` has index `snapshot_index + 1`
+    log: Vec<LogEntry<S::Payload>>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    snapshot: Value,
+    commit_index: u64,
+    last_applied: u64,
+    service: S,
+
+    // NOTE: leader only
+    next_index: HashMap<String, u64>,
+    match_index: HashMap<String, u64>,
+    last_sent: HashMap<String, Instant>,
+    pending: HashMap<u64, PendingReply>,
+
+    /// requests proxied to the leader, keyed by our `msg_id`, `(client, in_reply_to)`
+    forwarded: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl<S: RaftService> RaftNode<S> {
+    pub fn role(&self) -> Role {
+        return self.role;
+    }
+
+    pub fn term(&self) -> u64 {
+        return self.term;
+    }
+
+    pub fn leader_id(&self) -> Option<&str> {
+        return self.leader_id.as_deref();
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        return self.commit_index;
+    }
+
+    pub fn service(&self) -> &S {
+        return &self.service;
+    }
+
+    fn last_index(&self) -> u64 {
+        return self.snapshot_index + self.log.len() as u64;
+    }
+
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        if index < self.snapshot_index || index > self.last_index() {
+            return None;
+        }
+        return Some(self.log[(index - self.snapshot_index - 1) as usize].term);
+    }
+
+    fn last_term(&self) -> u64 {
+        return self.term_at(self.last_index()).unwrap_or(0);
+    }
+
+    fn majority(&self) -> usize {
+        let cluster_size = self.peers.len() + 1;
+        return cluster_size / 2 + 1;
+    }
+
+    fn reset_election_deadline(&mut self, now: Instant) {
+        let timeout = self
+            .rng
+            .gen_range(self.config.election_timeout_min..=self.config.election_timeout_max);
+        self.election_deadline = now + timeout;
+    }
+
+    fn send(
+        &mut self,
+        dest: &str,
+        message: RaftMessage<S::Payload>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let id = self.local_id;
+        self.local_id += 1;
+        return Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                clock: None,
+                payload: RaftPayload::Raft(message),
+            },
+        }
+        .send(output, "raft");
+    }
+
+    /// Write the term and vote to disk, before anyone hears of them
+    fn persist_vote(&mut self) -> anyhow::Result<()> {
+        if let Some(store) = &mut self.store {
+            let change = HardChange::Vote {
+                term: self.term,
+                voted_for: self.voted_for.clone(),
+            };
+            store.append(&change).context("persist raft vote")?;
+        }
+        return Ok(());
+    }
+
+    /// Write the log from `index` on to disk, before anyone hears of it
+    fn persist_log(&mut self, index: u64) -> anyhow::Result<()> {
+        if let Some(store) = &mut self.store {
+            let start = (index - self.snapshot_index - 1) as usize;
+            let change = HardChange::Append {
+                index,
+                entries: self.log[start..].to_vec(),
+            };
+            store.append(&change).context("persist raft log")?;
+        }
+        return Ok(());
+    }
+
+    /// Replace what is on disk with the current snapshot and the log after it
+    ///
+    /// NOTE: the store is only compacted here, so its log is at most `snapshot_threshold`
+    /// entries (and some votes) long and `PersistConfig::snapshot_every` isn't used
+    fn persist_snapshot(&mut self) -> anyhow::Result<()> {
+        if let Some(store) = &mut self.store {
+            let state = HardState {
+                term: self.term,
+                voted_for: self.voted_for.clone(),
+                log: self.log.clone(),
+                snapshot_index: self.snapshot_index,
+                snapshot_term: self.snapshot_term,
+                snapshot: self.snapshot.clone(),
+            };
+            store.snapshot(&state).context("persist raft snapshot")?;
+        }
+        return Ok(());
+    }
+
+    /// Switch to following `leader_id`
+    ///
+    /// Requests proxied to the previous leader get an indefinite error, it may or may not have
+    /// committed them and its answer is no longer awaited.
+    fn follow(&mut self, leader_id: Option<String>, output: &mut dyn Write) -> anyhow::Result<()> {
+        if self.leader_id == leader_id {
+            return Ok(());
+        }
+        self.leader_id = leader_id;
+        for (_, (client, in_reply_to)) in std::mem::take(&mut self.forwarded) {
+            let id = self.local_id;
+            self.local_id += 1;
+            Message {
+                src: self.node_id.clone(),
+                dest: client,
+                body: Body {
+                    id: Some(id),
+                    in_reply_to,
+                    clock: None,
+                    payload: RaftPayload::<S::Payload>::Client(S::error(
+                        ErrorCode::Timeout,
+                        "the leader changed before answering".to_string(),
+                    )),
+                },
+            }
+            .send(&mut *output, "leader changed")?;
+        }
+        return Ok(());
+    }
+
+    /// Adopt a newer term, which always demotes us to follower
+    fn observe_term(&mut self, term: u64, output: &mut dyn Write) -> anyhow::Result<()> {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+            self.role = Role::Follower;
+            self.votes.clear();
+            self.persist_vote()?;
+            self.follow(None, output)?;
+        }
+        return Ok(());
+    }
+
+    fn start_election(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.node_id.clone());
+        self.persist_vote()?;
+        self.follow(None, output)?;
+        self.votes = HashSet::from([self.node_id.clone()]);
+        self.reset_election_deadline(now);
+
+        if self.votes.len() >= self.majority() {
+            return self.become_leader(now, output);
+        }
+        let request = RaftMessage::RequestVote {
+            term: self.term,
+            candidate_id: self.node_id.clone(),
+            last_log_index: self.last_index(),
+            last_log_term: self.last_term(),
+        };
+        for peer in self.peers.clone() {
+            self.send(&peer, request.clone(), output)?;
+        }
+        return Ok(());
+    }
+
+    fn become_leader(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.role = Role::Leader;
+        self.follow(Some(self.node_id.clone()), output)?;
+        let next_index = self.last_index() + 1;
+        for peer in &self.peers {
+            self.next_index.insert(peer.clone(), next_index);
+            self.match_index.insert(peer.clone(), 0);
+        }
+        return self.replicate_to_all(now, output);
+    }
+
+    /// Send every peer what it is missing, a heartbeat if it is up to date
+    fn replicate_to_all(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        for peer in self.peers.clone() {
+            self.replicate_to(&peer, now, output)?;
+        }
+        return Ok(());
+    }
+
+    fn replicate_to(
+        &mut self,
+        peer: &str,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        self.last_sent.insert(peer.to_string(), now);
+        let next_index = self.next_index[peer];
+        if next_index <= self.snapshot_index {
+            // NOTE: the entries the peer needs were compacted away, ship the snapshot instead
+            let message = RaftMessage::InstallSnapshot {
+                term: self.term,
+                leader_id: self.node_id.clone(),
+                last_included_index: self.snapshot_index,
+                last_included_term: self.snapshot_term,
+                data: self.snapshot.clone(),
+            };
+            return self.send(peer, message, output);
+        }
+
+        let prev_log_index = next_index - 1;
+        let start = (next_index - self.snapshot_index - 1) as usize;
+        let end = self.log.len().min(start + self.config.max_batch);
+        let message = RaftMessage::AppendEntries {
+            term: self.term,
+            leader_id: self.node_id.clone(),
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index).unwrap_or(0),
+            entries: self.log[start..end].to_vec(),
+            leader_commit: self.commit_index,
+        };
+        return self.send(peer, message, output);
+    }
+
+    /// Commit the highest index that a majority has, as long as it is from the current term
+    fn advance_commit_index(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let mut indexes: Vec<u64> = self.match_index.values().copied().collect();
+        indexes.push(self.last_index());
+        indexes.sort_unstable_by(|a, b| b.cmp(a));
+        let majority_index = indexes[self.majority() - 1];
+        if majority_index > self.commit_index && self.term_at(majority_index) == Some(self.term) {
+            self.commit_index = majority_index;
+        }
+        return self.apply_committed(output);
+    }
+
+    fn apply_committed(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let index = self.last_applied;
+            let entry = &self.log[(index - self.snapshot_index - 1) as usize];
+            let entry_term = entry.term;
+            let reply = self.service.apply(&entry.command);
+
+            // NOTE: only answer if the entry is the one the client sent, not a replacement
+            if let Some((term, client, in_reply_to)) = self.pending.remove(&index) {
+                if term == entry_term {
+                    let id = self.local_id;
+                    self.local_id += 1;
+                    Message {
+                        src: self.node_id.clone(),
+                        dest: client,
+                        body: Body {
+                            id: Some(id),
+                            in_reply_to,
+                            clock: None,
+                            payload: RaftPayload::<S::Payload>::Client(reply),
+                        },
+                    }
+                    .send(&mut *output, "raft reply")?;
+                }
+            }
+        }
+        if self.last_applied - self.snapshot_index >= self.config.snapshot_threshold {
+            self.take_snapshot()?;
+        }
+        return Ok(());
+    }
+
+    fn take_snapshot(&mut self) -> anyhow::Result<()> {
+        let term = self.term_at(self.last_applied).unwrap_or(0);
+        self.snapshot = serde_json::to_value(&self.service).context("snapshot raft service")?;
+        self.log
+            .drain(..(self.last_applied - self.snapshot_index) as usize);
+        self.snapshot_index = self.last_applied;
+        self.snapshot_term = term;
+        return self.persist_snapshot();
+    }
+
+    fn handle_client(
+        &mut self,
+        src: String,
+        msg_id: Option<usize>,
+        in_reply_to: Option<usize>,
+        command: S::Payload,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        if let Some(answer) = self.service.answer_locally(&command) {
+            let id = self.local_id;
+            self.local_id += 1;
+            Message {
+                src: self.node_id.clone(),
+                dest: src,
+                body: Body {
+                    id: Some(id),
+                    in_reply_to: msg_id,
+                    clock: None,
+                    payload: RaftPayload::<S::Payload>::Client(answer),
+                },
+            }
+            .send(&mut *output, "local reply")?;
+            return Ok(());
+        }
+        if !S::is_request(&command) {
+            // NOTE: the leader answered a request we proxied, hand it to the client
+            let Some(forwarded) = in_reply_to else {
+                return Ok(());
+            };
+            if let Some((client, in_reply_to)) = self.forwarded.remove(&forwarded) {
+                let id = self.local_id;
+                self.local_id += 1;
+                Message {
+                    src: self.node_id.clone(),
+                    dest: client,
+                    body: Body {
+                        id: Some(id),
+                        in_reply_to,
+                        clock: None,
+                        payload: RaftPayload::<S::Payload>::Client(command),
+                    },
+                }
+                .send(&mut *output, "proxied reply")?;
+            }
+            return Ok(());
+        }
+
+        match (self.role, self.leader_id.clone()) {
+            | (Role::Leader, _) => {
+                let mut command = command;
+                S::stamp(&mut command);
+                self.log.push(LogEntry {
+                    term: self.term,
+                    command,
+                });
+                let index = self.last_index();
+                // NOTE: the leader counts itself towards the majority, so it persists first
+                self.persist_log(index)?;
+                self.pending.insert(index, (self.term, src, msg_id));
+                self.replicate_to_all(Instant::now(), output)?;
+                // NOTE: a single node cluster commits right away
+                self.advance_commit_index(output)?;
+            },
+            // NOTE: a node that isn't leader (any more) must never proxy to itself
+            | (_, Some(leader_id)) if leader_id != self.node_id => {
+                let id = self.local_id;
+                self.local_id += 1;
+                self.forwarded.insert(id, (src, msg_id));
+                Message {
+                    src: self.node_id.clone(),
+                    dest: leader_id,
+                    body: Body {
+                        id: Some(id),
+                        in_reply_to: None,
+                        clock: None,
+                        payload: RaftPayload::<S::Payload>::Client(command),
+                    },
+                }
+                .send(&mut *output, "proxy to leader")?;
+            },
+            | _ => {
+                let id = self.local_id;
+                self.local_id += 1;
+                Message {
+                    src: self.node_id.clone(),
+                    dest: src,
+                    body: Body {
+                        id: Some(id),
+                        in_reply_to: msg_id,
+                        clock: None,
+                        payload: RaftPayload::<S::Payload>::Client(S::error(
+                            ErrorCode::TemporarilyUnavailable,
+                            "no leader elected yet".to_string(),
+                        )),
+                    },
+                }
+                .send(&mut *output, "no leader")?;
+            },
+        }
+        return Ok(());
+    }
+
+    fn handle_raft(
+        &mut self,
+        src: String,
+        message: RaftMessage<S::Payload>,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        match message {
+            | RaftMessage::RequestVote {
+                term,
+                candidate_id,
+                last_log_index,
+                last_log_term,
+            } => {
+                self.observe_term(term, output)?;
+                let up_to_date =
+                    (last_log_term, last_log_index) >= (self.last_term(), self.last_index());
+                let vote_granted = term == self.term
+                    && up_to_date
+                    && self
+                        .voted_for
+                        .as_ref()
+                        .is_none_or(|voted_for| *voted_for == candidate_id);
+                if vote_granted {
+                    self.voted_for = Some(candidate_id);
+                    self.persist_vote()?;
+                    self.reset_election_deadline(now);
+                }
+                let reply = RaftMessage::RequestVoteOk {
+                    term: self.term,
+                    vote_granted,
+                };
+                self.send(&src, reply, output)?;
+            },
+            | RaftMessage::RequestVoteOk { term, vote_granted } => {
+                self.observe_term(term, output)?;
+                if self.role == Role::Candidate && term == self.term && vote_granted {
+                    self.votes.insert(src);
+                    if self.votes.len() >= self.majority() {
+                        self.become_leader(now, output)?;
+                    }
+                }
+            },
+            | RaftMessage::AppendEntries {
+                term,
+                leader_id,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                self.observe_term(term, output)?;
+                if term < self.term {
+                    let reply = RaftMessage::AppendEntriesOk {
+                        term: self.term,
+                        success: false,
+                        match_index: 0,
+                    };
+                    return self.send(&src, reply, output);
+                }
+                self.role = Role::Follower;
+                self.follow(Some(leader_id), output)?;
+                self.reset_election_deadline(now);
+
+                let reply = self.append_entries(
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                    output,
+                )?;
+                self.send(&src, reply, output)?;
+            },
+            | RaftMessage::AppendEntriesOk {
+                term,
+                success,
+                match_index,
+            } => {
+                self.observe_term(term, output)?;
+                if self.role != Role::Leader || term != self.term {
+                    return Ok(());
+                }
+                if success {
+                    let known = self.match_index.entry(src.clone()).or_insert(0);
+                    *known = (*known).max(match_index);
+                    let next_index = *known + 1;
+                    self.next_index.insert(src.clone(), next_index);
+                    self.advance_commit_index(output)?;
+                    if next_index <= self.last_index() {
+                        self.replicate_to(&src, now, output)?;
+                    }
+                } else {
+                    let next_index = self.next_index.entry(src.clone()).or_insert(1);
+                    *next_index = (match_index + 1).min(next_index.saturating_sub(1)).max(1);
+                    self.replicate_to(&src, now, output)?;
+                }
+            },
+            | RaftMessage::InstallSnapshot {
+                term,
+                leader_id,
+                last_included_index,
+                last_included_term,
+                data,
+            } => {
+                self.observe_term(term, output)?;
+                if term == self.term {
+                    self.role = Role::Follower;
+                    self.follow(Some(leader_id), output)?;
+                    self.reset_election_deadline(now);
+                    self.install_snapshot(last_included_index, last_included_term, data)?;
+                }
+                let reply = RaftMessage::InstallSnapshotOk {
+                    term: self.term,
+                    last_included_index,
+                };
+                self.send(&src, reply, output)?;
+            },
+            | RaftMessage::InstallSnapshotOk {
+                term,
+                last_included_index,
+            } => {
+                self.observe_term(term, output)?;
+                if self.role == Role::Leader && term == self.term {
+                    let known = self.match_index.entry(src.clone()).or_insert(0);
+                    *known = (*known).max(last_included_index);
+                    let next_index = *known + 1;
+                    self.next_index.insert(src.clone(), next_index);
+                    self.replicate_to(&src, now, output)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    /// Follower side of `append_entries`
+    ///
+    /// returns:
+    ///   - the reply for the leader
+    fn append_entries(
+        &mut self,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry<S::Payload>>,
+        leader_commit: u64,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<RaftMessage<S::Payload>> {
+        if prev_log_index > self.last_index() {
+            return Ok(RaftMessage::AppendEntriesOk {
+                term: self.term,
+                success: false,
+                match_index: self.last_index(),
+            });
+        }
+        // NOTE: everything up to our snapshot is committed and therefore identical
+        let skip = self.snapshot_index.saturating_sub(prev_log_index);
+        if skip == 0 && self.term_at(prev_log_index) != Some(prev_log_term) {
+            return Ok(RaftMessage::AppendEntriesOk {
+                term: self.term,
+                success: false,
+                match_index: prev_log_index.saturating_sub(1).max(self.commit_index),
+            });
+        }
+
+        let mut index = prev_log_index;
+        // NOTE: first index that changed, everything from there on is written to disk
+        let mut changed: Option<u64> = None;
+        for entry in entries {
+            index += 1;
+            if index <= self.snapshot_index {
+                continue;
+            }
+            match self.term_at(index) {
+                | Some(term) if term == entry.term => continue,
+                | Some(_) => {
+                    // NOTE: conflicting suffix, the leader's log wins
+                    self.log
+                        .truncate((index - self.snapshot_index - 1) as usize);
+                    self.log.push(entry);
+                },
+                | None => self.log.push(entry),
+            }
+            changed.get_or_insert(index);
+        }
+        if let Some(changed) = changed {
+            self.persist_log(changed)?;
+        }
+
+        // NOTE: a stale or reordered append may carry a lower commit index, never go back
+        if leader_commit > self.commit_index {
+            self.commit_index = self.commit_index.max(leader_commit.min(index));
+            self.apply_committed(output)?;
+        }
+        return Ok(RaftMessage::AppendEntriesOk {
+            term: self.term,
+            success: true,
+            match_index: index,
+        });
+    }
+
+    fn install_snapshot(
+        &mut self,
+        last_included_index: u64,
+        last_included_term: u64,
+        data: Value,
+    ) -> anyhow::Result<()> {
+        if last_included_index <= self.commit_index {
+            return Ok(());
+        }
+        self.service = serde_json::from_value(data.clone()).context("restore raft snapshot")?;
+        if self.term_at(last_included_index) == Some(last_included_term) {
+            self.log
+                .drain(..(last_included_index - self.snapshot_index) as usize);
+        } else {
+            self.log.clear();
+        }
+        self.snapshot_index = last_included_index;
+        self.snapshot_term = last_included_term;
+        self.snapshot = data;
+        self.commit_index = last_included_index;
+        self.last_applied = last_included_index;
+        return self.persist_snapshot();
+    }
+
+    fn tick(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        if self.role == Role::Leader {
+            let heartbeat_interval = self.config.heartbeat_interval;
+            let idle: Vec<String> = self
+                .peers
+                .iter()
+                .filter(|peer| {
+                    self.last_sent
+                        .get(*peer)
+                        .is_none_or(|sent| now.duration_since(*sent) >= heartbeat_interval)
+                })
+                .cloned()
+                .collect();
+            for peer in idle {
+                self.replicate_to(&peer, now, output)?;
+            }
+        } else if now >= self.election_deadline {
+            self.start_election(now, output)?;
+        }
+        return Ok(());
+    }
+}
+
+impl<S: RaftService> Node<RaftConfig, RaftPayload<S::Payload>, RaftTick> for RaftNode<S> {
+    fn from_init(
+        config: RaftConfig,
+        init: InitNodes,
+        sender: EventSender<RaftPayload<S::Payload>, RaftTick>,
+    ) -> anyhow::Result<Self> {
+        let node_id = init.node_id.clone();
+        let tick_interval = config.tick_interval;
+        std::thread::spawn(move || loop {
+            std::thread::sleep(tick_interval);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: RaftTick,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        let mut peers: Vec<String> = init
+            .node_ids
+            .into_iter()
+            .filter(|node_id| *node_id != init.node_id)
+            .collect();
+        peers.sort();
+        let (store, state) = match &config.persist {
+            | Some(persist) => {
+                let (store, state) = Store::open(persist, &init.node_id)?;
+                (Some(store), state)
+            },
+            | None => (None, HardState::default()),
+        };
+        // NOTE: everything in the snapshot was committed, the rest is committed again by the leader
+        let (service, snapshot) = match state.snapshot {
+            | Value::Null => {
+                let service = S::default();
+                let snapshot = serde_json::to_value(&service).context("snapshot raft service")?;
+                (service, snapshot)
+            },
+            | snapshot => {
+                let service =
+                    serde_json::from_value(snapshot.clone()).context("restore raft snapshot")?;
+                (service, snapshot)
+            },
+        };
+        let mut node = RaftNode {
+            node_id: init.node_id,
+            peers,
+            local_id: 1,
+            config,
+            rng: StdRng::from_entropy(),
+            role: Role::Follower,
+            term: state.term,
+            voted_for: state.voted_for,
+            leader_id: None,
+            votes: HashSet::new(),
+            election_deadline: Instant::now(),
+            store,
+            log: state.log,
+            snapshot_index: state.snapshot_index,
+            snapshot_term: state.snapshot_term,
+            snapshot,
+            commit_index: state.snapshot_index,
+            last_applied: state.snapshot_index,
+            service,
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            last_sent: HashMap::new(),
+            pending: HashMap::new(),
+            forwarded: HashMap::new(),
+        };
+        node.reset_election_deadline(Instant::now());
+        return Ok(node);
+    }
+
+    fn step(
+        &mut self,
+        event: Event<RaftPayload<S::Payload>, RaftTick>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        match event {
+            | Event::EndOfMessages => {},
+            | Event::GeneratedEvent(_) => self.tick(now, output)?,
+            | Event::Message(Message { src, body, .. }) => match body.payload {
+                | RaftPayload::Raft(message) => self.handle_raft(src, message, now, output)?,
+                | RaftPayload::Client(command) => {
+                    self.handle_client(src, body.id, body.in_reply_to, command, output)?
+                },
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> Value {
+        return serde_json::json!({
+            "role": self.role,
+            "term": self.term,
+            "leader": self.leader_id,
+            "last_index": self.last_index(),
+            "commit_index": self.commit_index,
+            "last_applied": self.last_applied,
+            "snapshot_index": self.snapshot_index,
+            "match_index": self.match_index,
+            // NOTE: client requests waiting for a commit or for the leader to answer
+            "pending": self.pending.len(),
+            "forwarded": self.forwarded.len(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::{event_queue, EventReceiver, QueueConfig};
+
+    /// Remembers every command it applied, in order
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct Applied {
+        commands: Vec<u64>,
+    }
+
+    impl RaftService for Applied {
+        type Payload = u64;
+
+        fn is_request(_payload: &u64) -> bool {
+            return true;
+        }
+
+        fn apply(&mut self, request: &u64) -> u64 {
+            self.commands.push(*request);
+            return *request;
+        }
+
+        fn error(_code: ErrorCode, _text: String) -> u64 {
+            return 0;
+        }
+    }
+
+    type Receiver = EventReceiver<RaftPayload<u64>, RaftTick>;
+
+    /// Follower n0 of a three node cluster, the receiver keeps its timer thread running
+    ///
+    /// NOTE: nothing reads the queue, so ticks never reach the node and it never campaigns
+    fn follower(config: RaftConfig) -> (RaftNode<Applied>, Receiver) {
+        let (sender, receiver) = event_queue(QueueConfig::default());
+        let init = InitNodes {
+            node_id: "n0".to_string(),
+            node_ids: ["n0", "n1", "n2"].map(String::from).into(),
+        };
+        let node = RaftNode::from_init(config, init, sender).unwrap();
+        return (node, receiver);
+    }
+
+    fn persisted(dir: &std::path::Path) -> RaftConfig {
+        return RaftConfig {
+            persist: Some(PersistConfig::new(dir)),
+            ..RaftConfig::default()
+        };
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("raft-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    fn deliver(node: &mut RaftNode<Applied>, message: RaftMessage<u64>) -> Vec<RaftMessage<u64>> {
+        let mut output = Vec::new();
+        let event = Event::Message(Message {
+            src: "n1".to_string(),
+            dest: "n0".to_string(),
+            body: Body {
+                id: Some(1),
+                in_reply_to: None,
+                clock: None,
+                payload: RaftPayload::Raft(message),
+            },
+        });
+        node.step(event, &mut output).unwrap();
+        return output
+            .split(|byte| *byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| {
+                let message: Message<RaftPayload<u64>> = serde_json::from_slice(line).unwrap();
+                return match message.body.payload {
+                    | RaftPayload::Raft(message) => Some(message),
+                    | RaftPayload::Client(_) => None,
+                };
+            })
+            .collect();
+    }
+
+    fn append(
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: &[(u64, u64)],
+        leader_commit: u64,
+    ) -> RaftMessage<u64> {
+        return RaftMessage::AppendEntries {
+            term,
+            leader_id: "n1".to_string(),
+            prev_log_index,
+            prev_log_term,
+            entries: entries
+                .iter()
+                .map(|(term, command)| LogEntry {
+                    term: *term,
+                    command: *command,
+                })
+                .collect(),
+            leader_commit,
+        };
+    }
+
+    fn assert_appended(replies: &[RaftMessage<u64>], expected: u64) {
+        match replies {
+            | [RaftMessage::AppendEntriesOk {
+                success: true,
+                match_index,
+                ..
+            }] => assert_eq!(*match_index, expected),
+            | _ => panic!("expected a successful append, got {:?}", replies),
+        }
+    }
+
+    #[test]
+    fn stale_append_keeps_the_commit_index() {
+        let (mut node, _receiver) = follower(RaftConfig::default());
+        let replies = deliver(&mut node, append(1, 0, 0, &[(1, 10), (1, 11), (1, 12)], 2));
+        assert_appended(&replies, 3);
+        assert_eq!(node.commit_index(), 2);
+
+        // NOTE: a delayed append with a single entry but a newer commit index
+        let replies = deliver(&mut node, append(1, 0, 0, &[(1, 10)], 3));
+        assert_appended(&replies, 1);
+        assert_eq!(node.commit_index(), 2);
+        assert_eq!(node.service().commands, vec![10, 11]);
+        assert_eq!(node.last_index(), 3);
+    }
+
+    #[test]
+    fn conflicting_suffix_is_replaced() {
+        let (mut node, _receiver) = follower(RaftConfig::default());
+        deliver(&mut node, append(1, 0, 0, &[(1, 10), (1, 11), (1, 12)], 0));
+        let replies = deliver(&mut node, append(2, 1, 1, &[(2, 21)], 0));
+        assert_appended(&replies, 2);
+        assert_eq!(node.last_index(), 2);
+        assert_eq!(node.term_at(2), Some(2));
+
+        // NOTE: a mismatching previous entry is refused
+        let replies = deliver(&mut node, append(2, 2, 1, &[(2, 22)], 0));
+        assert!(matches!(
+            replies[..],
+            [RaftMessage::AppendEntriesOk { success: false, .. }]
+        ));
+    }
+
+    #[test]
+    fn log_survives_a_restart() {
+        let dir = temp_dir("log");
+        let (mut node, receiver) = follower(persisted(&dir));
+        deliver(&mut node, append(1, 0, 0, &[(1, 10), (1, 11), (1, 12)], 1));
+        deliver(&mut node, append(2, 1, 1, &[(2, 21)], 1));
+        drop((node, receiver));
+
+        let (node, _receiver) = follower(persisted(&dir));
+        assert_eq!(node.term(), 2);
+        assert_eq!(node.last_index(), 2);
+        assert_eq!(node.term_at(1), Some(1));
+        assert_eq!(node.term_at(2), Some(2));
+        assert_eq!(node.log[1].command, 21);
+        // NOTE: the commit index isn't kept, the leader hands it out again
+        assert_eq!(node.commit_index(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn vote_survives_a_restart() {
+        let dir = temp_dir("vote");
+        let request = |candidate_id: &str| RaftMessage::RequestVote {
+            term: 3,
+            candidate_id: candidate_id.to_string(),
+            last_log_index: 0,
+            last_log_term: 0,
+        };
+        let (mut node, receiver) = follower(persisted(&dir));
+        let replies = deliver(&mut node, request("n1"));
+        assert!(matches!(
+            replies[..],
+            [RaftMessage::RequestVoteOk {
+                term: 3,
+                vote_granted: true
+            }]
+        ));
+        drop((node, receiver));
+
+        let (mut node, _receiver) = follower(persisted(&dir));
+        let replies = deliver(&mut node, request("n2"));
+        assert!(matches!(
+            replies[..],
+            [RaftMessage::RequestVoteOk {
+                term: 3,
+                vote_granted: false
+            }]
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_survives_a_restart() {
+        let dir = temp_dir("snapshot");
+        let config = RaftConfig {
+            snapshot_threshold: 2,
+            ..persisted(&dir)
+        };
+        let (mut node, receiver) = follower(config.clone());
+        deliver(
+            &mut node,
+            append(1, 0, 0, &[(1, 10), (1, 11), (1, 12), (1, 13)], 3),
+        );
+        assert_eq!(node.snapshot_index, 3);
+        drop((node, receiver));
+
+        let (node, _receiver) = follower(config);
+        assert_eq!(node.snapshot_index, 3);
+        assert_eq!(node.commit_index(), 3);
+        assert_eq!(node.service().commands, vec![10, 11, 12]);
+        assert_eq!(node.last_index(), 4);
+        assert_eq!(node.log[0].command, 13);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+// Purpose: at-least-once delivery between nodes.
+//
+// The sender keeps every message in an `Outbox` until the matching reply (`in_reply_to`) arrives,
+// re-sending it with exponential backoff and jitter. Because a retry can race the original, the
+// receiver filters repeats with a `DedupWindow` keyed by `(src, msg_id)`.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Serialize;
+
+use crate::Message;
+
+/// How often and for how long an unacknowledged message is re-sent
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// delay before the first retry
+    pub initial_backoff: Duration,
+    /// upper bound on the delay between two retries
+    pub max_backoff: Duration,
+    /// growth factor of the delay after every attempt
+    pub multiplier: f64,
+    /// fraction of the delay (0.0..=1.0) that is randomised to avoid retry storms
+    pub jitter: f64,
+    /// give up after this many sends, `None` retries forever
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        return RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        };
+    }
+}
+
+impl RetryPolicy {
+    /// Delay to wait after the `attempt`-th send (starting at 1) before re-sending
+    ///
+    /// The delay is `initial_backoff * multiplier^(attempt - 1)` capped at `max_backoff`, of which
+    /// the `jitter` fraction is drawn uniformly at random.
+    pub fn backoff(&self, attempt: usize, rng: &mut impl Rng) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as usize) as i32;
+        let delay = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let fixed = delay * (1.0 - jitter);
+        let random = delay * jitter * rng.gen::<f64>();
+        return Duration::from_secs_f64(fixed + random);
+    }
+}
+
+#[derive(Debug)]
+struct Pending<Payload> {
+    message: Message<Payload>,
+    attempts: usize,
+    next_retry: Instant,
+}
+
+/// Messages that were sent but not yet acknowledged, keyed by `msg_id`
+#[derive(Debug)]
+pub struct Outbox<Payload> {
+    policy: RetryPolicy,
+    pending: HashMap<usize, Pending<Payload>>,
+    rng: StdRng,
+}
+
+impl<Payload> Outbox<Payload>
+where
+    Payload: Serialize + Clone,
+{
+    pub fn new(policy: RetryPolicy) -> Self {
+        return Outbox {
+            policy,
+            pending: HashMap::new(),
+            rng: StdRng::from_entropy(),
+        };
+    }
+
+    /// Send `message` and keep it until it is acknowledged
+    ///
+    /// The message must carry a `msg_id`, the acknowledgement is the reply with a matching
+    /// `in_reply_to` and is registered with [`Outbox::ack`].
+    pub fn send(
+        &mut self,
+        message: Message<Payload>,
+        output: &mut (impl Write + ?Sized),
+        now: Instant,
+    ) -> anyhow::Result<()> {
+        let id = message
+            .body
+            .id
+            .context("reliable messages need a msg_id to be acknowledged")?;
+        let dest = message.dest.clone();
+        message.clone().send(output, &dest)?;
+        let next_retry = now + self.policy.backoff(1, &mut self.rng);
+        self.pending.insert(
+            id,
+            Pending {
+                message,
+                attempts: 1,
+                next_retry,
+            },
+        );
+        return Ok(());
+    }
+
+    /// Mark the message with `msg_id == in_reply_to` as delivered
+    ///
+    /// returns:
+    ///   - the acknowledged message, `None` if it was unknown or already acknowledged
+    pub fn ack(&mut self, in_reply_to: usize) -> Option<Message<Payload>> {
+        return self
+            .pending
+            .remove(&in_reply_to)
+            .map(|pending| pending.message);
+    }
+
+    /// Re-send every message whose backoff has elapsed
+    ///
+    /// returns:
+    ///   - messages that reached `max_attempts` and were dropped from the outbox
+    pub fn retry_due(
+        &mut self,
+        output: &mut (impl Write + ?Sized),
+        now: Instant,
+    ) -> anyhow::Result<Vec<Message<Payload>>> {
+        let mut given_up = Vec::new();
+        let due: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.next_retry <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in due {
+            let exhausted = {
+                let pending = &self.pending[&id];
+                self.policy
+                    .max_attempts
+                    .is_some_and(|max| pending.attempts >= max)
+            };
+            if exhausted {
+                given_up.push(self.pending.remove(&id).unwrap().message);
+                continue;
+            }
+
+            let pending = self.pending.get_mut(&id).unwrap();
+            pending.attempts += 1;
+            pending.next_retry = now + self.policy.backoff(pending.attempts, &mut self.rng);
+            let dest = pending.message.dest.clone();
+            pending
+                .message
+                .clone()
+                .send(output, &dest)
+                .context(format!("retrying msg {} to {}", id, dest))?;
+        }
+        return Ok(given_up);
+    }
+
+    /// Earliest time at which [`Outbox::retry_due`] has something to do
+    pub fn next_deadline(&self) -> Option<Instant> {
+        return self
+            .pending
+            .values()
+            .map(|pending| pending.next_retry)
+            .min();
+    }
+
+    /// Messages still waiting for an acknowledgement, in no particular order
+    pub fn pending(&self) -> impl Iterator<Item = &Message<Payload>> {
+        return self.pending.values().map(|pending| &pending.message);
+    }
+
+    /// Number of messages still waiting for an acknowledgement
+    pub fn len(&self) -> usize {
+        return self.pending.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.pending.is_empty();
+    }
+}
+
+/// Remembers the last `capacity` `(src, msg_id)` pairs to drop re-delivered messages
+#[derive(Debug)]
+pub struct DedupWindow {
+    capacity: usize,
+    seen: HashSet<(String, usize)>,
+    order: VecDeque<(String, usize)>,
+}
+
+impl DedupWindow {
+    pub fn new(capacity: usize) -> Self {
+        return DedupWindow {
+            capacity,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+        };
+    }
+
+    /// Record `(src, msg_id)`
+    ///
+    /// returns:
+    ///   - `true` the first time the pair is seen, `false` for a duplicate
+    pub fn observe(&mut self, src: &str, msg_id: usize) -> bool {
+        let key = (src.to_string(), msg_id);
+        if self.seen.contains(&key) {
+            return false;
+        }
+        // NOTE: oldest entries fall out of the window, a very late retry is delivered again
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.seen.insert(key.clone());
+        self.order.push_back(key);
+        return true;
+    }
+
+    /// Whether `message` was already delivered, recording it otherwise
+    ///
+    /// Messages without a `msg_id` cannot be told apart and are never duplicates.
+    pub fn is_duplicate<Payload>(&mut self, message: &Message<Payload>) -> bool {
+        return match message.body.id {
+            | Some(id) => !self.observe(&message.src, id),
+            | None => false,
+        };
+    }
+}
+
+impl Default for DedupWindow {
+    fn default() -> Self {
+        return DedupWindow::new(10_000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::Body;
+
+    fn policy(jitter: f64, max_attempts: Option<usize>) -> RetryPolicy {
+        return RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+            multiplier: 2.0,
+            jitter,
+            max_attempts,
+        };
+    }
+
+    fn message(id: usize) -> Message<Value> {
+        return Message {
+            src: "n0".to_string(),
+            dest: "n1".to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                clock: None,
+                payload: json!({"type": "share"}),
+            },
+        };
+    }
+
+    /// Lines written to `output`, one per message sent
+    fn sent(output: &[u8]) -> usize {
+        return output.iter().filter(|byte| **byte == b'\n').count();
+    }
+
+    #[test]
+    fn backoff_grows_by_the_multiplier_up_to_the_cap() {
+        let policy = policy(0.0, None);
+        let mut rng = StdRng::seed_from_u64(1);
+        let delays: Vec<u128> = (1..=6)
+            .map(|attempt| policy.backoff(attempt, &mut rng).as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(policy.backoff(usize::MAX, &mut rng).as_millis(), 1000);
+    }
+
+    #[test]
+    fn backoff_jitter_stays_within_its_fraction_of_the_delay() {
+        let jittered = policy(0.5, None);
+        let mut rng = StdRng::seed_from_u64(1);
+        let delays: Vec<Duration> = (0..1000).map(|_| jittered.backoff(3, &mut rng)).collect();
+        // NOTE: half of the 400ms delay is fixed, the other half random
+        assert!(delays
+            .iter()
+            .all(|delay| *delay >= Duration::from_millis(200)
+                && *delay <= Duration::from_millis(400)));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+
+        // NOTE: a jitter above 1 is clamped, the delay never exceeds its cap
+        let clamped = policy(2.0, None);
+        assert!((0..1000).all(|_| clamped.backoff(1, &mut rng) <= Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn retry_due_resends_only_after_the_backoff() {
+        let mut outbox = Outbox::new(policy(0.0, None));
+        let mut output = Vec::new();
+        let now = Instant::now();
+        outbox.send(message(1), &mut output, now).unwrap();
+        assert_eq!(sent(&output), 1);
+
+        let given_up = outbox
+            .retry_due(&mut output, now + Duration::from_millis(99))
+            .unwrap();
+        assert!(given_up.is_empty());
+        assert_eq!(sent(&output), 1);
+
+        outbox
+            .retry_due(&mut output, now + Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(sent(&output), 2);
+        // NOTE: the second retry waits twice as long
+        assert_eq!(
+            outbox.next_deadline(),
+            Some(now + Duration::from_millis(300))
+        );
+
+        assert_eq!(outbox.ack(1).map(|message| message.body.id), Some(Some(1)));
+        assert!(outbox.ack(1).is_none());
+        assert!(outbox.is_empty());
+        assert!(outbox
+            .retry_due(&mut output, now + Duration::from_secs(10))
+            .unwrap()
+            .is_empty());
+        assert_eq!(sent(&output), 2);
+    }
+
+    #[test]
+    fn retry_due_gives_up_after_max_attempts() {
+        let mut outbox = Outbox::new(policy(0.0, Some(3)));
+        let mut output = Vec::new();
+        let mut now = Instant::now();
+        outbox.send(message(7), &mut output, now).unwrap();
+        let mut given_up = Vec::new();
+        for _ in 0..10 {
+            now += Duration::from_secs(1);
+            given_up.extend(outbox.retry_due(&mut output, now).unwrap());
+        }
+        assert_eq!(sent(&output), 3);
+        assert_eq!(given_up.len(), 1);
+        assert_eq!(given_up[0].body.id, Some(7));
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn send_needs_a_msg_id() {
+        let mut outbox = Outbox::new(policy(0.0, None));
+        let mut unnumbered = message(1);
+        unnumbered.body.id = None;
+        assert!(outbox
+            .send(unnumbered, &mut Vec::new(), Instant::now())
+            .is_err());
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn dedup_window_drops_repeats_and_evicts_the_oldest() {
+        let mut window = DedupWindow::new(2);
+        assert!(window.observe("n1", 1));
+        assert!(!window.observe("n1", 1));
+        // NOTE: the same msg_id from another node is another message
+        assert!(window.observe("n2", 1));
+        assert!(!window.observe("n1", 1));
+
+        assert!(window.observe("n1", 2));
+        // NOTE: ("n1", 1) was the oldest and fell out, a very late retry gets through
+        assert!(window.observe("n1", 1));
+        assert!(!window.observe("n1", 2));
+
+        let mut unnumbered = message(1);
+        unnumbered.body.id = None;
+        assert!(!window.is_duplicate(&unnumbered));
+        assert!(!window.is_duplicate(&unnumbered));
+    }
+}
@@ -0,0 +1,544 @@
+// Purpose: a tiny in-process Maelstrom to run a cluster without Java.
+//
+// Every node runs the real `event_loop_with` on its own thread and talks JSON lines, exactly as it
+// would over stdin/stdout. A router thread plays the network: it delays messages between nodes,
+// drops them at random and blocks them across partitions. Clients are synchronous RPC handles
+// and, like in Maelstrom, are never partitioned from the nodes.
+//
+// Messages to `lin-kv`, `seq-kv` and `lww-kv` are answered by the router itself, see `kv_service`.
+// They cross the network like messages between nodes, delayed and lost alike, but partitions
+// never cut a node off from them.
+//
+// NOTE: the simulation runs in real time, timeouts of the nodes behave exactly as in production
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[cfg(feature = "async")]
+use crate::async_loop::{async_event_loop_with, AsyncNode};
+use crate::envelope::Decode;
+use crate::kv::KvPayload;
+use crate::kv_service::{self, KvService};
+use crate::{event_loop_with, ErrorCode, Node};
+
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    /// every message between nodes is delayed uniformly within `min_latency..=max_latency`
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// probability that a message between nodes is lost
+    pub loss: f64,
+    pub seed: u64,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        return NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(5),
+            loss: 0.0,
+            seed: 1,
+        };
+    }
+}
+
+/// One message seen by the network
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// seconds since the simulation started
+    pub time: f64,
+    pub src: String,
+    pub dest: String,
+    pub body: Value,
+    /// lost or blocked by a partition
+    #[serde(default)]
+    pub dropped: bool,
+}
+
+/// Node inputs, client mailboxes and nemesis state, shared with the router thread
+struct Network {
+    config: NetworkConfig,
+    rng: StdRng,
+    started: Instant,
+    trace: Vec<TraceEntry>,
+    node_inputs: HashMap<String, mpsc::Sender<String>>,
+    clients: HashMap<String, mpsc::Sender<Value>>,
+    /// directed links `(src, dest)` that currently drop everything
+    blocked: HashSet<(String, String)>,
+    /// Maelstrom's key/value services by name
+    services: HashMap<String, KvService>,
+    next_service_msg_id: usize,
+}
+
+enum RouterEvent {
+    Line(String),
+    Stop,
+}
+
+/// A scheduled delivery, ordered by time and then by send order
+type Delivery = Reverse<(Instant, u64, String, String)>;
+
+/// Feeds lines from a channel to `event_loop_with`, EOF once the sender is dropped
+struct LineReader {
+    lines: mpsc::Receiver<String>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl Read for LineReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.buffer.len() {
+            let Ok(mut line) = self.lines.recv() else {
+                return Ok(0);
+            };
+            line.push('\n');
+            self.buffer = line.into_bytes();
+            self.position = 0;
+        }
+        let count = buf.len().min(self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        return Ok(count);
+    }
+}
+
+/// Collects the output of a node and hands every complete line to the router
+struct LineWriter {
+    router: mpsc::Sender<RouterEvent>,
+    buffer: Vec<u8>,
+}
+
+impl Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]).to_string();
+            // NOTE: the router is gone once the simulation shuts down, nothing to deliver to
+            let _ = self.router.send(RouterEvent::Line(line));
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
+
+pub struct Simulation {
+    node_ids: Vec<String>,
+    network: Arc<Mutex<Network>>,
+    router_sender: mpsc::Sender<RouterEvent>,
+    router: Option<JoinHandle<()>>,
+    nodes: HashMap<String, JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Simulation {
+    /// Create the network for a cluster of `node_ids`, nodes are started with `Simulation::start`
+    ///
+    /// NOTE: node ids must not be names of the key/value services, see `kv_service::NAMES`
+    pub fn new(node_ids: &[&str], config: NetworkConfig) -> Self {
+        let (router_sender, router_receiver) = mpsc::channel();
+        let network = Arc::new(Mutex::new(Network {
+            rng: StdRng::seed_from_u64(config.seed),
+            started: Instant::now(),
+            trace: Vec::new(),
+            config,
+            node_inputs: HashMap::new(),
+            clients: HashMap::new(),
+            blocked: HashSet::new(),
+            services: kv_service::NAMES
+                .iter()
+                .filter_map(|name| Some((name.to_string(), KvService::named(name)?)))
+                .collect(),
+            next_service_msg_id: 1,
+        }));
+        let router_network = network.clone();
+        let router = thread::spawn(move || route(router_network, router_receiver));
+        return Simulation {
+            node_ids: node_ids.iter().map(|node_id| node_id.to_string()).collect(),
+            network,
+            router_sender,
+            router: Some(router),
+            nodes: HashMap::new(),
+        };
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        return &self.node_ids;
+    }
+
+    /// Boot `node_id` as a node of type `N` and send it the `init` message
+    pub fn start<N, State, Payload, GeneratedPayload>(
+        &mut self,
+        node_id: &str,
+        state: State,
+    ) -> anyhow::Result<()>
+    where
+        N: Node<State, Payload, GeneratedPayload>,
+        State: Send + 'static,
+        Payload: Decode + Send + 'static,
+        GeneratedPayload: Send + 'static,
+    {
+        return self.spawn(node_id, move |reader, writer| {
+            return event_loop_with::<N, State, Payload, GeneratedPayload>(state, reader, writer);
+        });
+    }
+
+    /// Boot `node_id` as an `async_loop::AsyncNode` of type `N` and send it the `init` message
+    #[cfg(feature = "async")]
+    pub fn start_async<N, State, Payload>(
+        &mut self,
+        node_id: &str,
+        state: State,
+    ) -> anyhow::Result<()>
+    where
+        N: AsyncNode<State, Payload>,
+        State: Send + 'static,
+        Payload: Decode + 'static,
+    {
+        return self.spawn(node_id, move |reader, writer| {
+            return async_event_loop_with::<N, State, Payload>(state, reader, writer);
+        });
+    }
+
+    /// Connect `node_id` to the network and run its event loop on a thread of its own
+    fn spawn(
+        &mut self,
+        node_id: &str,
+        run: impl FnOnce(LineReader, LineWriter) -> anyhow::Result<()> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.node_ids.iter().any(|known| known == node_id),
+            "{} is not part of the cluster",
+            node_id
+        );
+        anyhow::ensure!(!self.is_running(node_id), "{} is already running", node_id);
+        if let Some(crashed) = self.nodes.remove(node_id) {
+            crashed.join().expect("node thread paniced")?;
+        }
+
+        let (input, lines) = mpsc::channel();
+        let init = json!({
+            "src": "sim",
+            "dest": node_id,
+            "body": {"type": "init", "msg_id": 0, "node_id": node_id, "node_ids": self.node_ids},
+        });
+        input.send(init.to_string()).context("queue init message")?;
+        self.network
+            .lock()
+            .unwrap()
+            .node_inputs
+            .insert(node_id.to_string(), input);
+
+        let reader = LineReader {
+            lines,
+            buffer: Vec::new(),
+            position: 0,
+        };
+        let writer = LineWriter {
+            router: self.router_sender.clone(),
+            buffer: Vec::new(),
+        };
+        let handle = thread::Builder::new()
+            .name(node_id.to_string())
+            .spawn(move || run(reader, writer))
+            .context("spawn node thread")?;
+        self.nodes.insert(node_id.to_string(), handle);
+        return Ok(());
+    }
+
+    pub fn is_running(&self, node_id: &str) -> bool {
+        return self
+            .network
+            .lock()
+            .unwrap()
+            .node_inputs
+            .contains_key(node_id);
+    }
+
+    /// Crash `node_id`: it sees the end of its input and messages to it are dropped from now on
+    pub fn crash(&mut self, node_id: &str) -> anyhow::Result<()> {
+        self.network.lock().unwrap().node_inputs.remove(node_id);
+        if let Some(handle) = self.nodes.remove(node_id) {
+            handle.join().expect("node thread paniced")?;
+        }
+        return Ok(());
+    }
+
+    /// Crash `node_id` and boot it again with `state`, e.g. a `persist::PersistConfig` to recover
+    pub fn restart<N, State, Payload, GeneratedPayload>(
+        &mut self,
+        node_id: &str,
+        state: State,
+    ) -> anyhow::Result<()>
+    where
+        N: Node<State, Payload, GeneratedPayload>,
+        State: Send + 'static,
+        Payload: Decode + Send + 'static,
+        GeneratedPayload: Send + 'static,
+    {
+        self.crash(node_id)?;
+        return self.start::<N, State, Payload, GeneratedPayload>(node_id, state);
+    }
+
+    /// Register a client that can send requests to any node
+    pub fn client(&self, client_id: &str) -> Client {
+        let (sender, mailbox) = mpsc::channel();
+        self.network
+            .lock()
+            .unwrap()
+            .clients
+            .insert(client_id.to_string(), sender);
+        return Client {
+            client_id: client_id.to_string(),
+            router: self.router_sender.clone(),
+            mailbox,
+            next_msg_id: 1,
+        };
+    }
+
+    /// Split the cluster: nodes in different groups can't reach each other
+    ///
+    /// Nodes that aren't listed in any group keep talking to everybody.
+    pub fn partition(&self, groups: &[&[&str]]) {
+        let mut network = self.network.lock().unwrap();
+        for (i, group) in groups.iter().enumerate() {
+            for other in groups.iter().skip(i + 1) {
+                for a in group.iter() {
+                    for b in other.iter() {
+                        network.blocked.insert((a.to_string(), b.to_string()));
+                        network.blocked.insert((b.to_string(), a.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Cut `node_id` off from every other node
+    pub fn isolate(&self, node_id: &str) {
+        let others: Vec<&str> = self
+            .node_ids
+            .iter()
+            .map(|other| other.as_str())
+            .filter(|other| *other != node_id)
+            .collect();
+        self.partition(&[&[node_id], &others]);
+    }
+
+    /// Every message sent so far, in the order the network saw them
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        return self.network.lock().unwrap().trace.clone();
+    }
+
+    /// Dump the trace as JSON lines, the format `analysis::read_trace` parses
+    pub fn write_trace(&self, output: &mut impl Write) -> anyhow::Result<()> {
+        for entry in self.network.lock().unwrap().trace.iter() {
+            serde_json::to_writer(&mut *output, entry).context("serializing trace entry")?;
+            output.write_all(b"\n").context("writing trace")?;
+        }
+        return Ok(());
+    }
+
+    /// Remove all partitions
+    pub fn heal(&self) {
+        self.network.lock().unwrap().blocked.clear();
+    }
+
+    /// Stop all nodes and the network
+    pub fn shutdown(mut self) -> anyhow::Result<()> {
+        return self.stop();
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.network.lock().unwrap().node_inputs.clear();
+        let mut result = Ok(());
+        for (node_id, handle) in self.nodes.drain() {
+            let stopped = handle.join().expect("node thread paniced");
+            if let Err(error) = stopped {
+                result = Err(error.context(format!("node {} failed", node_id)));
+            }
+        }
+        let _ = self.router_sender.send(RouterEvent::Stop);
+        if let Some(router) = self.router.take() {
+            router.join().expect("router thread paniced");
+        }
+        return result;
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// Router thread: schedules every line for delivery and delivers it when it is due
+fn route(network: Arc<Mutex<Network>>, events: mpsc::Receiver<RouterEvent>) {
+    let mut scheduled: BinaryHeap<Delivery> = BinaryHeap::new();
+    let mut sequence = 0;
+    loop {
+        let timeout = scheduled
+            .peek()
+            .map(|Reverse((due, ..))| due.saturating_duration_since(Instant::now()))
+            .unwrap_or(Duration::from_millis(100));
+        match events.recv_timeout(timeout) {
+            | Ok(RouterEvent::Line(line)) => {
+                let Some((dest, delay)) = schedule(&network, &line) else {
+                    continue;
+                };
+                sequence += 1;
+                scheduled.push(Reverse((Instant::now() + delay, sequence, dest, line)));
+            },
+            | Ok(RouterEvent::Stop) | Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            | Err(mpsc::RecvTimeoutError::Timeout) => {},
+        }
+
+        let now = Instant::now();
+        while scheduled
+            .peek()
+            .is_some_and(|Reverse((due, ..))| *due <= now)
+        {
+            let Reverse((_, _, dest, line)) = scheduled.pop().unwrap();
+            let Some(reply) = deliver(&network, &dest, line) else {
+                continue;
+            };
+            if let Some((dest, delay)) = schedule(&network, &reply) {
+                sequence += 1;
+                scheduled.push(Reverse((now + delay, sequence, dest, reply)));
+            }
+        }
+    }
+}
+
+/// Decide whether and when `line` arrives
+///
+/// returns:
+///   - the destination and the delay, `None` if the message is dropped
+fn schedule(network: &Arc<Mutex<Network>>, line: &str) -> Option<(String, Duration)> {
+    let mut message: Value = serde_json::from_str(line).ok()?;
+    let src = message["src"].as_str()?.to_string();
+    let dest = message["dest"].as_str()?.to_string();
+
+    let mut network = network.lock().unwrap();
+    // NOTE: the key/value services are peers of the nodes, only partitions leave them out
+    let to_service = network.services.contains_key(&dest);
+    let between_nodes = (network.node_inputs.contains_key(&dest) || to_service)
+        && !network.clients.contains_key(&src);
+    let loss = network.config.loss;
+    let dropped = between_nodes
+        && ((!to_service && network.blocked.contains(&(src.clone(), dest.clone())))
+            || (loss > 0.0 && network.rng.gen::<f64>() < loss));
+    let entry = TraceEntry {
+        time: network.started.elapsed().as_secs_f64(),
+        src,
+        dest: dest.clone(),
+        body: message["body"].take(),
+        dropped,
+    };
+    network.trace.push(entry);
+
+    if dropped {
+        return None;
+    }
+    if !between_nodes {
+        return Some((dest, Duration::ZERO));
+    }
+    let (min, max) = (network.config.min_latency, network.config.max_latency);
+    let delay = if max > min {
+        network.rng.gen_range(min..=max)
+    } else {
+        min
+    };
+    return Some((dest, delay));
+}
+
+/// Hand `line` to `dest`
+///
+/// returns:
+///   - the reply of a key/value service, to be routed like any other message
+fn deliver(network: &Arc<Mutex<Network>>, dest: &str, line: String) -> Option<String> {
+    let mut network = network.lock().unwrap();
+    let network = &mut *network;
+    if let Some(service) = network.services.get_mut(dest) {
+        let request: Value = serde_json::from_str(&line).ok()?;
+        let src = request["src"].as_str()?;
+        let reply = match serde_json::from_value::<KvPayload>(request["body"].clone()) {
+            | Ok(payload) => service.handle(src, &payload, Instant::now(), &mut network.rng)?,
+            | Err(error) => KvPayload::error(ErrorCode::MalformedRequest, error.to_string()),
+        };
+        let mut body = serde_json::to_value(reply).ok()?;
+        body["msg_id"] = json!(network.next_service_msg_id);
+        body["in_reply_to"] = request["body"]["msg_id"].clone();
+        network.next_service_msg_id += 1;
+        return Some(json!({"src": dest, "dest": src, "body": body}).to_string());
+    }
+    if let Some(input) = network.node_inputs.get(dest) {
+        let _ = input.send(line);
+    } else if let Some(mailbox) = network.clients.get(dest) {
+        if let Ok(message) = serde_json::from_str(&line) {
+            let _ = mailbox.send(message);
+        }
+    }
+    // NOTE: messages to crashed nodes or unknown clients vanish, like in Maelstrom
+    return None;
+}
+
+/// Synchronous client of a `Simulation`
+pub struct Client {
+    client_id: String,
+    router: mpsc::Sender<RouterEvent>,
+    mailbox: mpsc::Receiver<Value>,
+    next_msg_id: usize,
+}
+
+impl Client {
+    pub fn client_id(&self) -> &str {
+        return &self.client_id;
+    }
+
+    /// Send `payload` to `dest` and wait for the reply
+    ///
+    /// returns:
+    ///   - the reply payload, an error if no reply arrived within `timeout`
+    pub fn call<Request, Response>(
+        &mut self,
+        dest: &str,
+        payload: &Request,
+        timeout: Duration,
+    ) -> anyhow::Result<Response>
+    where
+        Request: Serialize,
+        Response: DeserializeOwned,
+    {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        let mut body = serde_json::to_value(payload).context("serialize request")?;
+        body["msg_id"] = json!(msg_id);
+        let request = json!({"src": self.client_id, "dest": dest, "body": body});
+        self.router
+            .send(RouterEvent::Line(request.to_string()))
+            .context("network is shut down")?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let reply = self
+                .mailbox
+                .recv_timeout(remaining)
+                .context(format!("no reply from {} to msg {}", dest, msg_id))?;
+            // NOTE: late replies to earlier, timed out requests are skipped
+            if reply["body"]["in_reply_to"] == json!(msg_id) {
+                return serde_json::from_value(reply["body"].clone()).context("deserialize reply");
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Purpose: answer `{"type": "status"}` from the runtime, to see what a running node knows.
+//
+// The event loop takes status requests out of the input before they reach the node, so every
+// node answers them, whatever its payload. The reply carries what the runtime knows (queue
+// metrics, steps handled, uptime) and whatever the node reports through `Node::status`, e.g.
+// neighbors, value counts, per peer acks or requests still waiting for a reply.
+//
+// NOTE: Maelstrom can't send a custom message, `EventLoopConfig::status_interval` logs the same
+// report to stderr (the node log) instead
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::queue::QueueMetrics;
+use crate::Message;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum StatusPayload {
+    Status,
+    // NOTE: boxed, status requests are rare and `NodeStatus` is large
+    StatusOk(Box<NodeStatus>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub node_id: String,
+    /// seconds since `init`
+    pub uptime: f64,
+    /// events handled by the node
+    pub steps: u64,
+    pub queue: QueueMetrics,
+    /// what `Node::status` reports, `null` if the node reports nothing
+    pub node: Value,
+}
+
+/// The parts of a `NodeStatus` the event loop keeps track of
+pub(crate) struct Runtime {
+    pub(crate) node_id: String,
+    pub(crate) started: Instant,
+    pub(crate) steps: u64,
+}
+
+impl Runtime {
+    pub(crate) fn status(&self, queue: QueueMetrics, node: Value) -> NodeStatus {
+        return NodeStatus {
+            node_id: self.node_id.clone(),
+            uptime: self.started.elapsed().as_secs_f64(),
+            steps: self.steps,
+            queue,
+            node,
+        };
+    }
+}
+
+/// The status request in `line`, `None` for every other message
+///
+/// NOTE: the substring check keeps the cost off the hot path, only candidates are parsed
+pub(crate) fn parse_request(line: &str) -> Option<Message<StatusPayload>> {
+    if !line.contains("\"status\"") {
+        return None;
+    }
+    let request: Message<StatusPayload> = serde_json::from_str(line).ok()?;
+    return matches!(request.body.payload, StatusPayload::Status).then_some(request);
+}
@@ -0,0 +1,492 @@
+// Purpose: build the topology broadcast gossips over, and check that a topology is any good.
+//
+// Works on the `node -> neighbors` maps Maelstrom sends in `topology` and that `SmallWorld`
+// builds. Links are treated as undirected, a node can always answer whoever shares with it, and a
+// link to itself is counted but otherwise ignored.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fmt::Write;
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Serialize;
+
+/// Parameters of a [Watts-Strogatz](https://en.wikipedia.org/wiki/Watts-Strogatz_model) graph
+///
+/// IMPORTANT: every node builds the graph on its own, they only agree if they use the same
+/// parameters, seed included
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmallWorld {
+    /// links per node in the ring before rewiring, half on each side (rounded down to even)
+    pub neighbors: usize,
+    /// probability that a ring link is moved to a random node
+    pub rewire_probability: f64,
+    pub seed: u64,
+}
+
+impl Default for SmallWorld {
+    fn default() -> Self {
+        return SmallWorld {
+            neighbors: 10,
+            rewire_probability: 0.3,
+            seed: 1,
+        };
+    }
+}
+
+impl SmallWorld {
+    /// Generate the topology of `node_ids`
+    ///
+    /// The graph has no self loops, is symmetric and always connected: if rewiring splits it,
+    /// one link per extra component joins it back.
+    ///
+    /// returns:
+    ///   - `HashMap<String, HashSet<String>>`: map of node to neighbors, with every node as a key
+    pub fn generate<'a>(
+        &self,
+        node_ids: impl IntoIterator<Item = &'a String>,
+    ) -> HashMap<String, HashSet<String>> {
+        // NOTE: sorted so that the order doesn't depend on how the caller's set is hashed
+        let mut nodes: Vec<&String> = node_ids.into_iter().collect();
+        nodes.sort_by(|a, b| natural_order(a, b));
+        nodes.dedup();
+        let count = nodes.len();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut links: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); count];
+        let half = (self.neighbors / 2).min(count.saturating_sub(1) / 2);
+
+        // NOTE: ring lattice, every node is linked to the `half` next nodes on each side
+        for i in 0..count {
+            for j in 1..=half {
+                let neighbor = (i + j) % count;
+                links[i].insert(neighbor);
+                links[neighbor].insert(i);
+            }
+        }
+        // NOTE: rewire every clockwise link once, to a node that isn't linked to `i` yet
+        for j in 1..=half {
+            for i in 0..count {
+                let neighbor = (i + j) % count;
+                if !links[i].contains(&neighbor) || rng.gen::<f64>() >= self.rewire_probability {
+                    continue;
+                }
+                let candidates: Vec<usize> = (0..count)
+                    .filter(|other| *other != i && !links[i].contains(other))
+                    .collect();
+                let Some(&target) = candidates.choose(&mut rng) else {
+                    continue;
+                };
+                links[i].remove(&neighbor);
+                links[neighbor].remove(&i);
+                links[i].insert(target);
+                links[target].insert(i);
+            }
+        }
+        // NOTE: join components in ring order, the repaired graph is still deterministic
+        let mut component = vec![usize::MAX; count];
+        let mut roots = Vec::new();
+        for start in 0..count {
+            if component[start] != usize::MAX {
+                continue;
+            }
+            component[start] = roots.len();
+            let mut stack = vec![start];
+            while let Some(node) = stack.pop() {
+                for &neighbor in &links[node] {
+                    if component[neighbor] == usize::MAX {
+                        component[neighbor] = roots.len();
+                        stack.push(neighbor);
+                    }
+                }
+            }
+            roots.push(start);
+        }
+        for pair in roots.windows(2) {
+            links[pair[0]].insert(pair[1]);
+            links[pair[1]].insert(pair[0]);
+        }
+
+        return links
+            .iter()
+            .enumerate()
+            .map(|(i, neighbors)| {
+                let neighbors = neighbors.iter().map(|j| nodes[*j].clone()).collect();
+                return (nodes[i].clone(), neighbors);
+            })
+            .collect();
+    }
+}
+
+/// `n2` before `n10`, so the ring follows Maelstrom's numbering
+pub(crate) fn natural_order(a: &str, b: &str) -> std::cmp::Ordering {
+    let split = |id: &str| {
+        let digits = id.trim_start_matches(|c: char| !c.is_ascii_digit());
+        return (id.len() - digits.len(), digits.parse::<u64>().ok());
+    };
+    let ((a_prefix, a_number), (b_prefix, b_number)) = (split(a), split(b));
+    return a[..a_prefix]
+        .cmp(&b[..b_prefix])
+        .then(a_number.cmp(&b_number))
+        .then(a.cmp(b));
+}
+
+/// Undirected adjacency, sorted so that reports and DOT output are reproducible
+fn undirected(topology: &HashMap<String, HashSet<String>>) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut graph: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for (node, neighbors) in topology {
+        graph.entry(node).or_default();
+        for neighbor in neighbors {
+            graph.entry(neighbor).or_default();
+            if neighbor != node {
+                graph.get_mut(node.as_str()).unwrap().insert(neighbor);
+                graph.get_mut(neighbor.as_str()).unwrap().insert(node);
+            }
+        }
+    }
+    return graph;
+}
+
+/// Hop count from `start` to every node it can reach
+fn distances<'a>(graph: &BTreeMap<&'a str, BTreeSet<&'a str>>, start: &'a str) -> Vec<usize> {
+    let mut seen: HashMap<&str, usize> = HashMap::from([(start, 0)]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(node) = queue.pop_front() {
+        let distance = seen[node];
+        for neighbor in &graph[node] {
+            if !seen.contains_key(neighbor) {
+                seen.insert(neighbor, distance + 1);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    return seen.into_values().collect();
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TopologyReport {
+    pub nodes: usize,
+    /// undirected links between two different nodes
+    pub edges: usize,
+    /// links from a node to itself, they don't spread anything
+    pub self_loops: usize,
+    /// links only one of the two ends lists
+    pub one_way: usize,
+    /// connected components, 1 if every node can reach every other
+    pub components: usize,
+    /// longest shortest path, `None` if the graph isn't connected
+    pub diameter: Option<usize>,
+    /// mean hops between two nodes that can reach each other
+    pub average_path_length: Option<f64>,
+    /// mean of the local clustering coefficients, 0 for nodes with less than 2 neighbors
+    pub clustering_coefficient: f64,
+    /// degree -> number of nodes with that degree
+    pub degrees: BTreeMap<usize, usize>,
+}
+
+impl TopologyReport {
+    pub fn from_topology(topology: &HashMap<String, HashSet<String>>) -> Self {
+        let graph = undirected(topology);
+        let self_loops = topology
+            .iter()
+            .filter(|(node, neighbors)| neighbors.contains(*node))
+            .count();
+        let one_way = topology
+            .iter()
+            .flat_map(|(node, neighbors)| neighbors.iter().map(move |neighbor| (node, neighbor)))
+            .filter(|(node, neighbor)| {
+                node != neighbor
+                    && !topology
+                        .get(*neighbor)
+                        .is_some_and(|back| back.contains(*node))
+            })
+            .count();
+
+        let mut components = 0;
+        let mut visited: HashSet<&str> = HashSet::new();
+        for node in graph.keys() {
+            if visited.insert(node) {
+                components += 1;
+                let mut stack = vec![*node];
+                while let Some(current) = stack.pop() {
+                    for neighbor in &graph[current] {
+                        if visited.insert(neighbor) {
+                            stack.push(neighbor);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut longest = 0;
+        let mut total_hops = 0;
+        let mut pairs = 0;
+        for node in graph.keys() {
+            for distance in distances(&graph, node) {
+                if distance > 0 {
+                    longest = longest.max(distance);
+                    total_hops += distance;
+                    pairs += 1;
+                }
+            }
+        }
+
+        let mut clustering = 0.0;
+        let mut degrees = BTreeMap::new();
+        for neighbors in graph.values() {
+            *degrees.entry(neighbors.len()).or_insert(0) += 1;
+            if neighbors.len() < 2 {
+                continue;
+            }
+            // NOTE: every link between two neighbors is seen from both ends
+            let links: usize = neighbors
+                .iter()
+                .map(|neighbor| graph[neighbor].intersection(neighbors).count())
+                .sum();
+            let possible = neighbors.len() * (neighbors.len() - 1);
+            clustering += links as f64 / possible as f64;
+        }
+
+        return TopologyReport {
+            nodes: graph.len(),
+            edges: graph
+                .values()
+                .map(|neighbors| neighbors.len())
+                .sum::<usize>()
+                / 2,
+            self_loops,
+            one_way,
+            components,
+            diameter: (components == 1).then_some(longest),
+            average_path_length: (pairs > 0).then(|| total_hops as f64 / pairs as f64),
+            clustering_coefficient: if graph.is_empty() {
+                0.0
+            } else {
+                clustering / graph.len() as f64
+            },
+            degrees,
+        };
+    }
+
+    pub fn is_connected(&self) -> bool {
+        return self.components == 1;
+    }
+}
+
+impl fmt::Display for TopologyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nodes:            {}", self.nodes)?;
+        writeln!(
+            f,
+            "edges:            {} ({} self loops, {} one way)",
+            self.edges, self.self_loops, self.one_way
+        )?;
+        writeln!(f, "components:       {}", self.components)?;
+        match self.diameter {
+            | Some(diameter) => writeln!(f, "diameter:         {}", diameter)?,
+            | None => writeln!(f, "diameter:         infinite, not connected")?,
+        }
+        match self.average_path_length {
+            | Some(length) => writeln!(f, "avg path length:  {:.2}", length)?,
+            | None => writeln!(f, "avg path length:  no paths")?,
+        }
+        writeln!(f, "clustering:       {:.3}", self.clustering_coefficient)?;
+        let degrees: Vec<String> = self
+            .degrees
+            .iter()
+            .map(|(degree, count)| format!("{}:{}", degree, count))
+            .collect();
+        return writeln!(f, "degree:nodes:     {}", degrees.join(" "));
+    }
+}
+
+/// Render `topology` as an undirected Graphviz graph, e.g. for `dot -Tsvg`
+pub fn to_dot(topology: &HashMap<String, HashSet<String>>) -> String {
+    let graph = undirected(topology);
+    let mut dot = String::from("graph topology {\n");
+    for (node, neighbors) in &graph {
+        if neighbors.is_empty() {
+            let _ = writeln!(dot, "    \"{}\";", node);
+        }
+        for neighbor in neighbors
+            .range::<&str, _>((std::ops::Bound::Excluded(node), std::ops::Bound::Unbounded))
+        {
+            let _ = writeln!(dot, "    \"{}\" -- \"{}\";", node, neighbor);
+        }
+    }
+    dot.push_str("}\n");
+    return dot;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topology(links: &[(&str, &[&str])]) -> HashMap<String, HashSet<String>> {
+        return links
+            .iter()
+            .map(|(node, neighbors)| {
+                let neighbors = neighbors
+                    .iter()
+                    .map(|neighbor| neighbor.to_string())
+                    .collect();
+                return (node.to_string(), neighbors);
+            })
+            .collect();
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("no value");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn ring_report() {
+        let ring = topology(&[
+            ("n0", &["n1", "n5"]),
+            ("n1", &["n0", "n2"]),
+            ("n2", &["n1", "n3"]),
+            ("n3", &["n2", "n4"]),
+            ("n4", &["n3", "n5"]),
+            ("n5", &["n4", "n0"]),
+        ]);
+        let report = TopologyReport::from_topology(&ring);
+        assert_eq!((report.nodes, report.edges, report.components), (6, 6, 1));
+        assert_eq!(report.diameter, Some(3));
+        // NOTE: from any node 1, 1, 2, 2 and 3 hops
+        assert_close(report.average_path_length, 9.0 / 5.0);
+        assert_eq!(report.clustering_coefficient, 0.0);
+        assert_eq!(report.degrees, BTreeMap::from([(2, 6)]));
+    }
+
+    #[test]
+    fn star_report() {
+        let star = topology(&[
+            ("n0", &["n1", "n2", "n3", "n4"]),
+            ("n1", &["n0"]),
+            ("n2", &["n0"]),
+            ("n3", &["n0"]),
+            ("n4", &["n0"]),
+        ]);
+        let report = TopologyReport::from_topology(&star);
+        assert_eq!((report.nodes, report.edges), (5, 4));
+        assert_eq!(report.diameter, Some(2));
+        // NOTE: the hub is 1 hop from 4 leaves, each leaf 1 from the hub and 2 from 3 leaves
+        assert_close(report.average_path_length, (4.0 + 4.0 * 7.0) / 20.0);
+        assert_eq!(report.clustering_coefficient, 0.0);
+        assert_eq!(report.degrees, BTreeMap::from([(1, 4), (4, 1)]));
+    }
+
+    #[test]
+    fn complete_graph_report() {
+        let complete = topology(&[
+            ("n0", &["n1", "n2", "n3"]),
+            ("n1", &["n0", "n2", "n3"]),
+            ("n2", &["n0", "n1", "n3"]),
+            ("n3", &["n0", "n1", "n2"]),
+        ]);
+        let report = TopologyReport::from_topology(&complete);
+        assert_eq!((report.edges, report.diameter), (6, Some(1)));
+        assert_close(report.average_path_length, 1.0);
+        assert_close(Some(report.clustering_coefficient), 1.0);
+    }
+
+    #[test]
+    fn broken_topology_report() {
+        // NOTE: `n0` lists itself and `n1`, which doesn't list it back, `n2` is on its own
+        let broken = topology(&[("n0", &["n0", "n1"]), ("n1", &[]), ("n2", &[])]);
+        let report = TopologyReport::from_topology(&broken);
+        assert_eq!((report.nodes, report.edges), (3, 1));
+        assert_eq!((report.self_loops, report.one_way), (1, 1));
+        assert_eq!(report.components, 2);
+        assert!(!report.is_connected());
+        assert_eq!(report.diameter, None);
+        assert_close(report.average_path_length, 1.0);
+    }
+
+    #[test]
+    fn dot_lists_every_link_once() {
+        let dot = to_dot(&topology(&[("n0", &["n1"]), ("n1", &["n0"]), ("n2", &[])]));
+        assert_eq!(
+            dot,
+            "graph topology {\n    \"n0\" -- \"n1\";\n    \"n2\";\n}\n"
+        );
+    }
+
+    fn ids(count: usize) -> Vec<String> {
+        return (0..count).map(|i| format!("n{}", i)).collect();
+    }
+
+    #[test]
+    fn generated_graphs_are_connected_and_symmetric() {
+        for count in [0, 1, 2, 3, 5, 10, 25] {
+            for rewire_probability in [0.0, 0.3, 1.0] {
+                for seed in 1..=5 {
+                    let small_world = SmallWorld {
+                        neighbors: 4,
+                        rewire_probability,
+                        seed,
+                    };
+                    let node_ids = ids(count);
+                    let topology = small_world.generate(&node_ids);
+                    let case = format!("{} nodes, {:?}", count, small_world);
+                    let report = TopologyReport::from_topology(&topology);
+
+                    assert_eq!(topology.len(), count, "{}: not every node is a key", case);
+                    assert!(count == 0 || report.is_connected(), "{}: {}", case, report);
+                    assert_eq!(report.self_loops, 0, "{}: self loops", case);
+                    assert_eq!(report.one_way, 0, "{}: one way links", case);
+                    // NOTE: neighbors are sets so links can't repeat, but they must stay in the graph
+                    for (node, neighbors) in &topology {
+                        assert!(
+                            neighbors
+                                .iter()
+                                .all(|neighbor| topology.contains_key(neighbor)),
+                            "{}: {} links to an unknown node",
+                            case,
+                            node
+                        );
+                    }
+                    let reversed: Vec<String> = node_ids.iter().rev().cloned().collect();
+                    assert_eq!(small_world.generate(&reversed), topology, "{}", case);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_graph() {
+        let node_ids = ids(25);
+        let small_world = SmallWorld::default();
+        assert_eq!(
+            small_world.generate(&node_ids),
+            small_world.generate(&node_ids)
+        );
+        let other_seed = SmallWorld {
+            seed: 2,
+            ..small_world
+        };
+        assert_ne!(
+            small_world.generate(&node_ids),
+            other_seed.generate(&node_ids)
+        );
+    }
+
+    #[test]
+    fn no_rewiring_is_a_ring_lattice() {
+        let small_world = SmallWorld {
+            neighbors: 4,
+            rewire_probability: 0.0,
+            seed: 1,
+        };
+        let topology = small_world.generate(&ids(10));
+        let report = TopologyReport::from_topology(&topology);
+        assert_eq!(report.edges, 20);
+        assert_eq!(report.degrees, BTreeMap::from([(4, 10)]));
+        let expected: HashSet<String> = ["n1", "n2", "n8", "n9"].map(String::from).into();
+        assert_eq!(topology["n0"], expected);
+    }
+}
@@ -0,0 +1,96 @@
+// Purpose: total order delivery of broadcasts, every node delivers the same values in the same order.
+//
+// A fixed sequencer numbers every value it hears of, the others deliver values strictly in that
+// order and hold back everything that arrives ahead of a gap. The sequencer is the first node in
+// Maelstrom's numbering, so every node agrees on it without talking.
+//
+// NOTE: the sequencer is a single point of failure, while it is down nothing new is delivered.
+// Broadcast over `raft` (`workloads::broadcast::OrderedLog`) keeps ordering through crashes.
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use crate::topology::natural_order;
+
+/// The node that numbers the values of a cluster
+pub fn sequencer(node_ids: impl IntoIterator<Item = impl AsRef<str>>) -> Option<String> {
+    return node_ids
+        .into_iter()
+        .map(|node_id| node_id.as_ref().to_string())
+        .min_by(|a, b| natural_order(a, b));
+}
+
+pub struct TotalOrder<T> {
+    /// values waiting for a lower sequence number, by sequence number
+    pending: BTreeMap<u64, T>,
+    /// delivered values, the value with sequence number `n` at index `n - 1`
+    log: Vec<T>,
+    sequences: HashMap<T, u64>,
+}
+
+impl<T: Clone + Eq + Hash> TotalOrder<T> {
+    pub fn new() -> Self {
+        return TotalOrder {
+            pending: BTreeMap::new(),
+            log: Vec::new(),
+            sequences: HashMap::new(),
+        };
+    }
+
+    /// Sequencer only: number `value` and deliver it, a value is only ever numbered once
+    ///
+    /// returns:
+    ///   - the sequence number of `value`, starting at 1
+    pub fn sequence(&mut self, value: T) -> u64 {
+        if let Some(sequence) = self.sequences.get(&value) {
+            return *sequence;
+        }
+        let sequence = self.log.len() as u64 + 1;
+        self.deliver(value, sequence);
+        return sequence;
+    }
+
+    /// Take in a value the sequencer numbered
+    ///
+    /// returns:
+    ///   - the values delivered because of it, in order; empty for duplicates and for values
+    ///     that wait for a lower sequence number
+    pub fn receive(&mut self, value: T, sequence: u64) -> Vec<T> {
+        if sequence <= self.log.len() as u64 || self.pending.contains_key(&sequence) {
+            return Vec::new();
+        }
+        self.pending.insert(sequence, value);
+
+        let mut delivered = Vec::new();
+        while let Some(value) = self.pending.remove(&(self.log.len() as u64 + 1)) {
+            let sequence = self.log.len() as u64 + 1;
+            self.deliver(value.clone(), sequence);
+            delivered.push(value);
+        }
+        return delivered;
+    }
+
+    fn deliver(&mut self, value: T, sequence: u64) {
+        self.sequences.insert(value.clone(), sequence);
+        self.log.push(value);
+    }
+
+    /// Sequence number of a delivered value
+    pub fn sequence_of(&self, value: &T) -> Option<u64> {
+        return self.sequences.get(value).copied();
+    }
+
+    pub fn delivered(&self) -> &[T] {
+        return &self.log;
+    }
+
+    /// Values waiting for a lower sequence number
+    pub fn pending(&self) -> usize {
+        return self.pending.len();
+    }
+}
+
+impl<T: Clone + Eq + Hash> Default for TotalOrder<T> {
+    fn default() -> Self {
+        return TotalOrder::new();
+    }
+}
@@ -0,0 +1,676 @@
+// Purpose: atomic commit of a transaction spread over several nodes, with two-phase commit.
+//
+// The `Coordinator` sends every participant its part of the transaction in a `prepare`. A
+// participant locks what its part touches, runs it and votes yes with the results; when a lock
+// stays taken for `lock_timeout` it votes no, which also breaks deadlocks. All yes commits,
+// anything else (or a vote that doesn't come within `prepare_timeout`) aborts. The decision is
+// logged before anyone hears of it and sent until every participant acked it, across coordinator
+// restarts when the log is persisted.
+//
+// A participant that voted yes can't decide on its own, it holds its locks and asks the
+// coordinator every `inquire_interval`. Transactions the coordinator has no decision for are
+// presumed aborted: it never logged a commit, so nobody can have committed. With a
+// `PersistConfig` the participant logs its resource and the transactions it voted yes for, so
+// that a restart keeps its locks and staged writes and it can still apply the decision.
+//
+// Components only return the messages to send, `(destination, message)`, so that a node can
+// hand the ones addressed to itself straight to its own coordinator or participant.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::persist::{Durable, PersistConfig, Store};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum CommitMessage<Op> {
+    Prepare {
+        txn_id: String,
+        ops: Vec<Op>,
+    },
+    /// `results` are the participant's ops as it ran them, only with a yes vote
+    Vote {
+        txn_id: String,
+        yes: bool,
+        results: Vec<Op>,
+    },
+    Commit {
+        txn_id: String,
+    },
+    Abort {
+        txn_id: String,
+    },
+    Ack {
+        txn_id: String,
+    },
+    /// a prepared participant asking for the decision it is missing
+    Inquire {
+        txn_id: String,
+    },
+}
+
+/// Messages to send, `(destination, message)`
+pub type Outgoing<Op> = Vec<(String, CommitMessage<Op>)>;
+
+/// Whatever state a participant's part of a transaction runs against, serializable so that the
+/// participant can persist it with its locks and the effects kept aside
+pub trait Resource: Default + Serialize + DeserializeOwned {
+    type Op: Clone + Serialize + DeserializeOwned;
+
+    /// Lock what `ops` touch and run them, keeping their effects aside until `commit`
+    ///
+    /// returns:
+    ///   - the ops as they ran, e.g. with the values read; `None` when another transaction holds
+    ///     a lock, nothing is locked then and the prepare is retried once locks are released
+    fn prepare(&mut self, txn_id: &str, ops: &[Self::Op]) -> Option<Vec<Self::Op>>;
+
+    /// Apply what `prepare` kept aside and release the locks
+    fn commit(&mut self, txn_id: &str);
+
+    /// Drop what `prepare` kept aside and release the locks
+    fn abort(&mut self, txn_id: &str);
+}
+
+#[derive(Debug, Clone)]
+pub struct CommitConfig {
+    /// how long the coordinator waits for votes before it aborts
+    pub prepare_timeout: Duration,
+    /// how often the coordinator resends a decision that isn't acked yet
+    pub retry_interval: Duration,
+    /// how often a prepared participant asks for the decision
+    pub inquire_interval: Duration,
+    /// how long a prepare waits for locks before the participant votes no
+    pub lock_timeout: Duration,
+}
+
+impl Default for CommitConfig {
+    fn default() -> Self {
+        return CommitConfig {
+            prepare_timeout: Duration::from_millis(500),
+            retry_interval: Duration::from_millis(200),
+            inquire_interval: Duration::from_millis(500),
+            lock_timeout: Duration::from_millis(200),
+        };
+    }
+}
+
+/// A transaction that was decided, as the coordinator hands it back to its node
+#[derive(Debug, Clone)]
+pub struct Outcome<Op> {
+    pub txn_id: String,
+    pub committed: bool,
+    /// every participant's results, empty for aborted transactions
+    pub results: HashMap<String, Vec<Op>>,
+}
+
+/// Decisions that aren't acked by every participant yet, the coordinator's log
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CommitLog {
+    /// txn_id -> (committed, participants that didn't ack)
+    decisions: BTreeMap<String, (bool, BTreeSet<String>)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum CommitEntry {
+    Decided {
+        txn_id: String,
+        committed: bool,
+        participants: Vec<String>,
+    },
+    Acked {
+        txn_id: String,
+        participant: String,
+    },
+}
+
+impl Durable for CommitLog {
+    type Entry = CommitEntry;
+
+    fn apply(&mut self, entry: &CommitEntry) {
+        match entry {
+            | CommitEntry::Decided {
+                txn_id,
+                committed,
+                participants,
+            } => {
+                let waiting = participants.iter().cloned().collect();
+                self.decisions.insert(txn_id.clone(), (*committed, waiting));
+            },
+            | CommitEntry::Acked {
+                txn_id,
+                participant,
+            } => {
+                if let Some((_, waiting)) = self.decisions.get_mut(txn_id) {
+                    waiting.remove(participant);
+                    if waiting.is_empty() {
+                        self.decisions.remove(txn_id);
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// A transaction waiting for votes
+struct Voting<Op> {
+    started: Instant,
+    waiting: BTreeSet<String>,
+    results: HashMap<String, Vec<Op>>,
+}
+
+pub struct Coordinator<Op> {
+    /// prefix of every txn_id, unique per run of the node
+    prefix: String,
+    next_txn: u64,
+    config: CommitConfig,
+    voting: HashMap<String, Voting<Op>>,
+    log: CommitLog,
+    store: Option<Store<CommitLog>>,
+    last_retry: Instant,
+}
+
+impl<Op: Clone> Coordinator<Op> {
+    /// Coordinator of `node_id`, with the decisions an earlier run left in `persist`
+    pub fn new(
+        node_id: &str,
+        config: CommitConfig,
+        persist: Option<&PersistConfig>,
+        now: Instant,
+    ) -> anyhow::Result<Self> {
+        let (store, log) = match persist {
+            | Some(persist) => {
+                let (store, log) = Store::open(persist, node_id)?;
+                (Some(store), log)
+            },
+            | None => (None, CommitLog::default()),
+        };
+        // NOTE: a restarted node must not reuse the txn_ids its participants may still hold
+        let boot = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or(0);
+        return Ok(Coordinator {
+            prefix: format!("{}:{}", node_id, boot),
+            next_txn: 1,
+            config,
+            voting: HashMap::new(),
+            log,
+            store,
+            last_retry: now,
+        });
+    }
+
+    /// Decisions recovered or not acked yet
+    pub fn undelivered(&self) -> usize {
+        return self.log.decisions.len();
+    }
+
+    /// Transactions still waiting for votes
+    pub fn voting(&self) -> usize {
+        return self.voting.len();
+    }
+
+    /// Start a transaction
+    ///
+    /// args:
+    ///   - `parts`: every participant and its share of the transaction's ops
+    ///
+    /// returns:
+    ///   - the txn_id that the transaction's `Outcome` will carry and the prepares to send
+    pub fn begin(&mut self, parts: Vec<(String, Vec<Op>)>, now: Instant) -> (String, Outgoing<Op>) {
+        let txn_id = format!("{}:{}", self.prefix, self.next_txn);
+        self.next_txn += 1;
+        self.voting.insert(
+            txn_id.clone(),
+            Voting {
+                started: now,
+                waiting: parts
+                    .iter()
+                    .map(|(participant, _)| participant.clone())
+                    .collect(),
+                results: HashMap::new(),
+            },
+        );
+        let outbox = parts
+            .into_iter()
+            .map(|(participant, ops)| {
+                let prepare = CommitMessage::Prepare {
+                    txn_id: txn_id.clone(),
+                    ops,
+                };
+                return (participant, prepare);
+            })
+            .collect();
+        return (txn_id, outbox);
+    }
+
+    /// Take in a vote, an ack or an inquiry from a participant
+    ///
+    /// returns:
+    ///   - the messages to send and the outcome if this decided a transaction
+    pub fn receive(
+        &mut self,
+        src: &str,
+        message: CommitMessage<Op>,
+    ) -> anyhow::Result<(Outgoing<Op>, Option<Outcome<Op>>)> {
+        match message {
+            | CommitMessage::Vote {
+                txn_id,
+                yes,
+                results,
+            } => {
+                let Some(voting) = self.voting.get_mut(&txn_id) else {
+                    // NOTE: a prepare that arrived after the decision, its locks must go again
+                    let reply = (src.to_string(), self.decision_for(txn_id));
+                    return Ok((vec![reply], None));
+                };
+                if yes && voting.waiting.remove(src) {
+                    voting.results.insert(src.to_string(), results);
+                }
+                if yes && !voting.waiting.is_empty() {
+                    return Ok((Vec::new(), None));
+                }
+                let (outbox, outcome) = self.decide(&txn_id, yes)?;
+                return Ok((outbox, Some(outcome)));
+            },
+            | CommitMessage::Ack { txn_id } => {
+                let waiting = self
+                    .log
+                    .decisions
+                    .get(&txn_id)
+                    .is_some_and(|(_, waiting)| waiting.contains(src));
+                if !waiting {
+                    return Ok((Vec::new(), None));
+                }
+                self.record(CommitEntry::Acked {
+                    txn_id,
+                    participant: src.to_string(),
+                })?;
+                return Ok((Vec::new(), None));
+            },
+            | CommitMessage::Inquire { txn_id } => {
+                if self.voting.contains_key(&txn_id) {
+                    return Ok((Vec::new(), None));
+                }
+                let reply = (src.to_string(), self.decision_for(txn_id));
+                return Ok((vec![reply], None));
+            },
+            | CommitMessage::Prepare { .. }
+            | CommitMessage::Commit { .. }
+            | CommitMessage::Abort { .. } => return Ok((Vec::new(), None)),
+        }
+    }
+
+    /// Abort transactions that ran out of time to vote and resend unacked decisions
+    pub fn tick(&mut self, now: Instant) -> anyhow::Result<(Outgoing<Op>, Vec<Outcome<Op>>)> {
+        let expired: Vec<String> = self
+            .voting
+            .iter()
+            .filter(|(_, voting)| {
+                return now.saturating_duration_since(voting.started)
+                    >= self.config.prepare_timeout;
+            })
+            .map(|(txn_id, _)| txn_id.clone())
+            .collect();
+        let mut outbox = Vec::new();
+        let mut outcomes = Vec::new();
+        for txn_id in expired {
+            let (sent, outcome) = self.decide(&txn_id, false)?;
+            outbox.extend(sent);
+            outcomes.push(outcome);
+        }
+
+        if now.saturating_duration_since(self.last_retry) >= self.config.retry_interval {
+            self.last_retry = now;
+            for (txn_id, (committed, waiting)) in &self.log.decisions {
+                for participant in waiting {
+                    outbox.push((participant.clone(), decision(txn_id, *committed)));
+                }
+            }
+        }
+        return Ok((outbox, outcomes));
+    }
+
+    /// The decision for a transaction that isn't voting anymore
+    fn decision_for(&self, txn_id: String) -> CommitMessage<Op> {
+        // NOTE: presumed abort, a transaction without a logged decision never committed
+        return match self.log.decisions.get(&txn_id) {
+            | Some((true, _)) => CommitMessage::Commit { txn_id },
+            | Some((false, _)) | None => CommitMessage::Abort { txn_id },
+        };
+    }
+
+    fn decide(
+        &mut self,
+        txn_id: &str,
+        committed: bool,
+    ) -> anyhow::Result<(Outgoing<Op>, Outcome<Op>)> {
+        let voting = self
+            .voting
+            .remove(txn_id)
+            .expect("only transactions that are voting get decided");
+        let participants: Vec<String> = voting
+            .waiting
+            .iter()
+            .chain(voting.results.keys())
+            .cloned()
+            .collect();
+        // IMPORTANT: logged before anyone hears of it, a participant may commit right away
+        self.record(CommitEntry::Decided {
+            txn_id: txn_id.to_string(),
+            committed,
+            participants: participants.clone(),
+        })?;
+        let outbox = participants
+            .into_iter()
+            .map(|participant| (participant, decision(txn_id, committed)))
+            .collect();
+        let outcome = Outcome {
+            txn_id: txn_id.to_string(),
+            committed,
+            results: if committed {
+                voting.results
+            } else {
+                HashMap::new()
+            },
+        };
+        return Ok((outbox, outcome));
+    }
+
+    fn record(&mut self, entry: CommitEntry) -> anyhow::Result<()> {
+        self.log.apply(&entry);
+        if let Some(store) = &mut self.store {
+            store.record(&self.log, &entry)?;
+        }
+        return Ok(());
+    }
+}
+
+fn decision<Op>(txn_id: &str, committed: bool) -> CommitMessage<Op> {
+    let txn_id = txn_id.to_string();
+    if committed {
+        return CommitMessage::Commit { txn_id };
+    }
+    return CommitMessage::Abort { txn_id };
+}
+
+/// A transaction this participant voted yes for
+#[derive(Debug, Serialize, Deserialize)]
+struct Prepared<Op> {
+    coordinator: String,
+    results: Vec<Op>,
+}
+
+/// What a participant must not forget across restarts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ParticipantLog<R: Resource> {
+    resource: R,
+    prepared: BTreeMap<String, Prepared<R::Op>>,
+    /// so that a commit resent because the ack got lost is acked again
+    ///
+    /// NOTE: grows by one txn_id per commit, which is fine for a Maelstrom-length run
+    committed: BTreeSet<String>,
+}
+
+// NOTE: not derived, that would ask for `R::Op: Default`
+impl<R: Resource> Default for ParticipantLog<R> {
+    fn default() -> Self {
+        return ParticipantLog {
+            resource: R::default(),
+            prepared: BTreeMap::new(),
+            committed: BTreeSet::new(),
+        };
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ParticipantEntry<Op> {
+    /// only logged once `ops` got their locks, replaying it prepares them again
+    Prepared {
+        txn_id: String,
+        coordinator: String,
+        ops: Vec<Op>,
+    },
+    Committed {
+        txn_id: String,
+    },
+    Aborted {
+        txn_id: String,
+    },
+}
+
+impl<R: Resource> Durable for ParticipantLog<R> {
+    type Entry = ParticipantEntry<R::Op>;
+
+    fn apply(&mut self, entry: &ParticipantEntry<R::Op>) {
+        match entry {
+            | ParticipantEntry::Prepared {
+                txn_id,
+                coordinator,
+                ops,
+            } => {
+                if let Some(results) = self.resource.prepare(txn_id, ops) {
+                    let prepared = Prepared {
+                        coordinator: coordinator.clone(),
+                        results,
+                    };
+                    self.prepared.insert(txn_id.clone(), prepared);
+                }
+            },
+            | ParticipantEntry::Committed { txn_id } => {
+                if self.prepared.remove(txn_id).is_some() {
+                    self.resource.commit(txn_id);
+                }
+                self.committed.insert(txn_id.clone());
+            },
+            | ParticipantEntry::Aborted { txn_id } => {
+                if self.prepared.remove(txn_id).is_some() {
+                    self.resource.abort(txn_id);
+                }
+            },
+        }
+    }
+}
+
+/// A prepare waiting for locks another transaction holds
+struct Blocked<Op> {
+    coordinator: String,
+    txn_id: String,
+    ops: Vec<Op>,
+    since: Instant,
+}
+
+pub struct Participant<R: Resource> {
+    config: CommitConfig,
+    log: ParticipantLog<R>,
+    store: Option<Store<ParticipantLog<R>>>,
+    /// txn_id -> when the decision was last asked for, prepared transactions only
+    last_inquiry: HashMap<String, Instant>,
+    /// in arrival order, retried whenever a transaction releases its locks
+    blocked: Vec<Blocked<R::Op>>,
+}
+
+impl<R: Resource> Participant<R> {
+    /// Participant of `node_id`, with the resource and prepared transactions an earlier run left
+    /// in `persist`
+    pub fn new(
+        node_id: &str,
+        config: CommitConfig,
+        persist: Option<&PersistConfig>,
+    ) -> anyhow::Result<Self> {
+        let (store, log) = match persist {
+            | Some(persist) => {
+                // NOTE: the node's coordinator already keeps its log in `dir/<node_id>`
+                let (store, log) = Store::open(persist, &format!("{}-participant", node_id))?;
+                (Some(store), log)
+            },
+            | None => (None, ParticipantLog::default()),
+        };
+        return Ok(Participant {
+            config,
+            log,
+            store,
+            last_inquiry: HashMap::new(),
+            blocked: Vec::new(),
+        });
+    }
+
+    pub fn resource(&self) -> &R {
+        return &self.log.resource;
+    }
+
+    /// Transactions that hold locks while they wait for a decision
+    pub fn prepared(&self) -> usize {
+        return self.log.prepared.len();
+    }
+
+    /// Prepares waiting for locks
+    pub fn blocked(&self) -> usize {
+        return self.blocked.len();
+    }
+
+    /// Take in a prepare or a decision from a coordinator
+    pub fn receive(
+        &mut self,
+        src: &str,
+        message: CommitMessage<R::Op>,
+        now: Instant,
+    ) -> anyhow::Result<Outgoing<R::Op>> {
+        let reply = match message {
+            | CommitMessage::Prepare { txn_id, ops } => {
+                // NOTE: a repeated prepare gets the same vote, the locks are ours already
+                if let Some(prepared) = self.log.prepared.get(&txn_id) {
+                    let results = prepared.results.clone();
+                    return Ok(vec![(src.to_string(), yes(txn_id, results))]);
+                }
+                if self.blocked.iter().any(|blocked| blocked.txn_id == txn_id) {
+                    return Ok(Vec::new());
+                }
+                self.blocked.push(Blocked {
+                    coordinator: src.to_string(),
+                    txn_id,
+                    ops,
+                    since: now,
+                });
+                return self.unblock(now);
+            },
+            | CommitMessage::Commit { txn_id } => {
+                if self.log.prepared.contains_key(&txn_id) {
+                    self.last_inquiry.remove(&txn_id);
+                    self.record(ParticipantEntry::Committed {
+                        txn_id: txn_id.clone(),
+                    })?;
+                } else if !self.log.committed.contains(&txn_id) {
+                    // IMPORTANT: the prepare was lost in a restart without a `PersistConfig`, an
+                    // ack would tell the coordinator that writes which are gone were applied
+                    return Ok(Vec::new());
+                }
+                CommitMessage::Ack { txn_id }
+            },
+            | CommitMessage::Abort { txn_id } => {
+                // NOTE: another participant voted no while this one still waited for locks
+                self.blocked.retain(|blocked| blocked.txn_id != txn_id);
+                if self.log.prepared.contains_key(&txn_id) {
+                    self.last_inquiry.remove(&txn_id);
+                    self.record(ParticipantEntry::Aborted {
+                        txn_id: txn_id.clone(),
+                    })?;
+                }
+                CommitMessage::Ack { txn_id }
+            },
+            | CommitMessage::Vote { .. }
+            | CommitMessage::Ack { .. }
+            | CommitMessage::Inquire { .. } => return Ok(Vec::new()),
+        };
+        let mut outbox = vec![(src.to_string(), reply)];
+        outbox.extend(self.unblock(now)?);
+        return Ok(outbox);
+    }
+
+    /// Vote no for prepares that waited too long for locks, ask for decisions that are overdue
+    pub fn tick(&mut self, now: Instant) -> Outgoing<R::Op> {
+        let mut outbox = Vec::new();
+        let lock_timeout = self.config.lock_timeout;
+        self.blocked.retain(|blocked| {
+            if now.saturating_duration_since(blocked.since) < lock_timeout {
+                return true;
+            }
+            let no = CommitMessage::Vote {
+                txn_id: blocked.txn_id.clone(),
+                yes: false,
+                results: Vec::new(),
+            };
+            outbox.push((blocked.coordinator.clone(), no));
+            return false;
+        });
+        for (txn_id, prepared) in &self.log.prepared {
+            // NOTE: transactions recovered from the log ask right away
+            let due = self.last_inquiry.get(txn_id).is_none_or(|last_inquiry| {
+                return now.saturating_duration_since(*last_inquiry)
+                    >= self.config.inquire_interval;
+            });
+            if due {
+                self.last_inquiry.insert(txn_id.clone(), now);
+                let inquire = CommitMessage::Inquire {
+                    txn_id: txn_id.clone(),
+                };
+                outbox.push((prepared.coordinator.clone(), inquire));
+            }
+        }
+        return outbox;
+    }
+
+    /// Prepare the blocked transactions whose locks are free now, in arrival order
+    fn unblock(&mut self, now: Instant) -> anyhow::Result<Outgoing<R::Op>> {
+        let mut outbox = Vec::new();
+        let mut index = 0;
+        while index < self.blocked.len() {
+            let blocked = &self.blocked[index];
+            let entry = ParticipantEntry::Prepared {
+                txn_id: blocked.txn_id.clone(),
+                coordinator: blocked.coordinator.clone(),
+                ops: blocked.ops.clone(),
+            };
+            // NOTE: a prepare that finds a lock taken changes nothing and isn't logged
+            self.log.apply(&entry);
+            let Some(prepared) = self.log.prepared.get(&blocked.txn_id) else {
+                index += 1;
+                continue;
+            };
+            let vote = yes(blocked.txn_id.clone(), prepared.results.clone());
+            let blocked = self.blocked.remove(index);
+            self.persist(&entry)?;
+            self.last_inquiry.insert(blocked.txn_id, now);
+            outbox.push((blocked.coordinator, vote));
+        }
+        return Ok(outbox);
+    }
+
+    fn record(&mut self, entry: ParticipantEntry<R::Op>) -> anyhow::Result<()> {
+        self.log.apply(&entry);
+        return self.persist(&entry);
+    }
+
+    /// Log `entry`, which `self.log` already includes
+    fn persist(&mut self, entry: &ParticipantEntry<R::Op>) -> anyhow::Result<()> {
+        if let Some(store) = &mut self.store {
+            store.record(&self.log, entry)?;
+        }
+        return Ok(());
+    }
+}
+
+fn yes<Op>(txn_id: String, results: Vec<Op>) -> CommitMessage<Op> {
+    return CommitMessage::Vote {
+        txn_id,
+        yes: true,
+        results,
+    };
+}
@@ -0,0 +1,721 @@
+// Purpose: Broadcast values to every node, gossiping over a small world topology.
+use crate::causal::{CausalOrder, CausalStamp};
+use crate::failure_detector::{DetectorKind, FailureDetector};
+use crate::persist::{Durable, PersistConfig, Store};
+use crate::queue::EventSender;
+use crate::raft::RaftService;
+use crate::reliable::{DedupWindow, Outbox, RetryPolicy};
+use crate::topology::SmallWorld;
+use crate::total_order::{self, TotalOrder};
+use crate::*;
+
+use anyhow::{bail, Context, Ok};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Write,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")] // IMPORTANT: returns {type:"broadcast", message:...}
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    //NOTE: find a way to remove OKs from this enum
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        /// in delivery order unless the broadcast is unordered
+        messages: Vec<usize>,
+    },
+    Topology {
+        topology: HashMap<String, HashSet<String>>,
+    },
+    TopologyOk,
+    // NOTE: sent between nodes
+    Share {
+        messages: HashSet<usize>,
+        /// causal mode only, the stamp of every shared value
+        // NOTE: a list, integer map keys don't survive the `type` tag
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        stamps: Vec<(usize, CausalStamp)>,
+        /// sequencer mode only, the sequence number of every shared value
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sequences: Vec<(usize, u64)>,
+    },
+    ShareOk {
+        messages: HashSet<usize>,
+    },
+    Heartbeat,
+    /// sequencer mode, values still waiting for a number, resent until they come back numbered
+    Order {
+        messages: HashSet<usize>,
+    },
+    Error {
+        code: ErrorCode,
+        text: String,
+    },
+}
+
+/// In what order nodes deliver broadcast values, and so what `read` returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeliveryOrder {
+    /// values in any order, `read` is a set
+    #[default]
+    Unordered,
+    /// a value after everything its broadcaster had delivered, see `causal`
+    Causal,
+    /// the same order on every node, numbered by a fixed sequencer, see `total_order`
+    Sequencer,
+    /// the same order on every node, agreed on with Raft, served by `OrderedLog`
+    Consensus,
+}
+
+impl DeliveryOrder {
+    pub const ALL: [DeliveryOrder; 4] = [
+        DeliveryOrder::Unordered,
+        DeliveryOrder::Causal,
+        DeliveryOrder::Sequencer,
+        DeliveryOrder::Consensus,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            | DeliveryOrder::Unordered => "unordered",
+            | DeliveryOrder::Causal => "causal",
+            | DeliveryOrder::Sequencer => "sequencer",
+            | DeliveryOrder::Consensus => "consensus",
+        };
+    }
+}
+
+impl fmt::Display for DeliveryOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+impl FromStr for DeliveryOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let Some(order) = DeliveryOrder::ALL.iter().find(|order| order.name() == name) else {
+            let names: Vec<&str> = DeliveryOrder::ALL
+                .iter()
+                .map(|order| order.name())
+                .collect();
+            bail!(
+                "unknown delivery order {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+        };
+        return Ok(*order);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum GeneratedPayload {
+    Propogate,
+}
+
+/// Everything a node must not forget across a crash
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BroadcastState {
+    messages: HashSet<usize>,
+    known_by_node: HashMap<String, HashSet<usize>>,
+}
+
+/// Changes to `BroadcastState`, named after the message that caused them
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum BroadcastEntry {
+    Broadcast {
+        message: usize,
+    },
+    Share {
+        from: String,
+        messages: HashSet<usize>,
+    },
+    ShareOk {
+        from: String,
+        messages: HashSet<usize>,
+    },
+}
+
+impl Durable for BroadcastState {
+    type Entry = BroadcastEntry;
+
+    fn apply(&mut self, entry: &BroadcastEntry) {
+        match entry {
+            | BroadcastEntry::Broadcast { message } => {
+                self.messages.insert(*message);
+            },
+            | BroadcastEntry::Share { from, messages } => {
+                // NOTE: the sender obviously knows the values it shared with us
+                self.known_by_node
+                    .entry(from.clone())
+                    .or_default()
+                    .extend(messages.iter().copied());
+                self.messages.extend(messages.iter().copied());
+            },
+            | BroadcastEntry::ShareOk { from, messages } => {
+                // NOTE: The node knows that the source node has recieved our sent values
+                self.known_by_node
+                    .entry(from.clone())
+                    .or_default()
+                    .extend(messages.iter().copied());
+            },
+        }
+    }
+}
+
+pub struct BroadcastNode {
+    node_id: String,
+    node_ids: HashSet<String>,
+    local_id: usize,
+    state: BroadcastState,
+    /// `None` unless the node runs with a `PersistConfig`
+    store: Option<Store<BroadcastState>>,
+    neighbors: HashSet<String>,
+    topology: SmallWorld,
+    /// `Some` in causal mode
+    causal: Option<CausalOrder<usize>>,
+    /// `Some` in sequencer mode
+    total: Option<TotalOrder<usize>>,
+    sequencer: String,
+    /// values broadcast here that the sequencer hasn't numbered yet
+    unsequenced: HashSet<usize>,
+    failure_detector: Box<dyn FailureDetector>,
+    last_sent: HashMap<String, Instant>,
+    /// shares not acknowledged yet, resent with backoff until their `share_ok` arrives
+    shares: Outbox<Payload>,
+    /// shares already handled, a resent one is acknowledged again but not applied twice
+    seen_shares: DedupWindow,
+    /// peers outside the neighborhood that share with us, the stand-in of a neighbor they lost,
+    /// and when they last did
+    stand_in_for: HashMap<String, Instant>,
+}
+
+const PROPOGATION_DELAY: Duration = Duration::from_millis(450);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(1000);
+const SUSPECT_TIMEOUT: Duration = Duration::from_millis(3000);
+
+/// Settings of a broadcast node, the same for every node of a cluster
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastConfig {
+    /// replaces the topology Maelstrom suggests
+    pub topology: SmallWorld,
+    /// keep the state on disk, see `persist`
+    pub persist: Option<PersistConfig>,
+    /// `DeliveryOrder::Consensus` isn't served by `BroadcastNode` but by `RaftNode<OrderedLog>`
+    pub order: DeliveryOrder,
+    /// how neighbors to route around are found
+    pub detector: DetectorKind,
+}
+
+impl BroadcastNode {
+    /// Apply `entry` and log it, before anything that depends on it is acknowledged
+    fn record(&mut self, entry: BroadcastEntry) -> anyhow::Result<()> {
+        self.state.apply(&entry);
+        if let Some(store) = &mut self.store {
+            store
+                .record(&self.state, &entry)
+                .context("persisting broadcast state")?;
+        }
+        return Ok(());
+    }
+
+    /// Nodes to share with: live neighbors plus one live stand-in for every suspected neighbor
+    ///
+    /// NOTE: a stand-in is any live node outside the neighborhood, it relays the values to the
+    /// other side of the partition through its own neighbors, and shares back with the nodes it
+    /// stands in for, which may have lost all their neighbors
+    fn share_targets(&self, now: Instant) -> Vec<String> {
+        let mut targets: Vec<String> = Vec::new();
+        let mut suspected_count = 0;
+        for neighbor in &self.neighbors {
+            if self.failure_detector.is_alive(neighbor, now) {
+                targets.push(neighbor.clone());
+            } else {
+                suspected_count += 1;
+            }
+        }
+
+        let mut stand_ins: Vec<&String> = self
+            .node_ids
+            .iter()
+            .filter(|node_id| {
+                **node_id != self.node_id
+                    && !self.neighbors.contains(*node_id)
+                    && self.failure_detector.is_alive(node_id, now)
+            })
+            .collect();
+        stand_ins.sort();
+        targets.extend(stand_ins.into_iter().take(suspected_count).cloned());
+        for (peer, shared) in &self.stand_in_for {
+            if now.saturating_duration_since(*shared) < SUSPECT_TIMEOUT && !targets.contains(peer) {
+                targets.push(peer.clone());
+            }
+        }
+        return targets;
+    }
+
+    fn send_to_peer(
+        &mut self,
+        dest: &str,
+        payload: Payload,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        self.last_sent.insert(dest.to_string(), now);
+        return Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                clock: None,
+                payload,
+            },
+        }
+        .send(&mut *output, "Propogate")
+        .context(format!("Sharing/sending messages to {}", dest));
+    }
+
+    /// Send a `share` through the outbox, it goes out again until `dest` acknowledges it
+    fn share(
+        &mut self,
+        dest: &str,
+        payload: Payload,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        self.last_sent.insert(dest.to_string(), now);
+        let message = Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(self.local_id),
+                in_reply_to: None,
+                clock: None,
+                payload,
+            },
+        };
+        self.local_id += 1;
+        return self
+            .shares
+            .send(message, output, now)
+            .context(format!("Sharing messages with {}", dest));
+    }
+
+    /// Values in a share that `dest` hasn't acknowledged yet, per destination
+    fn in_flight(&self) -> HashMap<String, HashSet<usize>> {
+        let mut in_flight: HashMap<String, HashSet<usize>> = HashMap::new();
+        for message in self.shares.pending() {
+            if let Payload::Share { messages, .. } = &message.body.payload {
+                in_flight
+                    .entry(message.dest.clone())
+                    .or_default()
+                    .extend(messages.iter().copied());
+            }
+        }
+        return in_flight;
+    }
+}
+
+// NOTE: state machine
+impl Node<BroadcastConfig, Payload, GeneratedPayload> for BroadcastNode {
+    fn from_init(
+        config: BroadcastConfig,
+        init: InitNodes,
+        sender: EventSender<Payload, GeneratedPayload>,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            config.order != DeliveryOrder::Consensus,
+            "consensus ordered broadcast runs on RaftNode<OrderedLog>"
+        );
+        // NOTE: the delivery order and the buffers only live in memory
+        anyhow::ensure!(
+            config.order == DeliveryOrder::Unordered || config.persist.is_none(),
+            "{} broadcast can't be persisted",
+            config.order
+        );
+        let (store, mut state) = match &config.persist {
+            | Some(config) => {
+                let (store, state) = Store::open(config, &init.node_id)?;
+                (Some(store), state)
+            },
+            | None => (None, BroadcastState::default()),
+        };
+        for node_id in &init.node_ids {
+            state.known_by_node.entry(node_id.clone()).or_default();
+        }
+
+        let node_id = init.node_id.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(PROPOGATION_DELAY);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: GeneratedPayload::Propogate,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+        let causal =
+            (config.order == DeliveryOrder::Causal).then(|| CausalOrder::new(&init.node_id));
+        let total = (config.order == DeliveryOrder::Sequencer).then(TotalOrder::new);
+        let sequencer = total_order::sequencer(&init.node_ids).unwrap_or_default();
+        return Ok(BroadcastNode {
+            failure_detector: config.detector.build(
+                &init,
+                HEARTBEAT_INTERVAL,
+                SUSPECT_TIMEOUT,
+                Instant::now(),
+            ),
+            node_id: init.node_id,
+            local_id: 1,
+            state,
+            store,
+            neighbors: HashSet::new(),
+            topology: config.topology,
+            causal,
+            total,
+            sequencer,
+            unsequenced: HashSet::new(),
+            node_ids: init.node_ids,
+            last_sent: HashMap::new(),
+            shares: Outbox::new(RetryPolicy {
+                initial_backoff: PROPOGATION_DELAY,
+                ..RetryPolicy::default()
+            }),
+            seen_shares: DedupWindow::default(),
+            stand_in_for: HashMap::new(),
+        });
+    }
+
+    fn step(
+        &mut self,
+        event: Event<Payload, GeneratedPayload>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let now = Instant::now();
+        match event {
+            | Event::EndOfMessages => {
+                // IMPORTANT: handle terminating of Propogate loop
+                // NOTE: currently just ends on test end. That is fine
+            },
+            | Event::GeneratedEvent(message) => match message.body.payload {
+                | GeneratedPayload::Propogate => {
+                    // NOTE: retries never give up, a suspected peer is only tried less often
+                    self.shares.retry_due(&mut *output, now)?;
+                    let in_flight = self.in_flight();
+                    for node_to_message in self.share_targets(now) {
+                        let pending = in_flight.get(&node_to_message);
+                        let messages_to_send: HashSet<usize> = self
+                            .state
+                            .messages
+                            .iter()
+                            .copied()
+                            .filter(|message| {
+                                !self.state.known_by_node[&node_to_message].contains(message)
+                                    && !pending.is_some_and(|pending| pending.contains(message))
+                            })
+                            .collect();
+
+                        // IMPORTANT: For efficiency, only share if there is something to share.
+                        if !messages_to_send.is_empty() {
+                            let stamps = match &self.causal {
+                                | Some(causal) => messages_to_send
+                                    .iter()
+                                    .filter_map(|message| {
+                                        let stamp = causal.stamp(message)?.clone();
+                                        return Some((*message, stamp));
+                                    })
+                                    .collect(),
+                                | None => Vec::new(),
+                            };
+                            let sequences = match &self.total {
+                                | Some(total) => messages_to_send
+                                    .iter()
+                                    .filter_map(|message| {
+                                        let sequence = total.sequence_of(message)?;
+                                        return Some((*message, sequence));
+                                    })
+                                    .collect(),
+                                | None => Vec::new(),
+                            };
+                            self.share(
+                                &node_to_message,
+                                Payload::Share {
+                                    messages: messages_to_send,
+                                    stamps,
+                                    sequences,
+                                },
+                                now,
+                                output,
+                            )?;
+                        }
+                    }
+
+                    // NOTE: the sequencer may have missed an order, or be down, ask again
+                    if !self.unsequenced.is_empty() {
+                        let messages = self.unsequenced.clone();
+                        let sequencer = self.sequencer.clone();
+                        self.send_to_peer(&sequencer, Payload::Order { messages }, now, output)?;
+                    }
+
+                    // NOTE: idle links need a heartbeat so the peer doesn't suspect us; every node,
+                    // not only neighbors, a suspected neighbor's stand-in must be known alive
+                    let idle: Vec<String> = self
+                        .node_ids
+                        .iter()
+                        .filter(|peer| {
+                            **peer != self.node_id
+                                && self.last_sent.get(*peer).is_none_or(|sent| {
+                                    now.duration_since(*sent) >= HEARTBEAT_INTERVAL
+                                })
+                        })
+                        .cloned()
+                        .collect();
+                    for peer in idle {
+                        self.send_to_peer(&peer, Payload::Heartbeat, now, output)?;
+                    }
+                },
+            },
+            | Event::Message(message) => {
+                // NOTE: any traffic from a peer proves it is reachable
+                self.failure_detector.heartbeat(&message.src, now);
+                if matches!(message.body.payload, Payload::Share { .. })
+                    && !self.neighbors.contains(&message.src)
+                {
+                    self.stand_in_for.insert(message.src.clone(), now);
+                }
+                let duplicate_share = matches!(message.body.payload, Payload::Share { .. })
+                    && self.seen_shares.is_duplicate(&message);
+                let mut reply = message.into_reply(Some(&mut self.local_id));
+                match reply.body.payload {
+                    // NOTE: can make this more efficient by sending known_to and updating between
+                    // all nodes NOT just within a node
+                    | Payload::Broadcast { message } => {
+                        reply.body.payload = Payload::BroadcastOk;
+                        let is_sequencer = self.node_id == self.sequencer;
+                        if self.total.is_some() && !is_sequencer {
+                            // NOTE: delivered once it comes back numbered, like any other value
+                            if !self.state.messages.contains(&message)
+                                && self.unsequenced.insert(message)
+                            {
+                                let sequencer = self.sequencer.clone();
+                                let messages = HashSet::from([message]);
+                                self.send_to_peer(
+                                    &sequencer,
+                                    Payload::Order { messages },
+                                    now,
+                                    output,
+                                )?;
+                            }
+                        } else if !self.state.messages.contains(&message) {
+                            if let Some(causal) = &mut self.causal {
+                                causal.broadcast(message);
+                            }
+                            if let Some(total) = &mut self.total {
+                                total.sequence(message);
+                            }
+                            self.record(BroadcastEntry::Broadcast { message })?;
+                        }
+                        reply.send(output, "broadcast")?;
+                    },
+                    | Payload::Read => {
+                        let messages = match (&self.causal, &self.total) {
+                            | (Some(causal), _) => causal.delivered().to_vec(),
+                            | (_, Some(total)) => total.delivered().to_vec(),
+                            | (None, None) => self.state.messages.iter().copied().collect(),
+                        };
+                        reply.body.payload = Payload::ReadOk { messages };
+                        reply.send(output, "read")?;
+                    },
+                    | Payload::Topology { .. } => {
+                        reply.body.payload = Payload::TopologyOk;
+                        // NOTE: Maelstrom's grid is ignored, our own small world has fewer hops
+                        self.neighbors = self
+                            .topology
+                            .generate(&self.node_ids)
+                            .remove(&self.node_id)
+                            .unwrap_or_default();
+                        reply.send(output, "topology")?;
+                    },
+                    | Payload::Share { mut messages, .. } if duplicate_share => {
+                        // NOTE: our share_ok got lost, acknowledge again what we have by now
+                        let known = &self.state.messages;
+                        messages.retain(|message| known.contains(message));
+                        self.last_sent.insert(reply.dest.clone(), now);
+                        reply.body.payload = Payload::ShareOk { messages };
+                        reply.send(output, "share")?;
+                    },
+                    | Payload::Share {
+                        mut messages,
+                        stamps,
+                        sequences,
+                    } => {
+                        let mut delivered = Vec::new();
+                        if let Some(causal) = &mut self.causal {
+                            for (message, stamp) in stamps {
+                                delivered.extend(causal.receive(message, stamp));
+                            }
+                        }
+                        if let Some(total) = &mut self.total {
+                            for (message, sequence) in sequences {
+                                delivered.extend(total.receive(message, sequence));
+                            }
+                            for message in &delivered {
+                                self.unsequenced.remove(message);
+                            }
+                        }
+                        if self.causal.is_some() || self.total.is_some() {
+                            // NOTE: buffered values aren't acknowledged, the sender shares them
+                            // again until their predecessors arrived and they were delivered
+                            let known = &self.state.messages;
+                            messages.retain(|message| {
+                                return known.contains(message) || delivered.contains(message);
+                            });
+                        }
+                        // IMPORTANT: the ack tells the sender to never share these again
+                        self.record(BroadcastEntry::Share {
+                            from: reply.dest.clone(),
+                            messages: messages.clone(),
+                        })?;
+                        self.last_sent.insert(reply.dest.clone(), now);
+                        reply.body.payload = Payload::ShareOk { messages };
+                        reply.send(output, "share")?;
+                    },
+                    | Payload::ShareOk { messages: values } => {
+                        if let Some(share) = reply.body.in_reply_to {
+                            self.shares.ack(share);
+                        }
+                        self.record(BroadcastEntry::ShareOk {
+                            from: reply.dest, // NOTE: destination of reply
+                            messages: values,
+                        })?;
+                    },
+                    | Payload::Order { messages } => {
+                        // NOTE: numbering is idempotent, orders are resent until they arrive
+                        if self.node_id == self.sequencer {
+                            for message in messages {
+                                if !self.state.messages.contains(&message) {
+                                    if let Some(total) = &mut self.total {
+                                        total.sequence(message);
+                                    }
+                                    self.record(BroadcastEntry::Broadcast { message })?;
+                                }
+                            }
+                        }
+                    },
+                    | Payload::BroadcastOk
+                    | Payload::ReadOk { .. }
+                    | Payload::TopologyOk
+                    | Payload::Heartbeat
+                    | Payload::Error { .. } => {},
+                }
+                self.local_id += 1;
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let mut neighbors: Vec<&String> = self.neighbors.iter().collect();
+        neighbors.sort();
+        // NOTE: values a peer hasn't acknowledged yet, stuck convergence shows up here
+        let unacknowledged: HashMap<&String, usize> = self
+            .state
+            .known_by_node
+            .iter()
+            .filter(|(peer, _)| **peer != self.node_id)
+            .map(|(peer, known)| (peer, self.state.messages.difference(known).count()))
+            .collect();
+        let now = Instant::now();
+        let mut suspected: Vec<&String> = self
+            .node_ids
+            .iter()
+            .filter(|peer| **peer != self.node_id && !self.failure_detector.is_alive(peer, now))
+            .collect();
+        suspected.sort();
+        return serde_json::json!({
+            "neighbors": neighbors,
+            "messages": self.state.messages.len(),
+            "known_by_node": self
+                .state
+                .known_by_node
+                .iter()
+                .map(|(peer, known)| (peer, known.len()))
+                .collect::<HashMap<_, _>>(),
+            "unacknowledged": unacknowledged,
+            "shares_in_flight": self.shares.len(),
+            "causally_pending": self.causal.as_ref().map(|causal| causal.pending()),
+            "sequencer": self.total.as_ref().map(|_| &self.sequencer),
+            "out_of_sequence": self.total.as_ref().map(|total| total.pending()),
+            "unsequenced": self.total.as_ref().map(|_| self.unsequenced.len()),
+            "suspected": suspected,
+        });
+    }
+}
+
+/// Broadcast over Raft: the committed log is the delivery order, reads go through the log too
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OrderedLog {
+    messages: Vec<usize>,
+    known: HashSet<usize>,
+}
+
+impl RaftService for OrderedLog {
+    type Payload = Payload;
+
+    fn is_request(payload: &Payload) -> bool {
+        return matches!(payload, Payload::Broadcast { .. } | Payload::Read);
+    }
+
+    fn apply(&mut self, request: &Payload) -> Payload {
+        return match request {
+            | Payload::Broadcast { message } => {
+                if self.known.insert(*message) {
+                    self.messages.push(*message);
+                }
+                Payload::BroadcastOk
+            },
+            | Payload::Read => Payload::ReadOk {
+                messages: self.messages.clone(),
+            },
+            | _ => Payload::Error {
+                code: ErrorCode::NotSupported,
+                text: "not a broadcast request".to_string(),
+            },
+        };
+    }
+
+    fn error(code: ErrorCode, text: String) -> Payload {
+        return Payload::Error { code, text };
+    }
+
+    fn answer_locally(&self, request: &Payload) -> Option<Payload> {
+        // NOTE: Maelstrom sends the topology right after init, before any leader is elected
+        return match request {
+            | Payload::Topology { .. } => Some(Payload::TopologyOk),
+            | _ => None,
+        };
+    }
+}
@@ -0,0 +1,553 @@
+// Purpose: key/value store replicated with chain replication (van Renesse & Schneider).
+//
+// The nodes form a chain, `node_ids` sorted by name to start with. Writes and cas go to the
+// head, which applies them, numbers them and passes them down the chain; every node applies them
+// in that order and the tail answers the client, then acks back up so the others can forget them.
+// Reads are answered by the tail alone, it only holds updates every node has, so they are
+// linearizable.
+// Any node takes client requests and forwards them to the head or the tail.
+//
+// Reconfiguration: members heartbeat each other, and the first member a node believes alive is
+// the master. When the master suspects members it drops them from the chain under a new epoch:
+// - head gone: the next node becomes head and carries on numbering from what it applied
+// - tail gone: the previous node becomes tail and answers what it passed on beyond the last ack;
+//   the tail acks updates before it answers them, so the old tail never answered those unless
+//   their ack got lost
+// - middle gone: the previous node sends what wasn't acked yet to its new successor
+//
+// NOTE: like the paper this assumes nodes fail by crashing and failures are detected accurately;
+// a partition makes both sides drop each other and split the chain. Removed nodes don't rejoin,
+// they only forward client requests to the chain.
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::failure_detector::{DetectorKind, FailureDetector};
+use crate::kv::{KvPayload, KvStore};
+use crate::partition::Forwarder;
+use crate::queue::EventSender;
+use crate::topology::natural_order;
+use crate::{Body, ErrorCode, Event, InitNodes, Message, Node};
+
+const TICK: Duration = Duration::from_millis(50);
+
+/// A write or cas the head decided, on its way down the chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Update {
+    /// position in the head's order, the first update is 1
+    pub seq: u64,
+    pub key: Value,
+    /// the value written, `None` if the request didn't change anything, e.g. a failed cas
+    pub value: Option<Value>,
+    /// what the tail answers
+    pub reply: KvPayload,
+    /// who asked, a node when the request was forwarded
+    pub client: String,
+    /// `msg_id` of the request, `in_reply_to` would clash with the message body's
+    pub request_id: Option<usize>,
+}
+
+/// Traffic between the members of the chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ChainMessage {
+    Update(Update),
+    /// the tail applied and answered every update up to `seq`
+    Ack {
+        seq: u64,
+    },
+    Reconfigure {
+        epoch: u64,
+        chain: Vec<String>,
+    },
+    Heartbeat {
+        epoch: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)] // NOTE: `type` decides, anything that isn't chain traffic is a client message
+pub enum Payload {
+    Chain(ChainMessage),
+    Client(KvPayload),
+}
+
+pub struct Tick;
+
+#[derive(Debug, Clone)]
+pub struct ChainConfig {
+    pub heartbeat_interval: Duration,
+    /// silence after which a member is suspected and dropped from the chain
+    pub suspect_timeout: Duration,
+    /// how often updates that weren't acked are sent to the successor again
+    pub retry_interval: Duration,
+    /// how long a request forwarded to the head or the tail may take
+    pub forward_timeout: Duration,
+    /// how crashed members are found, `suspect_timeout` only applies to `DetectorKind::Timeout`
+    pub detector: DetectorKind,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        return ChainConfig {
+            heartbeat_interval: Duration::from_millis(100),
+            suspect_timeout: Duration::from_millis(500),
+            retry_interval: Duration::from_millis(200),
+            forward_timeout: Duration::from_secs(1),
+            detector: DetectorKind::default(),
+        };
+    }
+}
+
+pub struct ChainNode {
+    node_id: String,
+    local_id: usize,
+    config: ChainConfig,
+    epoch: u64,
+    /// members from head to tail
+    chain: Vec<String>,
+    detector: Box<dyn FailureDetector>,
+    forwarder: Forwarder,
+    store: KvStore,
+    /// seq of the last update applied to `store`
+    applied: u64,
+    /// updates from the predecessor that arrived before the ones preceding them
+    ahead: BTreeMap<u64, Update>,
+    /// updates passed to the successor that the tail didn't ack yet, in seq order
+    sent: VecDeque<Update>,
+    /// seq of the last update the tail answered, as far as this node knows
+    answered: u64,
+    last_heartbeat: Instant,
+    last_retry: Instant,
+    /// chains this node configured as master, for `status`
+    reconfigurations: u64,
+}
+
+impl ChainNode {
+    fn send(
+        &mut self,
+        dest: &str,
+        in_reply_to: Option<usize>,
+        payload: Payload,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let id = self.local_id;
+        self.local_id += 1;
+        return Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to,
+                clock: None,
+                payload,
+            },
+        }
+        .send(&mut *output, "chain kv");
+    }
+
+    fn position(&self) -> Option<usize> {
+        return self.chain.iter().position(|member| *member == self.node_id);
+    }
+
+    fn head(&self) -> &str {
+        return &self.chain[0];
+    }
+
+    fn tail(&self) -> &str {
+        return &self.chain[self.chain.len() - 1];
+    }
+
+    fn predecessor(&self) -> Option<String> {
+        let position = self.position()?;
+        return position
+            .checked_sub(1)
+            .map(|previous| self.chain[previous].clone());
+    }
+
+    fn successor(&self) -> Option<String> {
+        let position = self.position()?;
+        return self.chain.get(position + 1).cloned();
+    }
+
+    fn is_tail(&self) -> bool {
+        return self.position().is_some() && self.successor().is_none();
+    }
+
+    fn role(&self) -> &'static str {
+        return match self.position() {
+            | None => "removed",
+            | Some(_) if self.chain.len() == 1 => "head and tail",
+            | Some(0) => "head",
+            | Some(position) if position + 1 == self.chain.len() => "tail",
+            | Some(_) => "middle",
+        };
+    }
+
+    /// Answer the client of an update, this node is the tail
+    fn answer(&mut self, update: Update, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.answered = self.answered.max(update.seq);
+        return self.send(
+            &update.client,
+            update.request_id,
+            Payload::Client(update.reply),
+            output,
+        );
+    }
+
+    /// Hand an applied update to the successor, or answer it as the tail
+    fn pass_on(&mut self, update: Update, output: &mut dyn Write) -> anyhow::Result<()> {
+        let Some(successor) = self.successor() else {
+            return self.answer(update, output);
+        };
+        self.send(
+            &successor,
+            None,
+            Payload::Chain(ChainMessage::Update(update.clone())),
+            output,
+        )?;
+        self.sent.push_back(update);
+        return Ok(());
+    }
+
+    /// Take an update from the predecessor, applying it and everything it unblocks in order
+    fn receive(&mut self, update: Update, output: &mut dyn Write) -> anyhow::Result<()> {
+        let repeated = update.seq <= self.applied;
+        if !repeated {
+            self.ahead.insert(update.seq, update);
+        }
+        let mut unblocked = Vec::new();
+        while let Some(update) = self.ahead.remove(&(self.applied + 1)) {
+            self.applied = update.seq;
+            if let Some(value) = &update.value {
+                self.store.insert(&update.key, value.clone());
+            }
+            unblocked.push(update);
+        }
+        if !self.is_tail() {
+            for update in unblocked {
+                self.pass_on(update, output)?;
+            }
+            return Ok(());
+        }
+        // NOTE: a repeated update means the ack got lost, only the tail can ack it again
+        if !unblocked.is_empty() || repeated {
+            if let Some(predecessor) = self.predecessor() {
+                let ack = ChainMessage::Ack { seq: self.applied };
+                self.send(&predecessor, None, Payload::Chain(ack), output)?;
+            }
+        }
+        // IMPORTANT: acked before answered, should this node crash in between, the predecessor
+        // takes over as tail knowing which updates it must not answer again
+        for update in unblocked {
+            self.answer(update, output)?;
+        }
+        return Ok(());
+    }
+
+    /// Forget updates the tail applied and tell the predecessor
+    fn acked(&mut self, seq: u64, output: &mut dyn Write) -> anyhow::Result<()> {
+        self.answered = self.answered.max(seq);
+        while self.sent.front().is_some_and(|update| update.seq <= seq) {
+            self.sent.pop_front();
+        }
+        if let Some(predecessor) = self.predecessor() {
+            let ack = ChainMessage::Ack { seq };
+            self.send(&predecessor, None, Payload::Chain(ack), output)?;
+        }
+        return Ok(());
+    }
+
+    /// Switch to a newer chain and take over whatever the members that left were doing
+    fn adopt(
+        &mut self,
+        epoch: u64,
+        chain: Vec<String>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let was_tail = self.is_tail();
+        let successor = self.successor();
+        self.epoch = epoch;
+        self.chain = chain;
+        if self.position().is_none() {
+            self.ahead.clear();
+            self.sent.clear();
+            return Ok(());
+        }
+        if self.predecessor().is_none() {
+            // NOTE: updates beyond a gap came from the old head, nobody else has the ones missing
+            self.ahead.clear();
+        }
+        if self.is_tail() && !was_tail {
+            let sent: Vec<Update> = self.sent.drain(..).collect();
+            let seq = sent.last().map(|update| update.seq);
+            for update in sent {
+                // NOTE: the old tail answered these already, clients only see one reply each
+                if update.seq <= self.answered {
+                    continue;
+                }
+                self.answer(update, output)?;
+            }
+            if let Some(seq) = seq {
+                self.acked(seq, output)?;
+            }
+        } else if self.successor() != successor {
+            self.resend(output)?;
+        }
+        return Ok(());
+    }
+
+    /// Send the successor every update it didn't ack
+    fn resend(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        let Some(successor) = self.successor() else {
+            return Ok(());
+        };
+        let sent: Vec<Update> = self.sent.iter().cloned().collect();
+        for update in sent {
+            let update = ChainMessage::Update(update);
+            self.send(&successor, None, Payload::Chain(update), output)?;
+        }
+        return Ok(());
+    }
+
+    /// Drop suspected members if this node is the master, the first member it believes alive
+    fn reconfigure(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        let alive = |member: &String| -> bool {
+            return *member == self.node_id || self.detector.is_alive(member, now);
+        };
+        let Some(master) = self.chain.iter().find(|member| alive(member)) else {
+            return Ok(());
+        };
+        if *master != self.node_id {
+            return Ok(());
+        }
+        let chain: Vec<String> = self
+            .chain
+            .iter()
+            .filter(|member| alive(member))
+            .cloned()
+            .collect();
+        if chain.len() == self.chain.len() {
+            return Ok(());
+        }
+
+        self.reconfigurations += 1;
+        let epoch = self.epoch + 1;
+        let others: Vec<&String> = chain
+            .iter()
+            .filter(|member| **member != self.node_id)
+            .collect();
+        for member in others {
+            let reconfigure = ChainMessage::Reconfigure {
+                epoch,
+                chain: chain.clone(),
+            };
+            self.send(member, None, Payload::Chain(reconfigure), output)?;
+        }
+        return self.adopt(epoch, chain, output);
+    }
+
+    fn tick(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        for (client, in_reply_to) in self.forwarder.expire(now) {
+            let error = KvPayload::error(ErrorCode::Timeout, "the chain didn't answer in time");
+            self.send(&client, in_reply_to, Payload::Client(error), output)?;
+        }
+        self.reconfigure(now, output)?;
+
+        if now.saturating_duration_since(self.last_heartbeat) >= self.config.heartbeat_interval {
+            self.last_heartbeat = now;
+            let members: Vec<String> = self
+                .chain
+                .iter()
+                .filter(|member| **member != self.node_id)
+                .cloned()
+                .collect();
+            for member in members {
+                let heartbeat = ChainMessage::Heartbeat { epoch: self.epoch };
+                self.send(&member, None, Payload::Chain(heartbeat), output)?;
+            }
+        }
+        // NOTE: updates or acks may have been lost on the way
+        if now.saturating_duration_since(self.last_retry) >= self.config.retry_interval {
+            self.last_retry = now;
+            self.resend(output)?;
+        }
+        return Ok(());
+    }
+
+    fn request(
+        &mut self,
+        message: Message<Payload>,
+        request: KvPayload,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        if let KvPayload::Read { .. } = request {
+            if self.is_tail() {
+                let reply = self.store.apply(&request);
+                return self.send(
+                    &message.src,
+                    message.body.id,
+                    Payload::Client(reply),
+                    output,
+                );
+            }
+            let tail = self.tail().to_string();
+            return self
+                .forwarder
+                .forward(message, &tail, &mut self.local_id, now, output);
+        }
+        if self.position() != Some(0) {
+            let head = self.head().to_string();
+            return self
+                .forwarder
+                .forward(message, &head, &mut self.local_id, now, output);
+        }
+
+        // NOTE: the head's store already has every update it numbered, so it decides cas alone
+        let Some(key) = request.key().cloned() else {
+            return Ok(());
+        };
+        let reply = self.store.apply(&request);
+        let value = match reply {
+            | KvPayload::WriteOk | KvPayload::CasOk => self.store.get(&key).cloned(),
+            | _ => None,
+        };
+        self.applied += 1;
+        let update = Update {
+            seq: self.applied,
+            key,
+            value,
+            reply,
+            client: message.src,
+            request_id: message.body.id,
+        };
+        return self.pass_on(update, output);
+    }
+}
+
+impl Node<ChainConfig, Payload, Tick> for ChainNode {
+    fn from_init(
+        config: ChainConfig,
+        init: InitNodes,
+        sender: EventSender<Payload, Tick>,
+    ) -> anyhow::Result<Self> {
+        let now = Instant::now();
+        let mut chain: Vec<String> = init.node_ids.iter().cloned().collect();
+        chain.sort_by(|a, b| natural_order(a, b));
+
+        let node_id = init.node_id.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(TICK);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: Tick,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        return Ok(ChainNode {
+            node_id: init.node_id.clone(),
+            local_id: 1,
+            detector: config.detector.build(
+                &init,
+                config.heartbeat_interval,
+                config.suspect_timeout,
+                now,
+            ),
+            forwarder: Forwarder::new(config.forward_timeout),
+            config,
+            epoch: 0,
+            chain,
+            store: KvStore::new(),
+            applied: 0,
+            ahead: BTreeMap::new(),
+            sent: VecDeque::new(),
+            answered: 0,
+            last_heartbeat: now,
+            last_retry: now,
+            reconfigurations: 0,
+        });
+    }
+
+    fn step(&mut self, event: Event<Payload, Tick>, output: &mut dyn Write) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let message = match event {
+            | Event::EndOfMessages => return Ok(()),
+            | Event::GeneratedEvent(_) => return self.tick(now, output),
+            | Event::Message(message) => message,
+        };
+        // NOTE: any traffic from a member proves it is alive
+        self.detector.heartbeat(&message.src, now);
+
+        match &message.body.payload {
+            | Payload::Client(request) if request.is_request() => {
+                let request = request.clone();
+                self.request(message, request, now, output)?;
+            },
+            | Payload::Client(_) => {
+                // NOTE: replies only ever come back for requests we forwarded
+                self.forwarder.relay(message, &mut self.local_id, output)?;
+            },
+            | Payload::Chain(ChainMessage::Update(update)) => {
+                // NOTE: stale members may still send, only the predecessor's order counts
+                if self.predecessor().as_ref() == Some(&message.src) {
+                    let update = update.clone();
+                    self.receive(update, output)?;
+                }
+            },
+            | Payload::Chain(ChainMessage::Ack { seq }) => {
+                if self.successor().as_ref() == Some(&message.src) {
+                    self.acked(*seq, output)?;
+                } else {
+                    // NOTE: a late ack of a dropped member still tells what it answered
+                    self.answered = self.answered.max(*seq);
+                }
+            },
+            | Payload::Chain(ChainMessage::Reconfigure { epoch, chain }) => {
+                if *epoch > self.epoch {
+                    let (epoch, chain) = (*epoch, chain.clone());
+                    self.adopt(epoch, chain, output)?;
+                }
+            },
+            | Payload::Chain(ChainMessage::Heartbeat { epoch }) => {
+                // NOTE: a member that missed a reconfiguration, e.g. it restarted, catches up
+                if *epoch < self.epoch {
+                    let reconfigure = ChainMessage::Reconfigure {
+                        epoch: self.epoch,
+                        chain: self.chain.clone(),
+                    };
+                    self.send(&message.src, None, Payload::Chain(reconfigure), output)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> serde_json::Value {
+        return serde_json::json!({
+            "epoch": self.epoch,
+            "chain": self.chain,
+            "role": self.role(),
+            "keys": self.store.len(),
+            "applied": self.applied,
+            "answered": self.answered,
+            "unacked": self.sent.len(),
+            "ahead": self.ahead.len(),
+            "forwarding": self.forwarder.len(),
+            "reconfigurations": self.reconfigurations,
+        });
+    }
+}
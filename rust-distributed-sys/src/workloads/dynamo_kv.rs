@@ -0,0 +1,728 @@
+// Purpose: Dynamo style key/value store, each key on N nodes of a hash ring with R/W quorums.
+//
+// Whichever node a client picks coordinates its request. It reads the key from its replicas
+// until `read_quorum` answered, reconciles what they sent and, for writes and cas, writes a
+// version whose vector clock descends from every version it read until `write_quorum` acked it.
+//
+// - sloppy quorum: a replica that doesn't answer within `replica_timeout` is stood in for by the
+//   next node on the ring, which keeps what it is sent as a hint for the replica
+// - hinted handoff: hints are sent home every `handoff_interval` until the replica acks them
+// - read repair: replicas that answered a read with stale versions get the reconciled ones
+// - conflicts: versions with concurrent clocks are all kept as siblings, clients see the one
+//   with the latest hybrid timestamp and the next write supersedes them all
+//
+// The hybrid clock is the one `clock::install` runs for the node, so every message between
+// replicas carries it and a write is always stamped after every version its coordinator read,
+// however far the coordinator's wall clock lags behind.
+//
+// NOTE: this is eventually consistent, not linearizable. A cas compares and writes in two rounds,
+// and with R + W <= N, or stand-ins in a quorum, a read can miss the latest write.
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::clock::{self, Clock, HybridTimestamp, VectorClock};
+use crate::kv::{KvPayload, KvStore};
+use crate::partition::{HashRing, RingConfig};
+use crate::queue::EventSender;
+use crate::{Body, ErrorCode, Event, InitNodes, Message, Node};
+
+const TICK: Duration = Duration::from_millis(50);
+
+/// A value and the causal history it was written with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Version {
+    pub value: Value,
+    pub clock: VectorClock,
+    /// tie breaker between siblings, decides which one clients see
+    pub stamp: HybridTimestamp,
+}
+
+/// Add `incoming` to `versions`, keeping only versions that no other version descends from
+pub fn reconcile(versions: &mut Vec<Version>, incoming: impl IntoIterator<Item = Version>) {
+    for version in incoming {
+        let obsolete = versions.iter().any(|kept| {
+            return kept.clock == version.clock || version.clock.happened_before(&kept.clock);
+        });
+        if obsolete {
+            continue;
+        }
+        versions.retain(|kept| !kept.clock.happened_before(&version.clock));
+        versions.push(version);
+    }
+}
+
+/// The sibling clients see
+fn visible(versions: &[Version]) -> Option<&Version> {
+    return versions.iter().max_by_key(|version| version.stamp);
+}
+
+/// Traffic between a coordinator and the replicas of a key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaMessage {
+    ReplicaRead {
+        key: Value,
+    },
+    ReplicaReadOk {
+        versions: Vec<Version>,
+    },
+    ReplicaWrite {
+        key: Value,
+        versions: Vec<Version>,
+        /// the replica the receiver stands in for, `None` when it is a replica itself
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        hint: Option<String>,
+    },
+    ReplicaWriteOk,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)] // NOTE: `type` decides, anything that isn't replica traffic is a client request
+pub enum Payload {
+    Replica(ReplicaMessage),
+    Client(KvPayload),
+}
+
+pub struct Tick;
+
+#[derive(Debug, Clone)]
+pub struct DynamoConfig {
+    /// `replication` is N, the number of replicas of every key
+    pub ring: RingConfig,
+    /// R, replicas that must answer a read
+    pub read_quorum: usize,
+    /// W, replicas that must ack a write
+    pub write_quorum: usize,
+    /// how long to wait for a replica before asking the next node on the ring instead
+    pub replica_timeout: Duration,
+    /// how long a client request may take before it is answered with a timeout
+    pub request_timeout: Duration,
+    pub handoff_interval: Duration,
+}
+
+impl Default for DynamoConfig {
+    fn default() -> Self {
+        return DynamoConfig {
+            ring: RingConfig::default(),
+            read_quorum: 2,
+            write_quorum: 2,
+            replica_timeout: Duration::from_millis(200),
+            request_timeout: Duration::from_secs(1),
+            handoff_interval: Duration::from_millis(500),
+        };
+    }
+}
+
+/// A node asked on behalf of one of a key's replicas
+struct Asked {
+    /// the replica, `target` itself unless it is a stand-in
+    home: String,
+    target: String,
+    sent: Instant,
+    answered: bool,
+    /// a stand-in was asked after it timed out
+    replaced: bool,
+}
+
+enum Phase {
+    /// versions reconciled so far, and what every replica answered for read repair
+    Reading {
+        versions: Vec<Version>,
+        answers: Vec<(String, Vec<Version>)>,
+    },
+    /// the version being written, and the reply until the client got it
+    Writing {
+        version: Version,
+        reply: Option<KvPayload>,
+    },
+}
+
+/// A client request this node coordinates
+struct Operation {
+    client: String,
+    in_reply_to: Option<usize>,
+    request: KvPayload,
+    key: Value,
+    started: Instant,
+    phase: Phase,
+    asked: Vec<Asked>,
+}
+
+enum Answer {
+    Read(Vec<Version>),
+    Written,
+}
+
+pub struct DynamoNode {
+    node_id: String,
+    local_id: usize,
+    config: DynamoConfig,
+    ring: HashRing,
+    /// highest count of this node in any clock it wrote
+    written: u64,
+    /// siblings of every key this node is a replica of, keyed by the key's JSON text
+    store: HashMap<String, Vec<Version>>,
+    /// writes held for a replica that didn't answer: replica -> key's JSON text -> (key, siblings)
+    hints: HashMap<String, HashMap<String, (Value, Vec<Version>)>>,
+    operations: HashMap<u64, Operation>,
+    next_operation: u64,
+    /// msg_id of a replica request -> (operation, target)
+    requests: HashMap<usize, (u64, String)>,
+    /// msg_id of a handoff -> (replica, key's JSON text, siblings sent)
+    handoffs: HashMap<usize, (String, String, Vec<Version>)>,
+    last_handoff: Instant,
+    /// reads that found siblings, replicas repaired, hints taken and handed off, for `status`
+    conflicts: u64,
+    repairs: u64,
+    hinted: u64,
+    handed_off: u64,
+}
+
+impl DynamoNode {
+    fn send(
+        &mut self,
+        dest: &str,
+        in_reply_to: Option<usize>,
+        payload: Payload,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<usize> {
+        let id = self.local_id;
+        self.local_id += 1;
+        Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to,
+                clock: None,
+                payload,
+            },
+        }
+        .send(&mut *output, "dynamo kv")?;
+        return Ok(id);
+    }
+
+    /// Siblings of `key` held here, as a replica or a stand-in
+    fn replica_read(&self, key: &Value) -> Vec<Version> {
+        let key = key.to_string();
+        let mut versions = self.store.get(&key).cloned().unwrap_or_default();
+        for held in self.hints.values() {
+            if let Some((_, hinted)) = held.get(&key) {
+                reconcile(&mut versions, hinted.iter().cloned());
+            }
+        }
+        return versions;
+    }
+
+    fn replica_write(&mut self, key: &Value, versions: Vec<Version>, hint: Option<String>) {
+        match hint {
+            | Some(home) if home != self.node_id => {
+                self.hinted += 1;
+                let (_, hinted) = self
+                    .hints
+                    .entry(home)
+                    .or_default()
+                    .entry(key.to_string())
+                    .or_insert_with(|| (key.clone(), Vec::new()));
+                reconcile(hinted, versions);
+            },
+            | _ => reconcile(self.store.entry(key.to_string()).or_default(), versions),
+        }
+    }
+
+    /// Ask `target` on behalf of replica `home` for what the operation's phase needs
+    ///
+    /// returns:
+    ///   - the answer if `target` is this node, to be handed to `answered` once the caller is done
+    fn ask(
+        &mut self,
+        operation_id: u64,
+        home: &str,
+        target: &str,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<Option<Answer>> {
+        let Some(operation) = self.operations.get_mut(&operation_id) else {
+            return Ok(None);
+        };
+        operation.asked.push(Asked {
+            home: home.to_string(),
+            target: target.to_string(),
+            sent: now,
+            answered: false,
+            replaced: false,
+        });
+        let key = operation.key.clone();
+        let request = match &operation.phase {
+            | Phase::Reading { .. } => ReplicaMessage::ReplicaRead { key },
+            | Phase::Writing { version, .. } => ReplicaMessage::ReplicaWrite {
+                key,
+                versions: vec![version.clone()],
+                hint: (home != target).then(|| home.to_string()),
+            },
+        };
+        if target == self.node_id {
+            return Ok(Some(match request {
+                | ReplicaMessage::ReplicaRead { key } => Answer::Read(self.replica_read(&key)),
+                | ReplicaMessage::ReplicaWrite {
+                    key,
+                    versions,
+                    hint,
+                } => {
+                    self.replica_write(&key, versions, hint);
+                    Answer::Written
+                },
+                | ReplicaMessage::ReplicaReadOk { .. } | ReplicaMessage::ReplicaWriteOk => {
+                    unreachable!("only requests are sent to replicas")
+                },
+            }));
+        }
+        let id = self.send(target, None, Payload::Replica(request), output)?;
+        self.requests.insert(id, (operation_id, target.to_string()));
+        return Ok(None);
+    }
+
+    /// Start the operation's current phase by asking every replica of its key
+    fn ask_replicas(
+        &mut self,
+        operation_id: u64,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let Some(operation) = self.operations.get(&operation_id) else {
+            return Ok(());
+        };
+        let replicas: Vec<String> = self
+            .ring
+            .replicas(operation.key.to_string())
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let mut local = None;
+        for replica in &replicas {
+            if let Some(answer) = self.ask(operation_id, replica, replica, now, output)? {
+                local = Some(answer);
+            }
+        }
+        // IMPORTANT: only after every replica was asked, the answer may already end the phase
+        if let Some(answer) = local {
+            self.answered(operation_id, &self.node_id.clone(), answer, now, output)?;
+        }
+        return Ok(());
+    }
+
+    fn answered(
+        &mut self,
+        operation_id: u64,
+        target: &str,
+        answer: Answer,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let Some(operation) = self.operations.get_mut(&operation_id) else {
+            return Ok(());
+        };
+        let Some(asked) = operation
+            .asked
+            .iter_mut()
+            .find(|asked| asked.target == target && !asked.answered)
+        else {
+            return Ok(());
+        };
+        let quorum = match (&mut operation.phase, answer) {
+            | (Phase::Reading { versions, answers }, Answer::Read(read)) => {
+                reconcile(versions, read.iter().cloned());
+                if asked.home == asked.target {
+                    answers.push((target.to_string(), read));
+                }
+                self.config.read_quorum
+            },
+            | (Phase::Writing { .. }, Answer::Written) => self.config.write_quorum,
+            // NOTE: a late answer from the previous phase
+            | _ => return Ok(()),
+        };
+        asked.answered = true;
+        // NOTE: a replica and its stand-in may both answer, the quorum counts replicas
+        let homes: HashSet<&String> = operation
+            .asked
+            .iter()
+            .filter(|asked| asked.answered)
+            .map(|asked| &asked.home)
+            .collect();
+        if homes.len() < quorum {
+            return Ok(());
+        }
+
+        if let Phase::Writing { reply, .. } = &mut operation.phase {
+            // NOTE: the write carries on to the remaining replicas, or stand-ins for them, after
+            // the client got its answer; otherwise a down replica would never get a hint
+            let done = homes.len() >= self.ring.replication();
+            let reply = reply.take();
+            let client = operation.client.clone();
+            let in_reply_to = operation.in_reply_to;
+            if done {
+                self.operations.remove(&operation_id);
+            }
+            if let Some(reply) = reply {
+                self.send(&client, in_reply_to, Payload::Client(reply), output)?;
+            }
+            return Ok(());
+        }
+
+        let mut operation = self.operations.remove(&operation_id).unwrap();
+        // NOTE: `decide` puts the operation back with its write phase
+        let phase = std::mem::replace(
+            &mut operation.phase,
+            Phase::Reading {
+                versions: Vec::new(),
+                answers: Vec::new(),
+            },
+        );
+        if let Phase::Reading { versions, answers } = phase {
+            self.repair(&operation.key, &versions, answers, output)?;
+            self.decide(operation_id, operation, versions, now, output)?;
+        }
+        return Ok(());
+    }
+
+    /// Send the reconciled siblings to replicas that answered the read with anything else
+    fn repair(
+        &mut self,
+        key: &Value,
+        versions: &[Version],
+        answers: Vec<(String, Vec<Version>)>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        if versions.len() > 1 {
+            self.conflicts += 1;
+        }
+        for (replica, read) in answers {
+            let current = read.len() == versions.len()
+                && versions.iter().all(|version| read.contains(version));
+            if current {
+                continue;
+            }
+            self.repairs += 1;
+            if replica == self.node_id {
+                self.replica_write(key, versions.to_vec(), None);
+                continue;
+            }
+            let repair = ReplicaMessage::ReplicaWrite {
+                key: key.clone(),
+                versions: versions.to_vec(),
+                hint: None,
+            };
+            self.send(&replica, None, Payload::Replica(repair), output)?;
+        }
+        return Ok(());
+    }
+
+    /// Answer a read, or carry on with the write phase of a write or cas
+    fn decide(
+        &mut self,
+        operation_id: u64,
+        mut operation: Operation,
+        versions: Vec<Version>,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        // NOTE: what the request does to the visible value is plain single copy KV semantics
+        let mut current = KvStore::new();
+        if let Some(version) = visible(&versions) {
+            current.insert(&operation.key, version.value.clone());
+        }
+        let reply = current.apply(&operation.request);
+        let written = match (&reply, current.get(&operation.key)) {
+            | (KvPayload::WriteOk | KvPayload::CasOk, Some(value)) => value.clone(),
+            | _ => {
+                self.send(
+                    &operation.client,
+                    operation.in_reply_to,
+                    Payload::Client(reply),
+                    output,
+                )?;
+                return Ok(());
+            },
+        };
+
+        let stamp = clock::tick()
+            .and_then(|now| now.hybrid())
+            .context("dynamo-kv runs without a hybrid clock")?;
+        let mut clock = VectorClock::new();
+        for version in &versions {
+            clock.merge(&version.clock);
+        }
+        // NOTE: two writes coordinated here must never share a clock, even for the same read
+        clock.raise(&self.node_id, self.written);
+        self.written = clock.increment(&self.node_id);
+        operation.phase = Phase::Writing {
+            version: Version {
+                value: written,
+                clock,
+                stamp,
+            },
+            reply: Some(reply),
+        };
+        operation.asked.clear();
+        self.operations.insert(operation_id, operation);
+        return self.ask_replicas(operation_id, now, output);
+    }
+
+    /// Time out operations and ask stand-ins for replicas that are too slow to answer
+    fn check_operations(&mut self, now: Instant, output: &mut dyn Write) -> anyhow::Result<()> {
+        let expired: Vec<u64> = self
+            .operations
+            .iter()
+            .filter(|(_, operation)| {
+                return now.saturating_duration_since(operation.started)
+                    >= self.config.request_timeout;
+            })
+            .map(|(operation_id, _)| *operation_id)
+            .collect();
+        for operation_id in expired {
+            let operation = self.operations.remove(&operation_id).unwrap();
+            if let Phase::Writing { reply: None, .. } = operation.phase {
+                // NOTE: the client already has its answer, a replica just never got the write
+                continue;
+            }
+            let error = KvPayload::error(ErrorCode::Timeout, "no quorum of replicas answered");
+            self.send(
+                &operation.client,
+                operation.in_reply_to,
+                Payload::Client(error),
+                output,
+            )?;
+        }
+        let operations = &self.operations;
+        self.requests
+            .retain(|_, (operation_id, _)| operations.contains_key(operation_id));
+
+        let mut stand_ins = Vec::new();
+        for (operation_id, operation) in self.operations.iter_mut() {
+            let successors = self.ring.successors(operation.key.to_string());
+            let fallbacks: Vec<&str> = successors
+                .into_iter()
+                .skip(self.ring.replication())
+                .filter(|node| !operation.asked.iter().any(|asked| asked.target == *node))
+                .collect();
+            let mut fallbacks = fallbacks.into_iter();
+            for asked in operation.asked.iter_mut() {
+                let slow = !asked.answered
+                    && !asked.replaced
+                    && now.saturating_duration_since(asked.sent) >= self.config.replica_timeout;
+                if !slow {
+                    continue;
+                }
+                let Some(stand_in) = fallbacks.next() else {
+                    break;
+                };
+                asked.replaced = true;
+                stand_ins.push((*operation_id, asked.home.clone(), stand_in.to_string()));
+            }
+        }
+        for (operation_id, home, stand_in) in stand_ins {
+            if let Some(answer) = self.ask(operation_id, &home, &stand_in, now, output)? {
+                self.answered(operation_id, &stand_in, answer, now, output)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Offer every hint to its replica again
+    fn hand_off(&mut self, output: &mut dyn Write) -> anyhow::Result<()> {
+        // NOTE: unanswered handoffs are simply sent again, a late ack still finds the new one
+        self.handoffs.clear();
+        let mut handoffs = Vec::new();
+        for (home, held) in &self.hints {
+            for (key_text, (key, versions)) in held {
+                handoffs.push((
+                    home.clone(),
+                    key_text.clone(),
+                    key.clone(),
+                    versions.clone(),
+                ));
+            }
+        }
+        for (home, key_text, key, versions) in handoffs {
+            let handoff = ReplicaMessage::ReplicaWrite {
+                key,
+                versions: versions.clone(),
+                hint: None,
+            };
+            let id = self.send(&home, None, Payload::Replica(handoff), output)?;
+            self.handoffs.insert(id, (home, key_text, versions));
+        }
+        return Ok(());
+    }
+
+    /// Drop a hint its replica acked, unless it changed since it was sent
+    fn handed_off(&mut self, home: String, key_text: String, sent: Vec<Version>) {
+        let Some(held) = self.hints.get_mut(&home) else {
+            return;
+        };
+        if held.get(&key_text).map(|(_, versions)| versions) == Some(&sent) {
+            held.remove(&key_text);
+            self.handed_off += 1;
+        }
+        if held.is_empty() {
+            self.hints.remove(&home);
+        }
+    }
+}
+
+impl Node<DynamoConfig, Payload, Tick> for DynamoNode {
+    fn from_init(
+        config: DynamoConfig,
+        init: InitNodes,
+        sender: EventSender<Payload, Tick>,
+    ) -> anyhow::Result<Self> {
+        let ring = HashRing::from_init(&init, &config.ring)?;
+        let replication = ring.replication();
+        anyhow::ensure!(
+            (1..=replication).contains(&config.read_quorum)
+                && (1..=replication).contains(&config.write_quorum),
+            "quorums must be between 1 and the {} replicas of a key",
+            replication
+        );
+
+        clock::install(Clock::hybrid(), &init);
+        let node_id = init.node_id.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(TICK);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: Tick,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        return Ok(DynamoNode {
+            node_id: init.node_id,
+            local_id: 1,
+            config,
+            ring,
+            written: 0,
+            store: HashMap::new(),
+            hints: HashMap::new(),
+            operations: HashMap::new(),
+            next_operation: 1,
+            requests: HashMap::new(),
+            handoffs: HashMap::new(),
+            last_handoff: Instant::now(),
+            conflicts: 0,
+            repairs: 0,
+            hinted: 0,
+            handed_off: 0,
+        });
+    }
+
+    fn step(&mut self, event: Event<Payload, Tick>, output: &mut dyn Write) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let message = match event {
+            | Event::EndOfMessages => return Ok(()),
+            | Event::GeneratedEvent(_) => {
+                self.check_operations(now, output)?;
+                if now.saturating_duration_since(self.last_handoff) >= self.config.handoff_interval
+                {
+                    self.last_handoff = now;
+                    self.hand_off(output)?;
+                }
+                return Ok(());
+            },
+            | Event::Message(message) => message,
+        };
+
+        let src = message.src;
+        let id = message.body.id;
+        let in_reply_to = message.body.in_reply_to;
+        match message.body.payload {
+            | Payload::Client(request) => {
+                let Some(key) = request.key().cloned() else {
+                    return Ok(());
+                };
+                let operation_id = self.next_operation;
+                self.next_operation += 1;
+                self.operations.insert(
+                    operation_id,
+                    Operation {
+                        client: src,
+                        in_reply_to: id,
+                        request,
+                        key,
+                        started: now,
+                        phase: Phase::Reading {
+                            versions: Vec::new(),
+                            answers: Vec::new(),
+                        },
+                        asked: Vec::new(),
+                    },
+                );
+                self.ask_replicas(operation_id, now, output)?;
+            },
+            | Payload::Replica(ReplicaMessage::ReplicaRead { key }) => {
+                let versions = self.replica_read(&key);
+                let answer = ReplicaMessage::ReplicaReadOk { versions };
+                self.send(&src, id, Payload::Replica(answer), output)?;
+            },
+            | Payload::Replica(ReplicaMessage::ReplicaWrite {
+                key,
+                versions,
+                hint,
+            }) => {
+                self.replica_write(&key, versions, hint);
+                let answer = ReplicaMessage::ReplicaWriteOk;
+                self.send(&src, id, Payload::Replica(answer), output)?;
+            },
+            | Payload::Replica(ReplicaMessage::ReplicaReadOk { versions }) => {
+                if let Some((operation_id, target)) =
+                    in_reply_to.and_then(|id| self.requests.remove(&id))
+                {
+                    self.answered(operation_id, &target, Answer::Read(versions), now, output)?;
+                }
+            },
+            | Payload::Replica(ReplicaMessage::ReplicaWriteOk) => {
+                if let Some((home, key_text, sent)) =
+                    in_reply_to.and_then(|id| self.handoffs.remove(&id))
+                {
+                    self.handed_off(home, key_text, sent);
+                } else if let Some((operation_id, target)) =
+                    in_reply_to.and_then(|id| self.requests.remove(&id))
+                {
+                    self.answered(operation_id, &target, Answer::Written, now, output)?;
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> serde_json::Value {
+        return serde_json::json!({
+            "keys": self.store.len(),
+            "siblings": self.store.values().filter(|versions| versions.len() > 1).count(),
+            "hints": self
+                .hints
+                .iter()
+                .map(|(home, held)| (home, held.len()))
+                .collect::<HashMap<_, _>>(),
+            "operations": self.operations.len(),
+            "conflicts": self.conflicts,
+            "repairs": self.repairs,
+            "hinted": self.hinted,
+            "handed_off": self.handed_off,
+        });
+    }
+}
@@ -0,0 +1,44 @@
+// Purpose: Echo every request back to the client.
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::queue::EventSender;
+use crate::*;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")] // IMPORTANT: returns {type:"echo", echo:"..."}
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+pub struct EchoNode {
+    local_id: usize,
+}
+
+impl Node<(), Payload, ()> for EchoNode {
+    fn from_init(
+        _state: (),
+        _init: InitNodes,
+        _sender: EventSender<Payload, ()>,
+    ) -> anyhow::Result<Self> {
+        return Ok(EchoNode { local_id: 1 });
+    }
+
+    fn step(&mut self, event: Event<Payload, ()>, output: &mut dyn Write) -> anyhow::Result<()> {
+        let Event::Message(message) = event else {
+            return Ok(());
+        };
+        let mut reply = message.into_reply(Some(&mut self.local_id));
+        match reply.body.payload {
+            | Payload::Echo { echo } => {
+                reply.body.payload = Payload::EchoOk { echo };
+                reply.send(output, "echo")?;
+            },
+            | Payload::EchoOk { .. } => {},
+        }
+        return Ok(());
+    }
+}
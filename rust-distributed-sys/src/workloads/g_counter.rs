@@ -0,0 +1,33 @@
+// Purpose: Grow-only counter replicated to all nodes.
+use crate::crdt::{CrdtWorkload, GCounter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum PayLoad {
+    Add { delta: u64 },
+    AddOk,
+    Read,
+    ReadOk { value: u64 },
+}
+
+pub struct GlobalCounter;
+
+impl CrdtWorkload for GlobalCounter {
+    type State = GCounter;
+    type Payload = PayLoad;
+
+    fn handle(node_id: &str, state: &mut GCounter, request: PayLoad) -> Option<PayLoad> {
+        return match request {
+            | PayLoad::Read => Some(PayLoad::ReadOk {
+                value: state.value(),
+            }),
+            | PayLoad::Add { delta } => {
+                state.increment(node_id, delta);
+                Some(PayLoad::AddOk)
+            },
+            | PayLoad::ReadOk { .. } | PayLoad::AddOk => None,
+        };
+    }
+}
@@ -0,0 +1,90 @@
+// Purpose: Grow-only counter kept in Maelstrom's `seq-kv` service, written with `async_loop`.
+//
+// Every add is a read and compare-and-set loop on one key; the handler simply awaits each
+// reply from `seq-kv` instead of parking the request until the reply shows up as an event.
+use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::bail;
+use serde_json::{json, Value};
+
+use crate::async_loop::{AsyncNode, Context};
+use crate::kv::KvPayload;
+use crate::workloads::g_counter::PayLoad;
+use crate::{ErrorCode, InitNodes, Message};
+
+const SEQ_KV: &str = "seq-kv";
+const KEY: &str = "counter";
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct KvCounter;
+
+impl KvCounter {
+    /// Current value of the counter, 0 if nothing was added yet
+    async fn read(ctx: &Context) -> anyhow::Result<u64> {
+        // NOTE: seq-kv may serve a stale read, a write of our own orders the read after it
+        let barrier = KvPayload::Write {
+            key: json!(format!("barrier-{}", ctx.node_id())),
+            value: json!(rand::random::<u64>()),
+        };
+        let _: KvPayload = ctx.rpc(SEQ_KV, barrier, TIMEOUT).await?;
+
+        let read = KvPayload::Read { key: json!(KEY) };
+        return match ctx.rpc(SEQ_KV, read, TIMEOUT).await? {
+            | KvPayload::ReadOk { value } => Ok(value.as_u64().unwrap_or_default()),
+            | KvPayload::Error {
+                code: ErrorCode::KeyDoesNotExist,
+                ..
+            } => Ok(0),
+            | reply => bail!("unexpected reply to read: {:?}", reply),
+        };
+    }
+
+    async fn add(ctx: &Context, delta: u64) -> anyhow::Result<()> {
+        loop {
+            let current = Self::read(ctx).await?;
+            let cas = KvPayload::Cas {
+                key: json!(KEY),
+                from: Value::from(current),
+                to: Value::from(current + delta),
+                create_if_not_exists: true,
+            };
+            match ctx.rpc(SEQ_KV, cas, TIMEOUT).await? {
+                | KvPayload::CasOk => return Ok(()),
+                // NOTE: someone else added in between, retry with the new value
+                | KvPayload::Error {
+                    code: ErrorCode::PreconditionFailed,
+                    ..
+                } => continue,
+                | reply => bail!("unexpected reply to cas: {:?}", reply),
+            }
+        }
+    }
+}
+
+impl AsyncNode<(), PayLoad> for KvCounter {
+    fn from_init(_state: (), _init: InitNodes, _ctx: &Context) -> anyhow::Result<Self> {
+        return Ok(KvCounter);
+    }
+
+    fn handle(
+        self: Rc<Self>,
+        ctx: Context,
+        message: Message<PayLoad>,
+    ) -> impl std::future::Future<Output = anyhow::Result<()>> + 'static {
+        return async move {
+            match message.body.payload {
+                | PayLoad::Add { delta } => {
+                    Self::add(&ctx, delta).await?;
+                    ctx.reply(&message, PayLoad::AddOk)?;
+                },
+                | PayLoad::Read => {
+                    let value = Self::read(&ctx).await?;
+                    ctx.reply(&message, PayLoad::ReadOk { value })?;
+                },
+                | PayLoad::AddOk | PayLoad::ReadOk { .. } => {},
+            }
+            return Ok(());
+        };
+    }
+}
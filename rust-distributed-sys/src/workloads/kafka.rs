@@ -0,0 +1,123 @@
+// Purpose: Replicated append-only logs with committed offsets, Maelstrom's kafka workload.
+//
+// Every request goes through the Raft log, so all nodes assign the same offsets in the same
+// order and an acknowledged message or commit is never lost.
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::raft::RaftService;
+use crate::ErrorCode;
+
+/// Upper bound on the messages returned per key by a single poll
+const MAX_POLL: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Send {
+        key: String,
+        msg: Value,
+    },
+    SendOk {
+        offset: u64,
+    },
+    Poll {
+        offsets: BTreeMap<String, u64>,
+    },
+    PollOk {
+        /// `[offset, msg]` pairs
+        msgs: BTreeMap<String, Vec<(u64, Value)>>,
+    },
+    CommitOffsets {
+        offsets: BTreeMap<String, u64>,
+    },
+    CommitOffsetsOk,
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: BTreeMap<String, u64>,
+    },
+    Error {
+        code: ErrorCode,
+        text: String,
+    },
+}
+
+/// NOTE: the offset of a message is its index in the log of its key
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Kafka {
+    logs: BTreeMap<String, Vec<Value>>,
+    committed: BTreeMap<String, u64>,
+}
+
+impl RaftService for Kafka {
+    type Payload = Payload;
+
+    fn is_request(payload: &Payload) -> bool {
+        return matches!(
+            payload,
+            Payload::Send { .. }
+                | Payload::Poll { .. }
+                | Payload::CommitOffsets { .. }
+                | Payload::ListCommittedOffsets { .. }
+        );
+    }
+
+    fn apply(&mut self, request: &Payload) -> Payload {
+        return match request {
+            | Payload::Send { key, msg } => {
+                let log = self.logs.entry(key.clone()).or_default();
+                log.push(msg.clone());
+                Payload::SendOk {
+                    offset: log.len() as u64 - 1,
+                }
+            },
+            | Payload::Poll { offsets } => {
+                let msgs = offsets
+                    .iter()
+                    .filter_map(|(key, offset)| {
+                        let log = self.logs.get(key)?;
+                        let messages: Vec<(u64, Value)> = log
+                            .iter()
+                            .enumerate()
+                            .skip(*offset as usize)
+                            .take(MAX_POLL)
+                            .map(|(offset, msg)| (offset as u64, msg.clone()))
+                            .collect();
+                        return Some((key.clone(), messages));
+                    })
+                    .collect();
+                Payload::PollOk { msgs }
+            },
+            | Payload::CommitOffsets { offsets } => {
+                // NOTE: commits only move forward, a late commit can't undo a newer one
+                for (key, offset) in offsets {
+                    let committed = self.committed.entry(key.clone()).or_default();
+                    *committed = (*committed).max(*offset);
+                }
+                Payload::CommitOffsetsOk
+            },
+            | Payload::ListCommittedOffsets { keys } => Payload::ListCommittedOffsetsOk {
+                offsets: keys
+                    .iter()
+                    .filter_map(|key| Some((key.clone(), *self.committed.get(key)?)))
+                    .collect(),
+            },
+            | Payload::SendOk { .. }
+            | Payload::PollOk { .. }
+            | Payload::CommitOffsetsOk
+            | Payload::ListCommittedOffsetsOk { .. }
+            | Payload::Error { .. } => {
+                Kafka::error(ErrorCode::NotSupported, "not a kafka request".to_string())
+            },
+        };
+    }
+
+    fn error(code: ErrorCode, text: String) -> Payload {
+        return Payload::Error { code, text };
+    }
+}
@@ -0,0 +1,27 @@
+// Purpose: Linearizable key/value store replicated with Raft.
+use crate::kv::{KvPayload, KvStore};
+use crate::raft::RaftService;
+use crate::ErrorCode;
+use serde::{Deserialize, Serialize};
+
+/// Every request, reads included, goes through the log - that's what makes reads linearizable
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LinKv {
+    store: KvStore,
+}
+
+impl RaftService for LinKv {
+    type Payload = KvPayload;
+
+    fn is_request(payload: &KvPayload) -> bool {
+        return payload.is_request();
+    }
+
+    fn apply(&mut self, request: &KvPayload) -> KvPayload {
+        return self.store.apply(request);
+    }
+
+    fn error(code: ErrorCode, text: String) -> KvPayload {
+        return KvPayload::error(code, text);
+    }
+}
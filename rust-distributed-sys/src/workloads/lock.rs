@@ -0,0 +1,196 @@
+// Purpose: lock service with leases and fencing tokens, replicated with Raft.
+//
+// A client `acquire`s a lock for `ttl` milliseconds and gets a fencing token, keeps it with
+// `renew` and gives it up with `release`. A lease that wasn't renewed in time expires and the lock
+// can be acquired by someone else. Tokens only ever grow, so a resource that remembers the highest
+// token it saw can turn away a former holder that didn't notice its lease ran out.
+//
+// Every request goes through the Raft log like in `lin_kv`, so at most one lease per lock is
+// valid at any point of the log. Expiry can't use each node's clock when it applies an entry,
+// nodes apply at different times, so the leader stamps `acquire` and `renew` with its clock and
+// time only moves forward with the stamps in the log.
+//
+// NOTE: a client must count its lease from when it sent the request, it was granted later than
+// that; the lease is only as safe as the clocks of the leaders that stamp are close to its own
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::raft::RaftService;
+use crate::ErrorCode;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Acquire {
+        lock: String,
+        owner: String,
+        /// milliseconds
+        ttl: u64,
+        /// the leader's clock in milliseconds since the epoch, clients leave it out
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        at: Option<u64>,
+    },
+    AcquireOk {
+        token: u64,
+    },
+    Renew {
+        lock: String,
+        owner: String,
+        token: u64,
+        ttl: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        at: Option<u64>,
+    },
+    RenewOk,
+    Release {
+        lock: String,
+        owner: String,
+        token: u64,
+    },
+    ReleaseOk,
+    Error {
+        code: ErrorCode,
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Lease {
+    owner: String,
+    token: u64,
+    /// milliseconds since the epoch, on the leaders' clocks
+    expires: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LockService {
+    leases: BTreeMap<String, Lease>,
+    /// token of the last lease granted, on any lock
+    last_token: u64,
+    /// latest stamp applied
+    now: u64,
+}
+
+impl LockService {
+    fn precondition_failed(text: String) -> Payload {
+        return LockService::error(ErrorCode::PreconditionFailed, text);
+    }
+
+    /// The lease on `lock` if it hasn't expired yet
+    fn valid(&self, lock: &str) -> Option<&Lease> {
+        return self
+            .leases
+            .get(lock)
+            .filter(|lease| lease.expires > self.now);
+    }
+}
+
+impl RaftService for LockService {
+    type Payload = Payload;
+
+    fn is_request(payload: &Payload) -> bool {
+        return matches!(
+            payload,
+            Payload::Acquire { .. } | Payload::Renew { .. } | Payload::Release { .. }
+        );
+    }
+
+    fn stamp(request: &mut Payload) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        match request {
+            | Payload::Acquire { at, .. } | Payload::Renew { at, .. } => *at = Some(now),
+            | _ => {},
+        }
+    }
+
+    fn apply(&mut self, request: &Payload) -> Payload {
+        match request {
+            | Payload::Acquire { at, .. } | Payload::Renew { at, .. } => {
+                // NOTE: a new leader's clock may be behind the last one's
+                self.now = self.now.max(at.unwrap_or_default());
+            },
+            | _ => {},
+        }
+
+        return match request {
+            | Payload::Acquire {
+                lock, owner, ttl, ..
+            } => {
+                let token = match self.valid(lock) {
+                    | Some(lease) if lease.owner != *owner => {
+                        return LockService::precondition_failed(format!(
+                            "{} is held by {} for another {}ms",
+                            lock,
+                            lease.owner,
+                            lease.expires - self.now
+                        ));
+                    },
+                    // NOTE: the owner retried an acquire whose reply got lost
+                    | Some(lease) => lease.token,
+                    | None => {
+                        self.last_token += 1;
+                        self.last_token
+                    },
+                };
+                self.leases.insert(
+                    lock.clone(),
+                    Lease {
+                        owner: owner.clone(),
+                        token,
+                        expires: self.now + ttl,
+                    },
+                );
+                Payload::AcquireOk { token }
+            },
+            | Payload::Renew {
+                lock,
+                owner,
+                token,
+                ttl,
+                ..
+            } => {
+                let now = self.now;
+                match self.leases.get_mut(lock) {
+                    | Some(lease)
+                        if lease.owner == *owner
+                            && lease.token == *token
+                            && lease.expires > now =>
+                    {
+                        lease.expires = now + ttl;
+                        Payload::RenewOk
+                    },
+                    | _ => LockService::precondition_failed(format!(
+                        "{} holds no lease on {} with token {}, it expired or was released",
+                        owner, lock, token
+                    )),
+                }
+            },
+            | Payload::Release { lock, owner, token } => {
+                // NOTE: releasing a lease that is gone already is fine, so releases can be retried
+                let held = self
+                    .leases
+                    .get(lock)
+                    .is_some_and(|lease| lease.owner == *owner && lease.token == *token);
+                if held {
+                    self.leases.remove(lock);
+                }
+                Payload::ReleaseOk
+            },
+            | Payload::AcquireOk { .. }
+            | Payload::RenewOk
+            | Payload::ReleaseOk
+            | Payload::Error { .. } => {
+                LockService::error(ErrorCode::NotSupported, "not a lock request".to_string())
+            },
+        };
+    }
+
+    fn error(code: ErrorCode, text: String) -> Payload {
+        return Payload::Error { code, text };
+    }
+}
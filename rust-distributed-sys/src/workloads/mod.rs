@@ -0,0 +1,221 @@
+// Purpose: every Maelstrom workload this crate solves, selectable at runtime.
+//
+// Each workload keeps its own payload enum and node in its own module; `Workload` only maps a
+// name to the `event_loop` that runs it. Adding a workload means adding a module and a variant.
+// `g-counter-kv` runs on `async_loop` and is only available with the `async` feature.
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+#[cfg(feature = "async")]
+use crate::async_loop::async_event_loop_with_config;
+use crate::crdt::{GossipConfig, GossipNode};
+use crate::envelope::Decode;
+use crate::failure_detector::DetectorKind;
+use crate::persist::PersistConfig;
+use crate::raft::{RaftConfig, RaftNode};
+use crate::{event_loop_with_config, EventLoopConfig, Node};
+
+pub mod broadcast;
+pub mod chain_kv;
+pub mod dynamo_kv;
+pub mod echo;
+pub mod g_counter;
+#[cfg(feature = "async")]
+pub mod g_counter_kv;
+pub mod kafka;
+pub mod lin_kv;
+pub mod lock;
+pub mod pn_counter;
+pub mod two_phase_txn;
+pub mod txn;
+pub mod unique_ids;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    Echo,
+    UniqueIds,
+    Broadcast,
+    GCounter,
+    GCounterKv,
+    PnCounter,
+    Kafka,
+    Txn,
+    LinKv,
+    DynamoKv,
+    TwoPhaseTxn,
+    ChainKv,
+    Lock,
+}
+
+impl Workload {
+    pub const ALL: [Workload; 13] = [
+        Workload::Echo,
+        Workload::UniqueIds,
+        Workload::Broadcast,
+        Workload::GCounter,
+        Workload::GCounterKv,
+        Workload::PnCounter,
+        Workload::Kafka,
+        Workload::Txn,
+        Workload::LinKv,
+        Workload::DynamoKv,
+        Workload::TwoPhaseTxn,
+        Workload::ChainKv,
+        Workload::Lock,
+    ];
+
+    /// Name of the workload, the same as Maelstrom's `-w` argument
+    pub fn name(&self) -> &'static str {
+        return match self {
+            | Workload::Echo => "echo",
+            | Workload::UniqueIds => "unique-ids",
+            | Workload::Broadcast => "broadcast",
+            | Workload::GCounter => "g-counter",
+            | Workload::GCounterKv => "g-counter-kv",
+            | Workload::PnCounter => "pn-counter",
+            | Workload::Kafka => "kafka",
+            | Workload::Txn => "txn-rw-register",
+            | Workload::LinKv => "lin-kv",
+            // NOTE: not a Maelstrom workload and not linearizable: `-w lin-kv` can drive it but
+            // reports stale reads, `tests/dynamo_kv.rs` checks what it does promise
+            | Workload::DynamoKv => "dynamo-kv",
+            // NOTE: not a Maelstrom workload either, run it with `-w txn-rw-register`
+            | Workload::TwoPhaseTxn => "txn-2pc",
+            // NOTE: not a Maelstrom workload either, run it with `-w lin-kv`
+            | Workload::ChainKv => "chain-kv",
+            // NOTE: Maelstrom has no lock workload, `tests/lock_service.rs` runs it instead
+            | Workload::Lock => "lock",
+        };
+    }
+
+    /// Serve the workload on stdin/stdout until Maelstrom closes stdin
+    ///
+    /// args:
+    ///   - `persist`: where broadcast, the counters, the 2PC logs and Raft's term and vote are
+    ///     kept, in memory if `None`
+    ///   - `order`: the order broadcast values are delivered in, ignored by other workloads
+    ///   - `detector`: how broadcast and chain-kv find crashed or cut off peers
+    pub fn run(
+        &self,
+        config: EventLoopConfig,
+        persist: Option<PersistConfig>,
+        order: broadcast::DeliveryOrder,
+        detector: DetectorKind,
+    ) -> anyhow::Result<()> {
+        let gossip = GossipConfig {
+            persist: persist.clone(),
+            ..GossipConfig::default()
+        };
+        let raft = RaftConfig {
+            persist: persist.clone(),
+            ..RaftConfig::default()
+        };
+        return match self {
+            | Workload::Echo => serve::<echo::EchoNode, _, _, _>((), config),
+            | Workload::UniqueIds => serve::<unique_ids::UniqueIdNode, _, _, _>((), config),
+            | Workload::Broadcast if order == broadcast::DeliveryOrder::Consensus => {
+                serve::<RaftNode<broadcast::OrderedLog>, _, _, _>(raft, config)
+            },
+            | Workload::Broadcast => {
+                let broadcast = broadcast::BroadcastConfig {
+                    persist,
+                    order,
+                    detector,
+                    ..broadcast::BroadcastConfig::default()
+                };
+                serve::<broadcast::BroadcastNode, _, _, _>(broadcast, config)
+            },
+            | Workload::GCounter => {
+                serve::<GossipNode<g_counter::GlobalCounter>, _, _, _>(gossip, config)
+            },
+            #[cfg(feature = "async")]
+            | Workload::GCounterKv => {
+                async_event_loop_with_config::<g_counter_kv::KvCounter, _, _>(
+                    (),
+                    std::io::stdin(),
+                    std::io::stdout(),
+                    config,
+                )
+            },
+            #[cfg(not(feature = "async"))]
+            | Workload::GCounterKv => bail!("{} needs the node built with --features async", self),
+            | Workload::PnCounter => {
+                serve::<GossipNode<pn_counter::Counter>, _, _, _>(gossip, config)
+            },
+            | Workload::Kafka => serve::<RaftNode<kafka::Kafka>, _, _, _>(raft, config),
+            | Workload::Txn => serve::<RaftNode<txn::Txn>, _, _, _>(raft, config),
+            | Workload::LinKv => serve::<RaftNode<lin_kv::LinKv>, _, _, _>(raft, config),
+            | Workload::DynamoKv => {
+                let dynamo = dynamo_kv::DynamoConfig::default();
+                serve::<dynamo_kv::DynamoNode, _, _, _>(dynamo, config)
+            },
+            | Workload::TwoPhaseTxn => {
+                let two_phase = two_phase_txn::TwoPhaseConfig {
+                    persist,
+                    ..two_phase_txn::TwoPhaseConfig::default()
+                };
+                serve::<two_phase_txn::TwoPhaseTxnNode, _, _, _>(two_phase, config)
+            },
+            | Workload::ChainKv => {
+                let chain = chain_kv::ChainConfig {
+                    detector,
+                    ..chain_kv::ChainConfig::default()
+                };
+                serve::<chain_kv::ChainNode, _, _, _>(chain, config)
+            },
+            | Workload::Lock => serve::<RaftNode<lock::LockService>, _, _, _>(raft, config),
+        };
+    }
+}
+
+/// `event_loop` with the given output and queue settings
+fn serve<N, State, Payload, GeneratedPayload>(
+    state: State,
+    config: EventLoopConfig,
+) -> anyhow::Result<()>
+where
+    Payload: Decode + Send + 'static,
+    GeneratedPayload: Send + 'static,
+    N: Node<State, Payload, GeneratedPayload>,
+{
+    return event_loop_with_config::<N, State, Payload, GeneratedPayload>(
+        state,
+        std::io::stdin(),
+        std::io::stdout().lock(),
+        config,
+    );
+}
+
+impl fmt::Display for Workload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.name());
+    }
+}
+
+impl FromStr for Workload {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        // NOTE: `txn` is accepted as a short name for `txn-rw-register`
+        if name == "txn" {
+            return Ok(Workload::Txn);
+        }
+        let Some(workload) = Workload::ALL
+            .iter()
+            .find(|workload| workload.name() == name)
+        else {
+            let names: Vec<&str> = Workload::ALL
+                .iter()
+                .map(|workload| workload.name())
+                .collect();
+            bail!(
+                "unknown workload {}, expected one of {}",
+                name,
+                names.join(", ")
+            );
+        };
+        return Ok(*workload);
+    }
+}
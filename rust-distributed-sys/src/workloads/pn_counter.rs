@@ -0,0 +1,33 @@
+// Purpose: Counter that can go up and down, replicated to all nodes.
+use crate::crdt::{CrdtWorkload, PNCounter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum PayLoad {
+    Add { delta: i64 },
+    AddOk,
+    Read,
+    ReadOk { value: i64 },
+}
+
+pub struct Counter;
+
+impl CrdtWorkload for Counter {
+    type State = PNCounter;
+    type Payload = PayLoad;
+
+    fn handle(node_id: &str, state: &mut PNCounter, request: PayLoad) -> Option<PayLoad> {
+        return match request {
+            | PayLoad::Read => Some(PayLoad::ReadOk {
+                value: state.value(),
+            }),
+            | PayLoad::Add { delta } => {
+                state.add(node_id, delta);
+                Some(PayLoad::AddOk)
+            },
+            | PayLoad::ReadOk { .. } | PayLoad::AddOk => None,
+        };
+    }
+}
@@ -0,0 +1,380 @@
+// Purpose: txn-rw-register over keys partitioned across the nodes, committed with 2PC.
+//
+// Every key is owned by one node of the hash ring. The node a client sends a transaction to
+// coordinates it: each owner gets the ops on its keys as a participant, locks those keys and
+// runs them, and `two_phase` commits or aborts the transaction everywhere. A transaction that
+// waits too long for a locked key is aborted with `txn-conflict`, the client may simply retry it.
+//
+// NOTE: locks are exclusive and held until the decision, which makes committed transactions
+// serializable. With a `PersistConfig` the coordinator's log and the participant's registers,
+// locks and staged writes all survive a restart.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::kv::KvStore;
+use crate::partition::{HashRing, RingConfig};
+use crate::persist::PersistConfig;
+use crate::queue::EventSender;
+use crate::two_phase::{
+    CommitConfig, CommitMessage, Coordinator, Outcome, Outgoing, Participant, Resource,
+};
+use crate::workloads::txn::{self, Operation};
+use crate::{Body, ErrorCode, Event, InitNodes, Message, Node};
+
+const TICK: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)] // NOTE: `type` decides, anything that isn't 2PC traffic is a client request
+pub enum Payload {
+    Commit(CommitMessage<Operation>),
+    Client(txn::Payload),
+}
+
+pub struct Tick;
+
+#[derive(Debug, Clone, Default)]
+pub struct TwoPhaseConfig {
+    /// only the owner of a key is used, `replication` is ignored
+    pub ring: RingConfig,
+    pub commit: CommitConfig,
+    /// where the coordinator and the participant keep their logs, in memory if `None`
+    pub persist: Option<PersistConfig>,
+}
+
+/// Registers of the keys a node owns, each locked by at most one prepared transaction
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LockedStore {
+    store: KvStore,
+    /// key's JSON text -> txn_id holding it
+    locks: HashMap<String, String>,
+    /// writes of prepared transactions, `(key, value)` in txn order
+    staged: HashMap<String, Vec<(Value, Value)>>,
+}
+
+impl LockedStore {
+    fn release(&mut self, txn_id: &str) {
+        self.locks.retain(|_, holder| holder != txn_id);
+    }
+}
+
+impl Resource for LockedStore {
+    type Op = Operation;
+
+    fn prepare(&mut self, txn_id: &str, ops: &[Operation]) -> Option<Vec<Operation>> {
+        let keys: HashSet<String> = ops
+            .iter()
+            .map(|Operation(_, key, _)| key.to_string())
+            .collect();
+        let taken = keys.iter().any(|key| {
+            return self.locks.get(key).is_some_and(|holder| holder != txn_id);
+        });
+        if taken {
+            return None;
+        }
+        for key in keys {
+            self.locks.insert(key, txn_id.to_string());
+        }
+
+        let mut writes: Vec<(Value, Value)> = Vec::new();
+        let mut results = Vec::with_capacity(ops.len());
+        for Operation(op, key, value) in ops {
+            if op == "w" {
+                writes.push((key.clone(), value.clone()));
+                results.push(Operation(op.clone(), key.clone(), value.clone()));
+                continue;
+            }
+            // NOTE: a read sees the transaction's own earlier writes
+            let read = writes
+                .iter()
+                .rev()
+                .find(|(written, _)| written == key)
+                .map(|(_, value)| value)
+                .or_else(|| self.store.get(key))
+                .cloned()
+                .unwrap_or(Value::Null);
+            results.push(Operation(op.clone(), key.clone(), read));
+        }
+        self.staged.insert(txn_id.to_string(), writes);
+        return Some(results);
+    }
+
+    fn commit(&mut self, txn_id: &str) {
+        for (key, value) in self.staged.remove(txn_id).unwrap_or_default() {
+            self.store.insert(&key, value);
+        }
+        self.release(txn_id);
+    }
+
+    fn abort(&mut self, txn_id: &str) {
+        self.staged.remove(txn_id);
+        self.release(txn_id);
+    }
+}
+
+/// A client transaction waiting for its outcome
+struct Request {
+    client: String,
+    in_reply_to: Option<usize>,
+    /// owner of every op, in txn order
+    owners: Vec<String>,
+}
+
+pub struct TwoPhaseTxnNode {
+    node_id: String,
+    local_id: usize,
+    ring: HashRing,
+    coordinator: Coordinator<Operation>,
+    participant: Participant<LockedStore>,
+    requests: HashMap<String, Request>,
+    /// transactions this node coordinated, for `status`
+    committed: u64,
+    aborted: u64,
+}
+
+impl TwoPhaseTxnNode {
+    fn reply(
+        &mut self,
+        dest: &str,
+        in_reply_to: Option<usize>,
+        payload: txn::Payload,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let id = self.local_id;
+        self.local_id += 1;
+        return Message {
+            src: self.node_id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(id),
+                in_reply_to,
+                clock: None,
+                payload: Payload::Client(payload),
+            },
+        }
+        .send(&mut *output, "transaction");
+    }
+
+    /// Hand `message` to this node's coordinator or participant
+    fn route(
+        &mut self,
+        src: &str,
+        message: CommitMessage<Operation>,
+        now: Instant,
+    ) -> anyhow::Result<(Outgoing<Operation>, Option<Outcome<Operation>>)> {
+        return match message {
+            | CommitMessage::Prepare { .. }
+            | CommitMessage::Commit { .. }
+            | CommitMessage::Abort { .. } => {
+                Ok((self.participant.receive(src, message, now)?, None))
+            },
+            | CommitMessage::Vote { .. }
+            | CommitMessage::Ack { .. }
+            | CommitMessage::Inquire { .. } => self.coordinator.receive(src, message),
+        };
+    }
+
+    /// Send `outbox`, messages to this node are handled right away
+    fn dispatch(
+        &mut self,
+        outbox: Outgoing<Operation>,
+        now: Instant,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        let mut queue: VecDeque<(String, CommitMessage<Operation>)> = outbox.into();
+        while let Some((dest, message)) = queue.pop_front() {
+            if dest == self.node_id {
+                let src = self.node_id.clone();
+                let (outbox, outcome) = self.route(&src, message, now)?;
+                queue.extend(outbox);
+                if let Some(outcome) = outcome {
+                    self.conclude(outcome, output)?;
+                }
+                continue;
+            }
+            let id = self.local_id;
+            self.local_id += 1;
+            Message {
+                src: self.node_id.clone(),
+                dest,
+                body: Body {
+                    id: Some(id),
+                    in_reply_to: None,
+                    clock: None,
+                    payload: Payload::Commit(message),
+                },
+            }
+            .send(&mut *output, "two-phase commit")?;
+        }
+        return Ok(());
+    }
+
+    /// Answer the client of a decided transaction
+    fn conclude(
+        &mut self,
+        outcome: Outcome<Operation>,
+        output: &mut dyn Write,
+    ) -> anyhow::Result<()> {
+        // NOTE: decisions recovered from the log have no client waiting anymore
+        let Some(request) = self.requests.remove(&outcome.txn_id) else {
+            return Ok(());
+        };
+        if !outcome.committed {
+            self.aborted += 1;
+            let error = txn::Payload::Error {
+                code: ErrorCode::TxnConflict,
+                text: "aborted, a key stayed locked or a participant didn't vote in time"
+                    .to_string(),
+            };
+            return self.reply(&request.client, request.in_reply_to, error, output);
+        }
+        self.committed += 1;
+        let mut results: HashMap<String, std::vec::IntoIter<Operation>> = outcome
+            .results
+            .into_iter()
+            .map(|(participant, results)| (participant, results.into_iter()))
+            .collect();
+        // NOTE: every participant ran its ops in txn order, take them back in the same order
+        let txn = request
+            .owners
+            .iter()
+            .filter_map(|owner| results.get_mut(owner)?.next())
+            .collect();
+        return self.reply(
+            &request.client,
+            request.in_reply_to,
+            txn::Payload::TxnOk { txn },
+            output,
+        );
+    }
+}
+
+impl Node<TwoPhaseConfig, Payload, Tick> for TwoPhaseTxnNode {
+    fn from_init(
+        config: TwoPhaseConfig,
+        init: InitNodes,
+        sender: EventSender<Payload, Tick>,
+    ) -> anyhow::Result<Self> {
+        let ring = HashRing::from_init(&init, &config.ring)?;
+        let now = Instant::now();
+        let coordinator = Coordinator::new(
+            &init.node_id,
+            config.commit.clone(),
+            config.persist.as_ref(),
+            now,
+        )?;
+        let participant = Participant::new(&init.node_id, config.commit, config.persist.as_ref())?;
+
+        let node_id = init.node_id.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(TICK);
+            let tick = Message {
+                src: node_id.clone(),
+                dest: node_id.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    clock: None,
+                    payload: Tick,
+                },
+            };
+            if sender.send(Event::GeneratedEvent(tick)).is_err() {
+                break;
+            }
+        });
+
+        return Ok(TwoPhaseTxnNode {
+            node_id: init.node_id,
+            local_id: 1,
+            ring,
+            coordinator,
+            participant,
+            requests: HashMap::new(),
+            committed: 0,
+            aborted: 0,
+        });
+    }
+
+    fn step(&mut self, event: Event<Payload, Tick>, output: &mut dyn Write) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let message = match event {
+            | Event::EndOfMessages => return Ok(()),
+            | Event::GeneratedEvent(_) => {
+                let (mut outbox, outcomes) = self.coordinator.tick(now)?;
+                outbox.extend(self.participant.tick(now));
+                for outcome in outcomes {
+                    self.conclude(outcome, output)?;
+                }
+                return self.dispatch(outbox, now, output);
+            },
+            | Event::Message(message) => message,
+        };
+
+        match message.body.payload {
+            | Payload::Client(txn::Payload::Txn { txn }) => {
+                if let Some(Operation(op, ..)) =
+                    txn.iter().find(|Operation(op, ..)| op != "r" && op != "w")
+                {
+                    let error = txn::Payload::Error {
+                        code: ErrorCode::MalformedRequest,
+                        text: format!("unknown operation {}", op),
+                    };
+                    return self.reply(&message.src, message.body.id, error, output);
+                }
+                if txn.is_empty() {
+                    let reply = txn::Payload::TxnOk { txn };
+                    return self.reply(&message.src, message.body.id, reply, output);
+                }
+
+                let owners: Vec<String> = txn
+                    .iter()
+                    .map(|Operation(_, key, _)| self.ring.owner(key.to_string()).to_string())
+                    .collect();
+                let mut parts: Vec<(String, Vec<Operation>)> = Vec::new();
+                for (owner, op) in owners.iter().zip(txn) {
+                    match parts
+                        .iter_mut()
+                        .find(|(participant, _)| participant == owner)
+                    {
+                        | Some((_, ops)) => ops.push(op),
+                        | None => parts.push((owner.clone(), vec![op])),
+                    }
+                }
+                let (txn_id, outbox) = self.coordinator.begin(parts, now);
+                self.requests.insert(
+                    txn_id,
+                    Request {
+                        client: message.src,
+                        in_reply_to: message.body.id,
+                        owners,
+                    },
+                );
+                self.dispatch(outbox, now, output)?;
+            },
+            | Payload::Client(_) => {},
+            | Payload::Commit(commit) => {
+                let (outbox, outcome) = self.route(&message.src, commit, now)?;
+                if let Some(outcome) = outcome {
+                    self.conclude(outcome, output)?;
+                }
+                self.dispatch(outbox, now, output)?;
+            },
+        }
+        return Ok(());
+    }
+
+    fn status(&self) -> serde_json::Value {
+        return serde_json::json!({
+            "keys": self.participant.resource().store.len(),
+            "prepared": self.participant.prepared(),
+            "blocked": self.participant.blocked(),
+            "voting": self.coordinator.voting(),
+            "undelivered_decisions": self.coordinator.undelivered(),
+            "committed": self.committed,
+            "aborted": self.aborted,
+        });
+    }
+}
@@ -0,0 +1,69 @@
+// Purpose: Read/write register transactions, Maelstrom's txn-rw-register workload.
+//
+// A whole transaction is a single Raft log entry and is applied atomically on every node, which
+// makes the store strict serializable without any locking.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::kv::KvStore;
+use crate::raft::RaftService;
+use crate::ErrorCode;
+
+/// `["r", key, null]` or `["w", key, value]`, reads get their value filled in by the reply
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Operation(pub String, pub Value, pub Value);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Txn { txn: Vec<Operation> },
+    TxnOk { txn: Vec<Operation> },
+    Error { code: ErrorCode, text: String },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Txn {
+    store: KvStore,
+}
+
+impl RaftService for Txn {
+    type Payload = Payload;
+
+    fn is_request(payload: &Payload) -> bool {
+        return matches!(payload, Payload::Txn { .. });
+    }
+
+    fn apply(&mut self, request: &Payload) -> Payload {
+        let Payload::Txn { txn } = request else {
+            return Txn::error(ErrorCode::NotSupported, "not a transaction".to_string());
+        };
+        // NOTE: validate before anything is written, a bad transaction must have no effect
+        if let Some(Operation(op, ..)) = txn.iter().find(|Operation(op, ..)| op != "r" && op != "w")
+        {
+            return Txn::error(
+                ErrorCode::MalformedRequest,
+                format!("unknown operation {}", op),
+            );
+        }
+        let mut result = Vec::with_capacity(txn.len());
+        for Operation(op, key, value) in txn {
+            match op.as_str() {
+                | "r" => {
+                    let read = self.store.get(key).cloned().unwrap_or(Value::Null);
+                    result.push(Operation(op.clone(), key.clone(), read));
+                },
+                | "w" => {
+                    self.store.insert(key, value.clone());
+                    result.push(Operation(op.clone(), key.clone(), value.clone()));
+                },
+                | _ => unreachable!("operations are validated first"),
+            }
+        }
+        return Payload::TxnOk { txn: result };
+    }
+
+    fn error(code: ErrorCode, text: String) -> Payload {
+        return Payload::Error { code, text };
+    }
+}
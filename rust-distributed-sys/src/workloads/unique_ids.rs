@@ -0,0 +1,58 @@
+// Purpose: Generate globally unique ids without coordination.
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::queue::EventSender;
+use crate::*;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Generate,
+    GenerateOk {
+        #[serde(rename = "id")]
+        guid: Uuid,
+    },
+}
+
+pub struct UniqueIdNode {
+    local_id: usize,
+    /// NOTE: the "MAC address" of the v1 uuid, derived from the node id so nodes never collide
+    uuid_node: [u8; 6],
+}
+
+impl Node<(), Payload, ()> for UniqueIdNode {
+    fn from_init(
+        _state: (),
+        init: InitNodes,
+        _sender: EventSender<Payload, ()>,
+    ) -> anyhow::Result<Self> {
+        let mut uuid_node = [0u8; 6];
+        for (byte, id_byte) in uuid_node.iter_mut().rev().zip(init.node_id.bytes().rev()) {
+            *byte = id_byte;
+        }
+        return Ok(UniqueIdNode {
+            local_id: 1,
+            uuid_node,
+        });
+    }
+
+    fn step(&mut self, event: Event<Payload, ()>, output: &mut dyn Write) -> anyhow::Result<()> {
+        let Event::Message(message) = event else {
+            return Ok(());
+        };
+        let mut reply = message.into_reply(Some(&mut self.local_id));
+        match reply.body.payload {
+            | Payload::Generate => {
+                let guid = Uuid::now_v1(&self.uuid_node);
+                reply.body.payload = Payload::GenerateOk { guid };
+                reply.send(output, "generate")?;
+            },
+            | Payload::GenerateOk { .. } => {},
+        }
+        return Ok(());
+    }
+}
@@ -1,21 +1,44 @@
 #!/usr/bin/env fish
 set test $argv
+# NOTE: maelstrom can't pass arguments to the node, `node` picks its workload from WORKLOAD
+set node ./target/debug/node
 
 switch "$test"
     case echo
-        ~/maelstrom/maelstrom test -w echo --bin ./target/debug/broadcast --node-count 1 --time-limit 10
+        env WORKLOAD=echo ~/maelstrom/maelstrom test -w echo --bin $node --node-count 1 --time-limit 10
     case unique-ids
-        ~/maelstrom/maelstrom test -w unique-ids --bin ./target/debug/broadcast --node-count 1 --time-limit 10
+        env WORKLOAD=unique-ids ~/maelstrom/maelstrom test -w unique-ids --bin $node --node-count 1 --time-limit 10
     case single-broadcast
-        ~/maelstrom/maelstrom test -w broadcast --bin ./target/debug/broadcast --node-count 1 --time-limit 20 --rate 10
+        env WORKLOAD=broadcast ~/maelstrom/maelstrom test -w broadcast --bin $node --node-count 1 --time-limit 20 --rate 10
     case multi-broadcast
-        ~/maelstrom/maelstrom test -w broadcast --bin ./target/debug/broadcast --node-count 5 --time-limit 20 --rate 10
+        env WORKLOAD=broadcast ~/maelstrom/maelstrom test -w broadcast --bin $node --node-count 5 --time-limit 20 --rate 10
     case fault-tolarant
-        ~/maelstrom/maelstrom test -w broadcast --bin ./target/debug/broadcast --node-count 5 --time-limit 20 --rate 10 --nemesis partition
+        env WORKLOAD=broadcast ~/maelstrom/maelstrom test -w broadcast --bin $node --node-count 5 --time-limit 20 --rate 10 --nemesis partition
     case efficient-broadcast
-        ~/maelstrom/maelstrom test -w broadcast --bin ./target/debug/broadcast --node-count 25 --time-limit 20 --rate 100 --latency 100
+        env WORKLOAD=broadcast ~/maelstrom/maelstrom test -w broadcast --bin $node --node-count 25 --time-limit 20 --rate 100 --latency 100
+    case ordered-broadcast
+        env WORKLOAD=broadcast BROADCAST_ORDER=sequencer ~/maelstrom/maelstrom test -w broadcast --bin $node --node-count 5 --time-limit 20 --rate 10
+        and env WORKLOAD=broadcast BROADCAST_ORDER=consensus ~/maelstrom/maelstrom test -w broadcast --bin $node --node-count 5 --time-limit 20 --rate 10 --nemesis partition
     case g-counter
-        ~/maelstrom/maelstrom test -w g-counter --bin ./target/debug/g_counter --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+        env WORKLOAD=g-counter ~/maelstrom/maelstrom test -w g-counter --bin $node --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+    case g-counter-kv
+        cargo build --features async
+        and env WORKLOAD=g-counter-kv ~/maelstrom/maelstrom test -w g-counter --bin $node --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+    case pn-counter
+        env WORKLOAD=pn-counter ~/maelstrom/maelstrom test -w pn-counter --bin $node --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+    case kafka
+        env WORKLOAD=kafka ~/maelstrom/maelstrom test -w kafka --bin $node --node-count 3 --concurrency 2n --time-limit 20 --rate 1000
+    case txn
+        env WORKLOAD=txn-rw-register ~/maelstrom/maelstrom test -w txn-rw-register --bin $node --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+    case lin-kv
+        env WORKLOAD=lin-kv ~/maelstrom/maelstrom test -w lin-kv --bin $node --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+    case dynamo-kv
+        # NOTE: eventually consistent, expect Knossos to find non-linearizable histories
+        env WORKLOAD=dynamo-kv ~/maelstrom/maelstrom test -w lin-kv --bin $node --node-count 5 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
+    case chain-kv
+        env WORKLOAD=chain-kv ~/maelstrom/maelstrom test -w lin-kv --bin $node --node-count 3 --concurrency 2n --time-limit 20 --rate 100
+    case txn-2pc
+        env WORKLOAD=txn-2pc ~/maelstrom/maelstrom test -w txn-rw-register --bin $node --node-count 3 --concurrency 2n --time-limit 20 --rate 100 --nemesis partition
     case serve
         ~/maelstrom/maelstrom serve
     case '*'
@@ -0,0 +1,103 @@
+// Purpose: cut a node off from all its neighbors in the simulator and check broadcast routes around.
+//
+// usage: cargo test --test broadcast_reroute
+//
+// The nodes form a ring, so every node has two neighbors and the rest of the cluster only hears
+// from it through them. Once both links of one node are cut, its values can only get out, and
+// the others' values only get in, through a stand-in: a live node outside the neighborhood that
+// the failure detector must still know to be alive.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::topology::SmallWorld;
+use rust_distributed_sys_challenge::workloads::broadcast::{
+    BroadcastConfig, BroadcastNode, Payload,
+};
+
+const NODES: usize = 6;
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// the suspect timeout is 3s, after it a stand-in takes over
+const DEADLINE: Duration = Duration::from_secs(15);
+
+fn read(client: &mut Client, node_id: &str) -> anyhow::Result<Vec<usize>> {
+    return match client.call(node_id, &Payload::Read, TIMEOUT)? {
+        | Payload::ReadOk { messages } => Ok(messages),
+        | reply => bail!("unexpected reply to read: {:?}", reply),
+    };
+}
+
+#[test]
+fn values_reach_a_node_cut_off_from_its_neighbors() {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            ..NetworkConfig::default()
+        },
+    );
+    let topology = SmallWorld {
+        neighbors: 2,
+        rewire_probability: 0.0,
+        seed: 1,
+    };
+    let config = BroadcastConfig {
+        topology,
+        ..BroadcastConfig::default()
+    };
+    for node_id in &ids {
+        sim.start::<BroadcastNode, _, _, _>(node_id, config.clone())
+            .expect("starting a node failed");
+    }
+    let mut client = sim.client("c1");
+    for node_id in &ids {
+        let topology = Payload::Topology {
+            topology: HashMap::new(),
+        };
+        client
+            .call::<_, Payload>(node_id, &topology, TIMEOUT)
+            .expect("topology failed");
+    }
+
+    let cut_off = ids[0];
+    let neighbors = topology.generate(&node_ids).remove(cut_off).unwrap();
+    assert_eq!(
+        neighbors.len(),
+        2,
+        "the ring gives every node two neighbors"
+    );
+    for neighbor in &neighbors {
+        sim.partition(&[&[cut_off], &[neighbor.as_str()]]);
+    }
+    let far = ids[NODES / 2];
+    assert!(!neighbors.contains(far));
+    for (value, node_id) in [cut_off, far].into_iter().enumerate() {
+        let broadcast = Payload::Broadcast { message: value };
+        client
+            .call::<_, Payload>(node_id, &broadcast, TIMEOUT)
+            .expect("broadcast failed");
+    }
+
+    let started = Instant::now();
+    let mut missing: Vec<String> = ids.iter().map(|node_id| node_id.to_string()).collect();
+    while !missing.is_empty() && started.elapsed() < DEADLINE {
+        std::thread::sleep(Duration::from_millis(500));
+        missing.retain(|node_id| {
+            let mut messages = read(&mut client, node_id).expect("read failed");
+            messages.sort();
+            return messages != vec![0, 1];
+        });
+    }
+    sim.shutdown().expect("the simulation failed");
+    assert!(
+        missing.is_empty(),
+        "{:?} didn't get both values with {} cut off from {:?}",
+        missing,
+        cut_off,
+        neighbors
+    );
+}
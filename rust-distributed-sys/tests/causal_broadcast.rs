@@ -0,0 +1,199 @@
+// Purpose: run ordered broadcast in the simulator with heavy reordering and check what clients see.
+//
+// usage: cargo test --test causal_broadcast
+//
+// A client broadcasts every value at a random node right after reading that node, so the value
+// causally depends on everything the read returned. Every read, during the run and after the
+// cluster settled, must then contain the dependencies of each value it contains, and list them
+// first. Latencies between nodes vary from 0 to 200ms, so shares overtake each other all the
+// time. Unordered reads are sets, so only missing dependencies count there. With `sequencer` and
+// `consensus` every read must also be a prefix of the same sequence.
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use anyhow::bail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::raft::{RaftConfig, RaftNode};
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::workloads::broadcast::{
+    BroadcastConfig, BroadcastNode, DeliveryOrder, OrderedLog, Payload,
+};
+
+const NODES: usize = 5;
+const OPS: usize = 40;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// attempts of a request the cluster answers with an error, e.g. while Raft has no leader
+const ATTEMPTS: usize = 50;
+/// long enough for every value to reach every node once clients stop
+const SETTLE: Duration = Duration::from_secs(5);
+
+/// `Client::call`, repeated while the node answers with an error
+fn call(client: &mut Client, node_id: &str, request: &Payload) -> anyhow::Result<Payload> {
+    for _ in 0..ATTEMPTS {
+        match client.call(node_id, request, TIMEOUT)? {
+            | Payload::Error { .. } => std::thread::sleep(Duration::from_millis(100)),
+            | reply => return Ok(reply),
+        }
+    }
+    bail!("{} kept failing {:?}", node_id, request);
+}
+
+fn read(client: &mut Client, node_id: &str) -> anyhow::Result<Vec<usize>> {
+    return match call(client, node_id, &Payload::Read)? {
+        | Payload::ReadOk { messages } => Ok(messages),
+        | reply => bail!("unexpected reply to read: {:?}", reply),
+    };
+}
+
+/// Violations of causality in a read of `node_id`
+///
+/// args:
+///   - ordered: the read lists values in delivery order, plain broadcast reads are unordered
+fn check(
+    node_id: &str,
+    read: &[usize],
+    ordered: bool,
+    dependencies: &HashMap<usize, HashSet<usize>>,
+) -> Vec<String> {
+    let position: HashMap<usize, usize> = read
+        .iter()
+        .enumerate()
+        .map(|(position, value)| (*value, position))
+        .collect();
+    let mut violations = Vec::new();
+    for (index, value) in read.iter().enumerate() {
+        for dependency in dependencies.get(value).into_iter().flatten() {
+            match position.get(dependency) {
+                | None => violations.push(format!(
+                    "{} returned {} without its dependency {}",
+                    node_id, value, dependency
+                )),
+                | Some(before) if ordered && *before > index => violations.push(format!(
+                    "{} delivered {} before its dependency {}",
+                    node_id, value, dependency
+                )),
+                | Some(_) => {},
+            }
+        }
+    }
+    return violations;
+}
+
+/// Broadcast `OPS` values through a cluster delivering in `order`
+///
+/// returns:
+///   - every violation of `order` any read showed
+fn run(order: DeliveryOrder) -> anyhow::Result<Vec<String>> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(200),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    let config = BroadcastConfig {
+        order,
+        ..BroadcastConfig::default()
+    };
+    for node_id in &ids {
+        if order == DeliveryOrder::Consensus {
+            sim.start::<RaftNode<OrderedLog>, _, _, _>(node_id, RaftConfig::default())?;
+        } else {
+            sim.start::<BroadcastNode, _, _, _>(node_id, config.clone())?;
+        }
+    }
+    let ordered = order != DeliveryOrder::Unordered;
+    let total = matches!(order, DeliveryOrder::Sequencer | DeliveryOrder::Consensus);
+
+    let mut client = sim.client("c1");
+    let topology = Payload::Topology {
+        topology: HashMap::new(),
+    };
+    for node_id in &ids {
+        call(&mut client, node_id, &topology)?;
+    }
+
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut dependencies: HashMap<usize, HashSet<usize>> = HashMap::new();
+    let mut violations = Vec::new();
+    let mut reads: Vec<(&str, Vec<usize>)> = Vec::new();
+    for value in 0..OPS {
+        let node_id = ids[rng.gen_range(0..ids.len())];
+        let seen = read(&mut client, node_id)?;
+        violations.extend(check(node_id, &seen, ordered, &dependencies));
+        dependencies.insert(value, seen.iter().copied().collect());
+        reads.push((node_id, seen));
+        call(&mut client, node_id, &Payload::Broadcast { message: value })?;
+        std::thread::sleep(Duration::from_millis(rng.gen_range(0..50)));
+    }
+
+    std::thread::sleep(SETTLE);
+    for node_id in &ids {
+        let seen = read(&mut client, node_id)?;
+        violations.extend(check(node_id, &seen, ordered, &dependencies));
+        if seen.len() != OPS {
+            violations.push(format!(
+                "{} has {} of {} values after settling",
+                node_id,
+                seen.len(),
+                OPS
+            ));
+        }
+        reads.push((node_id, seen));
+    }
+    sim.shutdown()?;
+
+    if total {
+        // NOTE: every node delivers the same sequence, a read can only lag behind
+        let longest = reads
+            .iter()
+            .map(|(_, seen)| seen)
+            .max_by_key(|seen| seen.len())
+            .cloned()
+            .unwrap_or_default();
+        for (node_id, seen) in &reads {
+            if !longest.starts_with(seen) {
+                violations.push(format!(
+                    "{} read {:?} out of the total order",
+                    node_id, seen
+                ));
+            }
+        }
+    }
+    return Ok(violations);
+}
+
+fn assert_holds(order: DeliveryOrder) {
+    let violations = run(order).expect("the simulation failed");
+    assert!(
+        violations.is_empty(),
+        "{} broadcast violated its order:\n{}",
+        order,
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn unordered_reads_contain_dependencies() {
+    assert_holds(DeliveryOrder::Unordered);
+}
+
+#[test]
+fn causal_delivers_dependencies_first() {
+    assert_holds(DeliveryOrder::Causal);
+}
+
+#[test]
+fn sequencer_delivers_one_sequence() {
+    assert_holds(DeliveryOrder::Sequencer);
+}
+
+#[test]
+fn consensus_delivers_one_sequence() {
+    assert_holds(DeliveryOrder::Consensus);
+}
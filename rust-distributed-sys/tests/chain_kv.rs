@@ -0,0 +1,217 @@
+// Purpose: crash a member of the chain replicated KV in the simulator and check it stays
+// linearizable.
+//
+// usage: cargo test --test chain_kv
+//
+// Every key is written and read back through random nodes, then clients read, write and cas a
+// few keys through the survivors while the head, a middle node or the tail crashes. The survivors
+// must agree on the shortened chain, no client may get two replies to one request and the whole
+// history, timeouts included, must be linearizable.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::kv::KvPayload;
+use rust_distributed_sys_challenge::linearizability::{self, CasRegister, History};
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation, TraceEntry};
+use rust_distributed_sys_challenge::workloads::chain_kv::{ChainConfig, ChainNode};
+use serde_json::{json, Value};
+
+const NODES: usize = 5;
+const CLIENTS: usize = 4;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const KEYS: u64 = 3;
+/// time given to detect a crash and reconfigure, the suspect timeout is 500ms
+const SETTLE: Duration = Duration::from_secs(1);
+
+struct Checker {
+    client: Client,
+    violations: Vec<String>,
+}
+
+impl Checker {
+    fn expect(&mut self, node_id: &str, request: KvPayload, expected: KvPayload) {
+        match self.client.call::<_, KvPayload>(node_id, &request, TIMEOUT) {
+            | Ok(reply) if reply == expected => {},
+            | Ok(reply) => self.violations.push(format!(
+                "{} answered {:?} with {:?}, expected {:?}",
+                node_id, request, reply, expected
+            )),
+            | Err(error) => self.violations.push(format!(
+                "{} didn't answer {:?}: {:#}",
+                node_id, request, error
+            )),
+        }
+    }
+
+    /// Write every key through one random node and read it back through another
+    fn write_and_read(&mut self, node_ids: &[&str], value: Value, rng: &mut StdRng) {
+        for key in 0..KEYS {
+            let request = KvPayload::Write {
+                key: json!(key),
+                value: value.clone(),
+            };
+            let node_id = node_ids[rng.gen_range(0..node_ids.len())];
+            self.expect(node_id, request, KvPayload::WriteOk);
+            let request = KvPayload::Read { key: json!(key) };
+            let node_id = node_ids[rng.gen_range(0..node_ids.len())];
+            let expected = KvPayload::ReadOk {
+                value: value.clone(),
+            };
+            self.expect(node_id, request, expected);
+        }
+    }
+}
+
+/// Random reads, writes and cas through `node_ids` until `stop` is set
+fn run_client(mut client: Client, node_ids: Vec<String>, seed: u64, stop: Arc<AtomicBool>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    while !stop.load(Ordering::Relaxed) {
+        let node_id = &node_ids[rng.gen_range(0..node_ids.len())];
+        let key = json!(rng.gen_range(0..KEYS));
+        let request = match rng.gen_range(0..3) {
+            | 0 => KvPayload::Read { key },
+            | 1 => KvPayload::Write {
+                key,
+                value: json!(rng.gen_range(0..5)),
+            },
+            | _ => KvPayload::Cas {
+                key,
+                from: json!(rng.gen_range(0..5)),
+                to: json!(rng.gen_range(0..5)),
+                create_if_not_exists: false,
+            },
+        };
+        // NOTE: timeouts are part of the history, the checker treats them as unknown outcomes
+        let _ = client.call::<_, KvPayload>(node_id, &request, TIMEOUT);
+    }
+}
+
+/// Requests a client got more than one reply to
+fn repeated_replies(trace: &[TraceEntry]) -> Vec<String> {
+    let mut replies: HashMap<(&str, u64), usize> = HashMap::new();
+    for entry in trace {
+        if entry.dropped || !entry.dest.starts_with('c') {
+            continue;
+        }
+        if let Some(in_reply_to) = entry.body["in_reply_to"].as_u64() {
+            *replies.entry((&entry.dest, in_reply_to)).or_default() += 1;
+        }
+    }
+    return replies
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|((client, in_reply_to), count)| {
+            return format!(
+                "{} got {} replies to request {}",
+                client, count, in_reply_to
+            );
+        })
+        .collect();
+}
+
+/// Crash the `crashed`th member of the chain under concurrent clients
+///
+/// returns:
+///   - every violation the survivors, the trace or the history showed
+fn run(crashed: usize) -> anyhow::Result<Vec<String>> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in &ids {
+        sim.start::<ChainNode, _, _, _>(node_id, ChainConfig::default())?;
+    }
+    let mut checker = Checker {
+        client: sim.client("c0"),
+        violations: Vec::new(),
+    };
+    let mut rng = StdRng::seed_from_u64(SEED);
+    checker.write_and_read(&ids, json!(0), &mut rng);
+
+    // NOTE: nodes sort by name, so n0 is the head and the last node the tail
+    let crashed = ids[crashed];
+    let survivors: Vec<&str> = ids
+        .iter()
+        .copied()
+        .filter(|node_id| *node_id != crashed)
+        .collect();
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut threads = Vec::new();
+    for i in 1..=CLIENTS {
+        let client = sim.client(&format!("c{}", i));
+        let node_ids: Vec<String> = survivors
+            .iter()
+            .map(|node_id| node_id.to_string())
+            .collect();
+        let seed = SEED + i as u64;
+        let stop = stop.clone();
+        threads.push(std::thread::spawn(move || {
+            run_client(client, node_ids, seed, stop);
+        }));
+    }
+    std::thread::sleep(SETTLE / 2);
+    sim.crash(crashed)?;
+    std::thread::sleep(SETTLE);
+    stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        thread.join().expect("client thread panicked");
+    }
+
+    for node_id in &survivors {
+        let status: Value = checker
+            .client
+            .call(node_id, &json!({"type": "status"}), TIMEOUT)?;
+        if status["node"]["chain"] != json!(survivors) {
+            checker.violations.push(format!(
+                "{} has chain {} after {} crashed, expected {:?}",
+                node_id, status["node"]["chain"], crashed, survivors
+            ));
+        }
+    }
+    checker.write_and_read(&survivors, json!("after"), &mut rng);
+
+    let trace = sim.trace();
+    sim.shutdown()?;
+    checker.violations.extend(repeated_replies(&trace));
+    if let Err(counterexample) = linearizability::check(&CasRegister, &History::from_trace(&trace))
+    {
+        checker.violations.push(counterexample.to_string());
+    }
+    return Ok(checker.violations);
+}
+
+fn assert_linearizable(crashed: usize) {
+    let violations = run(crashed).expect("the simulation failed");
+    assert!(
+        violations.is_empty(),
+        "the chain misbehaved after n{} crashed:\n{}",
+        crashed,
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn head_crash() {
+    assert_linearizable(0);
+}
+
+#[test]
+fn middle_crash() {
+    assert_linearizable(NODES / 2);
+}
+
+#[test]
+fn tail_crash() {
+    assert_linearizable(NODES - 1);
+}
@@ -0,0 +1,263 @@
+// Purpose: run the Dynamo style KV in the simulator through partitions and check it heals.
+//
+// usage: cargo test --test dynamo_kv
+//
+// - healthy: every key is written and read back through random nodes, R + W > N so reads see the
+//   last write
+// - one node isolated: writes through the others must still succeed on sloppy quorums, and once
+//   the partition heals hinted handoff must bring the isolated node's replicas up to date
+// - split in two: both sides write the same key, after healing its replicas must hold both
+//   versions as siblings, a read must notice the conflict and the next write supersede them
+use std::time::Duration;
+
+use anyhow::bail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::kv::KvPayload;
+use rust_distributed_sys_challenge::partition::HashRing;
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::workloads::dynamo_kv::{
+    DynamoConfig, DynamoNode, ReplicaMessage, Version,
+};
+use serde_json::{json, Value};
+
+const NODES: usize = 5;
+const KEYS: usize = 50;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// time given to hinted handoff after a partition healed
+const SETTLE: Duration = Duration::from_secs(2);
+
+struct Checker {
+    client: Client,
+    violations: Vec<String>,
+}
+
+impl Checker {
+    fn expect(&mut self, node_id: &str, request: KvPayload, expected: KvPayload) {
+        match self.client.call::<_, KvPayload>(node_id, &request, TIMEOUT) {
+            | Ok(reply) if reply == expected => {},
+            | Ok(reply) => self.violations.push(format!(
+                "{} answered {:?} with {:?}, expected {:?}",
+                node_id, request, reply, expected
+            )),
+            | Err(error) => self.violations.push(format!(
+                "{} didn't answer {:?}: {:#}",
+                node_id, request, error
+            )),
+        }
+    }
+
+    fn write(&mut self, node_id: &str, key: usize, value: Value) {
+        let request = KvPayload::Write {
+            key: json!(key),
+            value,
+        };
+        self.expect(node_id, request, KvPayload::WriteOk);
+    }
+
+    fn read(&mut self, node_id: &str, key: usize, value: Value) {
+        let request = KvPayload::Read { key: json!(key) };
+        self.expect(node_id, request, KvPayload::ReadOk { value });
+    }
+
+    /// Siblings a replica holds for `key`, straight from the replica
+    fn versions(&mut self, node_id: &str, key: usize) -> anyhow::Result<Vec<Version>> {
+        let request = ReplicaMessage::ReplicaRead { key: json!(key) };
+        return match self.client.call(node_id, &request, TIMEOUT)? {
+            | ReplicaMessage::ReplicaReadOk { versions } => Ok(versions),
+            | reply => bail!("unexpected reply to a replica read: {:?}", reply),
+        };
+    }
+
+    /// Every replica of `key` holds exactly `expected`, in any order
+    fn expect_replicas(
+        &mut self,
+        phase: &str,
+        replicas: &[&str],
+        key: usize,
+        expected: &[Value],
+    ) -> anyhow::Result<()> {
+        for replica in replicas {
+            let mut values: Vec<Value> = self
+                .versions(replica, key)?
+                .into_iter()
+                .map(|version| version.value)
+                .collect();
+            values.sort_by_key(|value| value.to_string());
+            let mut expected = expected.to_vec();
+            expected.sort_by_key(|value| value.to_string());
+            if values != expected {
+                self.violations.push(format!(
+                    "{}: replica {} holds {:?} of key {}, expected {:?}",
+                    phase, replica, values, key, expected
+                ));
+            }
+        }
+        return Ok(());
+    }
+
+    /// No node still holds hints for a replica
+    fn expect_no_hints(&mut self, ids: &[&str]) -> anyhow::Result<()> {
+        for node_id in ids {
+            let status: Value = self
+                .client
+                .call(node_id, &json!({"type": "status"}), TIMEOUT)?;
+            let hints = status["node"]["hints"]
+                .as_object()
+                .map_or(0, |hints| hints.len());
+            if hints > 0 {
+                self.violations.push(format!(
+                    "{} still holds hints for {} replicas",
+                    node_id, hints
+                ));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// A cluster of `NODES` Dynamo nodes, with the ring they place keys on
+fn start(ids: &[&str]) -> anyhow::Result<(Simulation, HashRing)> {
+    let mut sim = Simulation::new(
+        ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    let config = DynamoConfig::default();
+    for node_id in ids {
+        sim.start::<DynamoNode, _, _, _>(node_id, config.clone())?;
+    }
+    let ring = HashRing::new(ids, &config.ring)?;
+    return Ok((sim, ring));
+}
+
+fn assert_no_violations(phase: &str, violations: Vec<String>) {
+    assert!(
+        violations.is_empty(),
+        "the quorum KV misbehaved {}:\n{}",
+        phase,
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn reads_see_the_last_write() -> anyhow::Result<()> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let (sim, _) = start(&ids)?;
+    let mut checker = Checker {
+        client: sim.client("c1"),
+        violations: Vec::new(),
+    };
+    let mut rng = StdRng::seed_from_u64(SEED);
+    for key in 0..KEYS {
+        checker.write(ids[rng.gen_range(0..ids.len())], key, json!(1));
+        checker.read(ids[rng.gen_range(0..ids.len())], key, json!(1));
+    }
+    sim.shutdown()?;
+    assert_no_violations("while healthy", checker.violations);
+    return Ok(());
+}
+
+#[test]
+fn isolated_replica_gets_hints_handed_off() -> anyhow::Result<()> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let (sim, ring) = start(&ids)?;
+    let mut checker = Checker {
+        client: sim.client("c1"),
+        violations: Vec::new(),
+    };
+    let mut rng = StdRng::seed_from_u64(SEED);
+
+    let isolated = ids[1];
+    let others: Vec<&str> = ids
+        .iter()
+        .copied()
+        .filter(|node_id| *node_id != isolated)
+        .collect();
+    sim.isolate(isolated);
+    for key in 0..KEYS {
+        checker.write(others[rng.gen_range(0..others.len())], key, json!(2));
+        checker.read(others[rng.gen_range(0..others.len())], key, json!(2));
+    }
+    sim.heal();
+    std::thread::sleep(SETTLE);
+    let mut handed_off = 0;
+    for key in 0..KEYS {
+        if ring.replicas(json!(key).to_string()).contains(&isolated) {
+            handed_off += 1;
+            checker.expect_replicas("handoff", &[isolated], key, &[json!(2)])?;
+        }
+    }
+    checker.expect_no_hints(&ids)?;
+    sim.shutdown()?;
+
+    assert!(handed_off > 0, "{} replicates none of the keys", isolated);
+    assert_no_violations(&format!("with {} isolated", isolated), checker.violations);
+    return Ok(());
+}
+
+#[test]
+fn split_writes_become_siblings() -> anyhow::Result<()> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let (sim, ring) = start(&ids)?;
+    let mut checker = Checker {
+        client: sim.client("c1"),
+        violations: Vec::new(),
+    };
+    let key = 0;
+    let replicas = ring.replicas(json!(key).to_string());
+
+    let (left, right) = ids.split_at(2);
+    sim.partition(&[left, right]);
+    checker.write(left[0], key, json!("left"));
+    checker.write(right[0], key, json!("right"));
+    sim.heal();
+    std::thread::sleep(SETTLE);
+    // NOTE: a side may have had no stand-in for some replica, only the replicas together are
+    // sure to hold both versions
+    let mut siblings: Vec<String> = Vec::new();
+    for replica in &replicas {
+        for version in checker.versions(replica, key)? {
+            siblings.push(version.value.to_string());
+        }
+    }
+    siblings.sort();
+    siblings.dedup();
+    assert_eq!(
+        siblings,
+        [r#""left""#, r#""right""#],
+        "siblings after healing"
+    );
+
+    let request = KvPayload::Read { key: json!(key) };
+    checker
+        .client
+        .call::<_, KvPayload>(ids[0], &request, TIMEOUT)?;
+    checker.write(ids[0], key, json!("resolved"));
+    checker.read(ids[ids.len() - 1], key, json!("resolved"));
+    checker.expect_replicas("resolved", &replicas, key, &[json!("resolved")])?;
+    checker.expect_no_hints(&ids)?;
+
+    let mut conflicts = 0;
+    for node_id in &ids {
+        let status: Value = checker
+            .client
+            .call(node_id, &json!({"type": "status"}), TIMEOUT)?;
+        conflicts += status["node"]["conflicts"].as_u64().unwrap_or(0);
+    }
+    sim.shutdown()?;
+
+    assert!(conflicts > 0, "no coordinator noticed the siblings");
+    assert_no_violations(
+        &format!("split {:?} | {:?}", left, right),
+        checker.violations,
+    );
+    return Ok(());
+}
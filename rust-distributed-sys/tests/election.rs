@@ -0,0 +1,287 @@
+// Purpose: run leader election in the simulator through a partition and check who leads.
+//
+// usage: cargo test --test election
+//
+// The cluster elects a leader, then the leader and one other node are cut off from the rest and
+// later the partition heals. Every node is asked for its view through `status` all along:
+//
+// - before the partition and after it healed every node follows the same leader, for bully the
+//   highest ranked node
+// - during the partition bully elects the highest ranked node of each side, lease leaves the
+//   minority without a leader and elects a new one with a higher term in the majority
+// - lease never has two nodes claiming the same term, nor two claiming leadership at once
+// - the last leader change `election::Elected` passed to each node matches its view
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rust_distributed_sys_challenge::election::{
+    Elected, ElectedConfig, ElectionKind, Follower, LeaderChange,
+};
+use rust_distributed_sys_challenge::queue::EventSender;
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::{Event, InitNodes, Node};
+use serde_json::{json, Value};
+
+const NODES: usize = 5;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// time given to the cluster to settle after every change
+const SETTLE: Duration = Duration::from_secs(4);
+
+/// A node that does nothing but count the leader changes `Elected` tells it of
+#[derive(Default)]
+struct Watcher {
+    changes: usize,
+    last_change: Option<LeaderChange>,
+}
+
+impl Node<(), Value, ()> for Watcher {
+    fn from_init(_: (), _: InitNodes, _: EventSender<Value, ()>) -> anyhow::Result<Self> {
+        return Ok(Watcher::default());
+    }
+
+    fn step(&mut self, _: Event<Value, ()>, _: &mut dyn Write) -> anyhow::Result<()> {
+        return Ok(());
+    }
+
+    fn status(&self) -> Value {
+        return json!({
+            "changes": self.changes,
+            "last_change": self.last_change,
+        });
+    }
+}
+
+impl Follower<(), Value, ()> for Watcher {
+    fn leader_changed(&mut self, change: LeaderChange, _: &mut dyn Write) -> anyhow::Result<()> {
+        self.changes += 1;
+        self.last_change = Some(change);
+        return Ok(());
+    }
+}
+
+/// What a node believes, from its `status`
+#[derive(Debug, Clone, PartialEq)]
+struct View {
+    term: u64,
+    leader: Option<String>,
+}
+
+/// Ask every node for its view and check what holds at any time
+struct Observer<'a> {
+    client: Client,
+    ids: &'a [&'a str],
+    algorithm: ElectionKind,
+    /// lease: who claimed each term
+    claims: HashMap<u64, String>,
+    violations: Vec<String>,
+}
+
+impl Observer<'_> {
+    fn views(&mut self) -> anyhow::Result<HashMap<String, View>> {
+        let mut views = HashMap::new();
+        for node_id in self.ids {
+            let status: Value = self
+                .client
+                .call(node_id, &json!({"type": "status"}), TIMEOUT)?;
+            let node = &status["node"];
+            let view = View {
+                term: node["term"].as_u64().context("status without term")?,
+                leader: node["leader"].as_str().map(str::to_string),
+            };
+            let last_change = &node["node"]["last_change"];
+            let notified = View {
+                term: last_change["term"].as_u64().unwrap_or(0),
+                leader: last_change["leader"].as_str().map(str::to_string),
+            };
+            // NOTE: a lease can run out between the last tick and the status request
+            if self.algorithm == ElectionKind::Bully && notified != view {
+                self.violations.push(format!(
+                    "{} was last notified of {:?} but believes {:?}",
+                    node_id, notified, view
+                ));
+            }
+            views.insert(node_id.to_string(), view);
+        }
+        if self.algorithm == ElectionKind::Lease {
+            self.check_leases(&views);
+        }
+        return Ok(views);
+    }
+
+    fn check_leases(&mut self, views: &HashMap<String, View>) {
+        let mut leaders: Vec<&String> = views
+            .iter()
+            .filter(|(node_id, view)| view.leader.as_ref() == Some(*node_id))
+            .map(|(node_id, _)| node_id)
+            .collect();
+        leaders.sort();
+        if leaders.len() > 1 {
+            self.violations
+                .push(format!("{:?} all hold a lease at once", leaders));
+        }
+        for leader in leaders {
+            let term = views[leader].term;
+            let holder = self.claims.entry(term).or_insert_with(|| leader.clone());
+            if holder != leader {
+                self.violations
+                    .push(format!("{} and {} both led term {}", holder, leader, term));
+            }
+        }
+    }
+
+    /// Keep observing for `duration`
+    fn watch(&mut self, duration: Duration) -> anyhow::Result<HashMap<String, View>> {
+        let deadline = Instant::now() + duration;
+        let mut views = self.views()?;
+        while Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(50));
+            views = self.views()?;
+        }
+        return Ok(views);
+    }
+
+    /// Every node in `group` follows `expected`, any single leader if `expected` is `None`
+    fn expect_leader(
+        &mut self,
+        phase: &str,
+        views: &HashMap<String, View>,
+        group: &[&str],
+        expected: Option<&str>,
+    ) -> Option<View> {
+        let first = views[group[0]].clone();
+        let agreed = group.iter().all(|node_id| views[*node_id] == first);
+        let right = match expected {
+            | Some(expected) => first.leader.as_deref() == Some(expected),
+            | None => first.leader.is_some(),
+        };
+        if !agreed || !right {
+            let seen: Vec<(&&str, &View)> = group
+                .iter()
+                .map(|node_id| (node_id, &views[*node_id]))
+                .collect();
+            self.violations.push(format!(
+                "{}: expected {:?} to follow {}, got {:?}",
+                phase,
+                group,
+                expected.unwrap_or("one leader"),
+                seen
+            ));
+            return None;
+        }
+        return Some(first);
+    }
+}
+
+/// Elect a leader, partition it away with one other node, then heal the partition
+///
+/// returns:
+///   - every violation the nodes' views showed along the way
+fn run(algorithm: ElectionKind) -> anyhow::Result<Vec<String>> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(20),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in &ids {
+        let config = ElectedConfig {
+            kind: algorithm,
+            ..ElectedConfig::default()
+        };
+        sim.start::<Elected<Watcher>, _, _, _>(node_id, config)?;
+    }
+    let highest = |group: &[&str]| -> String {
+        // NOTE: the ids are n0..nN, the last one of a group outranks the others
+        return group.last().unwrap().to_string();
+    };
+
+    let mut observer = Observer {
+        client: sim.client("c1"),
+        ids: &ids,
+        algorithm,
+        claims: HashMap::new(),
+        violations: Vec::new(),
+    };
+
+    let views = observer.watch(SETTLE)?;
+    let expected = (algorithm == ElectionKind::Bully).then(|| highest(&ids));
+    let Some(before) = observer.expect_leader("start", &views, &ids, expected.as_deref()) else {
+        // NOTE: nothing to partition away, the violation says why
+        sim.shutdown()?;
+        return Ok(observer.violations);
+    };
+    let leader = before.leader.clone().unwrap();
+
+    // NOTE: the leader and its lowest ranked peer end up in the minority
+    let other = *ids.iter().find(|node_id| **node_id != leader).unwrap();
+    let mut minority: Vec<&str> = vec![leader.as_str(), other];
+    minority.sort_by_key(|node_id| ids.iter().position(|id| id == node_id));
+    let majority: Vec<&str> = ids
+        .iter()
+        .copied()
+        .filter(|node_id| !minority.contains(node_id))
+        .collect();
+    sim.partition(&[&minority, &majority]);
+    let views = observer.watch(SETTLE)?;
+    match algorithm {
+        | ElectionKind::Bully => {
+            for group in [&minority, &majority] {
+                let expected = highest(group);
+                observer.expect_leader("partition", &views, group, Some(&expected));
+            }
+        },
+        | ElectionKind::Lease => {
+            for node_id in &minority {
+                if let Some(leader) = &views[*node_id].leader {
+                    observer.violations.push(format!(
+                        "partition: {} in the minority still follows {}",
+                        node_id, leader
+                    ));
+                }
+            }
+            if let Some(during) = observer.expect_leader("partition", &views, &majority, None) {
+                if during.term <= before.term {
+                    observer.violations.push(format!(
+                        "partition: term {} after term {}",
+                        during.term, before.term
+                    ));
+                }
+            }
+        },
+    }
+
+    sim.heal();
+    let views = observer.watch(SETTLE)?;
+    let expected = (algorithm == ElectionKind::Bully).then(|| highest(&ids));
+    observer.expect_leader("healed", &views, &ids, expected.as_deref());
+    sim.shutdown()?;
+    return Ok(observer.violations);
+}
+
+fn assert_elects(algorithm: ElectionKind) {
+    let violations = run(algorithm).expect("the simulation failed");
+    assert!(
+        violations.is_empty(),
+        "{:?} election misbehaved:\n{}",
+        algorithm,
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn bully() {
+    assert_elects(ElectionKind::Bully);
+}
+
+#[test]
+fn lease() {
+    assert_elects(ElectionKind::Lease);
+}
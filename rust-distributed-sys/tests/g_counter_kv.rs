@@ -0,0 +1,100 @@
+// Purpose: run the async g-counter-kv against the simulator's seq-kv and check the counter adds up.
+//
+// usage: cargo test --features async --test g_counter_kv
+//
+// Every add is a read and cas loop of `rpc`s to seq-kv that the handler awaits, so concurrent
+// clients exercise the whole async path: tasks suspended on replies, replies routed back to them
+// and cas conflicts retried.
+#![cfg(feature = "async")]
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::sim::{NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::workloads::g_counter::PayLoad;
+use rust_distributed_sys_challenge::workloads::g_counter_kv::KvCounter;
+use serde_json::{json, Value};
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const CLIENTS: usize = 4;
+const ADDS: usize = 20;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn start() -> anyhow::Result<Simulation> {
+    let mut sim = Simulation::new(
+        &NODES,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(5),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in NODES {
+        sim.start_async::<KvCounter, _, _>(node_id, ())?;
+    }
+    return Ok(sim);
+}
+
+#[test]
+fn concurrent_adds_add_up() -> anyhow::Result<()> {
+    let sim = start()?;
+    let mut threads = Vec::new();
+    for i in 1..=CLIENTS {
+        let mut client = sim.client(&format!("c{}", i));
+        let mut rng = StdRng::seed_from_u64(SEED + i as u64);
+        threads.push(std::thread::spawn(move || -> anyhow::Result<u64> {
+            let mut added = 0;
+            for _ in 0..ADDS {
+                let delta = rng.gen_range(1..10);
+                let node_id = NODES[rng.gen_range(0..NODES.len())];
+                let reply: PayLoad = client.call(node_id, &PayLoad::Add { delta }, TIMEOUT)?;
+                assert!(
+                    matches!(reply, PayLoad::AddOk),
+                    "{} answered {:?}",
+                    node_id,
+                    reply
+                );
+                added += delta;
+            }
+            return Ok(added);
+        }));
+    }
+    let mut total = 0;
+    for thread in threads {
+        total += thread.join().expect("client thread panicked")?;
+    }
+
+    let mut client = sim.client("c0");
+    for node_id in NODES {
+        let reply: PayLoad = client.call(node_id, &PayLoad::Read, TIMEOUT)?;
+        assert!(
+            matches!(reply, PayLoad::ReadOk { value } if value == total),
+            "{} answered {:?} to a read, {} was added",
+            node_id,
+            reply,
+            total
+        );
+    }
+    sim.shutdown()?;
+    return Ok(());
+}
+
+#[test]
+fn status_counts_queued_requests() -> anyhow::Result<()> {
+    let sim = start()?;
+    let mut client = sim.client("c1");
+    for delta in 1..=3 {
+        let reply: PayLoad = client.call("n0", &PayLoad::Add { delta }, TIMEOUT)?;
+        assert!(matches!(reply, PayLoad::AddOk));
+    }
+    let status: Value = client.call("n0", &json!({"type": "status"}), TIMEOUT)?;
+    sim.shutdown()?;
+
+    // NOTE: the adds came through the client lane, `init` (from `sim`) and seq-kv's replies through
+    // the peer lane
+    assert_eq!(status["queue"]["client"]["enqueued"], 3);
+    assert!(status["queue"]["peer"]["enqueued"].as_u64() >= Some(3 * 3));
+    assert_eq!(status["node"]["pending_rpcs"], 0);
+    return Ok(());
+}
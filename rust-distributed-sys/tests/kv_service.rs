@@ -0,0 +1,240 @@
+// Purpose: check the simulator's stand-ins for Maelstrom's key/value services keep their promises.
+//
+// usage: cargo test --test kv_service
+//
+// - lin-kv: clients read, write and cas a few keys at once, the history must be linearizable
+// - for each service a writer keeps raising one key while a reader reads it and writes and reads
+//   back a key of its own:
+//   - lin-kv: no read misses a write that was acknowledged before it was sent
+//   - seq-kv: some reads are stale, but a reader never goes back and sees its own writes; after a
+//     write of its own it sees the last value
+//   - lww-kv: some reads are stale, once writes stop every read sees the last value
+//
+// `tests/g_counter_kv.rs` checks a counter kept in seq-kv adds up.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::kv::KvPayload;
+use rust_distributed_sys_challenge::linearizability::{self, CasRegister, History};
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::ErrorCode;
+use serde_json::json;
+
+const CLIENTS: usize = 4;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(1);
+const KEYS: u64 = 3;
+const RUN: Duration = Duration::from_secs(1);
+/// longer than any write to `lww-kv` takes to reach every replica
+const CONVERGE: Duration = Duration::from_millis(300);
+
+fn network(seed: u64) -> NetworkConfig {
+    return NetworkConfig {
+        min_latency: Duration::ZERO,
+        max_latency: Duration::from_millis(5),
+        seed,
+        ..NetworkConfig::default()
+    };
+}
+
+/// Random reads, writes and cas on lin-kv for `RUN`, the history must be linearizable
+#[test]
+fn lin_kv_is_linearizable() -> anyhow::Result<()> {
+    let sim = Simulation::new(&[], network(SEED));
+    let mut threads = Vec::new();
+    for i in 1..=CLIENTS {
+        let mut client = sim.client(&format!("c{}", i));
+        let mut rng = StdRng::seed_from_u64(SEED + i as u64);
+        threads.push(std::thread::spawn(move || {
+            let deadline = Instant::now() + RUN;
+            while Instant::now() < deadline {
+                let key = json!(rng.gen_range(0..KEYS));
+                let request = match rng.gen_range(0..3) {
+                    | 0 => KvPayload::Read { key },
+                    | 1 => KvPayload::Write {
+                        key,
+                        value: json!(rng.gen_range(0..5)),
+                    },
+                    | _ => KvPayload::Cas {
+                        key,
+                        from: json!(rng.gen_range(0..5)),
+                        to: json!(rng.gen_range(0..5)),
+                        create_if_not_exists: false,
+                    },
+                };
+                let _ = client.call::<_, KvPayload>("lin-kv", &request, TIMEOUT);
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().expect("client thread panicked");
+    }
+
+    let history = History::from_trace(&sim.trace());
+    sim.shutdown()?;
+    assert!(!history.operations().is_empty(), "no operation completed");
+    if let Err(counterexample) = linearizability::check(&CasRegister, &history) {
+        panic!("lin-kv: {}", counterexample);
+    }
+    return Ok(());
+}
+
+/// What the reader saw while the writer kept raising `x`
+#[derive(Default)]
+struct Observed {
+    reads: usize,
+    /// reads older than a write acknowledged before they were sent
+    stale: usize,
+    /// reads older than an earlier read
+    backwards: usize,
+    /// reads of its own key that missed its last write
+    own_missed: usize,
+}
+
+fn read(client: &mut Client, service: &str, key: &str) -> anyhow::Result<u64> {
+    let request = KvPayload::Read { key: json!(key) };
+    return match client.call(service, &request, TIMEOUT)? {
+        | KvPayload::ReadOk { value } => value.as_u64().context("not a number"),
+        // NOTE: a stale read may come from before the first write
+        | KvPayload::Error {
+            code: ErrorCode::KeyDoesNotExist,
+            ..
+        } => Ok(0),
+        | reply => bail!("{} answered {:?} to a read of {}", service, reply, key),
+    };
+}
+
+fn write(client: &mut Client, service: &str, key: &str, value: u64) -> anyhow::Result<()> {
+    let request = KvPayload::Write {
+        key: json!(key),
+        value: json!(value),
+    };
+    return match client.call(service, &request, TIMEOUT)? {
+        | KvPayload::WriteOk => Ok(()),
+        | reply => bail!("{} answered {:?} to a write of {}", service, reply, key),
+    };
+}
+
+/// One writer raises `x` for `RUN` while a reader watches it
+///
+/// returns:
+///   - what the reader saw, the last value written, and the simulation with the reader still in it
+fn race(service: &str, seed: u64) -> anyhow::Result<(Observed, u64, Simulation, Client)> {
+    let sim = Simulation::new(&[], network(seed));
+    let mut writer = sim.client("writer");
+    let mut reader = sim.client("reader");
+    let acknowledged = Arc::new(AtomicU64::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let (acknowledged, stop) = (acknowledged.clone(), stop.clone());
+        let service = service.to_string();
+        std::thread::spawn(move || -> anyhow::Result<u64> {
+            let mut value = 0;
+            while !stop.load(Ordering::Relaxed) {
+                value += 1;
+                write(&mut writer, &service, "x", value)?;
+                acknowledged.store(value, Ordering::Relaxed);
+            }
+            return Ok(value);
+        })
+    };
+
+    let mut observed = Observed::default();
+    let mut latest = 0;
+    let mut own = 0;
+    let deadline = Instant::now() + RUN;
+    while Instant::now() < deadline {
+        let before = acknowledged.load(Ordering::Relaxed);
+        let value = read(&mut reader, service, "x")?;
+        observed.reads += 1;
+        if value < before {
+            observed.stale += 1;
+        }
+        if value < latest {
+            observed.backwards += 1;
+        }
+        latest = latest.max(value);
+
+        own += 1;
+        write(&mut reader, service, "own", own)?;
+        if read(&mut reader, service, "own")? != own {
+            observed.own_missed += 1;
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    let last = thread.join().expect("writer thread panicked")?;
+    return Ok((observed, last, sim, reader));
+}
+
+/// Race on `service`, then stop writing and read `x` until it must have settled
+///
+/// returns:
+///   - what the reader saw while racing, and a violation for every read after writes stopped that
+///     missed the last write
+fn race_and_settle(service: &str) -> anyhow::Result<(Observed, Vec<String>)> {
+    let (observed, last, sim, mut reader) = race(service, SEED)?;
+    // NOTE: on seq-kv only a write of the reader's own orders its reads after the last write
+    if service == "seq-kv" {
+        write(&mut reader, service, "barrier", 0)?;
+    }
+    std::thread::sleep(CONVERGE);
+    let mut violations = Vec::new();
+    for _ in 0..20 {
+        let value = read(&mut reader, service, "x")?;
+        if value != last {
+            violations.push(format!(
+                "read {} after writes stopped, the last write was {}",
+                value, last
+            ));
+            break;
+        }
+    }
+    sim.shutdown()?;
+    return Ok((observed, violations));
+}
+
+#[test]
+fn lin_kv_reads_are_fresh() -> anyhow::Result<()> {
+    let (observed, violations) = race_and_settle("lin-kv")?;
+    assert!(observed.reads > 0, "no read completed");
+    assert_eq!(observed.stale, 0, "stale reads");
+    assert_eq!(observed.backwards, 0, "reads went back in time");
+    assert_eq!(
+        observed.own_missed, 0,
+        "reads missed the reader's own writes"
+    );
+    assert!(violations.is_empty(), "{}", violations.join("\n"));
+    return Ok(());
+}
+
+#[test]
+fn seq_kv_reads_are_stale_but_monotonic() -> anyhow::Result<()> {
+    let (observed, violations) = race_and_settle("seq-kv")?;
+    assert!(
+        observed.stale > 0,
+        "no stale reads in {} reads",
+        observed.reads
+    );
+    assert_eq!(observed.backwards, 0, "reads went back in time");
+    assert_eq!(
+        observed.own_missed, 0,
+        "reads missed the reader's own writes"
+    );
+    assert!(violations.is_empty(), "{}", violations.join("\n"));
+    return Ok(());
+}
+
+#[test]
+fn lww_kv_reads_are_stale_until_writes_stop() -> anyhow::Result<()> {
+    let (observed, violations) = race_and_settle("lww-kv")?;
+    assert!(
+        observed.stale > 0,
+        "no stale reads in {} reads",
+        observed.reads
+    );
+    assert!(violations.is_empty(), "{}", violations.join("\n"));
+    return Ok(());
+}
@@ -0,0 +1,217 @@
+// Purpose: partition and crash the Raft replicated KV in the simulator and check it stays
+// linearizable.
+//
+// usage: cargo test --test lin_kv
+//
+// Clients read, write and cas a few keys through every node while the cluster is split, its
+// leader is cut off or crashes and restarts from disk. Requests to the minority may time out, but
+// once the cluster is whole again every node must serve requests and the whole history, timeouts
+// included, must be linearizable.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::kv::KvPayload;
+use rust_distributed_sys_challenge::linearizability::{self, CasRegister, History};
+use rust_distributed_sys_challenge::persist::PersistConfig;
+use rust_distributed_sys_challenge::raft::{RaftConfig, RaftNode};
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::workloads::lin_kv::LinKv;
+use serde_json::{json, Value};
+
+const NODES: usize = 5;
+const CLIENTS: usize = 4;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const KEYS: u64 = 3;
+/// time a fault lasts, several election timeouts (300ms to 600ms)
+const FAULT: Duration = Duration::from_secs(2);
+/// time given to elect a leader once the cluster is whole again
+const SETTLE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    /// n0 and n1 lose the other three nodes
+    Split,
+    /// the leader loses every other node
+    IsolateLeader,
+    /// the leader crashes and restarts from its data directory
+    RestartLeader,
+}
+
+/// Random reads, writes and cas through `node_ids` until `stop` is set
+fn run_client(mut client: Client, node_ids: Vec<String>, seed: u64, stop: Arc<AtomicBool>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    while !stop.load(Ordering::Relaxed) {
+        let node_id = &node_ids[rng.gen_range(0..node_ids.len())];
+        let key = json!(rng.gen_range(0..KEYS));
+        let request = match rng.gen_range(0..3) {
+            | 0 => KvPayload::Read { key },
+            | 1 => KvPayload::Write {
+                key,
+                value: json!(rng.gen_range(0..5)),
+            },
+            | _ => KvPayload::Cas {
+                key,
+                from: json!(rng.gen_range(0..5)),
+                to: json!(rng.gen_range(0..5)),
+                create_if_not_exists: false,
+            },
+        };
+        // NOTE: timeouts are part of the history, the checker treats them as unknown outcomes
+        let _ = client.call::<_, KvPayload>(node_id, &request, TIMEOUT);
+    }
+}
+
+/// Ask every node who it follows until one names a leader
+fn leader(client: &mut Client, node_ids: &[&str]) -> anyhow::Result<String> {
+    let deadline = Instant::now() + SETTLE;
+    while Instant::now() < deadline {
+        for node_id in node_ids {
+            let status: Value = client.call(node_id, &json!({"type": "status"}), TIMEOUT)?;
+            if let Some(leader) = status["node"]["leader"].as_str() {
+                return Ok(leader.to_string());
+            }
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    anyhow::bail!("no leader within {:?}", SETTLE);
+}
+
+/// Write through every node until it succeeds, then read the value back through every node
+///
+/// returns:
+///   - a violation for every node that didn't serve its request in time
+fn check_available(client: &mut Client, node_ids: &[&str]) -> Vec<String> {
+    let mut violations = Vec::new();
+    for (i, node_id) in node_ids.iter().enumerate() {
+        let request = KvPayload::Write {
+            key: json!("after"),
+            value: json!(i),
+        };
+        let deadline = Instant::now() + SETTLE;
+        loop {
+            match client.call::<_, KvPayload>(node_id, &request, TIMEOUT) {
+                | Ok(KvPayload::WriteOk) => break,
+                // NOTE: an election may still be under way
+                | _ if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+                | reply => {
+                    violations.push(format!(
+                        "{} couldn't write after healing: {:?}",
+                        node_id, reply
+                    ));
+                    break;
+                },
+            }
+        }
+    }
+    let expected = KvPayload::ReadOk {
+        value: json!(node_ids.len() - 1),
+    };
+    for node_id in node_ids {
+        let request = KvPayload::Read {
+            key: json!("after"),
+        };
+        match client.call::<_, KvPayload>(node_id, &request, TIMEOUT) {
+            | Ok(reply) if reply == expected => {},
+            | reply => violations.push(format!(
+                "{} answered the last read with {:?}, expected {:?}",
+                node_id, reply, expected
+            )),
+        }
+    }
+    return violations;
+}
+
+/// Inject `fault` under concurrent clients, then heal the cluster
+///
+/// returns:
+///   - every violation the healed cluster or the history showed
+fn run(fault: Fault) -> anyhow::Result<Vec<String>> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let data_dir = std::env::temp_dir().join(format!("lin_kv-{:?}-{}", fault, std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let config = RaftConfig {
+        persist: Some(PersistConfig::new(&data_dir)),
+        ..RaftConfig::default()
+    };
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in &ids {
+        sim.start::<RaftNode<LinKv>, _, _, _>(node_id, config.clone())?;
+    }
+    let mut client = sim.client("c0");
+    let leader = leader(&mut client, &ids)?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut threads = Vec::new();
+    for i in 1..=CLIENTS {
+        let client = sim.client(&format!("c{}", i));
+        let seed = SEED + i as u64;
+        let stop = stop.clone();
+        let node_ids = node_ids.clone();
+        threads.push(std::thread::spawn(move || {
+            run_client(client, node_ids, seed, stop);
+        }));
+    }
+    std::thread::sleep(FAULT / 2);
+    match fault {
+        | Fault::Split => sim.partition(&[&ids[..2], &ids[2..]]),
+        | Fault::IsolateLeader => sim.isolate(&leader),
+        | Fault::RestartLeader => sim.crash(&leader)?,
+    }
+    std::thread::sleep(FAULT);
+    match fault {
+        | Fault::Split | Fault::IsolateLeader => sim.heal(),
+        | Fault::RestartLeader => sim.restart::<RaftNode<LinKv>, _, _, _>(&leader, config)?,
+    }
+    std::thread::sleep(FAULT / 2);
+    stop.store(true, Ordering::Relaxed);
+    for thread in threads {
+        thread.join().expect("client thread panicked");
+    }
+
+    let mut violations = check_available(&mut client, &ids);
+    let trace = sim.trace();
+    sim.shutdown()?;
+    std::fs::remove_dir_all(&data_dir)?;
+    if let Err(counterexample) = linearizability::check(&CasRegister, &History::from_trace(&trace))
+    {
+        violations.push(counterexample.to_string());
+    }
+    return Ok(violations);
+}
+
+fn assert_linearizable(fault: Fault) {
+    let violations = run(fault).expect("the simulation failed");
+    assert!(
+        violations.is_empty(),
+        "lin-kv misbehaved under {:?}:\n{}",
+        fault,
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn split() {
+    assert_linearizable(Fault::Split);
+}
+
+#[test]
+fn isolated_leader() {
+    assert_linearizable(Fault::IsolateLeader);
+}
+
+#[test]
+fn restarted_leader() {
+    assert_linearizable(Fault::RestartLeader);
+}
@@ -0,0 +1,254 @@
+// Purpose: run the lock service in the simulator under partitions and check mutual exclusion.
+//
+// usage: cargo test --test lock_service
+//
+// Clients compete for one lock through random nodes while the cluster is split into a majority
+// and a minority over and over. A client holds the lock from the `acquire_ok` until it releases
+// it or its lease runs out, counted from when it sent the last `acquire` or `renew` that
+// succeeded. While holding it writes its fencing token to a shared resource that rejects tokens
+// lower than one it saw already.
+//
+// - no two clients ever hold the lock at the same time
+// - tokens grow from one holder to the next, so the resource never rejects a client that still
+//   holds the lock
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rust_distributed_sys_challenge::raft::{RaftConfig, RaftNode};
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::workloads::lock::{LockService, Payload};
+
+const NODES: usize = 5;
+const CLIENTS: usize = 4;
+const SEED: u64 = 1;
+/// how long clients compete while the cluster is split and healed
+const DURATION: Duration = Duration::from_secs(6);
+const TIMEOUT: Duration = Duration::from_secs(1);
+const LOCK: &str = "lock";
+const TTL: Duration = Duration::from_millis(300);
+const RENEW_INTERVAL: Duration = Duration::from_millis(100);
+/// pause after an acquire that failed, a node without a leader answers right away
+const BACKOFF: Duration = Duration::from_millis(20);
+/// how long the cluster stays split, and then healed, at a time
+const PARTITION_INTERVAL: Duration = Duration::from_millis(700);
+
+/// Storage guarded by fencing tokens
+#[derive(Default)]
+struct Resource {
+    highest: u64,
+    /// writes it turned away, `(client, token, highest)`
+    rejected: Vec<(String, u64, u64)>,
+}
+
+impl Resource {
+    fn write(&mut self, client: &str, token: u64) {
+        if token < self.highest {
+            self.rejected
+                .push((client.to_string(), token, self.highest));
+            return;
+        }
+        self.highest = token;
+    }
+}
+
+/// A time a client held the lock
+struct Holding {
+    client: String,
+    token: u64,
+    start: Instant,
+    end: Instant,
+}
+
+struct Competitor {
+    client: Client,
+    node_ids: Vec<String>,
+    rng: StdRng,
+    resource: Arc<Mutex<Resource>>,
+    holdings: Vec<Holding>,
+}
+
+impl Competitor {
+    /// `None` when the lock was taken, the node had no leader or didn't answer
+    fn call(&mut self, request: &Payload) -> Option<Payload> {
+        let node_id = &self.node_ids[self.rng.gen_range(0..self.node_ids.len())];
+        return match self.client.call(node_id, request, TIMEOUT) {
+            | Ok(Payload::Error { .. }) | Err(_) => None,
+            | Ok(reply) => Some(reply),
+        };
+    }
+
+    /// Acquire the lock, hold it for a while renewing the lease, and release it
+    fn compete(&mut self) {
+        let owner = self.client.client_id().to_string();
+        let sent = Instant::now();
+        let acquire = Payload::Acquire {
+            lock: LOCK.to_string(),
+            owner: owner.clone(),
+            ttl: TTL.as_millis() as u64,
+            at: None,
+        };
+        let Some(Payload::AcquireOk { token }) = self.call(&acquire) else {
+            std::thread::sleep(BACKOFF);
+            return;
+        };
+        let start = Instant::now();
+        let mut valid_until = sent + TTL;
+        if start >= valid_until {
+            // NOTE: the reply took longer than the lease, it may be someone else's already
+            return;
+        }
+        self.resource.lock().unwrap().write(&owner, token);
+
+        let hold_until = start + Duration::from_millis(self.rng.gen_range(50..600));
+        let mut end = loop {
+            let now = Instant::now();
+            if now >= hold_until {
+                break now;
+            }
+            std::thread::sleep(RENEW_INTERVAL.min(hold_until - now));
+            if Instant::now() >= hold_until {
+                break Instant::now();
+            }
+            let sent = Instant::now();
+            let renew = Payload::Renew {
+                lock: LOCK.to_string(),
+                owner: owner.clone(),
+                token,
+                ttl: TTL.as_millis() as u64,
+                at: None,
+            };
+            if self.call(&renew) != Some(Payload::RenewOk) || Instant::now() >= valid_until {
+                break Instant::now();
+            }
+            valid_until = sent + TTL;
+            self.resource.lock().unwrap().write(&owner, token);
+        };
+        end = end.min(valid_until);
+        self.holdings.push(Holding {
+            client: owner.clone(),
+            token,
+            start,
+            end,
+        });
+        let release = Payload::Release {
+            lock: LOCK.to_string(),
+            owner,
+            token,
+        };
+        self.call(&release);
+    }
+}
+
+/// What clients competing for the lock saw
+struct Outcome {
+    /// by start
+    holdings: Vec<Holding>,
+    /// writes the resource turned away, `(client, token, highest)`
+    rejected: Vec<(String, u64, u64)>,
+}
+
+/// Let `CLIENTS` clients compete for the lock for `DURATION` while the cluster is partitioned
+fn run() -> anyhow::Result<Outcome> {
+    let node_ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in &ids {
+        sim.start::<RaftNode<LockService>, _, _, _>(node_id, RaftConfig::default())?;
+    }
+
+    let resource = Arc::new(Mutex::new(Resource::default()));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut threads = Vec::new();
+    for i in 1..=CLIENTS {
+        let mut competitor = Competitor {
+            client: sim.client(&format!("c{}", i)),
+            node_ids: node_ids.clone(),
+            rng: StdRng::seed_from_u64(SEED + i as u64),
+            resource: resource.clone(),
+            holdings: Vec::new(),
+        };
+        let stop = stop.clone();
+        threads.push(std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                competitor.compete();
+            }
+            return competitor.holdings;
+        }));
+    }
+
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let deadline = Instant::now() + DURATION;
+    while Instant::now() < deadline {
+        let mut shuffled = ids.clone();
+        shuffled.shuffle(&mut rng);
+        let (minority, majority) = shuffled.split_at(rng.gen_range(1..=(ids.len() - 1) / 2));
+        sim.partition(&[minority, majority]);
+        std::thread::sleep(PARTITION_INTERVAL);
+        sim.heal();
+        std::thread::sleep(PARTITION_INTERVAL);
+    }
+    stop.store(true, Ordering::Relaxed);
+
+    let mut holdings = Vec::new();
+    for thread in threads {
+        holdings.extend(thread.join().expect("client thread panicked"));
+    }
+    sim.shutdown()?;
+    holdings.sort_by_key(|holding| holding.start);
+    let rejected = std::mem::take(&mut resource.lock().unwrap().rejected);
+    return Ok(Outcome { holdings, rejected });
+}
+
+#[test]
+fn leases_never_overlap() {
+    let outcome = run().expect("the simulation failed");
+    assert!(!outcome.holdings.is_empty(), "no client ever got the lock");
+    let mut overlaps = Vec::new();
+    for (i, earlier) in outcome.holdings.iter().enumerate() {
+        for later in &outcome.holdings[i + 1..] {
+            if later.start < earlier.end && later.client != earlier.client {
+                overlaps.push(format!(
+                    "{} (token {}) and {} (token {}) held the lock at the same time for {:?}",
+                    earlier.client,
+                    earlier.token,
+                    later.client,
+                    later.token,
+                    earlier.end.min(later.end) - later.start
+                ));
+            }
+        }
+    }
+    assert!(overlaps.is_empty(), "{}", overlaps.join("\n"));
+}
+
+#[test]
+fn tokens_grow() {
+    let outcome = run().expect("the simulation failed");
+    assert!(!outcome.holdings.is_empty(), "no client ever got the lock");
+    let mut violations = Vec::new();
+    for pair in outcome.holdings.windows(2) {
+        if pair[0].client != pair[1].client && pair[1].token <= pair[0].token {
+            violations.push(format!(
+                "{} got token {} after {} had token {}",
+                pair[1].client, pair[1].token, pair[0].client, pair[0].token
+            ));
+        }
+    }
+    for (client, token, highest) in &outcome.rejected {
+        violations.push(format!(
+            "the resource turned away {} with token {} while it held the lock, it saw {} already",
+            client, token, highest
+        ));
+    }
+    assert!(violations.is_empty(), "{}", violations.join("\n"));
+}
@@ -0,0 +1,139 @@
+// Purpose: run a KV partitioned over a hash ring in the simulator and check requests reach owners.
+//
+// usage: cargo test --test partition
+//
+// Every key is written, compare-and-set and read through random nodes, so most requests land on
+// a node that doesn't own the key and have to be forwarded. Every read must return the last value
+// written, and each node must have served exactly the requests for the keys it owns.
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::kv::{KvPayload, KvStore};
+use rust_distributed_sys_challenge::partition::{
+    HashRing, PartitionConfig, PartitionedNode, RingConfig,
+};
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use serde_json::{json, Value};
+
+const KEYS: usize = 200;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+fn expect(
+    client: &mut Client,
+    node_id: &str,
+    request: KvPayload,
+    expected: KvPayload,
+) -> Option<String> {
+    return match client.call::<_, KvPayload>(node_id, &request, TIMEOUT) {
+        | Ok(reply) if reply == expected => None,
+        | Ok(reply) => Some(format!(
+            "{} answered {:?} with {:?}, expected {:?}",
+            node_id, request, reply, expected
+        )),
+        | Err(error) => Some(format!(
+            "{} didn't answer {:?}: {:#}",
+            node_id, request, error
+        )),
+    };
+}
+
+/// Write, cas and read every key through random nodes out of `nodes`
+///
+/// returns:
+///   - a violation for every wrong answer and every node that didn't serve exactly three requests
+///     per key it owns
+fn run(nodes: usize) -> anyhow::Result<Vec<String>> {
+    let node_ids: Vec<String> = (0..nodes).map(|i| format!("n{}", i)).collect();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let mut sim = Simulation::new(
+        &ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in &ids {
+        sim.start::<PartitionedNode<KvStore>, _, _, _>(node_id, PartitionConfig::default())?;
+    }
+    let ring = HashRing::new(&ids, &RingConfig::default())?;
+
+    let mut client = sim.client("c1");
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let mut pick = || ids[rng.gen_range(0..ids.len())];
+    let mut violations: Vec<String> = Vec::new();
+    for key in 0..KEYS {
+        let key = json!(key);
+        let steps = [
+            (
+                KvPayload::Write {
+                    key: key.clone(),
+                    value: json!(1),
+                },
+                KvPayload::WriteOk,
+            ),
+            (
+                KvPayload::Cas {
+                    key: key.clone(),
+                    from: json!(1),
+                    to: json!(2),
+                    create_if_not_exists: false,
+                },
+                KvPayload::CasOk,
+            ),
+            (
+                KvPayload::Read { key: key.clone() },
+                KvPayload::ReadOk { value: json!(2) },
+            ),
+        ];
+        for (request, expected) in steps {
+            violations.extend(expect(&mut client, pick(), request, expected));
+        }
+    }
+
+    let mut served = Vec::new();
+    for node_id in &ids {
+        let status: Value = client.call(node_id, &json!({"type": "status"}), TIMEOUT)?;
+        served.push(status["node"]["served"].as_u64().unwrap_or(0));
+    }
+    sim.shutdown()?;
+
+    let mut owned = vec![0usize; ids.len()];
+    for key in 0..KEYS {
+        let owner = ring.owner(json!(key).to_string());
+        owned[ids.iter().position(|id| *id == owner).unwrap()] += 1;
+    }
+    for (index, node_id) in ids.iter().enumerate() {
+        // NOTE: three requests per key, each must end up at the owner
+        if served[index] != 3 * owned[index] as u64 {
+            violations.push(format!(
+                "{} served {} requests for its {} keys",
+                node_id, served[index], owned[index]
+            ));
+        }
+    }
+
+    return Ok(violations);
+}
+
+fn assert_reach_owners(nodes: usize) {
+    let violations = run(nodes).expect("the simulation failed");
+    assert!(
+        violations.is_empty(),
+        "requests to {} nodes went astray:\n{}",
+        nodes,
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn requests_reach_owners() {
+    assert_reach_owners(5);
+}
+
+#[test]
+fn single_node_owns_every_key() {
+    assert_reach_owners(1);
+}
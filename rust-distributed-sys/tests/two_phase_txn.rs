@@ -0,0 +1,220 @@
+// Purpose: run 2PC transactions in the simulator and check they are atomic and survive crashes.
+//
+// usage: cargo test --test two_phase_txn
+//
+// - atomicity: writers write one value to every key of a group spread over several owners while
+//   a reader reads the whole group, every committed read must see a single value
+// - recovery: with 300ms between nodes, a participant is cut off right after it voted, so it
+//   misses the commit, and both it and the coordinator crash; once they restarted from their logs
+//   and the partition healed, the participant must still commit
+use std::time::Duration;
+
+use anyhow::bail;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_distributed_sys_challenge::partition::HashRing;
+use rust_distributed_sys_challenge::persist::PersistConfig;
+use rust_distributed_sys_challenge::sim::{Client, NetworkConfig, Simulation};
+use rust_distributed_sys_challenge::two_phase::CommitConfig;
+use rust_distributed_sys_challenge::workloads::two_phase_txn::{TwoPhaseConfig, TwoPhaseTxnNode};
+use rust_distributed_sys_challenge::workloads::txn::{Operation, Payload};
+use rust_distributed_sys_challenge::ErrorCode;
+use serde_json::{json, Value};
+
+const NODES: usize = 5;
+/// transactions per writer, the reader runs twice as many
+const TXNS: usize = 30;
+const SEED: u64 = 1;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const WRITERS: u64 = 3;
+/// keys every transaction of the atomicity check touches
+const GROUP: [u64; 5] = [0, 1, 2, 3, 4];
+
+/// Run `txn` on `node_id`
+///
+/// returns:
+///   - the ops as they ran, `None` if the transaction was aborted
+fn transact(
+    client: &mut Client,
+    node_id: &str,
+    txn: Vec<Operation>,
+) -> anyhow::Result<Option<Vec<Operation>>> {
+    return match client.call(node_id, &Payload::Txn { txn }, TIMEOUT)? {
+        | Payload::TxnOk { txn } => Ok(Some(txn)),
+        | Payload::Error {
+            code: ErrorCode::TxnConflict,
+            ..
+        } => Ok(None),
+        | reply => bail!("unexpected reply to a transaction: {:?}", reply),
+    };
+}
+
+fn write(key: u64, value: Value) -> Operation {
+    return Operation("w".to_string(), json!(key), value);
+}
+
+fn read(key: u64) -> Operation {
+    return Operation("r".to_string(), json!(key), Value::Null);
+}
+
+/// Committed, aborted and torn reads of the atomicity check
+fn atomicity(ids: &[&str]) -> anyhow::Result<(usize, usize, Vec<String>)> {
+    let mut sim = Simulation::new(
+        ids,
+        NetworkConfig {
+            min_latency: Duration::ZERO,
+            max_latency: Duration::from_millis(10),
+            seed: SEED,
+            ..NetworkConfig::default()
+        },
+    );
+    for node_id in ids {
+        sim.start::<TwoPhaseTxnNode, _, _, _>(node_id, TwoPhaseConfig::default())?;
+    }
+
+    let node_ids: Vec<String> = ids.iter().map(|node_id| node_id.to_string()).collect();
+    let mut threads = Vec::new();
+    for writer in 0..=WRITERS {
+        let mut client = sim.client(&format!("c{}", writer));
+        let node_ids = node_ids.clone();
+        let mut rng = StdRng::seed_from_u64(SEED + writer);
+        // NOTE: client 0 reads, the others write
+        threads.push(std::thread::spawn(move || -> anyhow::Result<_> {
+            let mut outcomes = Vec::new();
+            for i in 0..TXNS * (1 + (writer == 0) as usize) {
+                let node_id = &node_ids[rng.gen_range(0..node_ids.len())];
+                let value = json!(writer * 1000 + i as u64);
+                let txn = match writer {
+                    | 0 => GROUP.iter().map(|key| read(*key)).collect(),
+                    | _ => GROUP.iter().map(|key| write(*key, value.clone())).collect(),
+                };
+                outcomes.push(transact(&mut client, node_id, txn)?);
+            }
+            return Ok(outcomes);
+        }));
+    }
+
+    let (mut committed, mut aborted) = (0, 0);
+    let mut torn = Vec::new();
+    for (writer, thread) in threads.into_iter().enumerate() {
+        let outcomes = thread.join().expect("client thread panicked")?;
+        for outcome in outcomes {
+            let Some(txn) = outcome else {
+                aborted += 1;
+                continue;
+            };
+            committed += 1;
+            let values: Vec<&Value> = txn.iter().map(|Operation(_, _, value)| value).collect();
+            if writer == 0 && values.iter().any(|value| *value != values[0]) {
+                torn.push(format!("read {:?} mixes transactions", values));
+            }
+        }
+    }
+    sim.shutdown()?;
+    return Ok((committed, aborted, torn));
+}
+
+/// Whether the crashed participant cut off from the crashed coordinator committed in the end
+fn recovery(ids: &[&str], data_dir: &std::path::Path) -> anyhow::Result<Vec<String>> {
+    let mut sim = Simulation::new(
+        ids,
+        NetworkConfig {
+            min_latency: Duration::from_millis(300),
+            max_latency: Duration::from_millis(300),
+            ..NetworkConfig::default()
+        },
+    );
+    // NOTE: a vote takes 600ms to come back, longer than the default prepare timeout
+    let config = TwoPhaseConfig {
+        commit: CommitConfig {
+            prepare_timeout: Duration::from_secs(1),
+            ..CommitConfig::default()
+        },
+        persist: Some(PersistConfig::new(data_dir)),
+        ..TwoPhaseConfig::default()
+    };
+    for node_id in ids {
+        sim.start::<TwoPhaseTxnNode, _, _, _>(node_id, config.clone())?;
+    }
+    let coordinator = ids[0];
+    let ring = HashRing::new(ids, &config.ring)?;
+    let key = (0..)
+        .find(|key: &u64| ring.owner(json!(key).to_string()) != coordinator)
+        .unwrap();
+    let participant = ring.owner(json!(key).to_string()).to_string();
+
+    // NOTE: prepare arrives at 300ms, the vote at 600ms and so would the commit at 900ms
+    let mut client = sim.client("c1");
+    let node_id = coordinator.to_string();
+    let txn = std::thread::spawn(move || {
+        return transact(&mut client, &node_id, vec![write(key, json!("recovered"))]);
+    });
+    std::thread::sleep(Duration::from_millis(450));
+    sim.isolate(&participant);
+    std::thread::sleep(Duration::from_millis(300));
+    sim.restart::<TwoPhaseTxnNode, _, _, _>(coordinator, config.clone())?;
+    // NOTE: the lock and the staged write now only exist in the participant's log
+    sim.restart::<TwoPhaseTxnNode, _, _, _>(&participant, config.clone())?;
+    std::thread::sleep(Duration::from_millis(500));
+    sim.heal();
+    // NOTE: the client's coordinator crashed, it never gets an answer
+    let _ = txn.join();
+
+    let mut violations = Vec::new();
+    let mut client = sim.client("c2");
+    let mut seen = None;
+    for _ in 0..20 {
+        std::thread::sleep(Duration::from_millis(250));
+        if let Some(txn) = transact(&mut client, &participant, vec![read(key)])? {
+            seen = Some(txn[0].2.clone());
+            break;
+        }
+    }
+    match seen {
+        | Some(value) if value == json!("recovered") => {},
+        | Some(value) => violations.push(format!(
+            "{} read {} of key {} after recovery, expected the committed write",
+            participant, value, key
+        )),
+        | None => violations.push(format!(
+            "{} still holds the lock on key {}",
+            participant, key
+        )),
+    }
+    sim.shutdown()?;
+    return Ok(violations);
+}
+
+fn ids() -> Vec<String> {
+    return (0..NODES).map(|i| format!("n{}", i)).collect();
+}
+
+#[test]
+fn reads_never_see_half_a_transaction() -> anyhow::Result<()> {
+    let node_ids = ids();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let (committed, aborted, torn) = atomicity(&ids)?;
+    assert!(
+        committed > 0,
+        "every transaction was aborted ({} txn-conflicts)",
+        aborted
+    );
+    assert!(torn.is_empty(), "torn reads:\n{}", torn.join("\n"));
+    return Ok(());
+}
+
+#[test]
+fn cut_off_participant_commits_after_crashes() -> anyhow::Result<()> {
+    let node_ids = ids();
+    let ids: Vec<&str> = node_ids.iter().map(|node_id| node_id.as_str()).collect();
+    let data_dir = std::env::temp_dir().join(format!("two_phase_txn-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let violations = recovery(&ids, &data_dir);
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let violations = violations?;
+    assert!(
+        violations.is_empty(),
+        "recovery failed:\n{}",
+        violations.join("\n")
+    );
+    return Ok(());
+}